@@ -0,0 +1,46 @@
+use figment::{
+    providers::{Env, Format, Toml},
+    Figment,
+};
+use serde::Deserialize;
+use std::env;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("couldn't load the configuration: {0}")]
+    Load(#[from] Box<figment::Error>),
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+}
+
+// Settings of the image consumer, read from the TOML file in CONFIG_FILE
+// (image_consumer.toml by default) and overridden by environment variables of
+// the same name in upper case, e.g. GOOGLE_VISION_API_KEY
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    pub rabbit_address: String,
+    // Used to download the photos
+    pub telegram_bot_token: String,
+    pub google_vision_api_key: String,
+}
+
+impl Config {
+    pub fn load() -> Result<Self, ConfigError> {
+        let file = env::var("CONFIG_FILE").unwrap_or_else(|_| "image_consumer.toml".to_string());
+        let config: Config = Figment::new()
+            .merge(Toml::file(file))
+            .merge(Env::raw().split("__"))
+            .extract()
+            .map_err(Box::new)?;
+        let settings = [
+            ("rabbit_address", &config.rabbit_address),
+            ("telegram_bot_token", &config.telegram_bot_token),
+            ("google_vision_api_key", &config.google_vision_api_key),
+        ];
+        if let Some((name, _)) = settings.iter().find(|(_, value)| value.trim().is_empty()) {
+            return Err(ConfigError::Invalid(format!("{} must not be empty", name)));
+        }
+        Ok(config)
+    }
+}
@@ -1,3 +1,5 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use config::Config;
 use dotenvy::dotenv;
 use futures_util::StreamExt;
 use lapin::{
@@ -6,24 +8,24 @@ use lapin::{
     BasicProperties, Channel, Connection, ConnectionProperties, Consumer,
 };
 use log::info;
-use models::{
-    Feature, ImageContent, RabbitMessage, VisionRequest, VisionRequestItem, VisionResponse,
-};
 use reqwest::Client;
-use std::{env, error::Error};
-mod models;
+use rustin_models::{
+    markdown,
+    vision::{Feature, ImageContent, VisionRequest, VisionRequestItem, VisionResponse},
+    FileMessage, MessageBody, RabbitMessage,
+};
+use std::error::Error;
+
+mod config;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     pretty_env_logger::init();
     dotenv().expect("Failed to load .env file");
 
-    let rabbit_addr = env::var("RABBIT_ADDRESS").expect("RABBIT_ADDRESS must be set");
-    let telegram_token = env::var("TELEGRAM_BOT_TOKEN").expect("TELEGRAM_BOT_TOKEN must be set");
-    let google_api_key =
-        env::var("GOOGLE_VISION_API_KEY").expect("GOOGLE_VISION_API_KEY must be set");
+    let config = Config::load().expect("Invalid configuration");
 
-    let connection = Connection::connect(&rabbit_addr, ConnectionProperties::default())
+    let connection = Connection::connect(&config.rabbit_address, ConnectionProperties::default())
         .await
         .expect("Failed to connect to RabbitMQ");
 
@@ -41,17 +43,21 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     while let Some(delivery) = consumer.next().await {
         if let Ok(delivery) = delivery {
-            let message: RabbitMessage =
-                serde_json::from_slice(&delivery.data).expect("Failed to parse RabbitMessage");
+            let message: FileMessage =
+                serde_json::from_slice(&delivery.data).expect("Failed to parse FileMessage");
 
-            let base64_image = download_image_as_base64(&telegram_token, &message.text).await?;
-            let extracted_text = detect_text_from_image(&google_api_key, &base64_image).await?;
+            let base64_image =
+                download_image_as_base64(&config.telegram_bot_token, &message.text).await?;
+            let extracted_text =
+                detect_text_from_image(&config.google_vision_api_key, &base64_image).await?;
 
             // Publish the reply message
-            let reply_message = RabbitMessage {
-                chat_id: message.chat_id,
-                text: extracted_text,
-            };
+            let reply_message = RabbitMessage::new(
+                message.chat_id,
+                MessageBody::Result {
+                    text: markdown::escape(&extracted_text),
+                },
+            );
             publish_to_reply_queue(&channel, &reply_message).await?;
 
             // Acknowledge the message
@@ -84,7 +90,7 @@ async fn download_image_as_base64(
         telegram_token, file_path
     );
     let image_bytes = client.get(&download_url).send().await?.bytes().await?;
-    let base64_image = base64::encode(&image_bytes);
+    let base64_image = STANDARD.encode(&image_bytes);
 
     Ok(base64_image)
 }
@@ -122,7 +128,7 @@ async fn detect_text_from_image(
     let description = response
         .responses
         .first()
-        .and_then(|r| r.textAnnotations.as_ref())
+        .and_then(|r| r.text_annotations.as_ref())
         .and_then(|annotations| annotations.first())
         .map(|annotation| annotation.description.clone())
         .unwrap_or_else(|| "No text found.".to_string());
@@ -0,0 +1,49 @@
+use crate::topology::Queues;
+use figment::{
+    providers::{Env, Format, Toml},
+    Figment,
+};
+use serde::Deserialize;
+use std::env;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("couldn't load the configuration: {0}")]
+    Load(#[from] Box<figment::Error>),
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+}
+
+// Settings of the reply service, read from the TOML file in CONFIG_FILE
+// (reply_service.toml by default) and overridden by environment variables of
+// the same name in upper case, e.g. HTTP_ADDR or QUEUES__REPLY
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    pub rabbit_address: String,
+    #[serde(default = "default_http_addr")]
+    pub http_addr: String,
+    #[serde(default)]
+    pub queues: Queues,
+}
+
+impl Config {
+    pub fn load() -> Result<Self, ConfigError> {
+        let file = env::var("CONFIG_FILE").unwrap_or_else(|_| "reply_service.toml".to_string());
+        let config: Config = Figment::new()
+            .merge(Toml::file(file))
+            .merge(Env::raw().split("__"))
+            .extract()
+            .map_err(Box::new)?;
+        if config.queues.reply.trim().is_empty() || config.queues.audio_cache.trim().is_empty() {
+            return Err(ConfigError::Invalid(
+                "queue names must not be empty".to_string(),
+            ));
+        }
+        Ok(config)
+    }
+}
+
+fn default_http_addr() -> String {
+    "0.0.0.0:9001".to_string()
+}
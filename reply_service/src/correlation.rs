@@ -0,0 +1,33 @@
+use lapin::{
+    message::Delivery,
+    types::{AMQPValue, FieldTable, ShortString},
+};
+use rustin_models::REQUEST_ID_HEADER;
+use uuid::Uuid;
+
+// The request ID the song consumer passed along, or a fresh one for replies
+// published without it
+pub fn request_id(delivery: &Delivery) -> String {
+    delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(&ShortString::from(REQUEST_ID_HEADER)))
+        .and_then(|value| match value {
+            AMQPValue::LongString(id) => Some(id.to_string()),
+            AMQPValue::ShortString(id) => Some(id.to_string()),
+            _ => None,
+        })
+        .unwrap_or_else(|| Uuid::new_v4().simple().to_string())
+}
+
+// Headers of a message published while handling a reply, in its trace
+pub fn headers(request_id: &str) -> FieldTable {
+    let mut headers = FieldTable::default();
+    headers.insert(
+        REQUEST_ID_HEADER.into(),
+        AMQPValue::LongString(request_id.into()),
+    );
+    rustin_telemetry::inject(&mut headers);
+    headers
+}
@@ -0,0 +1,408 @@
+use crate::{correlation, topology};
+use lapin::{options::BasicPublishOptions, BasicProperties, Channel};
+use log::{error, info, warn};
+use rustin_models::{
+    file_name, i18n::tr_args, InlineAudio, InlineAudioSource, MessageBody, RabbitMessage,
+    SearchCandidate, PICK_CALLBACK_PREFIX,
+};
+use teloxide::{
+    prelude::*,
+    types::{
+        InlineKeyboardButton, InlineKeyboardMarkup, InlineQueryResult, InlineQueryResultAudio,
+        InlineQueryResultCachedAudio, InputFile, LinkPreviewOptions, ParseMode,
+    },
+    RequestError,
+};
+use url::Url;
+
+// Send the reply to its chat, results as Markdown and errors as plain text
+pub async fn deliver(
+    bot: &Bot,
+    channel: &Channel,
+    request_id: &str,
+    message: &RabbitMessage,
+) -> Result<(), RequestError> {
+    let chat_id = ChatId(message.chat_id);
+    let language = message.options.language.as_deref();
+
+    match &message.body {
+        MessageBody::Result { text } => deliver_markdown(bot, chat_id, text).await,
+        MessageBody::Audio {
+            file_path,
+            title,
+            performer,
+            thumbnail_path,
+            cache_key,
+            caption,
+            lyrics_path,
+        } => {
+            let sent = deliver_audio(
+                bot,
+                chat_id,
+                file_path,
+                thumbnail_path.as_deref(),
+                title,
+                performer.as_deref(),
+                caption.as_deref(),
+            )
+            .await;
+            if let Some(lyrics_path) = lyrics_path {
+                deliver_lyrics(
+                    bot,
+                    chat_id,
+                    lyrics_path,
+                    title,
+                    performer.as_deref(),
+                    &sent,
+                )
+                .await;
+            }
+            let sent = sent?;
+            if let (Some(cache_key), Some(audio)) = (cache_key, sent.audio()) {
+                report_upload(
+                    channel,
+                    request_id,
+                    message.chat_id,
+                    cache_key,
+                    &audio.file.id,
+                )
+                .await;
+            }
+            Ok(())
+        }
+        MessageBody::Archive {
+            file_path,
+            file_name,
+        } => deliver_archive(bot, chat_id, file_path, file_name).await,
+        MessageBody::Video { file_path, title } => {
+            deliver_video(bot, chat_id, file_path, title).await
+        }
+        MessageBody::CachedAudio {
+            file_id,
+            title,
+            performer,
+            caption,
+            lyrics_path,
+        } => {
+            let mut request = bot
+                .send_audio(chat_id, InputFile::file_id(file_id.clone()))
+                .title(title);
+            if let Some(performer) = performer {
+                request = request.performer(performer);
+            }
+            if let Some(caption) = caption {
+                request = request.caption(caption);
+            }
+            let sent = request.await;
+            if let Some(lyrics_path) = lyrics_path {
+                deliver_lyrics(
+                    bot,
+                    chat_id,
+                    lyrics_path,
+                    title,
+                    performer.as_deref(),
+                    &sent,
+                )
+                .await;
+            }
+            sent?;
+            info!("Resent cached audio '{}' to chat_id {}", title, chat_id);
+            Ok(())
+        }
+        MessageBody::Progress { completed, total } => {
+            let text = tr_args(
+                language,
+                "progress.converted",
+                &[
+                    ("completed", &completed.to_string()),
+                    ("total", &total.to_string()),
+                ],
+            );
+            bot.send_message(chat_id, text).await?;
+            Ok(())
+        }
+        MessageBody::InlineAnswer {
+            inline_query_id,
+            results,
+        } => answer_inline_query(bot, inline_query_id, results).await,
+        MessageBody::SearchResults { query, candidates } => {
+            offer_candidates(bot, chat_id, language, query, candidates).await
+        }
+        MessageBody::Error { message } => {
+            bot.send_message(chat_id, format!("⚠️ {}", message)).await?;
+            Ok(())
+        }
+        other => {
+            warn!("Ignoring request message on the Reply queue: {:?}", other);
+            Ok(())
+        }
+    }
+}
+
+// Send the text as MarkdownV2, falling back to plain text when Telegram
+// rejects the markup (e.g. a producer that forgot to escape its text)
+async fn deliver_markdown(bot: &Bot, chat_id: ChatId, text: &str) -> Result<(), RequestError> {
+    let markdown_result = bot
+        .send_message(chat_id, text)
+        .parse_mode(ParseMode::MarkdownV2)
+        .link_preview_options(LinkPreviewOptions {
+            is_disabled: true,
+            url: None,
+            prefer_small_media: false,
+            prefer_large_media: false,
+            show_above_text: false,
+        })
+        .await;
+
+    match markdown_result {
+        Ok(_) => {
+            info!("Delivered reply to chat_id {}", chat_id);
+            Ok(())
+        }
+        Err(RequestError::Api(err)) => {
+            warn!(
+                "Markdown rejected for chat_id {} ({}), resending as plain text",
+                chat_id, err
+            );
+            bot.send_message(chat_id, text).await?;
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// Offer the found songs in the inline query menu. Results whose link is not
+// a valid URL are left out rather than failing the whole answer
+async fn answer_inline_query(
+    bot: &Bot,
+    inline_query_id: &str,
+    results: &[InlineAudio],
+) -> Result<(), RequestError> {
+    let results: Vec<InlineQueryResult> = results
+        .iter()
+        .filter_map(|audio| {
+            let result = match &audio.source {
+                InlineAudioSource::Url { url } => {
+                    let url = match Url::parse(url) {
+                        Ok(url) => url,
+                        Err(err) => {
+                            warn!("Skipping inline result with invalid URL {}: {}", url, err);
+                            return None;
+                        }
+                    };
+                    let mut result = InlineQueryResultAudio::new(&audio.id, url, &audio.title);
+                    if let Some(performer) = &audio.performer {
+                        result = result.performer(performer);
+                    }
+                    InlineQueryResult::Audio(result)
+                }
+                InlineAudioSource::Cached { file_id } => InlineQueryResult::CachedAudio(
+                    InlineQueryResultCachedAudio::new(&audio.id, file_id),
+                ),
+            };
+            Some(result)
+        })
+        .collect();
+
+    let count = results.len();
+    bot.answer_inline_query(inline_query_id, results).await?;
+    info!(
+        "Answered inline query {} with {} results",
+        inline_query_id, count
+    );
+    Ok(())
+}
+
+// Let the user pick one of the search results, the chosen button comes back
+// to the bot as a callback query
+async fn offer_candidates(
+    bot: &Bot,
+    chat_id: ChatId,
+    language: Option<&str>,
+    query: &str,
+    candidates: &[SearchCandidate],
+) -> Result<(), RequestError> {
+    let query_arg = [("query", query)];
+    if candidates.is_empty() {
+        let text = tr_args(language, "picker.nothing_found", &query_arg);
+        bot.send_message(chat_id, format!("⚠️ {}", text)).await?;
+        return Ok(());
+    }
+
+    let rows = candidates.iter().map(|candidate| {
+        [InlineKeyboardButton::callback(
+            button_label(candidate),
+            format!("{}{}", PICK_CALLBACK_PREFIX, candidate.video_id),
+        )]
+    });
+    bot.send_message(chat_id, tr_args(language, "picker.prompt", &query_arg))
+        .reply_markup(InlineKeyboardMarkup::new(rows))
+        .await?;
+    info!(
+        "Offered {} search results to chat_id {}",
+        candidates.len(),
+        chat_id
+    );
+    Ok(())
+}
+
+// Telegram cuts long button labels off, so keep the title short enough for
+// the channel and duration to stay visible
+fn button_label(candidate: &SearchCandidate) -> String {
+    const MAX_TITLE_CHARS: usize = 40;
+
+    let mut title: String = candidate.title.chars().take(MAX_TITLE_CHARS).collect();
+    if candidate.title.chars().count() > MAX_TITLE_CHARS {
+        title.push('…');
+    }
+    match &candidate.duration {
+        Some(duration) => format!("{} · {} · {}", title, candidate.channel, duration),
+        None => format!("{} · {}", title, candidate.channel),
+    }
+}
+
+// Upload a downloaded MP3 and remove it and its thumbnail from the shared
+// media directory
+async fn deliver_audio(
+    bot: &Bot,
+    chat_id: ChatId,
+    file_path: &str,
+    thumbnail_path: Option<&str>,
+    title: &str,
+    performer: Option<&str>,
+    caption: Option<&str>,
+) -> Result<Message, RequestError> {
+    // Named like the entries of the playlist that may come after it
+    let audio = InputFile::file(file_path).file_name(file_name::song(title, performer, "mp3"));
+    let mut request = bot.send_audio(chat_id, audio).title(title);
+    if let Some(performer) = performer {
+        request = request.performer(performer);
+    }
+    if let Some(caption) = caption {
+        request = request.caption(caption);
+    }
+    if let Some(thumbnail_path) = thumbnail_path {
+        request = request.thumbnail(InputFile::file(thumbnail_path));
+    }
+    let result = request.await;
+
+    for path in std::iter::once(file_path).chain(thumbnail_path) {
+        if let Err(err) = tokio::fs::remove_file(path).await {
+            warn!("Failed to remove {}: {}", path, err);
+        }
+    }
+
+    let sent = result?;
+    info!("Delivered audio '{}' to chat_id {}", title, chat_id);
+    Ok(sent)
+}
+
+// Send the synced lyrics of a song as an .lrc document right after its audio,
+// so both can be imported into a player. The file is removed either way, and
+// a song whose audio didn't go out doesn't get its lyrics either
+async fn deliver_lyrics(
+    bot: &Bot,
+    chat_id: ChatId,
+    lyrics_path: &str,
+    title: &str,
+    performer: Option<&str>,
+    sent: &Result<Message, RequestError>,
+) {
+    if sent.is_ok() {
+        let document =
+            InputFile::file(lyrics_path).file_name(file_name::song(title, performer, "lrc"));
+        match bot.send_document(chat_id, document).await {
+            Ok(_) => info!("Delivered lyrics of '{}' to chat_id {}", title, chat_id),
+            // The song itself arrived, which is what matters
+            Err(err) => warn!("Failed to send the lyrics of '{}': {}", title, err),
+        }
+    }
+
+    if let Err(err) = tokio::fs::remove_file(lyrics_path).await {
+        warn!("Failed to remove {}: {}", lyrics_path, err);
+    }
+}
+
+// Upload an MP4 for /video and remove it from the shared media directory.
+// The consumer already sent the ones over Telegram's limit as links
+async fn deliver_video(
+    bot: &Bot,
+    chat_id: ChatId,
+    file_path: &str,
+    title: &str,
+) -> Result<(), RequestError> {
+    let result = bot
+        .send_video(chat_id, InputFile::file(file_path))
+        .caption(title)
+        .supports_streaming(true)
+        .await;
+
+    if let Err(err) = tokio::fs::remove_file(file_path).await {
+        warn!("Failed to remove {}: {}", file_path, err);
+    }
+
+    result?;
+    info!("Delivered video '{}' to chat_id {}", title, chat_id);
+    Ok(())
+}
+
+// Upload a ZIP of songs or an M3U playlist as a document and remove it from
+// the shared media directory
+async fn deliver_archive(
+    bot: &Bot,
+    chat_id: ChatId,
+    file_path: &str,
+    file_name: &str,
+) -> Result<(), RequestError> {
+    let result = bot
+        .send_document(
+            chat_id,
+            InputFile::file(file_path).file_name(file_name.to_string()),
+        )
+        .await;
+
+    if let Err(err) = tokio::fs::remove_file(file_path).await {
+        warn!("Failed to remove {}: {}", file_path, err);
+    }
+
+    result?;
+    info!("Delivered archive '{}' to chat_id {}", file_name, chat_id);
+    Ok(())
+}
+
+// Tell the song consumer which file_id Telegram assigned to an upload, so
+// the next request for the same song doesn't have to download it again
+async fn report_upload(
+    channel: &Channel,
+    request_id: &str,
+    chat_id: i64,
+    cache_key: &str,
+    file_id: &str,
+) {
+    let message = RabbitMessage::new(
+        chat_id,
+        MessageBody::AudioUploaded {
+            cache_key: cache_key.to_string(),
+            file_id: file_id.to_string(),
+        },
+    );
+
+    let result = match serde_json::to_vec(&message) {
+        Ok(payload) => channel
+            .basic_publish(
+                "",
+                &topology::queues().audio_cache,
+                BasicPublishOptions::default(),
+                &payload,
+                BasicProperties::default().with_headers(correlation::headers(request_id)),
+            )
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+
+    if let Err(e) = result {
+        error!("Failed to report file_id for {}: {}", cache_key, e);
+    }
+}
@@ -0,0 +1,22 @@
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use lapin::ConnectionStatus;
+
+// /healthz and /readyz both report whether the RabbitMQ connection is open,
+// the reply service has nothing else to wait for
+pub fn router(rabbit: ConnectionStatus) -> Router {
+    Router::new()
+        .route("/healthz", get(check))
+        .route("/readyz", get(check))
+        .with_state(rabbit)
+}
+
+async fn check(State(rabbit): State<ConnectionStatus>) -> (StatusCode, &'static str) {
+    if rabbit.connected() {
+        (StatusCode::OK, "ok")
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "RabbitMQ connection is down",
+        )
+    }
+}
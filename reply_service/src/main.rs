@@ -1,3 +1,5 @@
+use config::Config;
+use delivery::deliver;
 use dotenvy::dotenv;
 use futures_util::StreamExt;
 use lapin::{
@@ -5,40 +7,56 @@ use lapin::{
     types::FieldTable,
     Connection, ConnectionProperties, Consumer,
 };
-use serde::Deserialize;
-use std::{env, error::Error};
-use teloxide::{prelude::*, types::ChatId, Bot};
+use log::{error, info};
+use rustin_models::RabbitMessage;
+use std::error::Error;
+use teloxide::Bot;
+use tracing::Instrument;
 
-// Define the structure of the expected message
-#[derive(Deserialize)]
-struct RabbitMessage {
-    chat_id: i64,
-    text: String,
-}
+mod config;
+mod correlation;
+mod delivery;
+mod health;
+mod topology;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Initialize the logger and load the .env file
-    pretty_env_logger::init();
+    // Load the .env file and initialize the logger and trace export
     dotenv().expect("Failed to load .env file");
+    let _telemetry = rustin_telemetry::init("reply_service");
+
+    // Bad settings stop the service here instead of failing mid-run
+    let config = Config::load()?;
+    topology::init(config.queues.clone());
 
-    // Retrieve RabbitMQ address and connect
-    let rabbit_addr = env::var("RABBIT_ADDRESS").expect("RABBIT_ADDRESS must be set");
-    let connection = Connection::connect(&rabbit_addr, ConnectionProperties::default())
+    // Connect to RabbitMQ
+    let connection = Connection::connect(&config.rabbit_address, ConnectionProperties::default())
         .await
         .expect("Failed to connect to RabbitMQ");
 
+    // Serve the orchestrator probes next to the consumer
+    let listener = tokio::net::TcpListener::bind(&config.http_addr).await?;
+    let app = health::router(connection.status().clone());
+    tokio::spawn(async move {
+        if let Err(err) = axum::serve(listener, app).await {
+            error!("Health server stopped: {}", err);
+        }
+    });
+    info!("Serving /healthz and /readyz on {}", config.http_addr);
+
     let channel = connection.create_channel().await?;
+    topology::declare(&channel).await?;
+
     let mut consumer: Consumer = channel
         .basic_consume(
-            "Reply",          // Queue name
-            "reply_consumer", // Consumer tag
+            &topology::queues().reply, // Queue name
+            "reply_consumer",          // Consumer tag
             BasicConsumeOptions::default(),
             FieldTable::default(),
         )
         .await?;
 
-    println!("Waiting for messages...");
+    info!("Waiting for messages...");
 
     // Initialize the bot from environment variables
     let bot = Bot::from_env();
@@ -46,29 +64,38 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Process incoming messages from RabbitMQ
     while let Some(delivery) = consumer.next().await {
         if let Ok(delivery) = delivery {
-            // Parse the message as JSON
-            match serde_json::from_slice::<RabbitMessage>(&delivery.data) {
-                Ok(rabbit_message) => {
-                    println!(
-                        "Received message for chat_id {}: {}",
-                        rabbit_message.chat_id, rabbit_message.text
-                    );
+            let request_id = correlation::request_id(&delivery);
+            let span = tracing::info_span!("request", request_id = %request_id);
+            // Continues the trace of the worker that published the reply
+            rustin_telemetry::set_parent(&span, &delivery);
 
-                    // Send a message to the specified chat_id
-                    let chat_id = ChatId(rabbit_message.chat_id);
-                    if let Err(err) = bot.send_message(chat_id, rabbit_message.text.clone()).await {
-                        eprintln!("Failed to send message: {}", err);
+            async {
+                // Parse the message as JSON
+                match serde_json::from_slice::<RabbitMessage>(&delivery.data) {
+                    Ok(rabbit_message) => {
+                        info!(
+                            "Received message for chat_id {}: {:?}",
+                            rabbit_message.chat_id, rabbit_message.body
+                        );
+
+                        // Send a message to the specified chat_id
+                        if let Err(err) =
+                            deliver(&bot, &channel, &request_id, &rabbit_message).await
+                        {
+                            error!("Failed to send message: {}", err);
+                        }
+                    }
+                    Err(err) => {
+                        error!("Failed to parse message: {}", err);
                     }
-                }
-                Err(err) => {
-                    eprintln!("Failed to parse message: {}", err);
                 }
             }
+            .instrument(span)
+            .await;
 
             // Acknowledge the message
             delivery.ack(BasicAckOptions::default()).await?;
         }
     }
-
     Ok(())
 }
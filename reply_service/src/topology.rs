@@ -0,0 +1,59 @@
+use lapin::{options::QueueDeclareOptions, types::FieldTable, Channel};
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+// Names of the queues, matching the song consumer's configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Queues {
+    pub reply: String,
+    // Queue the song consumer listens on for file_ids of uploaded audio
+    pub audio_cache: String,
+}
+
+impl Default for Queues {
+    fn default() -> Self {
+        Self {
+            reply: "Reply".to_string(),
+            audio_cache: "AudioCache".to_string(),
+        }
+    }
+}
+
+static QUEUES: OnceLock<Queues> = OnceLock::new();
+
+// Set the queue names once at startup, before anything is declared
+pub fn init(queues: Queues) {
+    if QUEUES.set(queues).is_err() {
+        log::warn!("Queue names were already initialised, keeping the first ones");
+    }
+}
+
+pub fn queues() -> &'static Queues {
+    QUEUES.get_or_init(Queues::default)
+}
+
+// Declare the queues the reply service consumes from and publishes to.
+// Declaring is idempotent and matches the song consumer's declarations
+pub async fn declare(channel: &Channel) -> Result<(), lapin::Error> {
+    let queues = queues();
+    for queue in [&queues.reply, &queues.audio_cache] {
+        channel
+            .queue_declare(
+                queue,
+                QueueDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+    }
+
+    log::info!(
+        "Declared queues '{}' and '{}'",
+        queues.reply,
+        queues.audio_cache
+    );
+    Ok(())
+}
@@ -0,0 +1,254 @@
+use crate::{commands::HandlerResult, producer::Producer};
+use rustin_storage::Storage;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use teloxide::{prelude::*, utils::command::BotCommands};
+use tokio::sync::RwLock;
+
+// Stats cover this much of the recent past
+const STATS_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+// Pause between broadcast messages, to stay under Telegram's rate limits
+const BROADCAST_DELAY: Duration = Duration::from_millis(50);
+
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase", description = "Admin commands:")]
+pub enum AdminCommand {
+    #[command(description = "show queue depths, conversion counts and YouTube quota usage.")]
+    Stats,
+    #[command(description = "send a message to every chat.")]
+    Broadcast(String),
+    #[command(description = "stop handling updates from a chat: /ban <chat_id>.")]
+    Ban(String),
+    #[command(description = "handle updates from a banned chat again.")]
+    Unban(String),
+}
+
+// Telegram user IDs allowed to run admin commands, from admin_ids
+#[derive(Default)]
+pub struct Admins {
+    ids: HashSet<i64>,
+}
+
+impl Admins {
+    pub fn new(ids: &[i64]) -> Self {
+        Self {
+            ids: ids.iter().copied().collect(),
+        }
+    }
+
+    pub fn is_admin(&self, msg: &Message) -> bool {
+        msg.from
+            .as_ref()
+            .is_some_and(|user| self.ids.contains(&(user.id.0 as i64)))
+    }
+}
+
+// Chats whose updates are dropped before anything is enqueued. Kept in memory
+// and saved to storage, when there is one, so bans survive restarts
+pub struct Bans {
+    chats: RwLock<HashSet<i64>>,
+    storage: Option<Arc<Storage>>,
+}
+
+impl Bans {
+    pub async fn load(storage: Option<Arc<Storage>>) -> Self {
+        let chats = match &storage {
+            Some(storage) => storage.banned_chats().await.unwrap_or_else(|e| {
+                log::error!("Failed to load banned chats: {}", e);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        Self {
+            chats: RwLock::new(chats.into_iter().collect()),
+            storage,
+        }
+    }
+
+    // Inline queries have no chat, so the sender is checked as well
+    pub async fn is_banned(&self, update: &Update) -> bool {
+        let chats = self.chats.read().await;
+        update.chat().is_some_and(|chat| chats.contains(&chat.id.0))
+            || update
+                .from()
+                .is_some_and(|user| chats.contains(&(user.id.0 as i64)))
+    }
+
+    async fn ban(&self, chat_id: i64, banned_by: i64) {
+        self.chats.write().await.insert(chat_id);
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.ban_chat(chat_id, banned_by).await {
+                log::error!("Failed to save ban of chat {}: {}", chat_id, e);
+            }
+        }
+    }
+
+    async fn unban(&self, chat_id: i64) -> bool {
+        let removed = self.chats.write().await.remove(&chat_id);
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.unban_chat(chat_id).await {
+                log::error!("Failed to remove ban of chat {}: {}", chat_id, e);
+            }
+        }
+        removed
+    }
+}
+
+#[tracing::instrument(skip_all, fields(chat_id = msg.chat.id.0))]
+pub async fn handle_admin_command(
+    bot: Bot,
+    msg: Message,
+    cmd: AdminCommand,
+    producer: Arc<Producer>,
+    storage: Option<Arc<Storage>>,
+    bans: Arc<Bans>,
+) -> HandlerResult {
+    match cmd {
+        AdminCommand::Stats => {
+            show_stats(&bot, &msg, &producer, storage.as_deref()).await?;
+        }
+        AdminCommand::Broadcast(text) => {
+            broadcast(&bot, &msg, &text, storage).await?;
+        }
+        AdminCommand::Ban(chat_id) => {
+            ban(&bot, &msg, &chat_id, &bans).await?;
+        }
+        AdminCommand::Unban(chat_id) => {
+            unban(&bot, &msg, &chat_id, &bans).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn show_stats(
+    bot: &Bot,
+    msg: &Message,
+    producer: &Producer,
+    storage: Option<&Storage>,
+) -> HandlerResult {
+    let mut text = String::from("Queues:");
+    match producer.queue_depths().await {
+        Ok(depths) => {
+            for (queue_name, depth) in depths {
+                text.push_str(&format!("\n{}: {} waiting", queue_name, depth));
+            }
+        }
+        Err(e) => {
+            log::error!("Failed to read queue depths: {}", e);
+            text.push_str("\nunavailable");
+        }
+    }
+
+    text.push_str("\n\nLast 24 hours:");
+    match storage {
+        Some(storage) => match storage.conversion_stats(STATS_PERIOD).await {
+            Ok(stats) => text.push_str(&format!(
+                "\n{} requests\n{} songs converted\n{} songs failed",
+                stats.requests, stats.songs_converted, stats.songs_failed
+            )),
+            Err(e) => {
+                log::error!("Failed to load conversion stats: {}", e);
+                text.push_str("\nunavailable");
+            }
+        },
+        None => text.push_str("\nno history database configured"),
+    }
+
+    // Estimated by the consumers, the day starts at midnight Pacific time
+    if let Some(storage) = storage {
+        match storage.youtube_quota_units().await {
+            Ok(units) => text.push_str(&format!("\n\nYouTube quota used today: {} units", units)),
+            Err(e) => log::error!("Failed to load YouTube quota usage: {}", e),
+        }
+    }
+
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
+
+// Sending to every chat takes a while, so it runs in the background and
+// reports back when it's done
+async fn broadcast(
+    bot: &Bot,
+    msg: &Message,
+    text: &str,
+    storage: Option<Arc<Storage>>,
+) -> HandlerResult {
+    let text = text.trim().to_string();
+    if text.is_empty() {
+        bot.send_message(msg.chat.id, "Type /broadcast followed by the message.")
+            .await?;
+        return Ok(());
+    }
+    let Some(storage) = storage else {
+        bot.send_message(msg.chat.id, "Broadcasts need the history database.")
+            .await?;
+        return Ok(());
+    };
+    let chats = match storage.known_chats().await {
+        Ok(chats) => chats,
+        Err(e) => {
+            log::error!("Failed to load known chats: {}", e);
+            bot.send_message(msg.chat.id, "Something went wrong, please try again later.")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    bot.send_message(
+        msg.chat.id,
+        format!("Sending your message to {} chats…", chats.len()),
+    )
+    .await?;
+
+    let bot = bot.clone();
+    let admin_chat = msg.chat.id;
+    tokio::spawn(async move {
+        let (mut sent, mut failed) = (0, 0);
+        for chat_id in chats {
+            match bot.send_message(ChatId(chat_id), &text).await {
+                Ok(_) => sent += 1,
+                Err(e) => {
+                    log::warn!("Failed to broadcast to chat {}: {}", chat_id, e);
+                    failed += 1;
+                }
+            }
+            tokio::time::sleep(BROADCAST_DELAY).await;
+        }
+        log::info!("Broadcast sent to {} chats, {} failed", sent, failed);
+        let report = format!("Broadcast done: {} sent, {} failed.", sent, failed);
+        if let Err(e) = bot.send_message(admin_chat, report).await {
+            log::error!("Failed to report broadcast: {}", e);
+        }
+    });
+    Ok(())
+}
+
+async fn ban(bot: &Bot, msg: &Message, chat_id: &str, bans: &Bans) -> HandlerResult {
+    let Ok(chat_id) = chat_id.trim().parse::<i64>() else {
+        bot.send_message(msg.chat.id, "Type /ban followed by a chat ID.")
+            .await?;
+        return Ok(());
+    };
+    let banned_by = msg.from.as_ref().map_or(0, |user| user.id.0 as i64);
+    bans.ban(chat_id, banned_by).await;
+    log::info!("Chat {} banned by {}", chat_id, banned_by);
+    bot.send_message(msg.chat.id, format!("Chat {} is banned.", chat_id))
+        .await?;
+    Ok(())
+}
+
+async fn unban(bot: &Bot, msg: &Message, chat_id: &str, bans: &Bans) -> HandlerResult {
+    let Ok(chat_id) = chat_id.trim().parse::<i64>() else {
+        bot.send_message(msg.chat.id, "Type /unban followed by a chat ID.")
+            .await?;
+        return Ok(());
+    };
+    let reply = if bans.unban(chat_id).await {
+        log::info!("Chat {} unbanned", chat_id);
+        format!("Chat {} is no longer banned.", chat_id)
+    } else {
+        format!("Chat {} wasn't banned.", chat_id)
+    };
+    bot.send_message(msg.chat.id, reply).await?;
+    Ok(())
+}
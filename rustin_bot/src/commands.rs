@@ -0,0 +1,788 @@
+use crate::{
+    groups::GroupMode,
+    history, playlists,
+    preferences::{Preferences, SUPPORTED_BITRATES},
+    producer::Producer,
+    request_id::RequestId,
+    settings,
+    spotify::SpotifyAccounts,
+    status,
+    youtube::{self, YouTubeAccounts},
+};
+use log::info;
+use rustin_models::{
+    clip::{self, Clip},
+    i18n::{tr, tr_args},
+    request_text, schedule, MediaFormat, RequestOptions,
+};
+use rustin_storage::Storage;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use teloxide::{prelude::*, types::Me, utils::command::BotCommands};
+
+pub type HandlerResult = Result<(), Box<dyn Error + Send + Sync + 'static>>;
+
+// What the commands about a user's own data need: the database their history,
+// schedules and logins are kept in, and the services they can sign in to
+pub struct Accounts {
+    pub storage: Option<Arc<Storage>>,
+    pub spotify: Option<Arc<SpotifyAccounts>>,
+    pub youtube: Option<Arc<YouTubeAccounts>>,
+}
+
+#[derive(BotCommands, Clone)]
+#[command(
+    rename_rule = "lowercase",
+    description = "These commands are supported:"
+)]
+pub enum Command {
+    #[command(description = "start using the bot.")]
+    Start,
+    #[command(description = "display this text.")]
+    Help,
+    #[command(description = "convert songs to MP3, one title per line.")]
+    Song(String),
+    #[command(description = "get the videos as MP4 instead, one title per line.")]
+    Video(String),
+    #[command(description = "pick the right song from the top search results.")]
+    Search(String),
+    #[command(description = "show the lyrics of a song.")]
+    Lyrics(String),
+    #[command(description = "get songs later, e.g. /later 8h followed by the titles.")]
+    Later(String),
+    #[command(description = "set the MP3 bitrate, e.g. /quality 320.")]
+    Quality(String),
+    #[command(description = "get each song as soon as it's ready: /stream on or /stream off.")]
+    Stream(String),
+    #[command(description = "get large requests as a ZIP archive: /zip on or /zip off.")]
+    Zip(String),
+    #[command(description = "convert one of your Spotify playlists, /spotify logout to sign out.")]
+    Spotify(String),
+    #[command(description = "put songs in a new YouTube playlist instead, one title per line.")]
+    Ytplaylist(String),
+    #[command(description = "change your bitrate, delivery, language and more.")]
+    Settings,
+    #[command(description = "show the songs you converted recently.")]
+    History,
+    #[command(description = "cancel your current request.")]
+    Cancel,
+    #[command(description = "show how far your latest request, or /status <request>, has got.")]
+    Status(String),
+}
+
+// Bots can only download files of up to 20 MB from Telegram
+const MAX_DOWNLOAD_SIZE: u32 = 20 * 1024 * 1024;
+// A tracklist of thousands of songs is still well under a megabyte
+const MAX_TRACKLIST_SIZE: u32 = 1024 * 1024;
+// Uploaded files that are read as tracklists
+const TRACKLIST_EXTENSIONS: [&str; 5] = ["txt", "csv", "tsv", "m3u", "m3u8"];
+
+#[tracing::instrument(skip_all, fields(request_id = %request_id, chat_id = msg.chat.id.0))]
+pub async fn handle_command(
+    bot: Bot,
+    msg: Message,
+    cmd: Command,
+    request_id: RequestId,
+    producer: Arc<Producer>,
+    preferences: Arc<Preferences>,
+    accounts: Arc<Accounts>,
+) -> HandlerResult {
+    let Accounts {
+        storage,
+        spotify,
+        youtube,
+    } = accounts.as_ref();
+    let language = language_code(&msg, &preferences);
+    match cmd {
+        Command::Start => {
+            bot.send_message(msg.chat.id, tr(language, "bot.welcome"))
+                .await?;
+        }
+        Command::Help => {
+            let help_text = format!(
+                "{}\n\n{}",
+                tr(language, "bot.commands"),
+                tr(language, "bot.usage")
+            );
+            bot.send_message(msg.chat.id, help_text).await?;
+        }
+        Command::Song(titles) => {
+            enqueue_songs(
+                &bot,
+                &msg,
+                &titles,
+                MediaFormat::Mp3,
+                &request_id,
+                &producer,
+                &preferences,
+            )
+            .await?;
+        }
+        Command::Video(titles) => {
+            enqueue_songs(
+                &bot,
+                &msg,
+                &titles,
+                MediaFormat::Mp4,
+                &request_id,
+                &producer,
+                &preferences,
+            )
+            .await?;
+        }
+        Command::Search(query) => {
+            search(&bot, &msg, &query, &request_id, &producer, &preferences).await?;
+        }
+        Command::Lyrics(query) => {
+            lyrics(&bot, &msg, &query, &request_id, &producer, &preferences).await?;
+        }
+        Command::Later(args) => {
+            enqueue_later(
+                &bot,
+                &msg,
+                &args,
+                &request_id,
+                &producer,
+                &preferences,
+                storage.as_deref(),
+            )
+            .await?;
+        }
+        Command::Quality(bitrate) => {
+            set_quality(&bot, &msg, &bitrate, &preferences).await?;
+        }
+        Command::Stream(mode) => {
+            set_streaming(&bot, &msg, &mode, &preferences).await?;
+        }
+        Command::Zip(mode) => {
+            set_zipping(&bot, &msg, &mode, &preferences).await?;
+        }
+        Command::Spotify(args) => {
+            playlists::show_playlists(&bot, &msg, &args, spotify.as_deref(), &preferences).await?;
+        }
+        Command::Ytplaylist(titles) => {
+            youtube::create_playlist(
+                &bot,
+                &msg,
+                &titles,
+                &request_id,
+                &producer,
+                youtube.as_deref(),
+                &preferences,
+            )
+            .await?;
+        }
+        Command::Settings => {
+            settings::show_settings(&bot, &msg, &preferences).await?;
+        }
+        Command::History => {
+            history::show_history(&bot, &msg, storage.as_deref(), &preferences).await?;
+        }
+        Command::Cancel => {
+            cancel(&bot, &msg, &request_id, &producer, &preferences).await?;
+        }
+        Command::Status(status_request_id) => {
+            status::show_status(
+                &bot,
+                &msg,
+                &status_request_id,
+                storage.as_deref(),
+                &preferences,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+// Any plain text message is treated as a list of song titles, once the
+// trigger and mentions of the bot are taken out
+#[tracing::instrument(skip_all, fields(request_id = %request_id, chat_id = msg.chat.id.0))]
+pub async fn handle_text(
+    bot: Bot,
+    msg: Message,
+    request_id: RequestId,
+    producer: Arc<Producer>,
+    preferences: Arc<Preferences>,
+    group_mode: Arc<GroupMode>,
+    me: Me,
+) -> HandlerResult {
+    if let Some(text) = msg.text() {
+        enqueue_songs(
+            &bot,
+            &msg,
+            &group_mode.request_text(text, &me),
+            MediaFormat::Mp3,
+            &request_id,
+            &producer,
+            &preferences,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+// Text posted to a channel the bot is an admin of is converted the same way,
+// with the songs posted back to the channel
+#[tracing::instrument(skip_all, fields(request_id = %request_id, chat_id = msg.chat.id.0))]
+pub async fn handle_channel_post(
+    bot: Bot,
+    msg: Message,
+    request_id: RequestId,
+    producer: Arc<Producer>,
+    preferences: Arc<Preferences>,
+) -> HandlerResult {
+    if let Some(text) = msg.text() {
+        info!(
+            "Received channel post {} in chat ID {}",
+            request_id, msg.chat.id
+        );
+        enqueue_songs(
+            &bot,
+            &msg,
+            text,
+            MediaFormat::Mp3,
+            &request_id,
+            &producer,
+            &preferences,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+// Photos are treated as screenshots of a tracklist and sent off for OCR
+#[tracing::instrument(skip_all, fields(request_id = %request_id, chat_id = msg.chat.id.0))]
+pub async fn handle_photo(
+    bot: Bot,
+    msg: Message,
+    request_id: RequestId,
+    producer: Arc<Producer>,
+    preferences: Arc<Preferences>,
+) -> HandlerResult {
+    let language = language_code(&msg, &preferences);
+    // Telegram sends several sizes of the same photo, the largest reads best
+    let Some(largest) = msg
+        .photo()
+        .and_then(|sizes| sizes.iter().max_by_key(|size| size.width * size.height))
+    else {
+        return Ok(());
+    };
+
+    let file = bot.get_file(largest.file.id.clone()).await?;
+    let photo_url = format!(
+        "https://api.telegram.org/file/bot{}/{}",
+        bot.token(),
+        file.path
+    );
+
+    let options = preferences.request_options(msg.chat.id.0, language);
+    if let Err(e) = producer
+        .publish_photo_request(&request_id, msg.chat.id.0, &photo_url, options)
+        .await
+    {
+        log::error!("Failed to publish photo request: {}", e);
+        bot.send_message(msg.chat.id, tr(language, "error.generic"))
+            .await?;
+        return Ok(());
+    }
+
+    bot.send_message(msg.chat.id, tr(language, "bot.screenshot_received"))
+        .await?;
+    Ok(())
+}
+
+// Uploaded .txt, .csv and .m3u files are read as tracklists and converted as
+// a whole, like a playlist
+#[tracing::instrument(skip_all, fields(request_id = %request_id, chat_id = msg.chat.id.0))]
+pub async fn handle_document(
+    bot: Bot,
+    msg: Message,
+    request_id: RequestId,
+    producer: Arc<Producer>,
+    preferences: Arc<Preferences>,
+) -> HandlerResult {
+    let language = language_code(&msg, &preferences);
+    let Some(document) = msg.document() else {
+        return Ok(());
+    };
+
+    let file_name = document.file_name.clone().unwrap_or_default();
+    let is_tracklist = file_name.rsplit_once('.').is_some_and(|(_, extension)| {
+        TRACKLIST_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+    });
+    if !is_tracklist {
+        bot.send_message(msg.chat.id, tr(language, "bot.document_unsupported"))
+            .await?;
+        return Ok(());
+    }
+    if document.file.size > MAX_TRACKLIST_SIZE {
+        bot.send_message(msg.chat.id, tr(language, "bot.document_too_big"))
+            .await?;
+        return Ok(());
+    }
+
+    let file = bot.get_file(document.file.id.clone()).await?;
+    let document_url = format!(
+        "https://api.telegram.org/file/bot{}/{}",
+        bot.token(),
+        file.path
+    );
+
+    let options = preferences.request_options(msg.chat.id.0, language);
+    if let Err(e) = producer
+        .publish_document_request(
+            &request_id,
+            msg.chat.id.0,
+            &document_url,
+            &file_name,
+            options,
+        )
+        .await
+    {
+        log::error!("Failed to publish document request: {}", e);
+        bot.send_message(msg.chat.id, tr(language, "error.generic"))
+            .await?;
+        return Ok(());
+    }
+
+    bot.send_message(msg.chat.id, tr(language, "bot.document_received"))
+        .await?;
+    Ok(())
+}
+
+// Voice messages are sent off to be recognized as a song
+#[tracing::instrument(skip_all, fields(request_id = %request_id, chat_id = msg.chat.id.0))]
+pub async fn handle_voice(
+    bot: Bot,
+    msg: Message,
+    request_id: RequestId,
+    producer: Arc<Producer>,
+    preferences: Arc<Preferences>,
+) -> HandlerResult {
+    let language = language_code(&msg, &preferences);
+    let Some(voice) = msg.voice() else {
+        return Ok(());
+    };
+
+    let file = bot.get_file(voice.file.id.clone()).await?;
+    let voice_url = format!(
+        "https://api.telegram.org/file/bot{}/{}",
+        bot.token(),
+        file.path
+    );
+
+    let options = preferences.request_options(msg.chat.id.0, language);
+    if let Err(e) = producer
+        .publish_voice_request(&request_id, msg.chat.id.0, &voice_url, options)
+        .await
+    {
+        log::error!("Failed to publish voice request: {}", e);
+        bot.send_message(msg.chat.id, tr(language, "error.generic"))
+            .await?;
+        return Ok(());
+    }
+
+    bot.send_message(msg.chat.id, tr(language, "bot.voice_received"))
+        .await?;
+    Ok(())
+}
+
+// Forwarded audio files and video notes are sampled and recognized, after
+// which the user can pick a clean MP3 of the song
+#[tracing::instrument(skip_all, fields(request_id = %request_id, chat_id = msg.chat.id.0))]
+pub async fn handle_media(
+    bot: Bot,
+    msg: Message,
+    request_id: RequestId,
+    producer: Arc<Producer>,
+    preferences: Arc<Preferences>,
+) -> HandlerResult {
+    let language = language_code(&msg, &preferences);
+    let Some(file) = msg
+        .audio()
+        .map(|audio| &audio.file)
+        .or_else(|| msg.video_note().map(|video_note| &video_note.file))
+    else {
+        return Ok(());
+    };
+
+    if file.size > MAX_DOWNLOAD_SIZE {
+        bot.send_message(msg.chat.id, tr(language, "bot.file_too_big"))
+            .await?;
+        return Ok(());
+    }
+
+    let file = bot.get_file(file.id.clone()).await?;
+    let media_url = format!(
+        "https://api.telegram.org/file/bot{}/{}",
+        bot.token(),
+        file.path
+    );
+
+    let options = preferences.request_options(msg.chat.id.0, language);
+    if let Err(e) = producer
+        .publish_media_request(&request_id, msg.chat.id.0, &media_url, options)
+        .await
+    {
+        log::error!("Failed to publish media request: {}", e);
+        bot.send_message(msg.chat.id, tr(language, "error.generic"))
+            .await?;
+        return Ok(());
+    }
+
+    bot.send_message(msg.chat.id, tr(language, "bot.media_received"))
+        .await?;
+    Ok(())
+}
+
+pub async fn handle_unknown_command(
+    bot: Bot,
+    msg: Message,
+    preferences: Arc<Preferences>,
+) -> HandlerResult {
+    info!(
+        "Unknown command from chat ID {}: {:?}",
+        msg.chat.id,
+        msg.text()
+    );
+    bot.send_message(
+        msg.chat.id,
+        tr(language_code(&msg, &preferences), "bot.unknown_command"),
+    )
+    .await?;
+    Ok(())
+}
+
+// The language the chat picked in /settings, or else the user's Telegram
+// language, which picks the language of the replies
+pub fn language_code<'a>(msg: &'a Message, preferences: &Preferences) -> Option<&'a str> {
+    preferences
+        .language(msg.chat.id.0)
+        .or_else(|| msg.from.as_ref()?.language_code.as_deref())
+}
+
+pub fn is_command(msg: &Message) -> bool {
+    msg.text().is_some_and(|text| text.starts_with('/'))
+}
+
+async fn enqueue_songs(
+    bot: &Bot,
+    msg: &Message,
+    titles: &str,
+    format: MediaFormat,
+    request_id: &RequestId,
+    producer: &Producer,
+    preferences: &Preferences,
+) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    let titles = titles.trim();
+    if titles.is_empty() {
+        let usage = match format {
+            MediaFormat::Mp3 => "bot.usage",
+            MediaFormat::Mp4 => "bot.video_usage",
+        };
+        bot.send_message(msg.chat.id, tr(language, usage)).await?;
+        return Ok(());
+    }
+    let Some(titles) = validate_text(bot, msg, titles, language).await? else {
+        return Ok(());
+    };
+    let Some((titles, clips)) = split_clips(bot, msg, &titles, language).await? else {
+        return Ok(());
+    };
+
+    let options = RequestOptions {
+        format,
+        ..preferences.request_options(msg.chat.id.0, language)
+    };
+    if let Err(e) = producer
+        .publish_song_request(request_id, msg.chat.id.0, &titles, clips, options)
+        .await
+    {
+        log::error!("Failed to publish song request: {}", e);
+        bot.send_message(msg.chat.id, tr(language, "error.generic"))
+            .await?;
+        return Ok(());
+    }
+
+    // Channel subscribers are only there for the songs
+    if msg.chat.is_channel() {
+        return Ok(());
+    }
+    bot.send_message(
+        msg.chat.id,
+        tr_args(
+            language,
+            "bot.songs_queued",
+            &[("request_id", request_id.as_str())],
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+// Same as /song, with the results held back until the delay has passed
+async fn enqueue_later(
+    bot: &Bot,
+    msg: &Message,
+    args: &str,
+    request_id: &RequestId,
+    producer: &Producer,
+    preferences: &Preferences,
+    storage: Option<&Storage>,
+) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    // The consumer keeps the results in the database until they're due
+    if storage.is_none() {
+        bot.send_message(msg.chat.id, tr(language, "bot.later_unavailable"))
+            .await?;
+        return Ok(());
+    }
+
+    let args = args.trim();
+    let (delay_text, titles) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let titles = titles.trim();
+    let Some(delay) = schedule::parse_delay(delay_text).filter(|_| !titles.is_empty()) else {
+        bot.send_message(msg.chat.id, tr(language, "bot.later_usage"))
+            .await?;
+        return Ok(());
+    };
+    let Some(titles) = validate_text(bot, msg, titles, language).await? else {
+        return Ok(());
+    };
+    let Some((titles, clips)) = split_clips(bot, msg, &titles, language).await? else {
+        return Ok(());
+    };
+    let deliver_at = (SystemTime::now() + delay)
+        .duration_since(UNIX_EPOCH)?
+        .as_secs();
+
+    let options = RequestOptions {
+        deliver_at: Some(deliver_at),
+        ..preferences.request_options(msg.chat.id.0, language)
+    };
+    if let Err(e) = producer
+        .publish_song_request(request_id, msg.chat.id.0, &titles, clips, options)
+        .await
+    {
+        log::error!("Failed to publish scheduled song request: {}", e);
+        bot.send_message(msg.chat.id, tr(language, "error.generic"))
+            .await?;
+        return Ok(());
+    }
+
+    bot.send_message(
+        msg.chat.id,
+        tr_args(
+            language,
+            "bot.later_queued",
+            &[("delay", delay_text), ("request_id", request_id.as_str())],
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+// Check the titles against the request limits and take out links to sites
+// that aren't supported. None when they're over a limit, after telling the
+// user what to change
+async fn validate_text(
+    bot: &Bot,
+    msg: &Message,
+    titles: &str,
+    language: Option<&str>,
+) -> Result<Option<String>, Box<dyn Error + Send + Sync + 'static>> {
+    match request_text::validate(titles) {
+        Ok(titles) => Ok(Some(titles)),
+        Err(invalid) => {
+            info!("Rejected request text: {:?}", invalid);
+            bot.send_message(msg.chat.id, invalid.message(language))
+                .await?;
+            Ok(None)
+        }
+    }
+}
+
+// Take time ranges such as "[0:45-2:30]" off the titles. None when one of
+// them can't be used, after telling the user which line it's on
+async fn split_clips(
+    bot: &Bot,
+    msg: &Message,
+    titles: &str,
+    language: Option<&str>,
+) -> Result<Option<(String, BTreeMap<usize, Clip>)>, Box<dyn Error + Send + Sync + 'static>> {
+    match clip::split_clips(titles) {
+        Ok(split) => Ok(Some(split)),
+        Err(invalid) => {
+            bot.send_message(
+                msg.chat.id,
+                tr_args(language, "bot.clip_invalid", &[("line", &invalid.line)]),
+            )
+            .await?;
+            Ok(None)
+        }
+    }
+}
+
+async fn search(
+    bot: &Bot,
+    msg: &Message,
+    query: &str,
+    request_id: &RequestId,
+    producer: &Producer,
+    preferences: &Preferences,
+) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    let query = query.trim();
+    if query.is_empty() {
+        bot.send_message(msg.chat.id, tr(language, "bot.search_usage"))
+            .await?;
+        return Ok(());
+    }
+
+    let options = preferences.request_options(msg.chat.id.0, language);
+    if let Err(e) = producer
+        .publish_search_request(request_id, msg.chat.id.0, query, options)
+        .await
+    {
+        log::error!("Failed to publish search request: {}", e);
+        bot.send_message(msg.chat.id, tr(language, "error.generic"))
+            .await?;
+    }
+    Ok(())
+}
+
+async fn lyrics(
+    bot: &Bot,
+    msg: &Message,
+    query: &str,
+    request_id: &RequestId,
+    producer: &Producer,
+    preferences: &Preferences,
+) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    let query = query.trim();
+    if query.is_empty() {
+        bot.send_message(msg.chat.id, tr(language, "bot.lyrics_usage"))
+            .await?;
+        return Ok(());
+    }
+
+    let options = preferences.request_options(msg.chat.id.0, language);
+    if let Err(e) = producer
+        .publish_lyrics_request(request_id, msg.chat.id.0, query, options)
+        .await
+    {
+        log::error!("Failed to publish lyrics request: {}", e);
+        bot.send_message(msg.chat.id, tr(language, "error.generic"))
+            .await?;
+    }
+    Ok(())
+}
+
+// The consumer answers with how many songs were done before it stopped
+async fn cancel(
+    bot: &Bot,
+    msg: &Message,
+    request_id: &RequestId,
+    producer: &Producer,
+    preferences: &Preferences,
+) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    let reply = match producer.publish_cancel(request_id, msg.chat.id.0).await {
+        Ok(true) => tr(language, "bot.cancelling"),
+        Ok(false) => tr(language, "bot.nothing_to_cancel"),
+        Err(e) => {
+            log::error!("Failed to publish cancellation: {}", e);
+            tr(language, "error.generic")
+        }
+    };
+    bot.send_message(msg.chat.id, reply).await?;
+    Ok(())
+}
+
+async fn set_quality(
+    bot: &Bot,
+    msg: &Message,
+    bitrate: &str,
+    preferences: &Preferences,
+) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    let supported = SUPPORTED_BITRATES
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    match bitrate.trim().trim_end_matches("kbps").parse::<u32>() {
+        Ok(bitrate) if SUPPORTED_BITRATES.contains(&bitrate) => {
+            preferences.set_bitrate(msg.chat.id.0, bitrate).await;
+            bot.send_message(
+                msg.chat.id,
+                tr_args(
+                    language,
+                    "bot.quality_set",
+                    &[("bitrate", &bitrate.to_string())],
+                ),
+            )
+            .await?;
+        }
+        _ => {
+            bot.send_message(
+                msg.chat.id,
+                tr_args(language, "bot.quality_usage", &[("bitrates", &supported)]),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn set_streaming(
+    bot: &Bot,
+    msg: &Message,
+    mode: &str,
+    preferences: &Preferences,
+) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    let reply = match mode.trim().to_lowercase().as_str() {
+        "on" => {
+            preferences.set_streaming(msg.chat.id.0, true).await;
+            tr(language, "bot.stream_on")
+        }
+        "off" => {
+            preferences.set_streaming(msg.chat.id.0, false).await;
+            tr(language, "bot.stream_off")
+        }
+        _ => tr(language, "bot.stream_usage"),
+    };
+    bot.send_message(msg.chat.id, reply).await?;
+    Ok(())
+}
+
+async fn set_zipping(
+    bot: &Bot,
+    msg: &Message,
+    mode: &str,
+    preferences: &Preferences,
+) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    let reply = match mode.trim().to_lowercase().as_str() {
+        "on" => {
+            preferences.set_zipping(msg.chat.id.0, true).await;
+            tr(language, "bot.zip_on")
+        }
+        "off" => {
+            preferences.set_zipping(msg.chat.id.0, false).await;
+            tr(language, "bot.zip_off")
+        }
+        _ => tr(language, "bot.zip_usage"),
+    };
+    bot.send_message(msg.chat.id, reply).await?;
+    Ok(())
+}
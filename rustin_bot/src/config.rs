@@ -0,0 +1,226 @@
+use crate::topology::Queues;
+use figment::{
+    providers::{Env, Format, Toml},
+    Figment,
+};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::env;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("couldn't load the configuration: {0}")]
+    Load(#[from] Box<figment::Error>),
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+}
+
+// Settings of the bot, read from the TOML file in CONFIG_FILE (rustin_bot.toml
+// by default) and overridden by environment variables of the same name in
+// upper case, e.g. ADMIN_IDS or VERIFY_NEW_CHATS
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    pub rabbit_address: String,
+    // Postgres for the request history, bans and chat settings. Nothing
+    // outlives a restart when unset
+    pub database_url: Option<String>,
+    // Telegram user IDs allowed to run admin commands, e.g. "123,456".
+    // Nobody is an admin when unset
+    #[serde(default, deserialize_with = "deserialize_ids")]
+    pub admin_ids: Vec<i64>,
+    // Prefix that calls the bot in groups, e.g. "!song"
+    pub group_trigger: Option<String>,
+    // New chats have to pass an emoji challenge before their first request
+    #[serde(default, deserialize_with = "figment::util::bool_from_str_or_int")]
+    pub verify_new_chats: bool,
+    // Where the OAuth callbacks are served, behind the redirect URIs below
+    #[serde(default = "default_oauth_callback_addr")]
+    pub oauth_callback_addr: String,
+    // Spotify sign-in is only offered when both are set
+    pub spotify_client_id: Option<String>,
+    pub spotify_redirect_uri: Option<String>,
+    // YouTube sign-in is only offered when all three are set
+    pub google_client_id: Option<String>,
+    pub google_client_secret: Option<String>,
+    pub google_redirect_uri: Option<String>,
+    #[serde(default)]
+    pub queues: Queues,
+}
+
+impl Config {
+    pub fn load() -> Result<Self, ConfigError> {
+        let file = env::var("CONFIG_FILE").unwrap_or_else(|_| "rustin_bot.toml".to_string());
+        let config: Config = Figment::new()
+            .merge(Toml::file(file))
+            .merge(Env::raw().split("__"))
+            .extract()
+            .map_err(Box::new)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.rabbit_address.trim().is_empty() {
+            return Err(ConfigError::Invalid(
+                "rabbit_address must not be empty".to_string(),
+            ));
+        }
+        if self.spotify_client_id.is_some() != self.spotify_redirect_uri.is_some() {
+            return Err(ConfigError::Invalid(
+                "spotify_client_id and spotify_redirect_uri must be set together".to_string(),
+            ));
+        }
+        let google = [
+            &self.google_client_id,
+            &self.google_client_secret,
+            &self.google_redirect_uri,
+        ];
+        if google.iter().any(|value| value.is_some()) && !google.iter().all(|value| value.is_some())
+        {
+            return Err(ConfigError::Invalid(
+                "google_client_id, google_client_secret and google_redirect_uri must be set together"
+                    .to_string(),
+            ));
+        }
+        if !self
+            .spotify_redirect_uri
+            .iter()
+            .chain(&self.google_redirect_uri)
+            .all(|url| is_valid_redirect_uri(url))
+        {
+            return Err(ConfigError::Invalid(
+                "redirect URIs must be http or https URLs".to_string(),
+            ));
+        }
+        self.queues.validate().map_err(ConfigError::Invalid)
+    }
+}
+
+fn is_valid_redirect_uri(url: &str) -> bool {
+    reqwest::Url::parse(url)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some())
+}
+
+// A comma separated list in the environment, a list in the TOML file
+fn deserialize_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<i64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Ids {
+        One(i64),
+        List(Vec<i64>),
+        Text(String),
+    }
+
+    match Ids::deserialize(deserializer)? {
+        Ids::One(id) => Ok(vec![id]),
+        Ids::List(ids) => Ok(ids),
+        Ids::Text(text) => text
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| {
+                id.parse()
+                    .map_err(|_| D::Error::custom(format!("invalid admin ID '{}'", id)))
+            })
+            .collect(),
+    }
+}
+
+fn default_oauth_callback_addr() -> String {
+    "0.0.0.0:8088".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Result<Config, ConfigError> {
+        let config: Config = Figment::new()
+            .merge(Toml::string(toml))
+            .extract()
+            .map_err(Box::new)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    const REQUIRED: &str = "rabbit_address = \"amqp://localhost\"\n";
+
+    #[test]
+    fn fills_in_defaults() {
+        let config = parse(REQUIRED).unwrap();
+        assert_eq!(config.rabbit_address, "amqp://localhost");
+        assert!(config.database_url.is_none());
+        assert!(config.admin_ids.is_empty());
+        assert!(!config.verify_new_chats);
+        assert_eq!(config.oauth_callback_addr, "0.0.0.0:8088");
+        assert_eq!(config.queues.music_fast, "Music.fast");
+    }
+
+    #[test]
+    fn reads_admin_ids() {
+        for ids in ["\"12, 34\"", "[12, 34]"] {
+            let config = parse(&format!("{}admin_ids = {}\n", REQUIRED, ids)).unwrap();
+            assert_eq!(config.admin_ids, vec![12, 34]);
+        }
+        let config = parse(&format!("{}admin_ids = 12\n", REQUIRED)).unwrap();
+        assert_eq!(config.admin_ids, vec![12]);
+
+        assert!(parse(&format!("{}admin_ids = \"12,abc\"\n", REQUIRED)).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_required_settings() {
+        assert!(matches!(parse(""), Err(ConfigError::Load(_))));
+        assert!(matches!(
+            parse("rabbit_address = \"\"\n"),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn reads_nested_queue_names() {
+        let config = parse(&format!("{}[queues]\nmusic = \"Songs\"\n", REQUIRED)).unwrap();
+        assert_eq!(config.queues.music, "Songs");
+        assert_eq!(config.queues.control_exchange, "Control");
+
+        assert!(matches!(
+            parse(&format!("{}[queues]\nreply = \" \"\n", REQUIRED)),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn reads_verify_new_chats_as_a_flag() {
+        for flag in ["true", "1", "\"TRUE\""] {
+            let config = parse(&format!("{}verify_new_chats = {}\n", REQUIRED, flag)).unwrap();
+            assert!(config.verify_new_chats);
+        }
+        assert!(parse(&format!("{}verify_new_chats = \"maybe\"\n", REQUIRED)).is_err());
+    }
+
+    #[test]
+    fn rejects_half_configured_oauth_clients() {
+        assert!(matches!(
+            parse(&format!("{}spotify_client_id = \"id\"\n", REQUIRED)),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            parse(&format!(
+                "{}google_client_id = \"id\"\ngoogle_client_secret = \"secret\"\n",
+                REQUIRED
+            )),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_redirect_uris() {
+        assert!(matches!(
+            parse(&format!(
+                "{}spotify_client_id = \"id\"\nspotify_redirect_uri = \"localhost/callback\"\n",
+                REQUIRED
+            )),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{commands::language_code, preferences::Preferences};
+use dashmap::DashMap;
+use rustin_models::i18n::tr;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use teloxide::prelude::*;
+
+// Messages a chat can send in a burst before it's throttled
+const BURST: f64 = 5.0;
+// Messages per second a chat can keep sending
+const LEAK_PER_SECOND: f64 = 1.0;
+// How often buckets that have leaked empty are forgotten
+const PRUNE_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Verdict {
+    Allow,
+    // The first throttled message, answered with a single warning
+    Warn,
+    Drop,
+}
+
+// A leaky bucket: every message adds a drop, the bucket leaks at a steady
+// rate and a message that would overflow it is throttled
+#[derive(Debug)]
+struct Bucket {
+    level: f64,
+    updated: Instant,
+    // Whether the chat was already told to slow down since it was throttled
+    warned: bool,
+}
+
+impl Bucket {
+    fn new(now: Instant) -> Self {
+        Self {
+            level: 0.0,
+            updated: now,
+            warned: false,
+        }
+    }
+
+    fn leak(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.level = (self.level - elapsed * LEAK_PER_SECOND).max(0.0);
+        self.updated = now;
+    }
+
+    fn add(&mut self, now: Instant) -> Verdict {
+        self.leak(now);
+        if self.level + 1.0 <= BURST {
+            self.level += 1.0;
+            self.warned = false;
+            return Verdict::Allow;
+        }
+        match std::mem::replace(&mut self.warned, true) {
+            false => Verdict::Warn,
+            true => Verdict::Drop,
+        }
+    }
+
+    fn is_empty(&mut self, now: Instant) -> bool {
+        self.leak(now);
+        self.level == 0.0
+    }
+}
+
+// Throttles chats that send messages faster than anyone types them, so a
+// flood turns into one "slow down" reply instead of a request per message
+#[derive(Default)]
+pub struct FloodControl {
+    buckets: DashMap<i64, Bucket>,
+}
+
+impl FloodControl {
+    // Whether the message should be handled, telling the chat to slow down
+    // the first time it isn't
+    pub async fn admit(&self, bot: &Bot, msg: &Message, preferences: &Preferences) -> bool {
+        let chat_id = msg.chat.id.0;
+        let verdict = self
+            .buckets
+            .entry(chat_id)
+            .or_insert_with(|| Bucket::new(Instant::now()))
+            .add(Instant::now());
+        match verdict {
+            Verdict::Allow => true,
+            Verdict::Warn => {
+                log::warn!("Throttling chat {} for flooding", chat_id);
+                let language = language_code(msg, preferences);
+                if let Err(e) = bot
+                    .send_message(msg.chat.id, tr(language, "bot.slow_down"))
+                    .await
+                {
+                    log::error!("Failed to tell chat {} to slow down: {}", chat_id, e);
+                }
+                false
+            }
+            Verdict::Drop => {
+                log::debug!("Dropping message from throttled chat {}", chat_id);
+                false
+            }
+        }
+    }
+
+    // Forget chats whose buckets have leaked empty, so chats that only
+    // write once don't stay in memory
+    pub async fn prune_periodically(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+            self.buckets.retain(|_, bucket| !bucket.is_empty(now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BURST_SIZE: usize = BURST as usize;
+
+    fn fill(bucket: &mut Bucket, now: Instant) {
+        for _ in 0..BURST_SIZE {
+            assert_eq!(bucket.add(now), Verdict::Allow);
+        }
+    }
+
+    #[test]
+    fn allows_a_burst() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(now);
+        fill(&mut bucket, now);
+        assert_ne!(bucket.add(now), Verdict::Allow);
+    }
+
+    #[test]
+    fn warns_once_then_drops() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(now);
+        fill(&mut bucket, now);
+        assert_eq!(bucket.add(now), Verdict::Warn);
+        assert_eq!(bucket.add(now), Verdict::Drop);
+        assert_eq!(bucket.add(now), Verdict::Drop);
+    }
+
+    #[test]
+    fn leaks_at_a_steady_rate() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(now);
+        fill(&mut bucket, now);
+        let later = now + Duration::from_secs_f64(1.0 / LEAK_PER_SECOND);
+        assert_eq!(bucket.add(later), Verdict::Allow);
+        assert_eq!(bucket.add(later), Verdict::Warn);
+    }
+
+    #[test]
+    fn warns_again_after_recovering() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(now);
+        fill(&mut bucket, now);
+        assert_eq!(bucket.add(now), Verdict::Warn);
+        let later = now + Duration::from_secs_f64(1.0 / LEAK_PER_SECOND);
+        assert_eq!(bucket.add(later), Verdict::Allow);
+        assert_eq!(bucket.add(later), Verdict::Warn);
+    }
+
+    #[test]
+    fn empties_once_fully_leaked() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(now);
+        fill(&mut bucket, now);
+        let drained = Duration::from_secs_f64(BURST / LEAK_PER_SECOND);
+        assert!(!bucket.is_empty(now + drained / 2));
+        assert!(bucket.is_empty(now + drained));
+    }
+
+    #[test]
+    fn does_not_leak_before_the_last_update() {
+        let now = Instant::now() + Duration::from_secs(10);
+        let mut bucket = Bucket::new(now);
+        fill(&mut bucket, now);
+        assert_eq!(bucket.add(now - Duration::from_secs(5)), Verdict::Warn);
+    }
+}
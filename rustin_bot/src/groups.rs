@@ -0,0 +1,79 @@
+use crate::preferences::Preferences;
+use teloxide::{prelude::*, types::Me};
+
+// In groups the bot only answers messages meant for it: commands, replies to
+// its messages, mentions and messages starting with the trigger. Groups can
+// turn this off in /settings to have every message taken as a request
+pub struct GroupMode {
+    // Lowercase prefix that calls the bot, e.g. "!song", from group_trigger
+    trigger: Option<String>,
+}
+
+impl GroupMode {
+    pub fn new(trigger: Option<&str>) -> Self {
+        let trigger = trigger
+            .map(|trigger| trigger.trim().to_lowercase())
+            .filter(|trigger| !trigger.is_empty());
+        Self { trigger }
+    }
+
+    pub fn is_addressed(&self, msg: &Message, me: &Me, preferences: &Preferences) -> bool {
+        let is_group = msg.chat.is_group() || msg.chat.is_supergroup();
+        if !is_group || preferences.settings(msg.chat.id.0).all_messages {
+            return true;
+        }
+
+        let replied_to_bot = msg
+            .reply_to_message()
+            .and_then(|reply| reply.from.as_ref())
+            .is_some_and(|user| user.id == me.id);
+        let text = msg.text().or_else(|| msg.caption()).unwrap_or_default();
+        replied_to_bot || self.is_called(text, me)
+    }
+
+    fn is_called(&self, text: &str, me: &Me) -> bool {
+        let text = text.trim_start().to_lowercase();
+        let username = me.username().to_lowercase();
+        // Commands of other bots, e.g. /start@OtherBot, are left to them
+        if let Some(command) = text.strip_prefix('/') {
+            let command = command.split_whitespace().next().unwrap_or_default();
+            return command
+                .split_once('@')
+                .is_none_or(|(_, bot)| bot == username);
+        }
+
+        let mention = format!("@{}", username);
+        text.split_whitespace().any(|word| word == mention)
+            || self
+                .trigger
+                .as_ref()
+                .is_some_and(|trigger| text.starts_with(trigger.as_str()))
+    }
+
+    // The request of a message, without the trigger and mentions of the bot
+    pub fn request_text(&self, text: &str, me: &Me) -> String {
+        let mut text = text.trim_start();
+        if let Some(trigger) = &self.trigger {
+            let called = text
+                .get(..trigger.len())
+                .is_some_and(|prefix| prefix.to_lowercase() == *trigger);
+            if called {
+                text = &text[trigger.len()..];
+            }
+        }
+
+        let mention = format!("@{}", me.username());
+        text.trim_start()
+            .lines()
+            .map(|line| match line.contains('@') {
+                true => line
+                    .split_whitespace()
+                    .filter(|word| !word.eq_ignore_ascii_case(&mention))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                false => line.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
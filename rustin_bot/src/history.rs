@@ -0,0 +1,139 @@
+use crate::{
+    commands::{language_code, HandlerResult},
+    preferences::Preferences,
+    request_id::RequestId,
+};
+use rustin_models::{i18n::tr, PICK_CALLBACK_PREFIX};
+use rustin_storage::{HistoryEntry, Storage};
+use std::sync::Arc;
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, InputFile},
+};
+
+// Number of songs listed by /history
+const HISTORY_LENGTH: i64 = 10;
+// Prefix of the callback data of history buttons, followed by the song's ID
+pub const RESEND_CALLBACK_PREFIX: &str = "resend:";
+// Telegram cuts long button labels off
+const MAX_LABEL_CHARS: usize = 50;
+// Bytes of callback data Telegram accepts per button
+const MAX_CALLBACK_DATA: usize = 64;
+
+// List the songs the chat converted recently, with buttons to get them again
+pub async fn show_history(
+    bot: &Bot,
+    msg: &Message,
+    storage: Option<&Storage>,
+    preferences: &Preferences,
+) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    let Some(storage) = storage else {
+        bot.send_message(msg.chat.id, tr(language, "history.unavailable"))
+            .await?;
+        return Ok(());
+    };
+
+    let songs = match storage.recent_songs(msg.chat.id.0, HISTORY_LENGTH).await {
+        Ok(songs) => songs,
+        Err(e) => {
+            log::error!("Failed to load history: {}", e);
+            bot.send_message(msg.chat.id, tr(language, "error.generic"))
+                .await?;
+            return Ok(());
+        }
+    };
+    if songs.is_empty() {
+        bot.send_message(msg.chat.id, tr(language, "history.empty"))
+            .await?;
+        return Ok(());
+    }
+
+    let rows: Vec<_> = songs
+        .iter()
+        .filter_map(history_button)
+        .map(|b| [b])
+        .collect();
+    bot.send_message(msg.chat.id, tr(language, "history.title"))
+        .reply_markup(InlineKeyboardMarkup::new(rows))
+        .await?;
+    Ok(())
+}
+
+// Uploaded songs are resent by their file_id, songs that were only sent as a
+// link are converted again
+fn history_button(song: &HistoryEntry) -> Option<InlineKeyboardButton> {
+    let data = match (&song.file_id, &song.video_id) {
+        (Some(_), _) => format!("{}{}", RESEND_CALLBACK_PREFIX, song.id),
+        (None, Some(video_id)) => format!("{}{}", PICK_CALLBACK_PREFIX, video_id),
+        (None, None) => return None,
+    };
+    // Telegram rejects the whole keyboard over one button with too much
+    // data, which long SoundCloud track IDs can reach
+    if data.len() > MAX_CALLBACK_DATA {
+        return None;
+    }
+    let mut label: String = song.title.chars().take(MAX_LABEL_CHARS).collect();
+    if song.title.chars().count() > MAX_LABEL_CHARS {
+        label.push('…');
+    }
+    Some(InlineKeyboardButton::callback(label, data))
+}
+
+pub fn is_resend(query: &CallbackQuery) -> bool {
+    query
+        .data
+        .as_deref()
+        .is_some_and(|data| data.starts_with(RESEND_CALLBACK_PREFIX))
+}
+
+// A history button of an uploaded song was pressed
+#[tracing::instrument(skip_all, fields(request_id = %request_id, user_id = query.from.id.0))]
+pub async fn handle_resend(
+    bot: Bot,
+    query: CallbackQuery,
+    request_id: RequestId,
+    storage: Option<Arc<Storage>>,
+    preferences: Arc<Preferences>,
+) -> HandlerResult {
+    let song_id = query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(RESEND_CALLBACK_PREFIX))
+        .and_then(|id| id.parse().ok());
+    let chat_id = query.message.as_ref().map(|message| message.chat().id);
+
+    let song = match (song_id, chat_id, storage.as_deref()) {
+        (Some(song_id), Some(chat_id), Some(storage)) => storage
+            .song(chat_id.0, song_id)
+            .await
+            .inspect_err(|e| log::error!("Failed to load song {}: {}", song_id, e))
+            .ok()
+            .flatten(),
+        _ => None,
+    };
+    let (
+        Some(chat_id),
+        Some(HistoryEntry {
+            title,
+            file_id: Some(file_id),
+            ..
+        }),
+    ) = (chat_id, song)
+    else {
+        let language = chat_id
+            .and_then(|chat_id| preferences.language(chat_id.0))
+            .or(query.from.language_code.as_deref());
+        bot.answer_callback_query(query.id.clone())
+            .text(tr(language, "history.song_gone"))
+            .await?;
+        return Ok(());
+    };
+
+    bot.answer_callback_query(query.id.clone()).await?;
+    bot.send_audio(chat_id, InputFile::file_id(file_id))
+        .title(&title)
+        .await?;
+    log::info!("Resent '{}' from the history of chat ID {}", title, chat_id);
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use crate::{commands::HandlerResult, producer::Producer, request_id::RequestId};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use teloxide::prelude::*;
+use tokio::sync::Mutex;
+
+// Telegram sends a new inline query for every keystroke, so a query is only
+// passed on once the user has stopped typing for this long
+const TYPING_PAUSE: Duration = Duration::from_millis(700);
+
+// The latest inline query ID of every user
+#[derive(Default)]
+pub struct LatestQueries {
+    queries: Mutex<HashMap<u64, String>>,
+}
+
+impl LatestQueries {
+    async fn set(&self, user_id: u64, query_id: &str) {
+        self.queries
+            .lock()
+            .await
+            .insert(user_id, query_id.to_string());
+    }
+
+    async fn is_latest(&self, user_id: u64, query_id: &str) -> bool {
+        self.queries
+            .lock()
+            .await
+            .get(&user_id)
+            .is_some_and(|latest| latest == query_id)
+    }
+}
+
+// Inline queries (@RustinBot song name) are answered by the reply service
+// once the consumer has found and converted the song. Inline mode has to be
+// enabled for the bot through BotFather
+#[tracing::instrument(skip_all, fields(request_id = %request_id, user_id = query.from.id.0))]
+pub async fn handle_inline_query(
+    query: InlineQuery,
+    request_id: RequestId,
+    producer: Arc<Producer>,
+    latest_queries: Arc<LatestQueries>,
+) -> HandlerResult {
+    let text = query.query.trim();
+    if text.chars().count() < 3 {
+        return Ok(());
+    }
+
+    let user_id = query.from.id.0;
+    latest_queries.set(user_id, &query.id).await;
+    tokio::time::sleep(TYPING_PAUSE).await;
+    if !latest_queries.is_latest(user_id, &query.id).await {
+        return Ok(());
+    }
+
+    producer
+        .publish_inline_query(&request_id, user_id as i64, &query.id, text)
+        .await
+}
@@ -1,27 +1,243 @@
+use admin::{handle_admin_command, AdminCommand, Admins, Bans};
+use commands::{
+    handle_channel_post, handle_command, handle_document, handle_media, handle_photo, handle_text,
+    handle_unknown_command, handle_voice, is_command, Accounts, Command,
+};
+use config::Config;
+use dotenvy::dotenv;
+use flood::FloodControl;
+use groups::GroupMode;
+use history::{handle_resend, is_resend};
+use inline::{handle_inline_query, LatestQueries};
 use log::info;
-use teloxide::prelude::*;
+use picker::handle_callback_query;
+use playlists::{handle_spotify_callback, is_spotify_callback};
+use preferences::Preferences;
+use producer::Producer;
+use request_id::RequestId;
+use rustin_storage::Storage;
+use settings::{handle_settings_callback, is_settings_callback};
+use spotify::SpotifyAccounts;
+use std::sync::Arc;
+use teloxide::{prelude::*, types::Me};
+use verification::{handle_verify_callback, is_verify_callback, Verification};
+use youtube::YouTubeAccounts;
+
+mod admin;
+mod commands;
+mod config;
+mod flood;
+mod groups;
+mod history;
+mod inline;
+mod oauth;
+mod picker;
+mod playlists;
+mod preferences;
+mod producer;
+mod request_id;
+mod settings;
+mod spotify;
+mod status;
+mod topology;
+mod verification;
+mod youtube;
 
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init();
-    log::info!("Starting throw dice bot...");
+    dotenv().expect("Failed to load .env file");
+    let _telemetry = rustin_telemetry::init("rustin_bot");
+    log::info!("Starting rustin bot...");
+
+    // Bad settings stop the bot here instead of failing mid-run
+    let config = Config::load().expect("Invalid configuration");
+
+    let storage = match &config.database_url {
+        Some(database_url) => Some(Arc::new(
+            Storage::connect(database_url)
+                .await
+                .expect("Failed to connect to the history database"),
+        )),
+        None => None,
+    };
+    let producer = Arc::new(
+        Producer::connect(
+            &config.rabbit_address,
+            config.queues.clone(),
+            storage.clone(),
+        )
+        .await
+        .expect("Failed to connect to RabbitMQ"),
+    );
+
+    let preferences = Arc::new(Preferences::load(storage.clone()).await);
+    let latest_queries = Arc::new(LatestQueries::default());
+    let admins = Arc::new(Admins::new(&config.admin_ids));
+    let bans = Arc::new(Bans::load(storage.clone()).await);
+    let group_mode = Arc::new(GroupMode::new(config.group_trigger.as_deref()));
+    let flood_control = Arc::new(FloodControl::default());
+    let verification = Arc::new(Verification::load(config.verify_new_chats, storage.clone()).await);
+    tokio::spawn(flood_control.clone().prune_periodically());
+    let accounts = Arc::new(Accounts {
+        storage: storage.clone(),
+        spotify: SpotifyAccounts::from_config(&config, storage.clone()).map(Arc::new),
+        youtube: YouTubeAccounts::from_config(&config, storage.clone()).map(Arc::new),
+    });
 
     let bot = Bot::from_env();
 
-    teloxide::repl(bot, |bot: Bot, msg: Message| async move {
-        match serde_json::to_string_pretty(&msg) {
-            Ok(json) => {
-                info!("Received message: {}", json);
-            }
-            Err(e) => {
-                log::error!("Failed to convert message to JSON: {}", e);
+    // Spotify and YouTube send users back here once they've signed in
+    let logins: Vec<_> = [
+        accounts
+            .spotify
+            .as_ref()
+            .map(|spotify| spotify.oauth.clone()),
+        accounts
+            .youtube
+            .as_ref()
+            .map(|youtube| youtube.oauth.clone()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !logins.is_empty() {
+        let callback_addr = &config.oauth_callback_addr;
+        let listener = tokio::net::TcpListener::bind(&callback_addr)
+            .await
+            .expect("Failed to bind the OAuth callback address");
+        let app = logins.into_iter().fold(axum::Router::new(), |app, login| {
+            app.merge(oauth::callback_router(
+                login,
+                bot.clone(),
+                preferences.clone(),
+            ))
+        });
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                log::error!("OAuth callback server stopped: {}", e);
             }
-        }
+        });
+        info!("Serving the OAuth callbacks on {}", callback_addr);
+    }
+
+    // Messages and channel posts alike are throttled and held back until
+    // their chat is verified
+    let admission = dptree::entry()
+        // A chat sending messages faster than anyone types gets one warning,
+        // the rest of the flood is dropped before it's published
+        .filter_async(
+            |bot: Bot,
+             msg: Message,
+             flood_control: Arc<FloodControl>,
+             preferences: Arc<Preferences>| async move {
+                flood_control.admit(&bot, &msg, &preferences).await
+            },
+        )
+        // Chats have to pass a challenge before their first request, when
+        // verify_new_chats is set
+        .filter_async(
+            |bot: Bot,
+             msg: Message,
+             admins: Arc<Admins>,
+             verification: Arc<Verification>,
+             preferences: Arc<Preferences>| async move {
+                admins.is_admin(&msg) || verification.admit(&bot, &msg, &preferences).await
+            },
+        );
+
+    let message_handler = Update::filter_message()
+        // Group messages that aren't meant for the bot aren't even logged
+        .filter(
+            |msg: Message, me: Me, group_mode: Arc<GroupMode>, preferences: Arc<Preferences>| {
+                group_mode.is_addressed(&msg, &me, &preferences)
+            },
+        )
+        .chain(admission.clone())
+        // Every message gets its own ID, passed on to the workers for tracing
+        .map(RequestId::generate)
+        .inspect(
+            |msg: Message, request_id: RequestId| match serde_json::to_string_pretty(&msg) {
+                Ok(json) => {
+                    info!("Received message {}: {}", request_id, json);
+                }
+                Err(e) => {
+                    log::error!("Failed to convert message to JSON: {}", e);
+                }
+            },
+        )
+        .branch(
+            dptree::filter(|msg: Message, admins: Arc<Admins>| admins.is_admin(&msg))
+                .filter_command::<AdminCommand>()
+                .endpoint(handle_admin_command),
+        )
+        .branch(
+            dptree::entry()
+                .filter_command::<Command>()
+                .endpoint(handle_command),
+        )
+        .branch(dptree::filter(|msg: Message| msg.photo().is_some()).endpoint(handle_photo))
+        .branch(dptree::filter(|msg: Message| msg.document().is_some()).endpoint(handle_document))
+        .branch(dptree::filter(|msg: Message| msg.voice().is_some()).endpoint(handle_voice))
+        .branch(
+            dptree::filter(|msg: Message| msg.audio().is_some() || msg.video_note().is_some())
+                .endpoint(handle_media),
+        )
+        .branch(dptree::filter(|msg: Message| is_command(&msg)).endpoint(handle_unknown_command))
+        .branch(dptree::endpoint(handle_text));
+
+    // Commands posted to a channel have no sender to answer to
+    let channel_post_handler = Update::filter_channel_post()
+        .filter(|msg: Message| msg.text().is_some() && !is_command(&msg))
+        .chain(admission)
+        .map(RequestId::generate)
+        .endpoint(handle_channel_post);
+
+    let inline_handler = Update::filter_inline_query()
+        .map(RequestId::generate)
+        .endpoint(handle_inline_query);
+
+    let callback_handler = Update::filter_callback_query()
+        .map(RequestId::generate)
+        .branch(
+            dptree::filter(|query: CallbackQuery| is_verify_callback(&query))
+                .endpoint(handle_verify_callback),
+        )
+        .branch(dptree::filter(|query: CallbackQuery| is_resend(&query)).endpoint(handle_resend))
+        .branch(
+            dptree::filter(|query: CallbackQuery| is_settings_callback(&query))
+                .endpoint(handle_settings_callback),
+        )
+        .branch(
+            dptree::filter(|query: CallbackQuery| is_spotify_callback(&query))
+                .endpoint(handle_spotify_callback),
+        )
+        .branch(dptree::endpoint(handle_callback_query));
 
-        // Example: Send a dice emoji for fun
-        bot.send_dice(msg.chat.id).await?;
+    // Updates from banned chats are dropped before any handler sees them
+    let handler = dptree::entry()
+        .filter_async(
+            |update: Update, bans: Arc<Bans>| async move { !bans.is_banned(&update).await },
+        )
+        .branch(message_handler)
+        .branch(channel_post_handler)
+        .branch(inline_handler)
+        .branch(callback_handler);
 
-        Ok(())
-    })
-    .await;
+    Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![
+            producer,
+            preferences,
+            latest_queries,
+            storage,
+            admins,
+            bans,
+            group_mode,
+            flood_control,
+            verification,
+            accounts
+        ])
+        .enable_ctrlc_handler()
+        .build()
+        .dispatch()
+        .await;
 }
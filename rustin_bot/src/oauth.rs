@@ -0,0 +1,287 @@
+use crate::preferences::Preferences;
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use reqwest::{Client, StatusCode, Url};
+use rustin_models::{i18n::tr, oauth::TokenResponse};
+use rustin_storage::{OAuthProvider, OAuthToken, Storage};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use teloxide::prelude::*;
+use uuid::Uuid;
+
+type DynError = Box<dyn Error + Send + Sync + 'static>;
+
+// How long a login link keeps working
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// A service users sign in to and what the bot asks it for
+pub struct OAuthApp {
+    pub provider: OAuthProvider,
+    // Shown on the page users are sent back to
+    pub name: &'static str,
+    pub authorize_url: &'static str,
+    pub token_url: &'static str,
+    pub scopes: &'static str,
+    // Extra parameters of the login link, e.g. to be given a refresh token
+    pub login_params: &'static [(&'static str, &'static str)],
+    // Told in the chat once the user has signed in
+    pub signed_in_key: &'static str,
+}
+
+// A login that the service hasn't sent back yet
+struct PendingLogin {
+    user_id: i64,
+    chat_id: i64,
+    verifier: String,
+    started: Instant,
+}
+
+// Signs users in with the authorization code flow and PKCE, and keeps the
+// tokens in the database so logins survive restarts
+pub struct OAuthClient {
+    app: &'static OAuthApp,
+    client: Client,
+    client_id: String,
+    // Spotify does without one thanks to PKCE, Google still wants it
+    client_secret: Option<String>,
+    // The public URL of the callback, registered with the app
+    redirect_uri: String,
+    storage: Arc<Storage>,
+    // Keyed by the state parameter of the login link
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+impl OAuthClient {
+    pub fn new(
+        app: &'static OAuthApp,
+        client_id: String,
+        client_secret: Option<String>,
+        redirect_uri: String,
+        storage: Arc<Storage>,
+    ) -> Self {
+        Self {
+            app,
+            client: Client::new(),
+            client_id,
+            client_secret,
+            redirect_uri,
+            storage,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // The link that signs the user in, after which the chat is told
+    pub fn login_url(&self, user_id: i64, chat_id: i64) -> Result<Url, DynError> {
+        let state = Uuid::new_v4().simple().to_string();
+        // 64 characters, PKCE wants between 43 and 128
+        let verifier = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+        let url = Url::parse_with_params(
+            self.app.authorize_url,
+            [
+                ("client_id", self.client_id.as_str()),
+                ("response_type", "code"),
+                ("redirect_uri", self.redirect_uri.as_str()),
+                ("code_challenge_method", "S256"),
+                ("code_challenge", challenge.as_str()),
+                ("scope", self.app.scopes),
+                ("state", state.as_str()),
+            ]
+            .into_iter()
+            .chain(self.app.login_params.iter().copied()),
+        )?;
+
+        let mut pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        pending.retain(|_, login| login.started.elapsed() < LOGIN_TIMEOUT);
+        pending.insert(
+            state,
+            PendingLogin {
+                user_id,
+                chat_id,
+                verifier,
+                started: Instant::now(),
+            },
+        );
+        Ok(url)
+    }
+
+    // Trade the code the service sent back for tokens. Returns the chat the
+    // login was started from
+    async fn finish_login(&self, state: &str, code: &str) -> Result<i64, DynError> {
+        let login = self
+            .pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(state)
+            .filter(|login| login.started.elapsed() < LOGIN_TIMEOUT)
+            .ok_or("unknown or expired login")?;
+
+        let response = self
+            .token_request(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.redirect_uri.as_str()),
+                ("code_verifier", login.verifier.as_str()),
+            ])
+            .await?;
+        let response: TokenResponse = response.error_for_status()?.json().await?;
+        let token = OAuthToken {
+            refresh_token: response.refresh_token.ok_or("no refresh token")?,
+            access_token: response.access_token,
+            expires_at: expiry(response.expires_in),
+        };
+        self.storage
+            .save_oauth_token(login.user_id, self.app.provider, &token)
+            .await?;
+        log::info!("User {} signed in to {}", login.user_id, self.app.name);
+        Ok(login.chat_id)
+    }
+
+    pub async fn sign_out(&self, user_id: i64) -> Result<(), DynError> {
+        self.storage
+            .delete_oauth_token(user_id, self.app.provider)
+            .await?;
+        Ok(())
+    }
+
+    // The user's access token, refreshed once it's about to expire, or None
+    // when they aren't signed in. A login the user revoked is forgotten, so
+    // they're asked to sign in again
+    pub async fn access_token(&self, user_id: i64) -> Result<Option<String>, DynError> {
+        let Some(token) = self.storage.oauth_token(user_id, self.app.provider).await? else {
+            return Ok(None);
+        };
+        if SystemTime::now() < token.expires_at {
+            return Ok(Some(token.access_token));
+        }
+
+        let response = self
+            .token_request(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", token.refresh_token.as_str()),
+            ])
+            .await?;
+        if response.status() == StatusCode::BAD_REQUEST {
+            log::info!("{} login of user {} was revoked", self.app.name, user_id);
+            self.sign_out(user_id).await?;
+            return Ok(None);
+        }
+        let response: TokenResponse = response.error_for_status()?.json().await?;
+
+        // A new refresh token may be handed out, the old one stops working then
+        let token = OAuthToken {
+            access_token: response.access_token,
+            refresh_token: response.refresh_token.unwrap_or(token.refresh_token),
+            expires_at: expiry(response.expires_in),
+        };
+        self.storage
+            .save_oauth_token(user_id, self.app.provider, &token)
+            .await?;
+        Ok(Some(token.access_token))
+    }
+
+    async fn token_request(&self, form: &[(&str, &str)]) -> Result<reqwest::Response, DynError> {
+        let mut form = form.to_vec();
+        form.push(("client_id", self.client_id.as_str()));
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret.as_str()));
+        }
+        Ok(self
+            .client
+            .post(self.app.token_url)
+            .form(&form)
+            .send()
+            .await?)
+    }
+}
+
+// Tokens are renewed a minute early, so they don't expire mid-request
+fn expiry(expires_in: u64) -> SystemTime {
+    SystemTime::now() + Duration::from_secs(expires_in.saturating_sub(60))
+}
+
+#[derive(Clone)]
+struct CallbackState {
+    oauth: Arc<OAuthClient>,
+    bot: Bot,
+    preferences: Arc<Preferences>,
+}
+
+// What the service sends the user back with, a code or the error of a login
+// that was cancelled
+#[derive(Deserialize)]
+struct CallbackParams {
+    state: Option<String>,
+    code: Option<String>,
+    error: Option<String>,
+}
+
+// The page users are sent back to once they've signed in, at
+// /<provider>/callback. The outcome is told in the chat the login was
+// started from
+pub fn callback_router(oauth: Arc<OAuthClient>, bot: Bot, preferences: Arc<Preferences>) -> Router {
+    let path = format!("/{}/callback", oauth.app.provider.as_str());
+    Router::new()
+        .route(&path, get(callback))
+        .with_state(CallbackState {
+            oauth,
+            bot,
+            preferences,
+        })
+}
+
+async fn callback(
+    State(state): State<CallbackState>,
+    Query(params): Query<CallbackParams>,
+) -> (StatusCode, String) {
+    let app = state.oauth.app;
+    let (Some(login_state), Some(code)) = (params.state, params.code) else {
+        log::info!(
+            "{} login failed: {}",
+            app.name,
+            params.error.unwrap_or_default()
+        );
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("{} login was cancelled, you can close this page.", app.name),
+        );
+    };
+
+    match state.oauth.finish_login(&login_state, &code).await {
+        Ok(chat_id) => {
+            let language = state.preferences.language(chat_id);
+            let sent = state
+                .bot
+                .send_message(ChatId(chat_id), tr(language, app.signed_in_key))
+                .await;
+            if let Err(e) = sent {
+                log::warn!("Failed to confirm the {} login: {}", app.name, e);
+            }
+            (
+                StatusCode::OK,
+                format!("Signed in to {}, you can go back to Telegram.", app.name),
+            )
+        }
+        Err(e) => {
+            log::error!("Failed to finish the {} login: {}", app.name, e);
+            (
+                StatusCode::BAD_REQUEST,
+                format!("{} login failed, please sign in again.", app.name),
+            )
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::{
+    commands::HandlerResult, preferences::Preferences, producer::Producer, request_id::RequestId,
+};
+use rustin_models::{i18n::tr, PICK_CALLBACK_PREFIX};
+use std::sync::Arc;
+use teloxide::prelude::*;
+
+// A button of the /search result picker was pressed
+#[tracing::instrument(skip_all, fields(request_id = %request_id, user_id = query.from.id.0))]
+pub async fn handle_callback_query(
+    bot: Bot,
+    query: CallbackQuery,
+    request_id: RequestId,
+    producer: Arc<Producer>,
+    preferences: Arc<Preferences>,
+) -> HandlerResult {
+    let chat_id = query.message.as_ref().map(|message| message.chat().id);
+    let language = chat_id
+        .and_then(|chat_id| preferences.language(chat_id.0))
+        .or(query.from.language_code.as_deref());
+    let video_id = query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(PICK_CALLBACK_PREFIX));
+    let (Some(video_id), Some(message)) = (video_id, &query.message) else {
+        bot.answer_callback_query(query.id.clone())
+            .text(tr(language, "picker.expired"))
+            .await?;
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+
+    // Remove the buttons so the same search can't be picked from twice
+    if let Err(e) = bot.edit_message_reply_markup(chat_id, message.id()).await {
+        log::warn!("Failed to remove search result buttons: {}", e);
+    }
+
+    let options = preferences.request_options(chat_id.0, language);
+    let answer = match producer
+        .publish_picked_video(&request_id, chat_id.0, video_id, options)
+        .await
+    {
+        Ok(()) => tr(language, "picker.song_queued"),
+        Err(e) => {
+            log::error!("Failed to publish picked video: {}", e);
+            tr(language, "error.generic")
+        }
+    };
+    bot.answer_callback_query(query.id.clone())
+        .text(answer)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,233 @@
+use crate::{
+    commands::{language_code, Accounts, HandlerResult},
+    preferences::Preferences,
+    producer::Producer,
+    request_id::RequestId,
+    spotify::SpotifyAccounts,
+};
+use rustin_models::{
+    i18n::{tr, tr_args},
+    spotify::PlaylistsPage,
+};
+use std::{collections::BTreeMap, sync::Arc};
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+// Prefix of the callback data of playlist browser buttons, followed by
+// "page:<offset>" or "pick:<playlist ID>"
+pub const SPOTIFY_CALLBACK_PREFIX: &str = "spotify:";
+// Playlists listed per page of the browser
+const PAGE_SIZE: u32 = 8;
+// Telegram cuts long button labels off
+const MAX_LABEL_CHARS: usize = 40;
+
+// /spotify lists the user's playlists to pick one to convert, signing them in
+// to Spotify first if they haven't. /spotify logout forgets the login
+pub async fn show_playlists(
+    bot: &Bot,
+    msg: &Message,
+    args: &str,
+    spotify: Option<&SpotifyAccounts>,
+    preferences: &Preferences,
+) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    let Some(spotify) = spotify else {
+        bot.send_message(msg.chat.id, tr(language, "spotify.unavailable"))
+            .await?;
+        return Ok(());
+    };
+    // Login links and playlists are personal
+    let (true, Some(user)) = (msg.chat.is_private(), &msg.from) else {
+        bot.send_message(msg.chat.id, tr(language, "spotify.private_only"))
+            .await?;
+        return Ok(());
+    };
+    let user_id = user.id.0 as i64;
+
+    match args.trim() {
+        "" => {}
+        "logout" => {
+            let reply = match spotify.oauth.sign_out(user_id).await {
+                Ok(()) => tr(language, "spotify.signed_out"),
+                Err(e) => {
+                    log::error!("Failed to sign user {} out of Spotify: {}", user_id, e);
+                    tr(language, "error.generic")
+                }
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+            return Ok(());
+        }
+        _ => {
+            bot.send_message(msg.chat.id, tr(language, "spotify.usage"))
+                .await?;
+            return Ok(());
+        }
+    }
+
+    match spotify.playlists(user_id, 0, PAGE_SIZE).await {
+        Ok(Some(page)) if page.items.is_empty() => {
+            bot.send_message(msg.chat.id, tr(language, "spotify.no_playlists"))
+                .await?;
+        }
+        Ok(Some(page)) => {
+            bot.send_message(msg.chat.id, tr(language, "spotify.playlists"))
+                .reply_markup(keyboard(&page, 0))
+                .await?;
+        }
+        Ok(None) => match spotify.oauth.login_url(user_id, msg.chat.id.0) {
+            Ok(url) => {
+                let button = InlineKeyboardButton::url(tr(language, "spotify.sign_in"), url);
+                bot.send_message(msg.chat.id, tr(language, "spotify.sign_in_prompt"))
+                    .reply_markup(InlineKeyboardMarkup::new([[button]]))
+                    .await?;
+            }
+            Err(e) => {
+                log::error!("Failed to build the Spotify login link: {}", e);
+                bot.send_message(msg.chat.id, tr(language, "error.generic"))
+                    .await?;
+            }
+        },
+        Err(e) => {
+            log::error!("Failed to list the Spotify playlists: {}", e);
+            bot.send_message(msg.chat.id, tr(language, "error.generic"))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+pub fn is_spotify_callback(query: &CallbackQuery) -> bool {
+    query
+        .data
+        .as_deref()
+        .is_some_and(|data| data.starts_with(SPOTIFY_CALLBACK_PREFIX))
+}
+
+// A button of the playlist browser was pressed, to turn the page or pick
+// the playlist to convert
+#[tracing::instrument(skip_all, fields(request_id = %request_id, user_id = query.from.id.0))]
+pub async fn handle_spotify_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    request_id: RequestId,
+    producer: Arc<Producer>,
+    preferences: Arc<Preferences>,
+    accounts: Arc<Accounts>,
+) -> HandlerResult {
+    let chat_id = query.message.as_ref().map(|message| message.chat().id);
+    let language = chat_id
+        .and_then(|chat_id| preferences.language(chat_id.0))
+        .or(query.from.language_code.as_deref());
+    let action = query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(SPOTIFY_CALLBACK_PREFIX))
+        .and_then(|data| data.split_once(':'));
+    let (Some(spotify), Some(action), Some(message)) =
+        (accounts.spotify.as_deref(), action, &query.message)
+    else {
+        bot.answer_callback_query(query.id.clone())
+            .text(tr(language, "picker.expired"))
+            .await?;
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+    let user_id = query.from.id.0 as i64;
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    let reply = match action {
+        ("page", offset) => {
+            let offset = offset.parse().unwrap_or(0);
+            match spotify.playlists(user_id, offset, PAGE_SIZE).await {
+                Ok(Some(page)) => {
+                    bot.edit_message_reply_markup(chat_id, message.id())
+                        .reply_markup(keyboard(&page, offset))
+                        .await?;
+                    return Ok(());
+                }
+                Ok(None) => tr(language, "spotify.sign_in_again"),
+                Err(e) => {
+                    log::error!("Failed to list the Spotify playlists: {}", e);
+                    tr(language, "error.generic")
+                }
+            }
+        }
+        ("pick", playlist_id) => {
+            // Remove the buttons so the same playlist isn't converted twice
+            if let Err(e) = bot.edit_message_reply_markup(chat_id, message.id()).await {
+                log::warn!("Failed to remove playlist buttons: {}", e);
+            }
+            match spotify.playlist_tracks(user_id, playlist_id).await {
+                Ok(Some(lines)) if lines.is_empty() => tr(language, "spotify.playlist_empty"),
+                Ok(Some(lines)) => {
+                    let options = preferences.request_options(chat_id.0, language);
+                    let published = producer
+                        .publish_song_request(
+                            &request_id,
+                            chat_id.0,
+                            &lines.join("\n"),
+                            BTreeMap::new(),
+                            options,
+                        )
+                        .await;
+                    match published {
+                        Ok(()) => tr_args(
+                            language,
+                            "spotify.playlist_queued",
+                            &[
+                                ("count", &lines.len().to_string()),
+                                ("request_id", request_id.as_str()),
+                            ],
+                        ),
+                        Err(e) => {
+                            log::error!("Failed to publish Spotify playlist: {}", e);
+                            tr(language, "error.generic")
+                        }
+                    }
+                }
+                Ok(None) => tr(language, "spotify.sign_in_again"),
+                Err(e) => {
+                    log::error!("Failed to read Spotify playlist {}: {}", playlist_id, e);
+                    tr(language, "error.generic")
+                }
+            }
+        }
+        _ => tr(language, "picker.expired"),
+    };
+    bot.send_message(chat_id, reply).await?;
+    Ok(())
+}
+
+// One button per playlist, with the page buttons underneath
+fn keyboard(page: &PlaylistsPage, offset: u32) -> InlineKeyboardMarkup {
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = page
+        .items
+        .iter()
+        .map(|playlist| {
+            let mut name: String = playlist.name.chars().take(MAX_LABEL_CHARS).collect();
+            if playlist.name.chars().count() > MAX_LABEL_CHARS {
+                name.push('…');
+            }
+            let label = format!("{} ({})", name, playlist.tracks.total);
+            let data = format!("{}pick:{}", SPOTIFY_CALLBACK_PREFIX, playlist.id);
+            vec![InlineKeyboardButton::callback(label, data)]
+        })
+        .collect();
+
+    let mut pages = Vec::new();
+    if offset > 0 {
+        let previous = offset.saturating_sub(PAGE_SIZE);
+        let data = format!("{}page:{}", SPOTIFY_CALLBACK_PREFIX, previous);
+        pages.push(InlineKeyboardButton::callback("◀", data));
+    }
+    if offset + PAGE_SIZE < page.total {
+        let data = format!("{}page:{}", SPOTIFY_CALLBACK_PREFIX, offset + PAGE_SIZE);
+        pages.push(InlineKeyboardButton::callback("▶", data));
+    }
+    if !pages.is_empty() {
+        rows.push(pages);
+    }
+    InlineKeyboardMarkup::new(rows)
+}
@@ -0,0 +1,106 @@
+use rustin_models::{i18n, MediaFormat, RequestOptions};
+use rustin_storage::{ChatSettings, Storage};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+// Bitrates the consumer knows how to pick, in kbps
+pub const SUPPORTED_BITRATES: [u32; 3] = [128, 256, 320];
+
+// Per-chat preferences applied to every request the chat enqueues. Kept in
+// memory and saved to storage, when there is one, so they survive restarts
+pub struct Preferences {
+    chats: RwLock<HashMap<i64, ChatSettings>>,
+    storage: Option<Arc<Storage>>,
+}
+
+impl Preferences {
+    pub async fn load(storage: Option<Arc<Storage>>) -> Self {
+        let chats = match &storage {
+            Some(storage) => storage.all_chat_settings().await.unwrap_or_else(|e| {
+                log::error!("Failed to load chat settings: {}", e);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        Self {
+            chats: RwLock::new(chats.into_iter().collect()),
+            storage,
+        }
+    }
+
+    pub fn settings(&self, chat_id: i64) -> ChatSettings {
+        self.chats
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&chat_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // The language the chat picked in /settings, if any
+    pub fn language(&self, chat_id: i64) -> Option<&'static str> {
+        let settings = self.settings(chat_id);
+        settings
+            .language
+            .map(|code| i18n::language(Some(code.as_str())))
+    }
+
+    pub async fn set_bitrate(&self, chat_id: i64, bitrate: u32) {
+        self.update(chat_id, |settings| {
+            settings.bitrate = i32::try_from(bitrate).ok()
+        })
+        .await;
+    }
+
+    pub async fn set_streaming(&self, chat_id: i64, enabled: bool) {
+        self.update(chat_id, |settings| settings.stream = enabled)
+            .await;
+    }
+
+    pub async fn set_zipping(&self, chat_id: i64, enabled: bool) {
+        self.update(chat_id, |settings| settings.zip = enabled)
+            .await;
+    }
+
+    // Apply any change to the settings of a chat and save them
+    pub async fn update(&self, chat_id: i64, change: impl FnOnce(&mut ChatSettings)) {
+        let settings = {
+            let mut chats = self
+                .chats
+                .write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let settings = chats.entry(chat_id).or_default();
+            change(settings);
+            settings.clone()
+        };
+
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.save_chat_settings(chat_id, &settings).await {
+                log::error!("Failed to save the settings of chat {}: {}", chat_id, e);
+            }
+        }
+    }
+
+    // Options to attach to the next request of this chat. The language the
+    // chat picked wins over the one of the user's Telegram app
+    pub fn request_options(&self, chat_id: i64, language: Option<&str>) -> RequestOptions {
+        let settings = self.settings(chat_id);
+        RequestOptions {
+            bitrate: settings
+                .bitrate
+                .and_then(|bitrate| u32::try_from(bitrate).ok()),
+            stream: settings.stream,
+            zip: settings.zip,
+            normalize: settings.normalize,
+            links: settings.links,
+            language: self.language(chat_id).or(language).map(str::to_string),
+            lyrics: settings.lyrics,
+            song_link: settings.song_link,
+            playlist: settings.playlist,
+            deliver_at: None,
+            format: MediaFormat::Mp3,
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use crate::{
+    request_id::RequestId,
+    topology::{self, Queues},
+};
+use lapin::{
+    options::{BasicPublishOptions, QueueDeclareOptions},
+    types::{AMQPValue, FieldTable},
+    BasicProperties, Channel, Connection, ConnectionProperties,
+};
+use log::info;
+use rustin_models::{clip::Clip, MessageBody, RabbitMessage, RequestOptions, REQUEST_ID_HEADER};
+use rustin_storage::Storage;
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+type DynError = Box<dyn Error + Send + Sync + 'static>;
+
+// Publishes incoming Telegram messages to the RabbitMQ queues consumed by the workers
+pub struct Producer {
+    // Also used for short-lived channels, e.g. to look at queue depths
+    connection: Connection,
+    channel: Channel,
+    // The last request each chat enqueued, so /cancel knows what to cancel
+    latest_requests: Mutex<HashMap<i64, String>>,
+    // Requests are recorded as queued here, for /status
+    storage: Option<Arc<Storage>>,
+    queues: Queues,
+}
+
+impl Producer {
+    pub async fn connect(
+        rabbit_addr: &str,
+        queues: Queues,
+        storage: Option<Arc<Storage>>,
+    ) -> Result<Self, DynError> {
+        let connection = Connection::connect(rabbit_addr, ConnectionProperties::default()).await?;
+        let channel = connection.create_channel().await?;
+        info!("Connected to RabbitMQ at {}", rabbit_addr);
+        topology::declare(&channel, &queues).await?;
+
+        Ok(Self {
+            connection,
+            channel,
+            latest_requests: Mutex::new(HashMap::new()),
+            storage,
+            queues,
+        })
+    }
+
+    // Publish the song titles of a message to the Music queue
+    pub async fn publish_song_request(
+        &self,
+        request_id: &RequestId,
+        chat_id: i64,
+        text: &str,
+        clips: BTreeMap<usize, Clip>,
+        options: RequestOptions,
+    ) -> Result<(), DynError> {
+        let message = RabbitMessage::new(
+            chat_id,
+            MessageBody::TextRequest {
+                text: text.to_string(),
+                clips,
+            },
+        )
+        .with_options(options);
+        self.publish_request(request_id, &message).await?;
+        info!("Published song request for chat ID: {}", chat_id);
+        Ok(())
+    }
+
+    // Publish a tracklist screenshot to the Music queue so its text gets OCR'd
+    pub async fn publish_photo_request(
+        &self,
+        request_id: &RequestId,
+        chat_id: i64,
+        photo_url: &str,
+        options: RequestOptions,
+    ) -> Result<(), DynError> {
+        let message = RabbitMessage::new(
+            chat_id,
+            MessageBody::PhotoRequest {
+                photo_url: photo_url.to_string(),
+            },
+        )
+        .with_options(options);
+        self.publish_request(request_id, &message).await?;
+        info!("Published photo request for chat ID: {}", chat_id);
+        Ok(())
+    }
+
+    // Publish an uploaded tracklist to the Music queue, to be converted as a
+    // whole like a playlist
+    pub async fn publish_document_request(
+        &self,
+        request_id: &RequestId,
+        chat_id: i64,
+        document_url: &str,
+        file_name: &str,
+        options: RequestOptions,
+    ) -> Result<(), DynError> {
+        let message = RabbitMessage::new(
+            chat_id,
+            MessageBody::DocumentRequest {
+                document_url: document_url.to_string(),
+                file_name: file_name.to_string(),
+            },
+        )
+        .with_options(options);
+        self.publish_request(request_id, &message).await?;
+        info!("Published document request for chat ID: {}", chat_id);
+        Ok(())
+    }
+
+    // Publish a voice message to the Music queue so the song in it gets recognized
+    pub async fn publish_voice_request(
+        &self,
+        request_id: &RequestId,
+        chat_id: i64,
+        voice_url: &str,
+        options: RequestOptions,
+    ) -> Result<(), DynError> {
+        let message = RabbitMessage::new(
+            chat_id,
+            MessageBody::VoiceRequest {
+                voice_url: voice_url.to_string(),
+            },
+        )
+        .with_options(options);
+        self.publish_request(request_id, &message).await?;
+        info!("Published voice request for chat ID: {}", chat_id);
+        Ok(())
+    }
+
+    // Publish a forwarded audio file or video note to be sampled and recognized
+    pub async fn publish_media_request(
+        &self,
+        request_id: &RequestId,
+        chat_id: i64,
+        media_url: &str,
+        options: RequestOptions,
+    ) -> Result<(), DynError> {
+        let message = RabbitMessage::new(
+            chat_id,
+            MessageBody::MediaRequest {
+                media_url: media_url.to_string(),
+            },
+        )
+        .with_options(options);
+        self.publish_request(request_id, &message).await?;
+        info!("Published media request for chat ID: {}", chat_id);
+        Ok(())
+    }
+
+    // Publish a /search query, answered with buttons for the top results
+    pub async fn publish_search_request(
+        &self,
+        request_id: &RequestId,
+        chat_id: i64,
+        query: &str,
+        options: RequestOptions,
+    ) -> Result<(), DynError> {
+        let message = RabbitMessage::new(
+            chat_id,
+            MessageBody::SearchRequest {
+                query: query.to_string(),
+            },
+        )
+        .with_options(options);
+        self.publish_request(request_id, &message).await?;
+        info!("Published search request for chat ID: {}", chat_id);
+        Ok(())
+    }
+
+    // Publish a /lyrics query, answered with the lyrics as text
+    pub async fn publish_lyrics_request(
+        &self,
+        request_id: &RequestId,
+        chat_id: i64,
+        query: &str,
+        options: RequestOptions,
+    ) -> Result<(), DynError> {
+        let message = RabbitMessage::new(
+            chat_id,
+            MessageBody::LyricsRequest {
+                query: query.to_string(),
+            },
+        )
+        .with_options(options);
+        self.publish_request(request_id, &message).await?;
+        info!("Published lyrics request for chat ID: {}", chat_id);
+        Ok(())
+    }
+
+    // Publish song titles to the Music queue to be put in a YouTube playlist
+    pub async fn publish_youtube_playlist_request(
+        &self,
+        request_id: &RequestId,
+        chat_id: i64,
+        text: &str,
+        options: RequestOptions,
+    ) -> Result<(), DynError> {
+        let message = RabbitMessage::new(
+            chat_id,
+            MessageBody::YouTubePlaylistRequest {
+                text: text.to_string(),
+            },
+        )
+        .with_options(options);
+        self.publish_request(request_id, &message).await?;
+        info!(
+            "Published YouTube playlist request for chat ID: {}",
+            chat_id
+        );
+        Ok(())
+    }
+
+    // Publish the search result the user picked so it gets converted
+    pub async fn publish_picked_video(
+        &self,
+        request_id: &RequestId,
+        chat_id: i64,
+        video_id: &str,
+        options: RequestOptions,
+    ) -> Result<(), DynError> {
+        let message = RabbitMessage::new(
+            chat_id,
+            MessageBody::PickedVideo {
+                video_id: video_id.to_string(),
+            },
+        )
+        .with_options(options);
+        self.publish_request(request_id, &message).await?;
+        info!("Published picked video for chat ID: {}", chat_id);
+        Ok(())
+    }
+
+    // Publish an inline query, which the consumer answers with a single song
+    pub async fn publish_inline_query(
+        &self,
+        request_id: &RequestId,
+        user_id: i64,
+        inline_query_id: &str,
+        query: &str,
+    ) -> Result<(), DynError> {
+        let message = RabbitMessage::new(
+            user_id,
+            MessageBody::InlineQuery {
+                inline_query_id: inline_query_id.to_string(),
+                query: query.to_string(),
+            },
+        );
+        self.publish_request(request_id, &message).await?;
+        info!("Published inline query for user ID: {}", user_id);
+        Ok(())
+    }
+
+    // Ask the consumers to stop the last request of a chat. Returns false when
+    // the chat has no request that could still be running
+    pub async fn publish_cancel(
+        &self,
+        request_id: &RequestId,
+        chat_id: i64,
+    ) -> Result<bool, DynError> {
+        let Some(cancelled) = self.latest_requests.lock().await.remove(&chat_id) else {
+            return Ok(false);
+        };
+        let message = RabbitMessage::new(
+            chat_id,
+            MessageBody::CancelRequest {
+                request_id: cancelled.clone(),
+            },
+        );
+        self.publish_to(&self.queues.control_exchange, "", request_id, &message)
+            .await?;
+        info!("Published cancellation of request {}", cancelled);
+        Ok(true)
+    }
+
+    // Number of messages waiting in each queue, for /stats
+    pub async fn queue_depths(&self) -> Result<Vec<(&str, u32)>, DynError> {
+        // A passive declare of a missing queue closes its channel, so don't
+        // risk the publishing one
+        let channel = self.connection.create_channel().await?;
+        let mut depths = Vec::new();
+        let queues = &self.queues;
+        for queue_name in [&queues.music, &queues.music_fast, &queues.reply] {
+            let queue = channel
+                .queue_declare(
+                    queue_name,
+                    QueueDeclareOptions {
+                        passive: true,
+                        ..Default::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await?;
+            depths.push((queue_name.as_str(), queue.message_count()));
+        }
+        let _ = channel.close(200, "OK").await;
+        Ok(depths)
+    }
+
+    // Route the request to the fast lane when it converts at most one song
+    async fn publish_request(
+        &self,
+        request_id: &RequestId,
+        message: &RabbitMessage,
+    ) -> Result<(), DynError> {
+        let queue_name = if message.body.is_single_song() {
+            &self.queues.music_fast
+        } else {
+            &self.queues.music
+        };
+        self.publish_to("", queue_name, request_id, message).await?;
+        // A consumer may already have picked the request up, in which case
+        // this leaves its record alone
+        if let (Some(storage), Some(kind)) = (&self.storage, message.body.request_kind()) {
+            if let Err(e) = storage
+                .record_queued(request_id.as_str(), message.chat_id, kind)
+                .await
+            {
+                log::warn!("Failed to record queued request {}: {}", request_id, e);
+            }
+        }
+        // Inline queries are keyed by user and can't be cancelled
+        if !matches!(message.body, MessageBody::InlineQuery { .. }) {
+            self.latest_requests
+                .lock()
+                .await
+                .insert(message.chat_id, request_id.as_str().to_string());
+        }
+        Ok(())
+    }
+
+    // The trace of the handler goes on with the message to the workers
+    #[tracing::instrument(skip_all, name = "publish", fields(queue = routing_key))]
+    async fn publish_to(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        request_id: &RequestId,
+        message: &RabbitMessage,
+    ) -> Result<(), DynError> {
+        let serialized_message = serde_json::to_vec(message)?;
+        let mut headers = FieldTable::default();
+        headers.insert(
+            REQUEST_ID_HEADER.into(),
+            AMQPValue::LongString(request_id.as_str().into()),
+        );
+        rustin_telemetry::inject(&mut headers);
+        let published = self
+            .channel
+            .basic_publish(
+                exchange,
+                routing_key,
+                BasicPublishOptions::default(),
+                &serialized_message,
+                // Persistent, so queued requests survive a broker restart
+                BasicProperties::default()
+                    .with_delivery_mode(2)
+                    .with_message_id(Uuid::new_v4().to_string().into())
+                    .with_headers(headers),
+            )
+            .await;
+        if let Err(e) = &published {
+            rustin_telemetry::report_for_request(e, request_id.as_str());
+        }
+        published?;
+        Ok(())
+    }
+}
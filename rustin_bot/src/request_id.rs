@@ -0,0 +1,23 @@
+use std::fmt;
+use uuid::Uuid;
+
+// Identifies a single incoming Telegram message. It is sent along with every
+// queue message the update produces, so its logs can be followed across services
+#[derive(Clone, Debug)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().simple().to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{
+    commands::{language_code, HandlerResult},
+    preferences::{Preferences, SUPPORTED_BITRATES},
+};
+use rustin_models::i18n::{self, tr, tr_args};
+use rustin_storage::ChatSettings;
+use std::sync::Arc;
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+// Prefix of the callback data of settings buttons, followed by the setting
+pub const SETTINGS_CALLBACK_PREFIX: &str = "settings:";
+
+// Show the chat's settings as buttons, each one changes its setting
+pub async fn show_settings(bot: &Bot, msg: &Message, preferences: &Preferences) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    let settings = preferences.settings(msg.chat.id.0);
+    let is_group = msg.chat.is_group() || msg.chat.is_supergroup();
+    bot.send_message(msg.chat.id, tr(language, "settings.title"))
+        .reply_markup(keyboard(language, is_group, &settings))
+        .await?;
+    Ok(())
+}
+
+pub fn is_settings_callback(query: &CallbackQuery) -> bool {
+    query
+        .data
+        .as_deref()
+        .is_some_and(|data| data.starts_with(SETTINGS_CALLBACK_PREFIX))
+}
+
+// A button of the /settings menu was pressed
+#[tracing::instrument(skip_all, fields(user_id = query.from.id.0))]
+pub async fn handle_settings_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    preferences: Arc<Preferences>,
+) -> HandlerResult {
+    let setting = query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(SETTINGS_CALLBACK_PREFIX));
+    let change = setting.and_then(change_for);
+    let (Some(change), Some(message)) = (change, &query.message) else {
+        bot.answer_callback_query(query.id.clone())
+            .text(tr(query.from.language_code.as_deref(), "settings.expired"))
+            .await?;
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+
+    preferences.update(chat_id.0, change).await;
+    log::info!(
+        "Chat ID {} changed its {} setting",
+        chat_id,
+        setting.unwrap_or_default()
+    );
+
+    // The menu is redrawn in the language that may have just been picked
+    let language = preferences
+        .language(chat_id.0)
+        .or(query.from.language_code.as_deref());
+    let settings = preferences.settings(chat_id.0);
+    let is_group = message.chat().is_group() || message.chat().is_supergroup();
+    if let Err(e) = bot
+        .edit_message_text(chat_id, message.id(), tr(language, "settings.title"))
+        .reply_markup(keyboard(language, is_group, &settings))
+        .await
+    {
+        log::warn!("Failed to update the settings menu: {}", e);
+    }
+    bot.answer_callback_query(query.id.clone())
+        .text(tr(language, "settings.saved"))
+        .await?;
+    Ok(())
+}
+
+// How pressing the button of a setting changes it
+fn change_for(setting: &str) -> Option<fn(&mut ChatSettings)> {
+    let change: fn(&mut ChatSettings) = match setting {
+        "bitrate" => next_bitrate,
+        "language" => next_language,
+        "links" => |settings| settings.links = !settings.links,
+        "stream" => |settings| settings.stream = !settings.stream,
+        "zip" => |settings| settings.zip = !settings.zip,
+        "normalize" => |settings| settings.normalize = !settings.normalize,
+        "lyrics" => |settings| settings.lyrics = !settings.lyrics,
+        "song_link" => |settings| settings.song_link = !settings.song_link,
+        "playlist" => |settings| settings.playlist = !settings.playlist,
+        "all_messages" => |settings| settings.all_messages = !settings.all_messages,
+        _ => return None,
+    };
+    Some(change)
+}
+
+fn keyboard(
+    language: Option<&str>,
+    is_group: bool,
+    settings: &ChatSettings,
+) -> InlineKeyboardMarkup {
+    let bitrate = match settings.bitrate {
+        Some(bitrate) => format!("{} kbps", bitrate),
+        None => tr(language, "settings.default_bitrate"),
+    };
+    let chat_language = match &settings.language {
+        Some(code) => tr(Some(code), "language.name"),
+        None => tr(language, "settings.language_auto"),
+    };
+    let toggle = |setting: &str, enabled: bool| {
+        let key = format!(
+            "settings.{}_{}",
+            setting,
+            if enabled { "on" } else { "off" }
+        );
+        button(tr(language, &key), setting)
+    };
+
+    let mut rows = vec![
+        vec![button(
+            tr_args(language, "settings.bitrate", &[("bitrate", &bitrate)]),
+            "bitrate",
+        )],
+        vec![button(
+            tr_args(
+                language,
+                "settings.language",
+                &[("language", &chat_language)],
+            ),
+            "language",
+        )],
+        vec![toggle("links", settings.links)],
+        vec![toggle("stream", settings.stream)],
+        vec![toggle("zip", settings.zip)],
+        vec![toggle("normalize", settings.normalize)],
+        vec![toggle("lyrics", settings.lyrics)],
+        vec![toggle("song_link", settings.song_link)],
+        vec![toggle("playlist", settings.playlist)],
+    ];
+    // Private chats are always answered
+    if is_group {
+        rows.push(vec![toggle("all_messages", settings.all_messages)]);
+    }
+    InlineKeyboardMarkup::new(rows)
+}
+
+fn button(label: String, setting: &str) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(label, format!("{}{}", SETTINGS_CALLBACK_PREFIX, setting))
+}
+
+// The consumer default first, then every supported bitrate in turn
+fn next_bitrate(settings: &mut ChatSettings) {
+    let bitrates = SUPPORTED_BITRATES.map(|bitrate| bitrate as i32);
+    settings.bitrate = match settings.bitrate {
+        None => bitrates.first().copied(),
+        Some(current) => bitrates
+            .iter()
+            .position(|&bitrate| bitrate == current)
+            .and_then(|index| bitrates.get(index + 1))
+            .copied(),
+    };
+}
+
+// The user's Telegram language first, then every language with a catalog
+fn next_language(settings: &mut ChatSettings) {
+    let languages: Vec<_> = i18n::languages().collect();
+    settings.language = match settings.language.as_deref() {
+        None => languages.first().map(|code| code.to_string()),
+        Some(current) => languages
+            .iter()
+            .position(|&code| code == current)
+            .and_then(|index| languages.get(index + 1))
+            .map(|code| code.to_string()),
+    };
+}
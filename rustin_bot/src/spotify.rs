@@ -0,0 +1,111 @@
+use crate::{
+    config::Config,
+    oauth::{OAuthApp, OAuthClient},
+};
+use reqwest::Client;
+use rustin_models::spotify::{PlaylistTracksPage, PlaylistsPage, Track};
+use rustin_storage::{OAuthProvider, Storage};
+use serde::de::DeserializeOwned;
+use std::{error::Error, sync::Arc};
+
+type DynError = Box<dyn Error + Send + Sync + 'static>;
+
+const API_URL: &str = "https://api.spotify.com/v1";
+static APP: OAuthApp = OAuthApp {
+    provider: OAuthProvider::Spotify,
+    name: "Spotify",
+    authorize_url: "https://accounts.spotify.com/authorize",
+    token_url: "https://accounts.spotify.com/api/token",
+    // Reading the user's playlists is all the bot asks for
+    scopes: "playlist-read-private playlist-read-collaborative",
+    login_params: &[],
+    signed_in_key: "spotify.signed_in",
+};
+
+// Lets users sign in to Spotify so their private playlists can be listed and
+// converted. PKCE needs no client secret
+pub struct SpotifyAccounts {
+    client: Client,
+    pub oauth: Arc<OAuthClient>,
+}
+
+impl SpotifyAccounts {
+    // Needs spotify_client_id and spotify_redirect_uri, and the database to
+    // keep the tokens in
+    pub fn from_config(config: &Config, storage: Option<Arc<Storage>>) -> Option<Self> {
+        let client_id = config.spotify_client_id.clone()?;
+        let redirect_uri = config.spotify_redirect_uri.clone()?;
+        let Some(storage) = storage else {
+            log::warn!("Spotify login needs the history database, it's disabled");
+            return None;
+        };
+        Some(Self {
+            client: Client::new(),
+            oauth: Arc::new(OAuthClient::new(
+                &APP,
+                client_id,
+                None,
+                redirect_uri,
+                storage,
+            )),
+        })
+    }
+
+    // One page of the user's playlists, or None when they aren't signed in
+    pub async fn playlists(
+        &self,
+        user_id: i64,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Option<PlaylistsPage>, DynError> {
+        let Some(token) = self.oauth.access_token(user_id).await? else {
+            return Ok(None);
+        };
+        let url = format!("{}/me/playlists?limit={}&offset={}", API_URL, limit, offset);
+        Ok(Some(self.get(&token, &url).await?))
+    }
+
+    // The "artist - title" lines of a playlist, or None when the user isn't
+    // signed in
+    pub async fn playlist_tracks(
+        &self,
+        user_id: i64,
+        playlist_id: &str,
+    ) -> Result<Option<Vec<String>>, DynError> {
+        let Some(token) = self.oauth.access_token(user_id).await? else {
+            return Ok(None);
+        };
+        let mut tracks = Vec::new();
+        let mut next = Some(format!(
+            "{}/playlists/{}/tracks?limit=100&fields=items(track(name,artists(name))),next",
+            API_URL, playlist_id
+        ));
+        while let Some(url) = next {
+            let page: PlaylistTracksPage = self.get(&token, &url).await?;
+            tracks.extend(page.items.into_iter().filter_map(|item| item.track));
+            next = page.next;
+        }
+        Ok(Some(tracks.iter().map(format_track).collect()))
+    }
+
+    async fn get<T: DeserializeOwned>(&self, token: &str, url: &str) -> Result<T, DynError> {
+        Ok(self
+            .client
+            .get(url)
+            .bearer_auth(token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+fn format_track(track: &Track) -> String {
+    let artists: Vec<&str> = track.artists.iter().map(|a| a.name.as_str()).collect();
+    if artists.is_empty() {
+        track.name.clone()
+    } else {
+        format!("{} - {}", artists.join(", "), track.name)
+    }
+}
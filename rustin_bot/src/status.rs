@@ -0,0 +1,111 @@
+use crate::{
+    commands::{language_code, HandlerResult},
+    preferences::Preferences,
+};
+use rustin_models::i18n::{tr, tr_args};
+use rustin_storage::{
+    RequestStage, RequestStatus, RequestSummary, SongOutcome, SongStatus, Storage,
+};
+use teloxide::prelude::*;
+
+// Songs listed for a finished request, so the reply stays well within
+// Telegram's message limit
+const MAX_LISTED_SONGS: usize = 30;
+
+// Show how far a request of the chat has got, its latest one by default
+pub async fn show_status(
+    bot: &Bot,
+    msg: &Message,
+    request_id: &str,
+    storage: Option<&Storage>,
+    preferences: &Preferences,
+) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    let Some(storage) = storage else {
+        bot.send_message(msg.chat.id, tr(language, "status.unavailable"))
+            .await?;
+        return Ok(());
+    };
+
+    let request_id = Some(request_id.trim()).filter(|id| !id.is_empty());
+    let summary = match storage.request_summary(msg.chat.id.0, request_id).await {
+        Ok(Some(summary)) => summary,
+        Ok(None) => {
+            let reply = match request_id {
+                Some(request_id) => {
+                    tr_args(language, "status.not_found", &[("request_id", request_id)])
+                }
+                None => tr(language, "status.no_requests"),
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+            return Ok(());
+        }
+        Err(e) => {
+            log::error!("Failed to load request status: {}", e);
+            bot.send_message(msg.chat.id, tr(language, "error.generic"))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Songs are only stored once the request has finished
+    let songs = match summary.status {
+        RequestStatus::Pending => Vec::new(),
+        _ => storage
+            .request_songs(&summary.request_id)
+            .await
+            .unwrap_or_else(|e| {
+                log::error!("Failed to load the songs of a request: {}", e);
+                Vec::new()
+            }),
+    };
+
+    bot.send_message(msg.chat.id, describe(language, &summary, &songs))
+        .await?;
+    Ok(())
+}
+
+fn describe(language: Option<&str>, summary: &RequestSummary, songs: &[SongOutcome]) -> String {
+    let state = match summary.status {
+        RequestStatus::Pending => match summary.stage {
+            RequestStage::Queued => tr(language, "status.queued"),
+            RequestStage::Searching => tr(language, "status.searching"),
+            RequestStage::Converting => tr_args(
+                language,
+                "status.converting",
+                &[
+                    ("done", &summary.songs_done.to_string()),
+                    ("total", &summary.songs_total.unwrap_or(0).to_string()),
+                ],
+            ),
+            RequestStage::Delivering => tr(language, "status.delivering"),
+        },
+        RequestStatus::Completed => tr(language, "status.completed"),
+        RequestStatus::Failed => tr(language, "status.failed"),
+        RequestStatus::Cancelled => tr(language, "status.cancelled"),
+    };
+    let mut text = tr_args(
+        language,
+        "status.summary",
+        &[
+            ("request_id", &summary.request_id),
+            ("kind", &summary.kind),
+            ("state", &state),
+        ],
+    );
+
+    for song in songs.iter().take(MAX_LISTED_SONGS) {
+        let mark = match song.status {
+            SongStatus::Converted => "✅",
+            SongStatus::Failed => "❌",
+            SongStatus::Cancelled => "⏹",
+        };
+        text.push_str(&format!("\n{} {}", mark, song.title));
+    }
+    if songs.len() > MAX_LISTED_SONGS {
+        let more = (songs.len() - MAX_LISTED_SONGS).to_string();
+        text.push('\n');
+        text.push_str(&tr_args(language, "status.more_songs", &[("count", &more)]));
+    }
+    text
+}
@@ -0,0 +1,95 @@
+use lapin::{
+    options::{ExchangeDeclareOptions, QueueDeclareOptions},
+    types::{AMQPValue, FieldTable},
+    Channel, ExchangeKind,
+};
+use serde::Deserialize;
+
+// Names of the queues and exchanges the bot publishes to, matching the song
+// consumer's configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Queues {
+    pub music: String,
+    // Requests for a single song go here, so they don't wait behind playlists
+    pub music_fast: String,
+    // Replies of the workers, waiting for the reply service
+    pub reply: String,
+    // Where the consumers route rejected requests
+    pub dead_letter_exchange: String,
+    // Fanout exchange every consumer replica listens on for cancellations
+    pub control_exchange: String,
+}
+
+impl Default for Queues {
+    fn default() -> Self {
+        Self {
+            music: "Music".to_string(),
+            music_fast: "Music.fast".to_string(),
+            reply: "Reply".to_string(),
+            dead_letter_exchange: "Music.dlx".to_string(),
+            control_exchange: "Control".to_string(),
+        }
+    }
+}
+
+impl Queues {
+    pub fn validate(&self) -> Result<(), String> {
+        let names = [
+            ("music", &self.music),
+            ("music_fast", &self.music_fast),
+            ("reply", &self.reply),
+            ("dead_letter_exchange", &self.dead_letter_exchange),
+            ("control_exchange", &self.control_exchange),
+        ];
+        match names.iter().find(|(_, name)| name.trim().is_empty()) {
+            Some((field, _)) => Err(format!("queues.{} must not be empty", field)),
+            None => Ok(()),
+        }
+    }
+}
+
+// Requests published before any consumer ran would otherwise be dropped, so
+// the queues are declared here as well as in the consumer. They must have
+// the same arguments there, or RabbitMQ refuses them
+pub async fn declare(channel: &Channel, queues: &Queues) -> Result<(), lapin::Error> {
+    let mut music_arguments = FieldTable::default();
+    music_arguments.insert(
+        "x-dead-letter-exchange".into(),
+        AMQPValue::LongString(queues.dead_letter_exchange.clone().into()),
+    );
+    declare_queue(channel, &queues.music, music_arguments.clone()).await?;
+    declare_queue(channel, &queues.music_fast, music_arguments).await?;
+    declare_queue(channel, &queues.reply, FieldTable::default()).await?;
+
+    // Publishing to a missing exchange closes the channel
+    channel
+        .exchange_declare(
+            &queues.control_exchange,
+            ExchangeKind::Fanout,
+            ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+}
+
+async fn declare_queue(
+    channel: &Channel,
+    name: &str,
+    arguments: FieldTable,
+) -> Result<(), lapin::Error> {
+    channel
+        .queue_declare(
+            name,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            arguments,
+        )
+        .await?;
+    Ok(())
+}
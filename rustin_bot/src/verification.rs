@@ -0,0 +1,190 @@
+use crate::{
+    commands::{language_code, HandlerResult},
+    preferences::Preferences,
+};
+use rand::{seq::SliceRandom, Rng};
+use rustin_models::i18n::{tr, tr_args};
+use rustin_storage::Storage;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+};
+use tokio::sync::{Mutex, RwLock};
+
+// Prefix of the callback data of challenge buttons, followed by the index
+// of the emoji in EMOJIS
+pub const VERIFY_CALLBACK_PREFIX: &str = "verify:";
+
+// The buttons of a challenge, each with the catalog key of its name. The
+// challenge names the emoji to tap instead of showing it
+const EMOJIS: [(&str, &str); 6] = [
+    ("🎸", "verify.guitar"),
+    ("🎧", "verify.headphones"),
+    ("🎤", "verify.microphone"),
+    ("🥁", "verify.drum"),
+    ("🎺", "verify.trumpet"),
+    ("🎹", "verify.piano"),
+];
+
+// First-contact check against scripted abuse: with verify_new_chats set, a
+// chat has to tap the right emoji before its messages are handled. Verified
+// chats are kept in memory and saved to storage, when there is one
+pub struct Verification {
+    enabled: bool,
+    verified: RwLock<HashSet<i64>>,
+    // The index of the emoji each chat was last asked to tap
+    pending: Mutex<HashMap<i64, usize>>,
+    storage: Option<Arc<Storage>>,
+}
+
+impl Verification {
+    pub async fn load(enabled: bool, storage: Option<Arc<Storage>>) -> Self {
+        let chats = match (&storage, enabled) {
+            (Some(storage), true) => storage.verified_chats().await.unwrap_or_else(|e| {
+                log::error!("Failed to load verified chats: {}", e);
+                Vec::new()
+            }),
+            _ => Vec::new(),
+        };
+        if enabled {
+            log::info!("New chats are verified, {} already are", chats.len());
+        }
+        Self {
+            enabled,
+            verified: RwLock::new(chats.into_iter().collect()),
+            pending: Mutex::new(HashMap::new()),
+            storage,
+        }
+    }
+
+    // Whether the message should be handled. Messages of chats that aren't
+    // verified yet get a challenge instead
+    pub async fn admit(&self, bot: &Bot, msg: &Message, preferences: &Preferences) -> bool {
+        let chat_id = msg.chat.id.0;
+        if !self.enabled || self.verified.read().await.contains(&chat_id) {
+            return true;
+        }
+
+        let language = language_code(msg, preferences);
+        let (text, keyboard) = self.challenge(chat_id, language).await;
+        log::info!(
+            "Asking chat {} to verify before handling its message",
+            chat_id
+        );
+        if let Err(e) = bot
+            .send_message(msg.chat.id, text)
+            .reply_markup(keyboard)
+            .await
+        {
+            log::error!("Failed to send a challenge to chat {}: {}", chat_id, e);
+        }
+        false
+    }
+
+    // A new challenge for the chat, replacing the one it was asked before
+    async fn challenge(
+        &self,
+        chat_id: i64,
+        language: Option<&str>,
+    ) -> (String, InlineKeyboardMarkup) {
+        let (answer, order) = {
+            let mut rng = rand::thread_rng();
+            let mut order: Vec<usize> = (0..EMOJIS.len()).collect();
+            order.shuffle(&mut rng);
+            (rng.gen_range(0..EMOJIS.len()), order)
+        };
+        self.pending.lock().await.insert(chat_id, answer);
+
+        let text = tr_args(
+            language,
+            "verify.challenge",
+            &[("emoji", &tr(language, EMOJIS[answer].1))],
+        );
+        let buttons = order
+            .into_iter()
+            .map(|index| {
+                InlineKeyboardButton::callback(
+                    EMOJIS[index].0,
+                    format!("{}{}", VERIFY_CALLBACK_PREFIX, index),
+                )
+            })
+            .collect::<Vec<_>>();
+        (text, InlineKeyboardMarkup::new([buttons]))
+    }
+
+    async fn verify(&self, chat_id: i64) {
+        self.pending.lock().await.remove(&chat_id);
+        self.verified.write().await.insert(chat_id);
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.verify_chat(chat_id).await {
+                log::error!("Failed to save verification of chat {}: {}", chat_id, e);
+            }
+        }
+    }
+}
+
+pub fn is_verify_callback(query: &CallbackQuery) -> bool {
+    query
+        .data
+        .as_deref()
+        .is_some_and(|data| data.starts_with(VERIFY_CALLBACK_PREFIX))
+}
+
+// A button of a challenge was pressed. A wrong one, or one of a challenge
+// that was since replaced, turns the message into a new challenge
+#[tracing::instrument(skip_all, fields(user_id = query.from.id.0))]
+pub async fn handle_verify_callback(
+    bot: Bot,
+    query: CallbackQuery,
+    verification: Arc<Verification>,
+    preferences: Arc<Preferences>,
+) -> HandlerResult {
+    let Some(message) = &query.message else {
+        bot.answer_callback_query(query.id.clone()).await?;
+        return Ok(());
+    };
+    let chat_id = message.chat().id;
+    let language = preferences
+        .language(chat_id.0)
+        .or(query.from.language_code.as_deref());
+    let picked: Option<usize> = query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(VERIFY_CALLBACK_PREFIX))
+        .and_then(|index| index.parse().ok());
+    let answer = verification.pending.lock().await.get(&chat_id.0).copied();
+    // Someone else in the group may have been quicker
+    let verified = verification.verified.read().await.contains(&chat_id.0);
+
+    if verified || (picked.is_some() && picked == answer) {
+        if !verified {
+            verification.verify(chat_id.0).await;
+            log::info!("Chat {} passed verification", chat_id);
+        }
+        if let Err(e) = bot
+            .edit_message_text(chat_id, message.id(), tr(language, "verify.done"))
+            .await
+        {
+            log::warn!("Failed to update the challenge: {}", e);
+        }
+        bot.answer_callback_query(query.id.clone()).await?;
+        return Ok(());
+    }
+
+    let (text, keyboard) = verification.challenge(chat_id.0, language).await;
+    if let Err(e) = bot
+        .edit_message_text(chat_id, message.id(), text)
+        .reply_markup(keyboard)
+        .await
+    {
+        log::warn!("Failed to update the challenge: {}", e);
+    }
+    bot.answer_callback_query(query.id.clone())
+        .text(tr(language, "verify.wrong"))
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use crate::{
+    commands::{language_code, HandlerResult},
+    config::Config,
+    oauth::{OAuthApp, OAuthClient},
+    preferences::Preferences,
+    producer::Producer,
+    request_id::RequestId,
+};
+use rustin_models::i18n::{tr, tr_args};
+use rustin_storage::{OAuthProvider, Storage};
+use std::sync::Arc;
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+static APP: OAuthApp = OAuthApp {
+    provider: OAuthProvider::YouTube,
+    name: "YouTube",
+    authorize_url: "https://accounts.google.com/o/oauth2/v2/auth",
+    token_url: "https://oauth2.googleapis.com/token",
+    // Managing the user's playlists needs the full YouTube scope
+    scopes: "https://www.googleapis.com/auth/youtube",
+    // Google only hands out a refresh token for offline access, and only
+    // again once the user is asked to consent again
+    login_params: &[("access_type", "offline"), ("prompt", "consent")],
+    signed_in_key: "youtube.signed_in",
+};
+
+// Lets users sign in to YouTube so the songs they send can be put in a
+// playlist on their channel. The consumer creates the playlist with the
+// tokens saved here
+pub struct YouTubeAccounts {
+    pub oauth: Arc<OAuthClient>,
+}
+
+impl YouTubeAccounts {
+    // Needs google_client_id, google_client_secret and google_redirect_uri,
+    // and the database to keep the tokens in
+    pub fn from_config(config: &Config, storage: Option<Arc<Storage>>) -> Option<Self> {
+        let client_id = config.google_client_id.clone()?;
+        let client_secret = config.google_client_secret.clone()?;
+        let redirect_uri = config.google_redirect_uri.clone()?;
+        let Some(storage) = storage else {
+            log::warn!("YouTube login needs the history database, it's disabled");
+            return None;
+        };
+        Some(Self {
+            oauth: Arc::new(OAuthClient::new(
+                &APP,
+                client_id,
+                Some(client_secret),
+                redirect_uri,
+                storage,
+            )),
+        })
+    }
+}
+
+// /ytplaylist puts the songs in a new playlist on the user's YouTube channel
+// instead of converting them, signing them in first if they haven't.
+// /ytplaylist logout forgets the login
+pub async fn create_playlist(
+    bot: &Bot,
+    msg: &Message,
+    titles: &str,
+    request_id: &RequestId,
+    producer: &Producer,
+    youtube: Option<&YouTubeAccounts>,
+    preferences: &Preferences,
+) -> HandlerResult {
+    let language = language_code(msg, preferences);
+    let Some(youtube) = youtube else {
+        bot.send_message(msg.chat.id, tr(language, "youtube.unavailable"))
+            .await?;
+        return Ok(());
+    };
+    // The playlist goes on the channel of whoever signed in
+    let (true, Some(user)) = (msg.chat.is_private(), &msg.from) else {
+        bot.send_message(msg.chat.id, tr(language, "youtube.private_only"))
+            .await?;
+        return Ok(());
+    };
+    let user_id = user.id.0 as i64;
+
+    match titles.trim() {
+        "" => {
+            bot.send_message(msg.chat.id, tr(language, "youtube.usage"))
+                .await?;
+            return Ok(());
+        }
+        "logout" => {
+            let reply = match youtube.oauth.sign_out(user_id).await {
+                Ok(()) => tr(language, "youtube.signed_out"),
+                Err(e) => {
+                    log::error!("Failed to sign user {} out of YouTube: {}", user_id, e);
+                    tr(language, "error.generic")
+                }
+            };
+            bot.send_message(msg.chat.id, reply).await?;
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Refreshing the token here tells a revoked login apart before anything
+    // is searched for
+    let reply = match youtube.oauth.access_token(user_id).await {
+        Ok(Some(_)) => {
+            let options = preferences.request_options(msg.chat.id.0, language);
+            let published = producer
+                .publish_youtube_playlist_request(request_id, msg.chat.id.0, titles, options)
+                .await;
+            match published {
+                Ok(()) => tr_args(
+                    language,
+                    "youtube.creating",
+                    &[("request_id", request_id.as_str())],
+                ),
+                Err(e) => {
+                    log::error!("Failed to publish YouTube playlist request: {}", e);
+                    tr(language, "error.generic")
+                }
+            }
+        }
+        Ok(None) => match youtube.oauth.login_url(user_id, msg.chat.id.0) {
+            Ok(url) => {
+                let button = InlineKeyboardButton::url(tr(language, "youtube.sign_in"), url);
+                bot.send_message(msg.chat.id, tr(language, "youtube.sign_in_prompt"))
+                    .reply_markup(InlineKeyboardMarkup::new([[button]]))
+                    .await?;
+                return Ok(());
+            }
+            Err(e) => {
+                log::error!("Failed to build the YouTube login link: {}", e);
+                tr(language, "error.generic")
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Failed to read the YouTube login of user {}: {}",
+                user_id,
+                e
+            );
+            tr(language, "error.generic")
+        }
+    };
+    bot.send_message(msg.chat.id, reply).await?;
+    Ok(())
+}
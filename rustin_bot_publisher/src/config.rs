@@ -0,0 +1,43 @@
+use figment::{
+    providers::{Env, Format, Toml},
+    Figment,
+};
+use serde::Deserialize;
+use std::env;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("couldn't load the configuration: {0}")]
+    Load(#[from] Box<figment::Error>),
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+}
+
+// Settings of the webhook publisher, read from the TOML file in CONFIG_FILE
+// (rustin_bot_publisher.toml by default) and overridden by environment
+// variables of the same name in upper case, e.g. SERVER_ADDRESS
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    // Where the Telegram webhook is served
+    pub server_address: String,
+    pub rabbit_address: String,
+}
+
+impl Config {
+    pub fn load() -> Result<Self, ConfigError> {
+        let file =
+            env::var("CONFIG_FILE").unwrap_or_else(|_| "rustin_bot_publisher.toml".to_string());
+        let config: Config = Figment::new()
+            .merge(Toml::file(file))
+            .merge(Env::raw().split("__"))
+            .extract()
+            .map_err(Box::new)?;
+        if config.server_address.trim().is_empty() || config.rabbit_address.trim().is_empty() {
+            return Err(ConfigError::Invalid(
+                "server_address and rabbit_address must not be empty".to_string(),
+            ));
+        }
+        Ok(config)
+    }
+}
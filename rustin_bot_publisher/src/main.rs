@@ -1,24 +1,24 @@
-use std::{env, sync::Arc};
+use std::sync::Arc;
 
 use axum::{
     response::IntoResponse,
     routing::{get, post},
     Extension, Router,
 };
+use config::Config;
 use dotenvy::dotenv;
 use lapin::{Connection, ConnectionProperties};
 use webhook_handler::{receive_message, ChannelPool};
+mod config;
 pub mod webhook_handler;
 
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
     dotenv().expect("Failed to load .env file");
-    let server_address = env::var("SERVER_ADDRESS").expect("SERVER_ADDRESS must be set");
+    let config = Config::load().expect("Invalid configuration");
 
-    let rabbit_addr = env::var("RABBIT_ADDRESS").expect("RABBIT_ADDRESS must be set");
-
-    let connection = Connection::connect(&rabbit_addr, ConnectionProperties::default())
+    let connection = Connection::connect(&config.rabbit_address, ConnectionProperties::default())
         .await
         .expect("Failed to connect to RabbitMQ");
 
@@ -41,7 +41,7 @@ async fn main() {
         .route("/", get(hello))
         .route("/webhook", post(receive_message))
         .layer(Extension(Arc::clone(&channel_pool)));
-    let listener = tokio::net::TcpListener::bind(server_address)
+    let listener = tokio::net::TcpListener::bind(&config.server_address)
         .await
         .expect("Could not bind to address");
 
@@ -1,7 +1,8 @@
 use axum::{debug_handler, http::StatusCode, Extension, Json};
 use lapin::{options::BasicPublishOptions, BasicProperties, Channel};
 use log::info;
-use serde::{Deserialize, Serialize};
+use rustin_models::{markdown, FileMessage, MessageBody, RabbitMessage};
+use serde::Serialize;
 use serde_json::Value;
 use std::{iter::Cycle, sync::Arc, vec::IntoIter};
 use tokio::sync::Mutex;
@@ -24,12 +25,6 @@ impl ChannelPool {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct RabbitMessage {
-    chat_id: i64,
-    text: String,
-}
-
 #[debug_handler]
 pub async fn receive_message(
     Extension(channel_pool): Extension<Arc<ChannelPool>>,
@@ -80,7 +75,7 @@ async fn handle_readimage(
     channel_pool: &Arc<ChannelPool>,
 ) -> Result<(), StatusCode> {
     if let Some(file_id) = extract_largest_image_file_id(payload) {
-        let rabbit_message = RabbitMessage {
+        let rabbit_message = FileMessage {
             chat_id,
             text: file_id.to_string(),
         };
@@ -98,11 +93,12 @@ async fn handle_help_command(
     chat_id: i64,
     channel_pool: &Arc<ChannelPool>,
 ) -> Result<(), StatusCode> {
-    let help_message = RabbitMessage {
+    let help_message = RabbitMessage::new(
         chat_id,
-        text: "Type /songlinks, followed by up to 10 lines of song titles to get download links.\n/readimage with an attached image, to get the text from the image.\n/donate to get a QR code."
-            .to_string(),
-    };
+        MessageBody::Result {
+            text: markdown::escape("Type /songlinks, followed by up to 10 lines of song titles to get download links.\n/readimage with an attached image, to get the text from the image.\n/donate to get a QR code."),
+        },
+    );
     publish_to_queue("Reply", help_message, channel_pool).await?;
     info!("Published 'help' message to Reply queue.");
     Ok(())
@@ -117,10 +113,10 @@ fn extract_largest_image_file_id(payload: &Value) -> Option<&str> {
         .and_then(|photo| photo["file_id"].as_str())
 }
 
-// Publish a RabbitMessage to the specified RabbitMQ queue
-async fn publish_to_queue(
+// Publish a message to the specified RabbitMQ queue
+async fn publish_to_queue<T: Serialize>(
     queue_name: &str,
-    message: RabbitMessage,
+    message: T,
     channel_pool: &Arc<ChannelPool>,
 ) -> Result<(), StatusCode> {
     let serialized_message = serde_json::to_vec(&message).expect("Failed to serialize message");
@@ -150,10 +146,13 @@ async fn handle_songlinks(
         .map(|line| line.chars().take(50).collect()) // Truncate each line to 50 characters
         .collect();
 
-    let song_message = RabbitMessage {
+    let song_message = RabbitMessage::new(
         chat_id,
-        text: truncated_songs.join("\n"), // Join all truncated lines with newlines
-    };
+        MessageBody::TextRequest {
+            text: truncated_songs.join("\n"), // Join all truncated lines with newlines
+            clips: Default::default(),
+        },
+    );
 
     publish_to_queue("Music", song_message, channel_pool).await?;
     info!("Published 'songlinks' message to Music queue.");
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+// What the consumer publishes on the Analytics exchange, for dashboards of
+// how the bot is used. Events carry no chat or request IDs and no titles
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AnalyticsMessage {
+    // Unix time the event happened at
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: AnalyticsEvent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AnalyticsEvent {
+    // A request was taken off the queue, e.g. of kind "text" or "voice"
+    RequestReceived {
+        kind: String,
+    },
+    // A stage of a request took this long
+    StageFinished {
+        stage: Stage,
+        seconds: f64,
+    },
+    // The songs of a request were converted and sent
+    RequestFinished {
+        songs: usize,
+        converted: usize,
+        failed: usize,
+        cancelled: usize,
+    },
+    // A provider fetched a song or video
+    SongConverted {
+        provider: String,
+        seconds: f64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    // Turning the request into songs, e.g. expanding playlist links
+    Expanding,
+    Converting,
+    // Publishing the replies of the converted songs
+    Delivering,
+}
@@ -0,0 +1,21 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+pub struct AuddResponse {
+    pub status: String,
+    // Null when the recording didn't match any song
+    pub result: Option<AuddSong>,
+    pub error: Option<AuddError>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuddSong {
+    pub artist: String,
+    pub title: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuddError {
+    pub error_code: i64,
+    pub error_message: String,
+}
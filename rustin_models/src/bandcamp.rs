@@ -0,0 +1,21 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// The data-tralbum attribute of a Bandcamp track or album page, which the
+// embedded player reads its tracks from
+#[derive(Deserialize)]
+pub struct BandcampTralbum {
+    pub artist: String,
+    #[serde(default)]
+    pub trackinfo: Vec<BandcampTrack>,
+}
+
+#[derive(Deserialize)]
+pub struct BandcampTrack {
+    pub title: String,
+    // Page of the track relative to the artist's site, "/track/<slug>"
+    pub title_link: Option<String>,
+    // Stream URLs by format, such as "mp3-128". Missing for tracks that
+    // can't be streamed
+    pub file: Option<HashMap<String, String>>,
+}
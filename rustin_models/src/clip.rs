@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Part of a song to send instead of all of it, in seconds from the start
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Clip {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Clip {
+    // Shown to the user the way they'd type it, e.g. "0:45-2:30"
+    pub fn label(&self) -> String {
+        format!("{}-{}", format_time(self.start), format_time(self.end))
+    }
+}
+
+// A line whose time range is there but can't be used, e.g. "[2:30-0:45]"
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidClip {
+    pub line: String,
+}
+
+// Split the trailing time ranges such as "[0:45-2:30]" off the lines of a
+// request. The text comes back without them, with the clips keyed by the
+// index of their line. Brackets that don't hold a time range, such as
+// "[Official Video]", are left in the line
+pub fn split_clips(text: &str) -> Result<(String, BTreeMap<usize, Clip>), InvalidClip> {
+    let mut lines = Vec::new();
+    let mut clips = BTreeMap::new();
+    for (index, line) in text.lines().enumerate() {
+        let (rest, clip) = split_clip_suffix(line).map_err(|_| InvalidClip {
+            line: line.trim().to_string(),
+        })?;
+        if let Some(clip) = clip {
+            clips.insert(index, clip);
+        }
+        lines.push(rest);
+    }
+    Ok((lines.join("\n"), clips))
+}
+
+fn split_clip_suffix(line: &str) -> Result<(&str, Option<Clip>), ()> {
+    let trimmed = line.trim_end();
+    let Some((rest, range)) = trimmed
+        .strip_suffix(']')
+        .and_then(|without| without.rsplit_once('['))
+    else {
+        return Ok((line, None));
+    };
+    // Years such as "[2010-2011]" aren't taken for a range of seconds
+    let is_range = range.contains(['-', '–'])
+        && range.contains(':')
+        && range
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, ':' | '-' | '–' | ' '));
+    if !is_range {
+        return Ok((line, None));
+    }
+
+    let (start, end) = range.split_once(['-', '–']).ok_or(())?;
+    let clip = Clip {
+        start: parse_time(start).ok_or(())?,
+        end: parse_time(end).ok_or(())?,
+    };
+    if clip.end <= clip.start {
+        return Err(());
+    }
+    Ok((rest.trim_end(), Some(clip)))
+}
+
+// "45", "2:30" or "1:02:30", in seconds
+fn parse_time(time: &str) -> Option<u32> {
+    let parts: Vec<&str> = time.trim().split(':').collect();
+    if parts.len() > 3 || parts.iter().any(|part| part.is_empty()) {
+        return None;
+    }
+    let mut seconds = 0u32;
+    for (index, part) in parts.iter().enumerate() {
+        let value: u32 = part.parse().ok()?;
+        // Only the leading part may go past 59
+        if index > 0 && (value >= 60 || part.len() != 2) {
+            return None;
+        }
+        seconds = seconds.checked_mul(60)?.checked_add(value)?;
+    }
+    Some(seconds)
+}
+
+fn format_time(seconds: u32) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match hours {
+        0 => format!("{}:{:02}", minutes, seconds),
+        _ => format!("{}:{:02}:{:02}", hours, minutes, seconds),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_time_ranges_off_their_lines() {
+        let (text, clips) =
+            split_clips("Daft Punk - Around the World [0:45-2:30]\nJustice - D.A.N.C.E.").unwrap();
+        assert_eq!(text, "Daft Punk - Around the World\nJustice - D.A.N.C.E.");
+        assert_eq!(clips.len(), 1);
+        assert_eq!(
+            clips[&0],
+            Clip {
+                start: 45,
+                end: 150
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_brackets_that_are_not_time_ranges() {
+        for line in [
+            "Daft Punk - Around the World [Official Video]",
+            "Best of [2010-2011]",
+        ] {
+            assert_eq!(
+                split_clips(line).unwrap(),
+                (line.to_string(), BTreeMap::new())
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_ranges_that_cannot_be_used() {
+        assert!(split_clips("Song [2:30-0:45]").is_err());
+        assert!(split_clips("Song [0:75-2:00]").is_err());
+        assert!(split_clips("Song [1:00-]").is_err());
+    }
+
+    #[test]
+    fn parses_and_labels_times() {
+        assert_eq!(parse_time("45"), Some(45));
+        assert_eq!(parse_time("2:30"), Some(150));
+        assert_eq!(parse_time("1:02:30"), Some(3750));
+        assert_eq!(parse_time("2:3"), None);
+        assert_eq!(
+            Clip {
+                start: 45,
+                end: 3750
+            }
+            .label(),
+            "0:45-1:02:30"
+        );
+    }
+}
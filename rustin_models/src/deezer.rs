@@ -0,0 +1,13 @@
+use serde::Deserialize;
+
+// Response of api.deezer.com/track/<id>, which needs no API key
+#[derive(Deserialize)]
+pub struct DeezerTrack {
+    pub title: String,
+    pub artist: DeezerArtist,
+}
+
+#[derive(Deserialize)]
+pub struct DeezerArtist {
+    pub name: String,
+}
@@ -0,0 +1,40 @@
+// Characters that aren't allowed in file names on at least one platform
+const INVALID_NAME_CHARS: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+// "Performer - Title.extension", the name a song is sent under. The
+// consumer's archives and playlists name the songs the same way, so the
+// saved files match the entries of a playlist
+pub fn song(title: &str, performer: Option<&str>, extension: &str) -> String {
+    let name = match performer {
+        Some(performer) => format!("{} - {}", performer, title),
+        None => title.to_string(),
+    };
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || INVALID_NAME_CHARS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!("{}.{}", name.trim(), extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_songs_after_the_performer_and_title() {
+        assert_eq!(
+            song("Around the World", Some("Daft Punk"), "mp3"),
+            "Daft Punk - Around the World.mp3"
+        );
+        assert_eq!(
+            song("AC/DC: Thunderstruck? ", None, "lrc"),
+            "AC_DC_ Thunderstruck_.lrc"
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+// Catalogs of user-facing messages by language, English first as it is
+// the fallback for anything the others don't translate
+const CATALOG_SOURCES: [(&str, &str); 2] = [
+    ("en", include_str!("../locales/en.yaml")),
+    ("ro", include_str!("../locales/ro.yaml")),
+];
+const FALLBACK: &str = "en";
+
+static CATALOGS: LazyLock<HashMap<&'static str, HashMap<String, String>>> = LazyLock::new(|| {
+    CATALOG_SOURCES
+        .iter()
+        .map(|(language, source)| {
+            let catalog = serde_yaml::from_str(source)
+                .unwrap_or_else(|e| panic!("Invalid {} message catalog: {}", language, e));
+            (*language, catalog)
+        })
+        .collect()
+});
+
+// Languages there is a catalog for, English first
+pub fn languages() -> impl Iterator<Item = &'static str> {
+    CATALOG_SOURCES.iter().map(|(language, _)| *language)
+}
+
+// The catalog to use for a Telegram language_code such as "ro" or "pt-br"
+pub fn language(language_code: Option<&str>) -> &'static str {
+    let Some(code) = language_code else {
+        return FALLBACK;
+    };
+    let primary = code.split(['-', '_']).next().unwrap_or(code);
+    languages()
+        .find(|language| language.eq_ignore_ascii_case(primary))
+        .unwrap_or(FALLBACK)
+}
+
+// The message for the key in the user's language. Unknown keys come back as
+// they are, so a typo shows up in the reply instead of an empty message
+pub fn tr(language_code: Option<&str>, key: &str) -> String {
+    [language(language_code), FALLBACK]
+        .iter()
+        .find_map(|language| CATALOGS.get(language)?.get(key))
+        .cloned()
+        .unwrap_or_else(|| key.to_string())
+}
+
+// Like tr, with every {name} placeholder replaced by its argument
+pub fn tr_args(language_code: Option<&str>, key: &str, args: &[(&str, &str)]) -> String {
+    args.iter()
+        .fold(tr(language_code, key), |message, (name, value)| {
+            message.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_catalog_of_the_primary_language() {
+        assert_eq!(language(Some("ro")), "ro");
+        assert_eq!(language(Some("RO-md")), "ro");
+        assert_eq!(language(Some("pt-br")), "en");
+        assert_eq!(language(None), "en");
+    }
+
+    #[test]
+    fn falls_back_to_english() {
+        assert_eq!(
+            tr(Some("de"), "bot.search_usage"),
+            "Type /search followed by a song title."
+        );
+        assert_eq!(tr(Some("ro"), "no.such.key"), "no.such.key");
+    }
+
+    #[test]
+    fn fills_in_placeholders() {
+        assert_eq!(
+            tr_args(
+                None,
+                "progress.converted",
+                &[("completed", "3"), ("total", "10")]
+            ),
+            "Converted 3/10 songs…"
+        );
+    }
+
+    #[test]
+    fn every_catalog_translates_only_known_keys() {
+        let english = &CATALOGS[FALLBACK];
+        for (language, catalog) in CATALOGS.iter() {
+            for key in catalog.keys() {
+                assert!(
+                    english.contains_key(key),
+                    "{} has unknown key {}",
+                    language,
+                    key
+                );
+            }
+        }
+    }
+}
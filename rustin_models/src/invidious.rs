@@ -0,0 +1,21 @@
+use serde::Deserialize;
+
+// One entry of the /api/v1/search response of an Invidious instance
+#[derive(Deserialize)]
+pub struct InvidiousResult {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(rename = "videoId", default)]
+    pub video_id: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(rename = "lengthSeconds")]
+    pub length_seconds: Option<u64>,
+    #[serde(rename = "liveNow", default)]
+    pub live_now: bool,
+    // Premieres that haven't started yet
+    #[serde(rename = "isUpcoming", default)]
+    pub is_upcoming: bool,
+}
@@ -0,0 +1,16 @@
+use serde::Deserialize;
+
+// Response of itunes.apple.com/lookup, which also finds Apple Music songs
+#[derive(Deserialize)]
+pub struct ItunesLookup {
+    pub results: Vec<ItunesResult>,
+}
+
+// Looking up a song can also bring back its album, which has no track name
+#[derive(Deserialize)]
+pub struct ItunesResult {
+    #[serde(rename = "artistName")]
+    pub artist_name: Option<String>,
+    #[serde(rename = "trackName")]
+    pub track_name: Option<String>,
+}
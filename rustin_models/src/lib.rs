@@ -0,0 +1,31 @@
+//! Message and API types shared by the RustinBot services, so the producer
+//! and consumer sides of every queue serialize the same shapes.
+
+pub mod analytics;
+pub mod audd;
+pub mod bandcamp;
+pub mod clip;
+pub mod deezer;
+pub mod file_name;
+pub mod i18n;
+pub mod invidious;
+pub mod itunes;
+pub mod lrclib;
+pub mod markdown;
+pub mod oauth;
+pub mod odesli;
+pub mod piped;
+pub mod request_text;
+pub mod schedule;
+pub mod soundcloud;
+pub mod spotify;
+pub mod tomp3;
+pub mod vision;
+pub mod youtube;
+
+mod message;
+
+pub use message::{
+    FileMessage, InlineAudio, InlineAudioSource, MediaFormat, MessageBody, RabbitMessage,
+    RequestOptions, SearchCandidate, PICK_CALLBACK_PREFIX, REQUEST_ID_HEADER, SCHEMA_VERSION,
+};
@@ -0,0 +1,18 @@
+use serde::Deserialize;
+
+// One entry of the /api/search response of LRCLIB
+#[derive(Deserialize)]
+pub struct LrclibTrack {
+    #[serde(rename = "trackName")]
+    pub track_name: String,
+    #[serde(rename = "artistName")]
+    pub artist_name: String,
+    // Null for instrumentals and tracks nobody has added lyrics to yet
+    #[serde(rename = "plainLyrics")]
+    pub plain_lyrics: Option<String>,
+    // The same lyrics in LRC format, each line with its timestamp
+    #[serde(rename = "syncedLyrics")]
+    pub synced_lyrics: Option<String>,
+    #[serde(default)]
+    pub instrumental: bool,
+}
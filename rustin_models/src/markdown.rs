@@ -0,0 +1,46 @@
+// Characters Telegram's MarkdownV2 treats as markup anywhere in a message
+const SPECIAL_CHARACTERS: &str = "_*[]()~`>#+-=|{}.!\\";
+
+// Escape user-provided text (song titles, links, OCR output) so it shows up
+// literally in a MarkdownV2 message
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if SPECIAL_CHARACTERS.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_only_special_characters() {
+        assert_eq!(
+            escape("Daft Punk - Around the World"),
+            "Daft Punk \\- Around the World"
+        );
+        assert_eq!(escape("Bohemian Rhapsody"), "Bohemian Rhapsody");
+    }
+
+    #[test]
+    fn escapes_markup_characters() {
+        assert_eq!(
+            escape("*NSYNC_(live) [HD]"),
+            "\\*NSYNC\\_\\(live\\) \\[HD\\]"
+        );
+        assert_eq!(escape("a\\b"), "a\\\\b");
+    }
+
+    #[test]
+    fn escapes_links() {
+        assert_eq!(
+            escape("https://example.com/a_b?x=1"),
+            "https://example\\.com/a\\_b?x\\=1"
+        );
+    }
+}
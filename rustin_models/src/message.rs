@@ -0,0 +1,347 @@
+use crate::clip::Clip;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Bumped whenever the shape of RabbitMessage changes incompatibly
+pub const SCHEMA_VERSION: u32 = 1;
+
+// AMQP header carrying the ID that correlates the logs of one user request
+// across the bot and the workers
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Prefix of the callback data of search result buttons, followed by the video ID
+pub const PICK_CALLBACK_PREFIX: &str = "pick:";
+
+// Envelope for everything published on the Music and Reply queues
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RabbitMessage {
+    pub version: u32,
+    pub chat_id: i64,
+    pub body: MessageBody,
+    #[serde(default)]
+    pub options: RequestOptions,
+}
+
+// Per-request preferences, only meaningful on request messages
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RequestOptions {
+    // Preferred MP3 bitrate in kbps, the consumer default is used when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u32>,
+    // Send every song as soon as it's converted instead of all of them at
+    // the end
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+    // Bundle the MP3s of a large request into ZIP archives instead of
+    // sending them one by one
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub zip: bool,
+    // Even out the loudness of downloaded songs
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub normalize: bool,
+    // Send download links even when the consumer could upload the songs
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub links: bool,
+    // Telegram language_code of the user, picks the language of the replies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    // Send the synced lyrics of downloaded songs as .lrc files
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lyrics: bool,
+    // Add the song.link page of each song, to open it on other services
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub song_link: bool,
+    // Send an M3U playlist of the songs after a multi-song request
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub playlist: bool,
+    // Unix time to hold the results until, set by /later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deliver_at: Option<u64>,
+    // What the songs are delivered as, MP3 unless /video was used
+    #[serde(default, skip_serializing_if = "MediaFormat::is_mp3")]
+    pub format: MediaFormat,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaFormat {
+    #[default]
+    Mp3,
+    // The video itself, at a resolution that usually fits Telegram's limit
+    Mp4,
+}
+
+impl MediaFormat {
+    pub fn is_mp3(&self) -> bool {
+        *self == MediaFormat::Mp3
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum MessageBody {
+    // Song titles, one per line
+    TextRequest {
+        text: String,
+        // Only part of the song on these lines is wanted, by line index
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        clips: BTreeMap<usize, Clip>,
+    },
+    // Download URL of a tracklist screenshot to run through OCR
+    PhotoRequest {
+        photo_url: String,
+    },
+    // Download URL of an uploaded .txt, .csv or .m3u tracklist, whose name
+    // tells how to read it
+    DocumentRequest {
+        document_url: String,
+        file_name: String,
+    },
+    // Download URL of a voice message with a song to recognize
+    VoiceRequest {
+        voice_url: String,
+    },
+    // Download URL of a forwarded audio file or video note to recognize
+    MediaRequest {
+        media_url: String,
+    },
+    // Link to a playlist whose tracks should all be converted
+    PlaylistRequest {
+        url: String,
+    },
+    // /search query whose top results the user picks from
+    SearchRequest {
+        query: String,
+    },
+    // /lyrics query, answered with the lyrics of the best match
+    LyricsRequest {
+        query: String,
+    },
+    // /ytplaylist titles, added to a new playlist on the user's YouTube
+    // channel instead of being converted
+    YouTubePlaylistRequest {
+        text: String,
+    },
+    // Video the user picked from the search results
+    PickedVideo {
+        video_id: String,
+    },
+    // Inline query (@bot song name) that has to be answered within seconds,
+    // chat_id is the ID of the user who sent it
+    InlineQuery {
+        inline_query_id: String,
+        query: String,
+    },
+    // Stop the request with this ID, published on the Control exchange
+    CancelRequest {
+        request_id: String,
+    },
+    // Something went wrong, the message is meant for the user
+    Error {
+        message: String,
+    },
+    // Conversion results for the user, formatted as Telegram MarkdownV2
+    Result {
+        text: String,
+    },
+    // Interim update while a large request is still converting
+    Progress {
+        completed: u32,
+        total: u32,
+    },
+    // Downloaded MP3 in the shared media directory, to be sent as audio
+    Audio {
+        file_path: String,
+        title: String,
+        performer: Option<String>,
+        // JPEG next to the MP3 to show as the audio thumbnail
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        thumbnail_path: Option<String>,
+        // Key under which the Telegram file_id should be reported back
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_key: Option<String>,
+        // Plain text shown under the audio
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        caption: Option<String>,
+        // Synced lyrics in the shared media directory, sent after the audio
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        lyrics_path: Option<String>,
+    },
+    // Downloaded MP4 in the shared media directory, to be sent as a video
+    Video {
+        file_path: String,
+        title: String,
+    },
+    // ZIP of downloaded MP3s or M3U playlist in the shared media directory,
+    // to be sent as a document under file_name
+    Archive {
+        file_path: String,
+        file_name: String,
+    },
+    // Audio already uploaded to Telegram, resent by its file_id
+    CachedAudio {
+        file_id: String,
+        title: String,
+        performer: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        caption: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        lyrics_path: Option<String>,
+    },
+    // Search results to offer as buttons, answered with PickedVideo
+    SearchResults {
+        query: String,
+        candidates: Vec<SearchCandidate>,
+    },
+    // Results for an inline query, answered by the reply service
+    InlineAnswer {
+        inline_query_id: String,
+        results: Vec<InlineAudio>,
+    },
+    // Published by the reply service after an upload so the file_id can be reused
+    AudioUploaded {
+        cache_key: String,
+        file_id: String,
+    },
+}
+
+impl RabbitMessage {
+    pub fn new(chat_id: i64, body: MessageBody) -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            chat_id,
+            body,
+            options: RequestOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: RequestOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl MessageBody {
+    // Whether the request converts at most one song, so it can skip past
+    // playlists in the fast lane. Errs on the side of the slow lane when a
+    // text could expand into several songs
+    pub fn is_single_song(&self) -> bool {
+        match self {
+            MessageBody::TextRequest { text, .. } => {
+                let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+                match (lines.next(), lines.next()) {
+                    (Some(line), None) => !is_collection_link(line),
+                    _ => false,
+                }
+            }
+            MessageBody::VoiceRequest { .. }
+            | MessageBody::MediaRequest { .. }
+            | MessageBody::SearchRequest { .. }
+            | MessageBody::LyricsRequest { .. }
+            | MessageBody::PickedVideo { .. }
+            | MessageBody::InlineQuery { .. } => true,
+            _ => false,
+        }
+    }
+
+    // What kind of request a message is, or None for messages that aren't
+    // kept in the history (replies, and inline queries which are too frequent)
+    pub fn request_kind(&self) -> Option<&'static str> {
+        match self {
+            MessageBody::TextRequest { .. } => Some("text"),
+            MessageBody::PhotoRequest { .. } => Some("photo"),
+            MessageBody::DocumentRequest { .. } => Some("document"),
+            MessageBody::VoiceRequest { .. } => Some("voice"),
+            MessageBody::MediaRequest { .. } => Some("media"),
+            MessageBody::PlaylistRequest { .. } => Some("playlist"),
+            MessageBody::SearchRequest { .. } => Some("search"),
+            MessageBody::LyricsRequest { .. } => Some("lyrics"),
+            MessageBody::YouTubePlaylistRequest { .. } => Some("youtube_playlist"),
+            MessageBody::PickedVideo { .. } => Some("pick"),
+            _ => None,
+        }
+    }
+}
+
+// YouTube playlists, Spotify albums or playlists and Bandcamp albums
+fn is_collection_link(line: &str) -> bool {
+    line.contains("/playlist?")
+        || line.contains("open.spotify.com/album/")
+        || line.contains("open.spotify.com/playlist/")
+        || line.contains(".bandcamp.com/album/")
+}
+
+// A single audio result offered in the inline query menu
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InlineAudio {
+    // Unique among the results of one answer
+    pub id: String,
+    pub title: String,
+    pub performer: Option<String>,
+    pub source: InlineAudioSource,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum InlineAudioSource {
+    // MP3 link Telegram downloads itself
+    Url { url: String },
+    // Audio uploaded to Telegram before
+    Cached { file_id: String },
+}
+
+// A YouTube video offered in the search result picker
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SearchCandidate {
+    pub video_id: String,
+    pub title: String,
+    pub channel: String,
+    // Formatted for display, e.g. 4:13
+    pub duration: Option<String>,
+}
+
+// Message format of the ImageToText queue
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileMessage {
+    pub chat_id: i64,
+    pub text: String, // This will store the Telegram file_id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> MessageBody {
+        MessageBody::TextRequest {
+            text: text.to_string(),
+            clips: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn one_title_is_a_single_song() {
+        assert!(text("Daft Punk - Around the World\n").is_single_song());
+        assert!(text("https://youtu.be/dQw4w9WgXcQ").is_single_song());
+    }
+
+    #[test]
+    fn several_titles_are_not_a_single_song() {
+        assert!(!text("Daft Punk - Around the World\nJustice - D.A.N.C.E.").is_single_song());
+        assert!(!text("").is_single_song());
+    }
+
+    #[test]
+    fn collection_links_are_not_a_single_song() {
+        assert!(!text("https://www.youtube.com/playlist?list=PL590L5WQmH8f").is_single_song());
+        assert!(!text("https://open.spotify.com/album/4m2880jivSbbyEGAKfITCa").is_single_song());
+        assert!(!text("https://c418.bandcamp.com/album/minecraft-volume-alpha").is_single_song());
+    }
+
+    #[test]
+    fn photos_are_not_a_single_song() {
+        let photo = MessageBody::PhotoRequest {
+            photo_url: "https://example.com/tracklist.jpg".to_string(),
+        };
+        assert!(!photo.is_single_song());
+    }
+}
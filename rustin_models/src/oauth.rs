@@ -0,0 +1,11 @@
+use serde::Deserialize;
+
+// What the token endpoint of Spotify and Google answer with
+#[derive(Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub expires_in: u64,
+    // Only given to user logins, the client credentials flow has none
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
@@ -0,0 +1,30 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// Response of api.song.link/v1-alpha.1/links, the API behind song.link
+#[derive(Deserialize)]
+pub struct OdesliLinks {
+    // Key of the song the link is for in entities_by_unique_id
+    // The song.link page itself, e.g. https://song.link/y/<video ID>
+    #[serde(rename = "pageUrl")]
+    pub page_url: String,
+    #[serde(rename = "entityUniqueId")]
+    pub entity_unique_id: String,
+    #[serde(rename = "entitiesByUniqueId")]
+    pub entities_by_unique_id: HashMap<String, OdesliEntity>,
+    // The same song on other platforms, keyed by names such as "youtube"
+    #[serde(rename = "linksByPlatform", default)]
+    pub links_by_platform: HashMap<String, OdesliPlatformLink>,
+}
+
+#[derive(Deserialize)]
+pub struct OdesliEntity {
+    pub title: Option<String>,
+    #[serde(rename = "artistName")]
+    pub artist_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct OdesliPlatformLink {
+    pub url: String,
+}
@@ -0,0 +1,21 @@
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct PipedSearchResponse {
+    pub items: Vec<PipedItem>,
+}
+
+// Videos are "stream" items whose url is /watch?v=<video ID>
+#[derive(Deserialize)]
+pub struct PipedItem {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(rename = "uploaderName", default)]
+    pub uploader_name: String,
+    // In seconds, -1 for live streams
+    pub duration: Option<i64>,
+}
@@ -0,0 +1,172 @@
+use crate::i18n::tr_args;
+
+// Limits on typed requests. The bot checks them before publishing, the
+// consumer again for messages that reach the queue without going through it
+pub const MAX_LINES: usize = 100;
+pub const MAX_LINE_CHARS: usize = 300;
+
+// Sites the consumer can do something with a link to, subdomains included
+const SUPPORTED_HOSTS: [&str; 9] = [
+    "youtube.com",
+    "youtu.be",
+    "soundcloud.com",
+    "bandcamp.com",
+    "spotify.com",
+    "apple.com",
+    "deezer.com",
+    "song.link",
+    "odesli.co",
+];
+
+// Why a request can't be sent on, with lines counted from 1 as the user
+// sees them
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidText {
+    TooManyLines { lines: usize },
+    LineTooLong { line: usize },
+    ControlCharacter { line: usize },
+}
+
+impl InvalidText {
+    // What the user has to change, in their language
+    pub fn message(&self, language_code: Option<&str>) -> String {
+        let max_lines = MAX_LINES.to_string();
+        let max_chars = MAX_LINE_CHARS.to_string();
+        match self {
+            InvalidText::TooManyLines { lines } => tr_args(
+                language_code,
+                "request.too_many_lines",
+                &[("lines", &lines.to_string()), ("max", &max_lines)],
+            ),
+            InvalidText::LineTooLong { line } => tr_args(
+                language_code,
+                "request.line_too_long",
+                &[("line", &line.to_string()), ("max", &max_chars)],
+            ),
+            InvalidText::ControlCharacter { line } => tr_args(
+                language_code,
+                "request.control_character",
+                &[("line", &line.to_string())],
+            ),
+        }
+    }
+}
+
+// Check a request against the limits and take out links to sites that
+// aren't supported. Lines keep their positions, so clips keyed by line still
+// match, and a line that was nothing but such a link comes back empty
+pub fn validate(text: &str) -> Result<String, InvalidText> {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() > MAX_LINES {
+        return Err(InvalidText::TooManyLines { lines: lines.len() });
+    }
+    for (index, line) in lines.iter().enumerate() {
+        if line.chars().count() > MAX_LINE_CHARS {
+            return Err(InvalidText::LineTooLong { line: index + 1 });
+        }
+        if line.chars().any(|c| c.is_control() && c != '\t') {
+            return Err(InvalidText::ControlCharacter { line: index + 1 });
+        }
+    }
+    let sanitized: Vec<String> = lines.into_iter().map(strip_unsupported_links).collect();
+    Ok(sanitized.join("\n"))
+}
+
+fn strip_unsupported_links(line: &str) -> String {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let kept: Vec<&str> = words
+        .iter()
+        .copied()
+        .filter(|word| link_host(word).is_none_or(is_supported))
+        .collect();
+    // Lines without such links are left exactly as they were typed
+    match kept.len() == words.len() {
+        true => line.to_string(),
+        false => kept.join(" "),
+    }
+}
+
+// The host of a word that is a web link, lowercased and without any port
+fn link_host(word: &str) -> Option<String> {
+    let lower = word.to_lowercase();
+    let rest = lower
+        .strip_prefix("https://")
+        .or_else(|| lower.strip_prefix("http://"))
+        .or_else(|| lower.starts_with("www.").then_some(lower.as_str()))?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    let host = authority.rsplit('@').next().unwrap_or(authority);
+    let host = host.split(':').next().unwrap_or(host);
+    Some(host.to_string())
+}
+
+fn is_supported(host: String) -> bool {
+    SUPPORTED_HOSTS.iter().any(|supported| {
+        host == *supported
+            || host
+                .strip_suffix(supported)
+                .is_some_and(|subdomain| subdomain.ends_with('.'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_titles_and_supported_links() {
+        let text = "Daft Punk - Around the World [0:45-2:30]\n\
+                    https://youtu.be/dQw4w9WgXcQ\n\
+                    https://music.youtube.com/watch?v=dQw4w9WgXcQ\n\
+                    https://artist.bandcamp.com/track/song\n\
+                    Justice  -  D.A.N.C.E.";
+        assert_eq!(validate(text).unwrap(), text);
+    }
+
+    #[test]
+    fn strips_links_to_other_sites_in_place() {
+        let text = "Daft Punk - One More Time https://example.com/free-mp3\n\
+                    www.tracker.net/download\n\
+                    HTTPS://notyoutube.com/watch?v=x\n\
+                    Justice - Genesis";
+        assert_eq!(
+            validate(text).unwrap(),
+            "Daft Punk - One More Time\n\n\nJustice - Genesis"
+        );
+    }
+
+    #[test]
+    fn rejects_requests_over_the_limits() {
+        let many = vec!["Song"; MAX_LINES + 1].join("\n");
+        assert_eq!(
+            validate(&many),
+            Err(InvalidText::TooManyLines {
+                lines: MAX_LINES + 1
+            })
+        );
+        assert!(validate(&vec!["Song"; MAX_LINES].join("\n")).is_ok());
+
+        let long = format!("Song\n{}", "a".repeat(MAX_LINE_CHARS + 1));
+        assert_eq!(validate(&long), Err(InvalidText::LineTooLong { line: 2 }));
+        assert!(validate(&"ă".repeat(MAX_LINE_CHARS)).is_ok());
+    }
+
+    #[test]
+    fn rejects_control_characters() {
+        assert_eq!(
+            validate("Song\nDaft\u{0}Punk"),
+            Err(InvalidText::ControlCharacter { line: 2 })
+        );
+        assert_eq!(
+            validate("Daft Punk\u{1b}[31m"),
+            Err(InvalidText::ControlCharacter { line: 1 })
+        );
+        assert!(validate("Daft Punk\t- One More Time\r\nJustice").is_ok());
+    }
+
+    #[test]
+    fn explains_what_to_change() {
+        let message = InvalidText::LineTooLong { line: 3 }.message(None);
+        assert!(message.contains('3'), "{}", message);
+        assert!(message.contains(&MAX_LINE_CHARS.to_string()), "{}", message);
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+// Downloaded songs wait in the media directory until they're delivered, so
+// they can't be held for long
+pub const MAX_DELAY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// Parse a delay such as "45m", "8h" or "1d12h" for /later. Anything else,
+// or a delay that's zero or longer than MAX_DELAY, is None
+pub fn parse_delay(text: &str) -> Option<Duration> {
+    let text = text.trim().to_lowercase();
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return None,
+        };
+        let count: u64 = number.parse().ok()?;
+        total = total.checked_add(count.checked_mul(unit)?)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return None;
+    }
+
+    let delay = Duration::from_secs(total);
+    (!delay.is_zero() && delay <= MAX_DELAY).then_some(delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_delays() {
+        assert_eq!(parse_delay("45m"), Some(Duration::from_secs(45 * 60)));
+        assert_eq!(parse_delay("8H"), Some(Duration::from_secs(8 * 60 * 60)));
+        assert_eq!(
+            parse_delay("1d12h"),
+            Some(Duration::from_secs(36 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_delays() {
+        assert_eq!(parse_delay(""), None);
+        assert_eq!(parse_delay("8"), None);
+        assert_eq!(parse_delay("h"), None);
+        assert_eq!(parse_delay("0m"), None);
+        assert_eq!(parse_delay("8 hours"), None);
+        assert_eq!(parse_delay("8d"), None);
+        assert_eq!(parse_delay("99999999999999999999d"), None);
+    }
+}
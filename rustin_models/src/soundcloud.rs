@@ -0,0 +1,9 @@
+use serde::Deserialize;
+
+// Response of soundcloud.com/oembed, which describes a track without an API key
+#[derive(Deserialize)]
+pub struct SoundCloudOEmbed {
+    // "<track> by <artist>"
+    pub title: String,
+    pub author_name: String,
+}
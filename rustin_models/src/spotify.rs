@@ -0,0 +1,51 @@
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct Artist {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct Track {
+    pub name: String,
+    pub artists: Vec<Artist>,
+}
+
+// Paged list of album tracks
+#[derive(Deserialize)]
+pub struct AlbumTracksPage {
+    pub items: Vec<Track>,
+    pub next: Option<String>,
+}
+
+// Paged list of playlist entries, the track is missing for removed songs
+#[derive(Deserialize)]
+pub struct PlaylistTracksPage {
+    pub items: Vec<PlaylistItem>,
+    pub next: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistItem {
+    pub track: Option<Track>,
+}
+
+// Paged list of the playlists a user made or follows
+#[derive(Deserialize)]
+pub struct PlaylistsPage {
+    pub items: Vec<Playlist>,
+    pub total: u32,
+}
+
+#[derive(Deserialize)]
+pub struct Playlist {
+    pub id: String,
+    pub name: String,
+    pub tracks: PlaylistTracksRef,
+}
+
+// How many tracks a playlist has, without the tracks themselves
+#[derive(Deserialize)]
+pub struct PlaylistTracksRef {
+    pub total: u32,
+}
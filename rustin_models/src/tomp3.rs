@@ -0,0 +1,30 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+pub struct Tomp3Response {
+    pub links: Option<Links>,
+}
+
+#[derive(Deserialize)]
+pub struct Links {
+    pub mp3: Option<HashMap<String, Mp3Link>>,
+    pub mp4: Option<HashMap<String, Mp4Link>>,
+}
+
+#[derive(Deserialize)]
+pub struct Mp3Link {
+    pub k: String,
+}
+
+// q is the resolution, e.g. "720p"
+#[derive(Deserialize)]
+pub struct Mp4Link {
+    pub k: String,
+    pub q: String,
+}
+
+#[derive(Deserialize)]
+pub struct ConvertResponse {
+    pub dlink: String,
+}
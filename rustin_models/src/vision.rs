@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+pub struct VisionRequest {
+    pub requests: Vec<VisionRequestItem>,
+}
+
+#[derive(Serialize)]
+pub struct VisionRequestItem {
+    pub image: ImageContent,
+    pub features: Vec<Feature>,
+}
+
+#[derive(Serialize)]
+pub struct ImageContent {
+    pub content: String, // Base64-encoded image
+}
+
+#[derive(Serialize)]
+pub struct Feature {
+    pub r#type: String, // "TEXT_DETECTION"
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VisionResponse {
+    pub responses: Vec<TextAnnotations>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TextAnnotations {
+    #[serde(rename = "textAnnotations")]
+    pub text_annotations: Option<Vec<Annotation>>,
+    // Set instead of the annotations when this image couldn't be read
+    pub error: Option<Status>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Status {
+    pub message: String,
+}
+
+// The first annotation holds all of the text, the rest one word each
+#[derive(Deserialize, Debug)]
+pub struct Annotation {
+    pub description: String, // Extracted text from the image
+    #[serde(rename = "boundingPoly")]
+    pub bounding_poly: Option<BoundingPoly>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BoundingPoly {
+    #[serde(default)]
+    pub vertices: Vec<Vertex>,
+}
+
+// Coordinates of 0 are left out of the response
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct Vertex {
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub y: i32,
+}
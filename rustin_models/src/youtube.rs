@@ -0,0 +1,91 @@
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct YouTubeResponse {
+    pub items: Vec<YouTubeItem>,
+}
+
+#[derive(Deserialize)]
+pub struct YouTubeItem {
+    pub id: YouTubeVideoId,
+    pub snippet: Option<SearchSnippet>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchSnippet {
+    pub title: String,
+    #[serde(rename = "channelTitle")]
+    pub channel_title: String,
+    // "live" or "upcoming" for live streams and premieres, "none" otherwise
+    #[serde(rename = "liveBroadcastContent", default)]
+    pub live_broadcast_content: String,
+}
+
+#[derive(Deserialize)]
+pub struct YouTubeVideoId {
+    #[serde(rename = "videoId")]
+    pub video_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistItemsResponse {
+    pub items: Vec<PlaylistItem>,
+    #[serde(rename = "nextPageToken")]
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistItem {
+    pub snippet: PlaylistItemSnippet,
+}
+
+#[derive(Deserialize)]
+pub struct PlaylistItemSnippet {
+    pub title: String,
+    #[serde(rename = "resourceId")]
+    pub resource_id: YouTubeVideoId,
+}
+
+#[derive(Deserialize)]
+pub struct VideoListResponse {
+    pub items: Vec<VideoItem>,
+}
+
+#[derive(Deserialize)]
+pub struct VideoItem {
+    pub id: String,
+    #[serde(rename = "contentDetails")]
+    pub content_details: VideoContentDetails,
+}
+
+#[derive(Deserialize)]
+pub struct VideoContentDetails {
+    // ISO 8601 duration such as PT4M13S
+    pub duration: String,
+    #[serde(rename = "contentRating", default)]
+    pub content_rating: ContentRating,
+    #[serde(rename = "regionRestriction")]
+    pub region_restriction: Option<RegionRestriction>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ContentRating {
+    // "ytAgeRestricted" for videos that need a signed in adult
+    #[serde(rename = "ytRating")]
+    pub yt_rating: Option<String>,
+}
+
+// Countries as ISO 3166-1 alpha-2 codes. When allowed is set the video plays
+// only there, otherwise everywhere but the blocked ones
+#[derive(Deserialize)]
+pub struct RegionRestriction {
+    pub allowed: Option<Vec<String>>,
+    #[serde(default)]
+    pub blocked: Vec<String>,
+}
+
+// A playlist created on the user's channel
+#[derive(Deserialize)]
+pub struct PlaylistResource {
+    pub id: String,
+}
@@ -0,0 +1,27 @@
+-- One row per user request, updated as it moves through the pipeline
+CREATE TABLE requests (
+    request_id TEXT PRIMARY KEY,
+    chat_id BIGINT NOT NULL,
+    kind TEXT NOT NULL,
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX requests_status_idx ON requests (status);
+
+-- Every song of a finished request, with the Telegram file_id once uploaded
+CREATE TABLE songs (
+    id BIGSERIAL PRIMARY KEY,
+    request_id TEXT NOT NULL REFERENCES requests (request_id) ON DELETE CASCADE,
+    chat_id BIGINT NOT NULL,
+    title TEXT NOT NULL,
+    video_id TEXT,
+    status TEXT NOT NULL,
+    file_id TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX songs_chat_id_idx ON songs (chat_id, created_at DESC);
+CREATE INDEX songs_request_id_idx ON songs (request_id, video_id);
@@ -0,0 +1,13 @@
+-- Messages waiting to be published to RabbitMQ, written in place of a direct
+-- publish so they aren't lost when the broker is unreachable
+CREATE TABLE outbox (
+    id BIGSERIAL PRIMARY KEY,
+    queue TEXT NOT NULL,
+    request_id TEXT NOT NULL,
+    payload BYTEA NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX outbox_next_attempt_at_idx ON outbox (next_attempt_at);
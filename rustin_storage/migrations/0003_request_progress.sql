@@ -0,0 +1,7 @@
+-- How far a pending request has got, shown to the user by /status
+ALTER TABLE requests
+    ADD COLUMN stage TEXT NOT NULL DEFAULT 'queued',
+    ADD COLUMN songs_total INTEGER,
+    ADD COLUMN songs_done INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX requests_chat_id_idx ON requests (chat_id, created_at DESC);
@@ -0,0 +1,6 @@
+-- Chats an admin has blocked from using the bot
+CREATE TABLE banned_chats (
+    chat_id BIGINT PRIMARY KEY,
+    banned_by BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
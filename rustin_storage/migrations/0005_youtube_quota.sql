@@ -0,0 +1,6 @@
+-- Estimated YouTube Data API quota units spent per day. Google resets the
+-- quota at midnight Pacific time, so that's where days start
+CREATE TABLE youtube_quota_usage (
+    day DATE PRIMARY KEY,
+    units BIGINT NOT NULL DEFAULT 0
+);
@@ -0,0 +1,9 @@
+-- Preferences a chat has set, applied to every request it sends
+CREATE TABLE chat_settings (
+    chat_id BIGINT PRIMARY KEY,
+    bitrate INTEGER,
+    stream BOOLEAN NOT NULL DEFAULT false,
+    zip BOOLEAN NOT NULL DEFAULT false,
+    normalize BOOLEAN NOT NULL DEFAULT false,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
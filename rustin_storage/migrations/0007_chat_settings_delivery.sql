@@ -0,0 +1,5 @@
+-- Chats can ask for links instead of uploaded files, and pick the language
+-- of the replies instead of going by their Telegram app
+ALTER TABLE chat_settings
+    ADD COLUMN links BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN language TEXT;
@@ -0,0 +1,20 @@
+-- Requests whose results are held until a time picked with /later
+CREATE TABLE scheduled_deliveries (
+    request_id TEXT PRIMARY KEY,
+    chat_id BIGINT NOT NULL,
+    deliver_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX scheduled_deliveries_deliver_at_idx ON scheduled_deliveries (deliver_at);
+
+-- Replies of scheduled requests, moved to the outbox once they're due
+CREATE TABLE scheduled_replies (
+    id BIGSERIAL PRIMARY KEY,
+    queue TEXT NOT NULL,
+    request_id TEXT NOT NULL,
+    payload BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX scheduled_replies_request_id_idx ON scheduled_replies (request_id);
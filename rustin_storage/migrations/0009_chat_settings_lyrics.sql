@@ -0,0 +1,3 @@
+-- Chats can ask for the synced lyrics of their songs as .lrc files
+ALTER TABLE chat_settings
+    ADD COLUMN lyrics BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,4 @@
+-- Chats can have a song.link page added to their songs, which opens the song
+-- on other streaming services
+ALTER TABLE chat_settings
+    ADD COLUMN song_link BOOLEAN NOT NULL DEFAULT false;
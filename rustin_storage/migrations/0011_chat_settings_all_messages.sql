@@ -0,0 +1,4 @@
+-- Groups can have every message taken as a request, instead of only the
+-- ones that mention the bot, reply to it or start with the trigger
+ALTER TABLE chat_settings
+    ADD COLUMN all_messages BOOLEAN NOT NULL DEFAULT false;
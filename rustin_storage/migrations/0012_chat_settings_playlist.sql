@@ -0,0 +1,3 @@
+-- Multi-song requests can come with an M3U playlist of their songs
+ALTER TABLE chat_settings
+    ADD COLUMN playlist BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,8 @@
+-- Spotify logins of users who let the bot list their private playlists
+CREATE TABLE spotify_tokens (
+    user_id BIGINT PRIMARY KEY,
+    access_token TEXT NOT NULL,
+    refresh_token TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,6 @@
+-- Users can sign in to YouTube as well, the logins are kept side by side
+ALTER TABLE spotify_tokens RENAME TO oauth_tokens;
+ALTER TABLE oauth_tokens ADD COLUMN provider TEXT NOT NULL DEFAULT 'spotify';
+ALTER TABLE oauth_tokens ALTER COLUMN provider DROP DEFAULT;
+ALTER TABLE oauth_tokens DROP CONSTRAINT spotify_tokens_pkey;
+ALTER TABLE oauth_tokens ADD PRIMARY KEY (user_id, provider);
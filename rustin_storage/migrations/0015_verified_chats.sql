@@ -0,0 +1,5 @@
+-- Chats that passed the bot's first-contact challenge
+CREATE TABLE verified_chats (
+    chat_id BIGINT PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
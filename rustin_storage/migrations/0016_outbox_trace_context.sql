@@ -0,0 +1,3 @@
+-- The traceparent of the span that queued the message, so the reply joins
+-- the trace of its request once the drainer publishes it
+ALTER TABLE outbox ADD COLUMN trace_context TEXT;
@@ -0,0 +1,8 @@
+-- IDs of the RabbitMQ messages a consumer has processed and acked, so a
+-- redelivery of one is skipped when there is no Redis to remember it
+CREATE TABLE processed_messages (
+    message_id TEXT PRIMARY KEY,
+    processed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX processed_messages_processed_at ON processed_messages (processed_at);
@@ -0,0 +1,931 @@
+//! Request history shared by the RustinBot services. Every request and the
+//! songs it produced are stored in Postgres, so users can look back at what
+//! they converted and unfinished requests can be found after a crash.
+
+use sqlx::{
+    postgres::{PgPoolOptions, PgRow},
+    PgPool, Row,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub use sqlx::Error;
+
+// Where a request is in the pipeline. Requests still pending after their
+// worker went away are the ones to resume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestStatus {
+    Pending,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl RequestStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RequestStatus::Pending => "pending",
+            RequestStatus::Completed => "completed",
+            RequestStatus::Failed => "failed",
+            RequestStatus::Cancelled => "cancelled",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        [
+            RequestStatus::Pending,
+            RequestStatus::Completed,
+            RequestStatus::Failed,
+            RequestStatus::Cancelled,
+        ]
+        .into_iter()
+        .find(|status| status.as_str() == value)
+    }
+}
+
+// What a pending request is busy with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestStage {
+    // Published by the bot, not picked up by a consumer yet
+    Queued,
+    // Reading, recognizing or expanding the request into songs
+    Searching,
+    Converting,
+    // Publishing the converted songs to the reply service
+    Delivering,
+}
+
+impl RequestStage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RequestStage::Queued => "queued",
+            RequestStage::Searching => "searching",
+            RequestStage::Converting => "converting",
+            RequestStage::Delivering => "delivering",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        [
+            RequestStage::Queued,
+            RequestStage::Searching,
+            RequestStage::Converting,
+            RequestStage::Delivering,
+        ]
+        .into_iter()
+        .find(|stage| stage.as_str() == value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SongStatus {
+    Converted,
+    Failed,
+    // Not started because the request was cancelled
+    Cancelled,
+}
+
+impl SongStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SongStatus::Converted => "converted",
+            SongStatus::Failed => "failed",
+            SongStatus::Cancelled => "cancelled",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        [
+            SongStatus::Converted,
+            SongStatus::Failed,
+            SongStatus::Cancelled,
+        ]
+        .into_iter()
+        .find(|status| status.as_str() == value)
+    }
+}
+
+// A song as it was converted for a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct SongRecord {
+    pub title: String,
+    pub video_id: Option<String>,
+    pub status: SongStatus,
+    // Telegram file_id of the uploaded MP3, filled in once it's known
+    pub file_id: Option<String>,
+}
+
+// A converted song as listed in a chat's history
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub title: String,
+    pub video_id: Option<String>,
+    pub file_id: Option<String>,
+}
+
+impl HistoryEntry {
+    fn from_row(row: &PgRow) -> Result<Self, Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            title: row.try_get("title")?,
+            video_id: row.try_get("video_id")?,
+            file_id: row.try_get("file_id")?,
+        })
+    }
+}
+
+// Where a request of a chat stands, as shown by /status
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestSummary {
+    pub request_id: String,
+    pub kind: String,
+    pub status: RequestStatus,
+    pub stage: RequestStage,
+    // Known once the request has been expanded into songs
+    pub songs_total: Option<i32>,
+    pub songs_done: i32,
+}
+
+impl RequestSummary {
+    fn from_row(row: &PgRow) -> Result<Self, Error> {
+        let status: String = row.try_get("status")?;
+        let stage: String = row.try_get("stage")?;
+        Ok(Self {
+            request_id: row.try_get("request_id")?,
+            kind: row.try_get("kind")?,
+            status: RequestStatus::parse(&status).ok_or_else(|| decode_error("status", &status))?,
+            stage: RequestStage::parse(&stage).ok_or_else(|| decode_error("stage", &stage))?,
+            songs_total: row.try_get("songs_total")?,
+            songs_done: row.try_get("songs_done")?,
+        })
+    }
+}
+
+// A song of a finished request and what became of it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SongOutcome {
+    pub title: String,
+    pub status: SongStatus,
+}
+
+fn decode_error(column: &str, value: &str) -> Error {
+    Error::ColumnDecode {
+        index: column.to_string(),
+        source: format!("unknown value '{}'", value).into(),
+    }
+}
+
+// Request and song counts over some period, shown to admins by /stats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConversionStats {
+    pub requests: i64,
+    pub songs_converted: i64,
+    pub songs_failed: i64,
+}
+
+// Preferences of a chat, set with /quality, /stream, /zip and /settings
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChatSettings {
+    // Preferred MP3 bitrate in kbps, the consumer default when unset
+    pub bitrate: Option<i32>,
+    pub stream: bool,
+    pub zip: bool,
+    // Even out the loudness of downloaded songs
+    pub normalize: bool,
+    // Send download links even when songs could be uploaded
+    pub links: bool,
+    // Language of the replies, the one of the user's Telegram app when unset
+    pub language: Option<String>,
+    // Send the synced lyrics of downloaded songs as .lrc files
+    pub lyrics: bool,
+    // Add the song.link page of each song, to open it on other services
+    pub song_link: bool,
+    // In groups, take every message as a request and not only the ones
+    // meant for the bot
+    pub all_messages: bool,
+    // Send an M3U playlist of the songs after multi-song requests
+    pub playlist: bool,
+}
+
+impl ChatSettings {
+    fn from_row(row: &PgRow) -> Result<Self, Error> {
+        Ok(Self {
+            bitrate: row.try_get("bitrate")?,
+            stream: row.try_get("stream")?,
+            zip: row.try_get("zip")?,
+            normalize: row.try_get("normalize")?,
+            links: row.try_get("links")?,
+            language: row.try_get("language")?,
+            lyrics: row.try_get("lyrics")?,
+            song_link: row.try_get("song_link")?,
+            all_messages: row.try_get("all_messages")?,
+            playlist: row.try_get("playlist")?,
+        })
+    }
+}
+
+// A message in the outbox, claimed for publishing
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub queue: String,
+    pub request_id: String,
+    pub payload: Vec<u8>,
+    pub attempts: i32,
+    // W3C traceparent of the span that queued the message
+    pub trace_context: Option<String>,
+}
+
+// A service users sign in to, so the bot can act on their account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OAuthProvider {
+    // Reads their private playlists
+    Spotify,
+    // Creates playlists of their converted songs
+    YouTube,
+}
+
+impl OAuthProvider {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OAuthProvider::Spotify => "spotify",
+            OAuthProvider::YouTube => "youtube",
+        }
+    }
+}
+
+// The login of a user to an OAuthProvider
+#[derive(Debug, Clone, PartialEq)]
+pub struct OAuthToken {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: SystemTime,
+}
+
+impl OAuthToken {
+    fn from_row(row: &PgRow) -> Result<Self, Error> {
+        let expires_at: f64 = row.try_get("expires_at")?;
+        Ok(Self {
+            access_token: row.try_get("access_token")?,
+            refresh_token: row.try_get("refresh_token")?,
+            expires_at: UNIX_EPOCH + Duration::from_secs_f64(expires_at.max(0.0)),
+        })
+    }
+}
+
+pub struct Storage {
+    pool: PgPool,
+}
+
+impl Storage {
+    // Connect and bring the schema up to date
+    pub async fn connect(database_url: &str) -> Result<Self, Error> {
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(database_url)
+            .await?;
+        sqlx::migrate!("./migrations").run(&pool).await?;
+        log::info!("Connected to the history database");
+        Ok(Self { pool })
+    }
+
+    // Record a request the bot has just published. It only counts as an
+    // attempt once a consumer picks it up
+    pub async fn record_queued(
+        &self,
+        request_id: &str,
+        chat_id: i64,
+        kind: &str,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO requests (request_id, chat_id, kind, status, stage, attempts)
+             VALUES ($1, $2, $3, $4, $5, 0)
+             ON CONFLICT (request_id) DO NOTHING",
+        )
+        .bind(request_id)
+        .bind(chat_id)
+        .bind(kind)
+        .bind(RequestStatus::Pending.as_str())
+        .bind(RequestStage::Queued.as_str())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Record a request a consumer picked up, or count another attempt when
+    // it's redelivered
+    pub async fn record_request(
+        &self,
+        request_id: &str,
+        chat_id: i64,
+        kind: &str,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO requests (request_id, chat_id, kind, status, stage)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (request_id)
+             DO UPDATE SET attempts = requests.attempts + 1, stage = $5, updated_at = now()",
+        )
+        .bind(request_id)
+        .bind(chat_id)
+        .bind(kind)
+        .bind(RequestStatus::Pending.as_str())
+        .bind(RequestStage::Searching.as_str())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_stage(&self, request_id: &str, stage: RequestStage) -> Result<(), Error> {
+        sqlx::query("UPDATE requests SET stage = $2, updated_at = now() WHERE request_id = $1")
+            .bind(request_id)
+            .bind(stage.as_str())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // The request has been expanded into this many songs, none converted yet
+    pub async fn start_conversion(&self, request_id: &str, songs_total: i32) -> Result<(), Error> {
+        sqlx::query(
+            "UPDATE requests
+             SET stage = $2, songs_total = $3, songs_done = 0, updated_at = now()
+             WHERE request_id = $1",
+        )
+        .bind(request_id)
+        .bind(RequestStage::Converting.as_str())
+        .bind(songs_total)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // One more song of the request is done, converted or not
+    pub async fn record_song_done(&self, request_id: &str) -> Result<(), Error> {
+        sqlx::query(
+            "UPDATE requests SET songs_done = songs_done + 1, updated_at = now()
+             WHERE request_id = $1",
+        )
+        .bind(request_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // A request of the chat, or its latest one when no ID is given. None if
+    // there is no such request or it belongs to another chat
+    pub async fn request_summary(
+        &self,
+        chat_id: i64,
+        request_id: Option<&str>,
+    ) -> Result<Option<RequestSummary>, Error> {
+        let row = sqlx::query(
+            "SELECT request_id, kind, status, stage, songs_total, songs_done
+             FROM requests
+             WHERE chat_id = $1 AND ($2::TEXT IS NULL OR request_id = $2)
+             ORDER BY created_at DESC
+             LIMIT 1",
+        )
+        .bind(chat_id)
+        .bind(request_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(RequestSummary::from_row).transpose()
+    }
+
+    // The songs of a finished request in the order they were requested
+    pub async fn request_songs(&self, request_id: &str) -> Result<Vec<SongOutcome>, Error> {
+        let rows = sqlx::query("SELECT title, status FROM songs WHERE request_id = $1 ORDER BY id")
+            .bind(request_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter()
+            .map(|row| {
+                let status: String = row.try_get("status")?;
+                Ok(SongOutcome {
+                    title: row.try_get("title")?,
+                    status: SongStatus::parse(&status)
+                        .ok_or_else(|| decode_error("status", &status))?,
+                })
+            })
+            .collect()
+    }
+
+    // Store the outcome of a request together with its songs
+    pub async fn finish_request(
+        &self,
+        request_id: &str,
+        chat_id: i64,
+        status: RequestStatus,
+        songs: &[SongRecord],
+    ) -> Result<(), Error> {
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("UPDATE requests SET status = $2, updated_at = now() WHERE request_id = $1")
+            .bind(request_id)
+            .bind(status.as_str())
+            .execute(&mut *transaction)
+            .await?;
+
+        for song in songs {
+            sqlx::query(
+                "INSERT INTO songs (request_id, chat_id, title, video_id, status, file_id)
+                 VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(request_id)
+            .bind(chat_id)
+            .bind(&song.title)
+            .bind(&song.video_id)
+            .bind(song.status.as_str())
+            .bind(&song.file_id)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await
+    }
+
+    // Remember the file_id Telegram assigned to a song's upload
+    pub async fn record_file_id(
+        &self,
+        request_id: &str,
+        video_id: &str,
+        file_id: &str,
+    ) -> Result<(), Error> {
+        sqlx::query("UPDATE songs SET file_id = $3 WHERE request_id = $1 AND video_id = $2")
+            .bind(request_id)
+            .bind(video_id)
+            .bind(file_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // The most recently converted songs of a chat, newest first. A song
+    // converted several times is listed once, preferably with a file_id
+    pub async fn recent_songs(&self, chat_id: i64, limit: i64) -> Result<Vec<HistoryEntry>, Error> {
+        let rows = sqlx::query(
+            "SELECT id, title, video_id, file_id FROM (
+                 SELECT DISTINCT ON (COALESCE(video_id, title)) id, title, video_id, file_id, created_at
+                 FROM songs
+                 WHERE chat_id = $1 AND status = $2
+                 ORDER BY COALESCE(video_id, title), file_id IS NULL, created_at DESC
+             ) latest
+             ORDER BY created_at DESC
+             LIMIT $3",
+        )
+        .bind(chat_id)
+        .bind(SongStatus::Converted.as_str())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(HistoryEntry::from_row).collect()
+    }
+
+    // A song from the history of a chat, None if it belongs to another chat
+    pub async fn song(&self, chat_id: i64, song_id: i64) -> Result<Option<HistoryEntry>, Error> {
+        let row = sqlx::query(
+            "SELECT id, title, video_id, file_id FROM songs WHERE id = $1 AND chat_id = $2",
+        )
+        .bind(song_id)
+        .bind(chat_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(HistoryEntry::from_row).transpose()
+    }
+
+    // Counts of the requests and songs of the given period up to now
+    pub async fn conversion_stats(&self, period: Duration) -> Result<ConversionStats, Error> {
+        let seconds = period.as_secs_f64();
+        let requests: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM requests WHERE created_at > now() - make_interval(secs => $1)",
+        )
+        .bind(seconds)
+        .fetch_one(&self.pool)
+        .await?;
+        let row = sqlx::query(
+            "SELECT COUNT(*) FILTER (WHERE status = $2) AS converted,
+                    COUNT(*) FILTER (WHERE status = $3) AS failed
+             FROM songs
+             WHERE created_at > now() - make_interval(secs => $1)",
+        )
+        .bind(seconds)
+        .bind(SongStatus::Converted.as_str())
+        .bind(SongStatus::Failed.as_str())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(ConversionStats {
+            requests,
+            songs_converted: row.try_get("converted")?,
+            songs_failed: row.try_get("failed")?,
+        })
+    }
+
+    // Every chat that has ever sent a request, except banned ones
+    pub async fn known_chats(&self) -> Result<Vec<i64>, Error> {
+        sqlx::query_scalar(
+            "SELECT DISTINCT chat_id FROM requests
+             WHERE chat_id NOT IN (SELECT chat_id FROM banned_chats)",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn ban_chat(&self, chat_id: i64, banned_by: i64) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO banned_chats (chat_id, banned_by) VALUES ($1, $2)
+             ON CONFLICT (chat_id) DO NOTHING",
+        )
+        .bind(chat_id)
+        .bind(banned_by)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn unban_chat(&self, chat_id: i64) -> Result<(), Error> {
+        sqlx::query("DELETE FROM banned_chats WHERE chat_id = $1")
+            .bind(chat_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn banned_chats(&self) -> Result<Vec<i64>, Error> {
+        sqlx::query_scalar("SELECT chat_id FROM banned_chats")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    pub async fn is_banned(&self, chat_id: i64) -> Result<bool, Error> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM banned_chats WHERE chat_id = $1)")
+            .bind(chat_id)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    pub async fn verify_chat(&self, chat_id: i64) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO verified_chats (chat_id) VALUES ($1)
+             ON CONFLICT (chat_id) DO NOTHING",
+        )
+        .bind(chat_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn verified_chats(&self) -> Result<Vec<i64>, Error> {
+        sqlx::query_scalar("SELECT chat_id FROM verified_chats")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    pub async fn oauth_token(
+        &self,
+        user_id: i64,
+        provider: OAuthProvider,
+    ) -> Result<Option<OAuthToken>, Error> {
+        let row = sqlx::query(
+            "SELECT access_token, refresh_token,
+                 EXTRACT(EPOCH FROM expires_at)::float8 AS expires_at
+             FROM oauth_tokens WHERE user_id = $1 AND provider = $2",
+        )
+        .bind(user_id)
+        .bind(provider.as_str())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(OAuthToken::from_row).transpose()
+    }
+
+    pub async fn save_oauth_token(
+        &self,
+        user_id: i64,
+        provider: OAuthProvider,
+        token: &OAuthToken,
+    ) -> Result<(), Error> {
+        let expires_at = token
+            .expires_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        sqlx::query(
+            "INSERT INTO oauth_tokens (user_id, provider, access_token, refresh_token, expires_at)
+             VALUES ($1, $2, $3, $4, to_timestamp($5))
+             ON CONFLICT (user_id, provider) DO UPDATE SET
+                 access_token = $3, refresh_token = $4, expires_at = to_timestamp($5),
+                 updated_at = now()",
+        )
+        .bind(user_id)
+        .bind(provider.as_str())
+        .bind(&token.access_token)
+        .bind(&token.refresh_token)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_oauth_token(
+        &self,
+        user_id: i64,
+        provider: OAuthProvider,
+    ) -> Result<(), Error> {
+        sqlx::query("DELETE FROM oauth_tokens WHERE user_id = $1 AND provider = $2")
+            .bind(user_id)
+            .bind(provider.as_str())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn chat_settings(&self, chat_id: i64) -> Result<Option<ChatSettings>, Error> {
+        let row = sqlx::query(
+            "SELECT bitrate, stream, zip, normalize, links, language, lyrics, song_link,
+                 all_messages, playlist
+             FROM chat_settings WHERE chat_id = $1",
+        )
+        .bind(chat_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(ChatSettings::from_row).transpose()
+    }
+
+    // The settings of every chat that has changed any
+    pub async fn all_chat_settings(&self) -> Result<Vec<(i64, ChatSettings)>, Error> {
+        let rows = sqlx::query(
+            "SELECT chat_id, bitrate, stream, zip, normalize, links, language, lyrics,
+                 song_link, all_messages, playlist
+             FROM chat_settings",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| Ok((row.try_get("chat_id")?, ChatSettings::from_row(row)?)))
+            .collect()
+    }
+
+    pub async fn save_chat_settings(
+        &self,
+        chat_id: i64,
+        settings: &ChatSettings,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO chat_settings
+                 (chat_id, bitrate, stream, zip, normalize, links, language, lyrics, song_link,
+                  all_messages, playlist)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+             ON CONFLICT (chat_id) DO UPDATE SET
+                 bitrate = $2, stream = $3, zip = $4, normalize = $5, links = $6,
+                 language = $7, lyrics = $8, song_link = $9,
+                 all_messages = $10, playlist = $11, updated_at = now()",
+        )
+        .bind(chat_id)
+        .bind(settings.bitrate)
+        .bind(settings.stream)
+        .bind(settings.zip)
+        .bind(settings.normalize)
+        .bind(settings.links)
+        .bind(&settings.language)
+        .bind(settings.lyrics)
+        .bind(settings.song_link)
+        .bind(settings.all_messages)
+        .bind(settings.playlist)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Add to the YouTube quota units spent today, returning the new total
+    pub async fn add_youtube_quota_units(&self, units: i64) -> Result<i64, Error> {
+        // Google resets the quota at midnight Pacific time
+        sqlx::query_scalar(
+            "INSERT INTO youtube_quota_usage (day, units)
+             VALUES ((now() AT TIME ZONE 'America/Los_Angeles')::date, $1)
+             ON CONFLICT (day) DO UPDATE SET units = youtube_quota_usage.units + $1
+             RETURNING units",
+        )
+        .bind(units)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    // Raise today's units to at least the given amount, e.g. once Google has
+    // rejected a call for being over quota
+    pub async fn raise_youtube_quota_units(&self, units: i64) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO youtube_quota_usage (day, units)
+             VALUES ((now() AT TIME ZONE 'America/Los_Angeles')::date, $1)
+             ON CONFLICT (day) DO UPDATE SET units = GREATEST(youtube_quota_usage.units, $1)",
+        )
+        .bind(units)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn youtube_quota_units(&self) -> Result<i64, Error> {
+        let units: Option<i64> = sqlx::query_scalar(
+            "SELECT units FROM youtube_quota_usage
+             WHERE day = (now() AT TIME ZONE 'America/Los_Angeles')::date",
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(units.unwrap_or(0))
+    }
+
+    // Remember that a message has been processed, forgetting the ones older
+    // than ttl on the way
+    pub async fn mark_processed(&self, message_id: &str, ttl: Duration) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO processed_messages (message_id) VALUES ($1)
+             ON CONFLICT (message_id) DO UPDATE SET processed_at = now()",
+        )
+        .bind(message_id)
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(
+            "DELETE FROM processed_messages
+             WHERE processed_at < now() - make_interval(secs => $1)",
+        )
+        .bind(ttl.as_secs_f64())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Whether the message was processed within the last ttl
+    pub async fn is_processed(&self, message_id: &str, ttl: Duration) -> Result<bool, Error> {
+        sqlx::query_scalar(
+            "SELECT EXISTS (
+                 SELECT 1 FROM processed_messages
+                 WHERE message_id = $1 AND processed_at > now() - make_interval(secs => $2)
+             )",
+        )
+        .bind(message_id)
+        .bind(ttl.as_secs_f64())
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    // IDs of requests that never finished, oldest first
+    pub async fn pending_requests(&self) -> Result<Vec<String>, Error> {
+        sqlx::query_scalar("SELECT request_id FROM requests WHERE status = $1 ORDER BY created_at")
+            .bind(RequestStatus::Pending.as_str())
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    // Queue a message for publishing by the outbox drainer
+    pub async fn enqueue_outbox(
+        &self,
+        queue: &str,
+        request_id: &str,
+        payload: &[u8],
+        trace_context: Option<&str>,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO outbox (queue, request_id, payload, trace_context)
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(queue)
+        .bind(request_id)
+        .bind(payload)
+        .bind(trace_context)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Claim the oldest messages that are due. A claimed message is hidden
+    // from other drainers for the lease, after which it's retried unless it
+    // was deleted in the meantime
+    pub async fn claim_outbox(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> Result<Vec<OutboxEntry>, Error> {
+        let rows = sqlx::query(
+            "UPDATE outbox
+             SET attempts = attempts + 1,
+                 next_attempt_at = now() + make_interval(secs => $2)
+             WHERE id IN (
+                 SELECT id FROM outbox
+                 WHERE next_attempt_at <= now()
+                 ORDER BY id
+                 LIMIT $1
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, queue, request_id, payload, attempts, trace_context",
+        )
+        .bind(limit)
+        .bind(lease.as_secs_f64())
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut entries = rows
+            .iter()
+            .map(|row| {
+                Ok(OutboxEntry {
+                    id: row.try_get("id")?,
+                    queue: row.try_get("queue")?,
+                    request_id: row.try_get("request_id")?,
+                    payload: row.try_get("payload")?,
+                    attempts: row.try_get("attempts")?,
+                    trace_context: row.try_get("trace_context")?,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        // RETURNING doesn't keep the order of the subquery
+        entries.sort_by_key(|entry| entry.id);
+        Ok(entries)
+    }
+
+    // Remove a message once RabbitMQ has confirmed it
+    pub async fn delete_outbox(&self, id: i64) -> Result<(), Error> {
+        sqlx::query("DELETE FROM outbox WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // Hold the replies of a request until deliver_at. Redeliveries of the
+    // request keep the time it was first scheduled for
+    pub async fn schedule_delivery(
+        &self,
+        request_id: &str,
+        chat_id: i64,
+        deliver_at: SystemTime,
+    ) -> Result<(), Error> {
+        let deliver_at = deliver_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        sqlx::query(
+            "INSERT INTO scheduled_deliveries (request_id, chat_id, deliver_at)
+             VALUES ($1, $2, to_timestamp($3))
+             ON CONFLICT (request_id) DO NOTHING",
+        )
+        .bind(request_id)
+        .bind(chat_id)
+        .bind(deliver_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Keep a reply back when its request is scheduled for later. Returns
+    // false when it isn't, or when the time has come, so it's sent right away
+    pub async fn hold_reply(
+        &self,
+        queue: &str,
+        request_id: &str,
+        payload: &[u8],
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            "INSERT INTO scheduled_replies (queue, request_id, payload)
+             SELECT $1, $2, $3 FROM scheduled_deliveries
+             WHERE request_id = $2 AND deliver_at > now()",
+        )
+        .bind(queue)
+        .bind(request_id)
+        .bind(payload)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Move the replies of every delivery that is due to the outbox, in the
+    // order they were held. Replies held while their delivery was being
+    // released are left without one, and go out on the next call
+    pub async fn release_due_replies(&self) -> Result<u64, Error> {
+        let result = sqlx::query(
+            "WITH due AS (
+                 DELETE FROM scheduled_deliveries
+                 WHERE deliver_at <= now()
+                 RETURNING request_id
+             ),
+             released AS (
+                 DELETE FROM scheduled_replies AS reply
+                 WHERE reply.request_id IN (SELECT request_id FROM due)
+                    OR NOT EXISTS (
+                        SELECT 1 FROM scheduled_deliveries AS delivery
+                        WHERE delivery.request_id = reply.request_id
+                    )
+                 RETURNING id, queue, request_id, payload
+             )
+             INSERT INTO outbox (queue, request_id, payload)
+             SELECT queue, request_id, payload FROM released ORDER BY id",
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
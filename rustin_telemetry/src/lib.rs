@@ -0,0 +1,217 @@
+//! Logging and distributed tracing shared by the RustinBot services. Spans
+//! are exported over OTLP when OTEL_EXPORTER_OTLP_ENDPOINT is set, and their
+//! context travels with the queue messages, so one user request shows up as
+//! one trace across the bot and the workers. Errors worth a maintainer's
+//! attention are reported to Sentry when SENTRY_DSN is set.
+
+use lapin::{
+    message::Delivery,
+    types::{AMQPValue, FieldTable, ShortString},
+};
+use opentelemetry::{
+    propagation::{Extractor, Injector, TextMapPropagator},
+    trace::TracerProvider as _,
+    KeyValue,
+};
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator, runtime, trace::TracerProvider, Resource,
+};
+use sentry::{Breadcrumb, ClientInitGuard, Hub, SentryFutureExt};
+use std::{collections::HashMap, env, error::Error, future::Future, sync::Arc};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
+
+// W3C header that carries the trace and the span a message was published in
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+// Flushes the spans and Sentry events that haven't been sent yet when
+// dropped, so keep it alive until the service exits
+pub struct Telemetry {
+    provider: Option<TracerProvider>,
+    _sentry: Option<ClientInitGuard>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = &self.provider {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to export the remaining spans: {}", e);
+            }
+        }
+    }
+}
+
+// Log to stdout as filtered by RUST_LOG and, when an OTLP endpoint is
+// configured, export the spans of the service as well. Spans are exported
+// from the info level whatever RUST_LOG says
+pub fn init(service_name: &'static str) -> Telemetry {
+    let provider = match env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(_) => match tracer_provider(service_name) {
+            Ok(provider) => Some(provider),
+            Err(e) => {
+                eprintln!("Failed to set up the OTLP exporter: {}", e);
+                None
+            }
+        },
+        Err(_) => None,
+    };
+    let traces = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer(service_name))
+            .with_filter(LevelFilter::INFO)
+    });
+
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(EnvFilter::from_default_env()))
+        .with(traces)
+        .init();
+    if provider.is_some() {
+        tracing::info!("Exporting traces of {} over OTLP", service_name);
+    }
+    let sentry = init_sentry(service_name);
+    Telemetry {
+        provider,
+        _sentry: sentry,
+    }
+}
+
+// The client reads its DSN, environment and release from the standard
+// SENTRY_* variables. Panics are reported as well
+fn init_sentry(service_name: &'static str) -> Option<ClientInitGuard> {
+    env::var("SENTRY_DSN").ok()?;
+    let guard = sentry::init(sentry::ClientOptions::default());
+    if !guard.is_enabled() {
+        tracing::error!("Failed to set up Sentry, check SENTRY_DSN");
+        return None;
+    }
+    sentry::configure_scope(|scope| scope.set_tag("service", service_name));
+    tracing::info!("Reporting errors of {} to Sentry", service_name);
+    Some(guard)
+}
+
+// Run the handling of a request with its own Sentry scope, tagged with its
+// request ID, so its breadcrumbs don't mix with those of other requests
+pub async fn request_scope<F: Future>(request_id: &str, future: F) -> F::Output {
+    let hub = Arc::new(Hub::new_from_top(Hub::main()));
+    hub.configure_scope(|scope| scope.set_tag("request_id", request_id));
+    future.bind_hub(hub).await
+}
+
+// Keep a future spawned as its own task in the Sentry scope of the request
+// it's part of
+pub fn in_current_scope<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    future.bind_hub(Hub::current())
+}
+
+// A step of the request, sent along with any error reported after it
+pub fn breadcrumb(category: &str, message: impl Into<String>) {
+    sentry::add_breadcrumb(Breadcrumb {
+        category: Some(category.to_string()),
+        message: Some(message.into()),
+        ..Default::default()
+    });
+}
+
+// Report an error in the scope of the current request, if any
+pub fn report(error: &(dyn Error + 'static)) {
+    sentry::capture_error(error);
+}
+
+// Report an error outside a request scope, tagged with the request it
+// happened in
+pub fn report_for_request(error: &(dyn Error + 'static), request_id: &str) {
+    sentry::with_scope(
+        |scope| scope.set_tag("request_id", request_id),
+        || sentry::capture_error(error),
+    );
+}
+
+// The exporter reads its endpoint and headers from the standard OTEL_*
+// variables
+fn tracer_provider(service_name: &'static str) -> Result<TracerProvider, Box<dyn Error>> {
+    let exporter = SpanExporter::builder().with_tonic().build()?;
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new("service.name", service_name)]))
+        .build())
+}
+
+// The traceparent of the current span, to pass on with a message published
+// later. None when no trace is being recorded
+pub fn trace_context() -> Option<String> {
+    let mut fields = HashMap::new();
+    TraceContextPropagator::new().inject_context(&Span::current().context(), &mut fields);
+    fields.remove(TRACEPARENT_HEADER)
+}
+
+// Headers of an outgoing message, continuing the trace of the current span
+pub fn inject(headers: &mut FieldTable) {
+    if let Some(traceparent) = trace_context() {
+        insert_trace_context(headers, &traceparent);
+    }
+}
+
+// Headers of an outgoing message, continuing a trace saved earlier
+pub fn insert_trace_context(headers: &mut FieldTable, traceparent: &str) {
+    HeaderInjector(headers).set(TRACEPARENT_HEADER, traceparent.to_string());
+}
+
+// Make the span part of the trace the delivery was published in, if any
+pub fn set_parent(span: &Span, delivery: &Delivery) {
+    let Some(headers) = delivery.properties.headers() else {
+        return;
+    };
+    let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    span.set_parent(context);
+}
+
+struct HeaderInjector<'a>(&'a mut FieldTable);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0
+            .insert(key.into(), AMQPValue::LongString(value.into()));
+    }
+}
+
+struct HeaderExtractor<'a>(&'a FieldTable);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        match self.0.inner().get(&ShortString::from(key.to_string()))? {
+            AMQPValue::LongString(value) => std::str::from_utf8(value.as_bytes()).ok(),
+            AMQPValue::ShortString(value) => Some(value.as_str()),
+            _ => None,
+        }
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.inner().keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_the_traceparent_it_wrote() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let mut headers = FieldTable::default();
+        insert_trace_context(&mut headers, traceparent);
+        assert_eq!(
+            HeaderExtractor(&headers).get(TRACEPARENT_HEADER),
+            Some(traceparent)
+        );
+        assert_eq!(HeaderExtractor(&headers).keys(), vec![TRACEPARENT_HEADER]);
+    }
+
+    #[test]
+    fn has_no_context_outside_a_trace() {
+        assert_eq!(trace_context(), None);
+    }
+}
@@ -0,0 +1,245 @@
+// Poor man's alerting for small deployments: when requests keep failing, the
+// connection to RabbitMQ was down for a while or a provider's circuit opens,
+// the chat in ops_chat_id is told through the Reply queue like any user
+
+use crate::{instance, outbox, publish_buffer::PublishBuffer, topology, DynError};
+use lapin::Channel;
+use rustin_models::{i18n::tr_args, MessageBody, RabbitMessage};
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+// Alerts waiting to be published, e.g. while the connection is down
+const BUFFER: usize = 100;
+// Shorter outages are left to the reconnect logic
+const DISCONNECT_THRESHOLD: Duration = Duration::from_secs(60);
+// Requests the error rate is taken over
+const WINDOW: Duration = Duration::from_secs(5 * 60);
+// Too few requests say nothing about the error rate
+const MIN_REQUESTS: usize = 10;
+// Between two error rate alerts, so a bad spell is one alert
+const COOLDOWN: Duration = Duration::from_secs(30 * 60);
+
+// Failed and succeeded requests of the last WINDOW
+#[derive(Debug)]
+struct ErrorRate {
+    threshold: f64,
+    // When each request finished and whether it failed, oldest first
+    outcomes: VecDeque<(Instant, bool)>,
+    alerted: Option<Instant>,
+}
+
+impl ErrorRate {
+    fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            outcomes: VecDeque::new(),
+            alerted: None,
+        }
+    }
+
+    // The failed and total requests of the window, when they're worth an
+    // alert
+    fn record(&mut self, failed: bool, now: Instant) -> Option<(usize, usize)> {
+        while self
+            .outcomes
+            .front()
+            .is_some_and(|&(at, _)| now.saturating_duration_since(at) > WINDOW)
+        {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back((now, failed));
+
+        let requests = self.outcomes.len();
+        let failures = self.outcomes.iter().filter(|&&(_, failed)| failed).count();
+        let cooling_down = self
+            .alerted
+            .is_some_and(|at| now.saturating_duration_since(at) < COOLDOWN);
+        if requests < MIN_REQUESTS
+            || (failures as f64 / requests as f64) < self.threshold
+            || cooling_down
+        {
+            return None;
+        }
+        self.alerted = Some(now);
+        Some((failures, requests))
+    }
+}
+
+struct Alerts {
+    chat_id: i64,
+    buffer: PublishBuffer<String>,
+    error_rate: Mutex<ErrorRate>,
+    disconnected_at: Mutex<Option<Instant>>,
+}
+
+static ALERTS: OnceLock<Alerts> = OnceLock::new();
+
+// Turn alerting on, once at startup. Without an ops chat nothing is sent
+pub fn init(chat_id: Option<i64>, error_rate: f64) {
+    let Some(chat_id) = chat_id else {
+        log::info!("ops_chat_id not set, alerts are disabled");
+        return;
+    };
+    let alerts = Alerts {
+        chat_id,
+        buffer: PublishBuffer::new("an alert", BUFFER, log::Level::Warn),
+        error_rate: Mutex::new(ErrorRate::new(error_rate)),
+        disconnected_at: Mutex::new(None),
+    };
+    if ALERTS.set(alerts).is_err() {
+        log::warn!("Alerts were already initialised, keeping the first setup");
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ALERTS.get().is_some()
+}
+
+// Queue an alert from the catalog, filled in with the instance and the args
+fn send(key: &str, args: &[(&str, &str)]) {
+    let Some(alerts) = ALERTS.get() else {
+        return;
+    };
+    let instance = instance::id();
+    let args: Vec<_> = [("instance", instance)]
+        .into_iter()
+        .chain(args.iter().copied())
+        .collect();
+    alerts.buffer.push(tr_args(None, key, &args));
+}
+
+// A request was handled. Failures are the ones on our side, not e.g. songs
+// that couldn't be found
+pub fn record_request(failed: bool) {
+    let Some(alerts) = ALERTS.get() else {
+        return;
+    };
+    let alert = lock(&alerts.error_rate).record(failed, Instant::now());
+    if let Some((failed, requests)) = alert {
+        send(
+            "alert.error_rate",
+            &[
+                ("failed", &failed.to_string()),
+                ("requests", &requests.to_string()),
+            ],
+        );
+    }
+}
+
+pub fn circuit_opened(provider: &str, open_for: Duration) {
+    send(
+        "alert.circuit_open",
+        &[
+            ("provider", provider),
+            ("seconds", &open_for.as_secs().to_string()),
+        ],
+    );
+}
+
+// The connection to RabbitMQ went away. Nothing can be published until it's
+// back, so the alert waits for connected
+pub fn connection_lost() {
+    if let Some(alerts) = ALERTS.get() {
+        lock(&alerts.disconnected_at).get_or_insert_with(Instant::now);
+    }
+}
+
+pub fn connected() {
+    let Some(alerts) = ALERTS.get() else {
+        return;
+    };
+    let Some(since) = lock(&alerts.disconnected_at).take() else {
+        return;
+    };
+    let outage = since.elapsed();
+    if outage >= DISCONNECT_THRESHOLD {
+        send(
+            "alert.disconnected",
+            &[("seconds", &outage.as_secs().to_string())],
+        );
+    }
+}
+
+// Publish the queued alerts to the Reply queue until shutdown. Returns an
+// error when the channel breaks
+pub async fn publish(channel: Channel, shutdown: CancellationToken) -> Result<(), DynError> {
+    let Some(alerts) = ALERTS.get() else {
+        return Ok(());
+    };
+    let channel = &channel;
+    let queue = &topology::queues().reply;
+    let publish = |alert: String| async move {
+        log::warn!("Alerting the ops chat: {}", alert);
+        let message = RabbitMessage::new(alerts.chat_id, MessageBody::Error { message: alert });
+        let request_id = Uuid::new_v4().simple().to_string();
+        outbox::publish_confirmed(
+            channel,
+            queue,
+            &request_id,
+            None,
+            &serde_json::to_vec(&message)?,
+        )
+        .await
+    };
+    alerts.buffer.publish(shutdown, publish).await
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(rate: &mut ErrorRate, failed: bool, times: usize, now: Instant) -> bool {
+        (0..times).fold(false, |alerted, _| {
+            rate.record(failed, now).is_some() || alerted
+        })
+    }
+
+    #[test]
+    fn alerts_once_enough_requests_fail() {
+        let now = Instant::now();
+        let mut rate = ErrorRate::new(0.5);
+        assert!(!record(&mut rate, false, MIN_REQUESTS / 2, now));
+        assert!(!record(&mut rate, true, MIN_REQUESTS / 2 - 1, now));
+        assert_eq!(
+            rate.record(true, now),
+            Some((MIN_REQUESTS / 2, MIN_REQUESTS))
+        );
+    }
+
+    #[test]
+    fn waits_for_enough_requests() {
+        let now = Instant::now();
+        let mut rate = ErrorRate::new(0.5);
+        assert!(!record(&mut rate, true, MIN_REQUESTS - 1, now));
+    }
+
+    #[test]
+    fn alerts_again_only_after_the_cooldown() {
+        let now = Instant::now();
+        let mut rate = ErrorRate::new(0.5);
+        assert!(record(&mut rate, true, MIN_REQUESTS, now));
+        assert!(!record(&mut rate, true, MIN_REQUESTS, now + WINDOW / 2));
+        assert!(record(&mut rate, true, MIN_REQUESTS, now + COOLDOWN));
+    }
+
+    #[test]
+    fn forgets_requests_older_than_the_window() {
+        let now = Instant::now();
+        let mut rate = ErrorRate::new(0.5);
+        record(&mut rate, true, MIN_REQUESTS - 1, now);
+        let later = now + WINDOW + Duration::from_secs(1);
+        assert!(!record(&mut rate, true, 1, later));
+        assert_eq!(rate.outcomes.len(), 1);
+    }
+}
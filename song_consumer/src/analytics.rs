@@ -0,0 +1,85 @@
+// Usage events for dashboards, published on the Analytics exchange. They're
+// best effort: recording never waits, and events are dropped when the buffer
+// is full
+
+use crate::{publish_buffer::PublishBuffer, topology, DynError};
+use lapin::{options::BasicPublishOptions, BasicProperties, Channel};
+use rustin_models::analytics::{AnalyticsEvent, AnalyticsMessage, Stage};
+use rustin_storage::{SongRecord, SongStatus};
+use std::{
+    sync::OnceLock,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio_util::sync::CancellationToken;
+
+// Events waiting to be published
+const BUFFER: usize = 1000;
+
+static EVENTS: OnceLock<PublishBuffer<AnalyticsMessage>> = OnceLock::new();
+
+// Turn recording on, once at startup. Without it nothing is recorded
+pub fn init(enabled: bool) {
+    if !enabled {
+        log::info!("Analytics are turned off");
+        return;
+    }
+    let events = PublishBuffer::new("an analytics event", BUFFER, log::Level::Debug);
+    if EVENTS.set(events).is_err() {
+        log::warn!("Analytics were already initialised, keeping the first setup");
+    }
+}
+
+pub fn is_enabled() -> bool {
+    EVENTS.get().is_some()
+}
+
+pub fn record(event: AnalyticsEvent) {
+    let Some(events) = EVENTS.get() else {
+        return;
+    };
+    let at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    events.push(AnalyticsMessage { at, event });
+}
+
+pub fn record_stage(stage: Stage, started: Instant) {
+    record(AnalyticsEvent::StageFinished {
+        stage,
+        seconds: started.elapsed().as_secs_f64(),
+    });
+}
+
+// How the songs of a finished request went, counted from its history
+pub fn record_finished(songs: &[SongRecord]) {
+    let count = |status: SongStatus| songs.iter().filter(|song| song.status == status).count();
+    record(AnalyticsEvent::RequestFinished {
+        songs: songs.len(),
+        converted: count(SongStatus::Converted),
+        failed: count(SongStatus::Failed),
+        cancelled: count(SongStatus::Cancelled),
+    });
+}
+
+// Publish the recorded events until shutdown. Returns an error when the
+// channel breaks
+pub async fn publish(channel: Channel, shutdown: CancellationToken) -> Result<(), DynError> {
+    let Some(events) = EVENTS.get() else {
+        return Ok(());
+    };
+    let channel = &channel;
+    let exchange = &topology::queues().analytics_exchange;
+    let publish = |message: AnalyticsMessage| async move {
+        channel
+            .basic_publish(
+                exchange,
+                "",
+                BasicPublishOptions::default(),
+                &serde_json::to_vec(&message)?,
+                BasicProperties::default().with_content_type("application/json".into()),
+            )
+            .await?;
+        Ok(())
+    };
+    events.publish(shutdown, publish).await
+}
@@ -0,0 +1,72 @@
+use crate::metrics;
+use rustin_storage::Storage;
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc,
+};
+
+// What a search costs: search.list plus the videos.list call for durations
+pub const SEARCH_UNITS: i64 = 101;
+
+// Estimated YouTube Data API quota spent today, shared by the replicas
+// through storage so searches can move to the fallback providers before
+// Google starts rejecting them
+pub struct ApiBudget {
+    storage: Arc<Storage>,
+    daily_units: i64,
+    // The last total seen, which saves a query on every search
+    used: AtomicI64,
+}
+
+impl ApiBudget {
+    // Without storage the replicas can't agree on a total, so nothing is
+    // tracked and only Google's own rejections switch providers
+    pub fn new(storage: Option<Arc<Storage>>, daily_units: i64) -> Option<Self> {
+        Some(Self {
+            storage: storage?,
+            daily_units,
+            used: AtomicI64::new(0),
+        })
+    }
+
+    pub async fn allows(&self, units: i64) -> bool {
+        if self.used.load(Ordering::Relaxed) + units <= self.daily_units {
+            return true;
+        }
+        // The total might be from before the quota reset, so look again
+        match self.storage.youtube_quota_units().await {
+            Ok(used) => {
+                self.set_used(used);
+                used + units <= self.daily_units
+            }
+            Err(e) => {
+                log::warn!("Failed to load the YouTube quota usage: {}", e);
+                true
+            }
+        }
+    }
+
+    pub async fn spend(&self, units: i64) {
+        match self.storage.add_youtube_quota_units(units).await {
+            Ok(used) => self.set_used(used),
+            Err(e) => log::warn!("Failed to record YouTube quota usage: {}", e),
+        }
+    }
+
+    // Google says the quota is gone, whatever the estimate was
+    pub async fn exhaust(&self) {
+        if let Err(e) = self
+            .storage
+            .raise_youtube_quota_units(self.daily_units)
+            .await
+        {
+            log::warn!("Failed to record the exhausted YouTube quota: {}", e);
+        }
+        self.set_used(self.daily_units);
+    }
+
+    fn set_used(&self, used: i64) {
+        self.used.store(used, Ordering::Relaxed);
+        metrics::YOUTUBE_QUOTA_UNITS.set(used);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{downloader::AudioFile, DynError};
+use rustin_models::file_name;
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+// Telegram accepts documents of up to 50 MB from bots, the rest is left for
+// the ZIP headers
+const MAX_ARCHIVE_SIZE: u64 = 49 * 1024 * 1024;
+
+// A ZIP of songs in the media directory, waiting to be sent as a document
+pub struct Archive {
+    pub file_path: PathBuf,
+    // Shown to the user instead of the name on disk
+    pub file_name: String,
+}
+
+// Bundle the MP3s into as few archives as Telegram accepts. The MP3s are
+// copied into the archive one chunk at a time, so a long playlist is never
+// held in memory
+pub async fn write_archives(
+    media_dir: &Path,
+    audio: &[AudioFile],
+) -> Result<Vec<Archive>, DynError> {
+    let mut sizes = Vec::with_capacity(audio.len());
+    for song in audio {
+        sizes.push(tokio::fs::metadata(&song.file_path).await?.len());
+    }
+
+    let groups = group_by_size(&sizes, MAX_ARCHIVE_SIZE);
+    let count = groups.len();
+    let mut archives: Vec<Archive> = Vec::with_capacity(count);
+    for (part, group) in groups.into_iter().enumerate() {
+        let file_path = media_dir.join(format!("{}.zip", Uuid::new_v4().simple()));
+        let file_name = if count == 1 {
+            "songs.zip".to_string()
+        } else {
+            format!("songs-{}-of-{}.zip", part + 1, count)
+        };
+        // Synced lyrics go right next to their MP3, under the same name
+        let entries: Vec<_> = group
+            .flat_map(|index| {
+                let song = &audio[index];
+                let name = entry_name(index, song);
+                let lyrics = song.lyrics_path.clone().map(|path| {
+                    let name = format!("{}.lrc", name.trim_end_matches(".mp3"));
+                    (path, name)
+                });
+                std::iter::once((song.file_path.clone(), name)).chain(lyrics)
+            })
+            .collect();
+
+        let path = file_path.clone();
+        let written = tokio::task::spawn_blocking(move || write_zip(&path, &entries))
+            .await
+            .map_err(DynError::from)
+            .and_then(|result| result);
+        if let Err(e) = written {
+            // Don't leave the parts that did get written lying around
+            let written_paths: Vec<&Path> = archives
+                .iter()
+                .map(|archive| archive.file_path.as_path())
+                .chain([file_path.as_path()])
+                .collect();
+            for path in written_paths {
+                let _ = tokio::fs::remove_file(path).await;
+            }
+            return Err(e);
+        }
+        archives.push(Archive {
+            file_path,
+            file_name,
+        });
+    }
+    Ok(archives)
+}
+
+// Remove the MP3s, thumbnails and lyrics that went into the archives
+pub async fn remove_songs(audio: &[AudioFile]) {
+    let paths: Vec<&PathBuf> = audio
+        .iter()
+        .flat_map(|song| {
+            std::iter::once(&song.file_path)
+                .chain(&song.thumbnail_path)
+                .chain(&song.lyrics_path)
+        })
+        .collect();
+    for path in paths {
+        if let Err(e) = tokio::fs::remove_file(path).await {
+            log::warn!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+// MP3s are already compressed, so they're stored as they are
+fn write_zip(path: &Path, entries: &[(PathBuf, String)]) -> Result<(), DynError> {
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(path)?));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for (file_path, name) in entries {
+        zip.start_file(name.as_str(), options)?;
+        io::copy(&mut File::open(file_path)?, &mut zip)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+// Numbered so the songs keep the order of the request
+pub fn entry_name(index: usize, song: &AudioFile) -> String {
+    let name = file_name::song(&song.title, song.performer.as_deref(), "mp3");
+    format!("{:02} - {}", index + 1, name)
+}
+
+// Split the songs into runs whose sizes add up to at most max_size. A song
+// larger than that still gets an archive of its own
+fn group_by_size(sizes: &[u64], max_size: u64) -> Vec<Range<usize>> {
+    let mut groups = Vec::new();
+    let (mut start, mut total) = (0, 0);
+    for (index, &size) in sizes.iter().enumerate() {
+        if index > start && total + size > max_size {
+            groups.push(start..index);
+            start = index;
+            total = 0;
+        }
+        total += size;
+    }
+    if start < sizes.len() {
+        groups.push(start..sizes.len());
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str, performer: Option<&str>) -> AudioFile {
+        AudioFile {
+            file_path: PathBuf::from("song.mp3"),
+            title: title.to_string(),
+            performer: performer.map(str::to_string),
+            thumbnail_path: None,
+            cache_key: None,
+            caption: None,
+            lyrics_path: None,
+        }
+    }
+
+    #[test]
+    fn groups_songs_up_to_the_size_limit() {
+        assert_eq!(group_by_size(&[4, 4, 4, 4], 10), vec![0..2, 2..4]);
+        assert_eq!(group_by_size(&[3, 3, 3], 10), vec![0..3]);
+        assert_eq!(group_by_size(&[12, 1, 12], 10), vec![0..1, 1..2, 2..3]);
+        assert!(group_by_size(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn names_entries_after_the_song() {
+        assert_eq!(
+            entry_name(0, &song("Around the World", Some("Daft Punk"))),
+            "01 - Daft Punk - Around the World.mp3"
+        );
+        assert_eq!(
+            entry_name(11, &song("AC/DC: Thunderstruck?", None)),
+            "12 - AC_DC_ Thunderstruck_.mp3"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{error::SongError, http::Client};
+use rustin_models::bandcamp::{BandcampTrack, BandcampTralbum};
+
+// Bandcamp tracks go through the same caches, providers and history as
+// YouTube videos under an ID of this form, bandcamp:<artist>:<track>
+const ID_PREFIX: &str = "bandcamp:";
+// The only stream Bandcamp hands out without buying the track
+const STREAM_FORMAT: &str = "mp3-128";
+// Attribute of the track and album pages holding the embedded player's data
+const TRALBUM_ATTRIBUTE: &str = "data-tralbum=\"";
+
+pub fn track_id(artist: &str, slug: &str) -> String {
+    format!("{}{}:{}", ID_PREFIX, artist, slug)
+}
+
+// The page of a track ID, or None for YouTube video IDs
+pub fn track_url(id: &str) -> Option<String> {
+    let (artist, slug) = id.strip_prefix(ID_PREFIX)?.split_once(':')?;
+    Some(format!("https://{}.bandcamp.com/track/{}", artist, slug))
+}
+
+// Look up the "Artist - Track" title of a track, the way YouTube titles read
+pub async fn track_title(client: &Client, artist: &str, slug: &str) -> Result<String, SongError> {
+    let url = format!("https://{}.bandcamp.com/track/{}", artist, slug);
+    let tralbum = fetch_tralbum(client, &url).await?;
+    let track = tralbum
+        .trackinfo
+        .first()
+        .ok_or_else(|| SongError::Expansion(format!("no track on {}", url)))?;
+    Ok(format!("{} - {}", tralbum.artist, track.title))
+}
+
+// The title and track ID of every streamable track of an album, in order
+pub async fn album_tracks(
+    client: &Client,
+    artist: &str,
+    slug: &str,
+) -> Result<Vec<(String, String)>, SongError> {
+    let url = format!("https://{}.bandcamp.com/album/{}", artist, slug);
+    let tralbum = fetch_tralbum(client, &url).await?;
+
+    let tracks: Vec<_> = tralbum
+        .trackinfo
+        .iter()
+        .filter(|track| stream_of(track).is_some())
+        .filter_map(|track| {
+            let track_slug = track.title_link.as_deref()?.strip_prefix("/track/")?;
+            Some((
+                format!("{} - {}", tralbum.artist, track.title),
+                track_id(artist, track_slug),
+            ))
+        })
+        .collect();
+    if tracks.is_empty() {
+        return Err(SongError::Expansion(format!(
+            "no streamable tracks on {}",
+            url
+        )));
+    }
+    Ok(tracks)
+}
+
+// The MP3 stream of a track, straight from the embedded player
+pub async fn stream_url(client: &Client, id: &str) -> Result<String, SongError> {
+    let url = track_url(id)
+        .ok_or_else(|| SongError::Conversion(format!("{} is not a Bandcamp track", id)))?;
+    let tralbum = fetch_tralbum(client, &url).await?;
+    tralbum
+        .trackinfo
+        .first()
+        .and_then(stream_of)
+        .map(str::to_string)
+        .ok_or_else(|| SongError::Conversion(format!("{} can't be streamed", url)))
+}
+
+fn stream_of(track: &BandcampTrack) -> Option<&str> {
+    track.file.as_ref()?.get(STREAM_FORMAT).map(String::as_str)
+}
+
+async fn fetch_tralbum(client: &Client, page_url: &str) -> Result<BandcampTralbum, SongError> {
+    let html = client
+        .get(page_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    parse_tralbum(&html)
+        .ok_or_else(|| SongError::Expansion(format!("no player data on {}", page_url)))
+}
+
+// The player data is JSON, HTML escaped into an attribute
+fn parse_tralbum(html: &str) -> Option<BandcampTralbum> {
+    let start = html.find(TRALBUM_ATTRIBUTE)? + TRALBUM_ATTRIBUTE.len();
+    let end = start + html[start..].find('"')?;
+    let json = html[start..end]
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+    serde_json::from_str(&json)
+        .inspect_err(|e| log::warn!("Invalid Bandcamp player data: {}", e))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_ids_lead_back_to_the_track() {
+        let id = track_id("c418", "sweden");
+        assert_eq!(
+            track_url(&id).as_deref(),
+            Some("https://c418.bandcamp.com/track/sweden")
+        );
+        assert_eq!(track_url("dQw4w9WgXcQ"), None);
+    }
+
+    #[test]
+    fn reads_the_embedded_player_data() {
+        let html = r#"<script data-band="{}" data-tralbum="{&quot;artist&quot;:&quot;C418&quot;,&quot;trackinfo&quot;:[{&quot;title&quot;:&quot;Sweden &amp; Co&quot;,&quot;title_link&quot;:&quot;/track/sweden&quot;,&quot;file&quot;:{&quot;mp3-128&quot;:&quot;https://t4.bcbits.com/stream/abc&quot;}},{&quot;title&quot;:&quot;Bonus&quot;,&quot;title_link&quot;:&quot;/track/bonus&quot;,&quot;file&quot;:null}]}"></script>"#;
+        let tralbum = parse_tralbum(html).unwrap();
+        assert_eq!(tralbum.artist, "C418");
+        assert_eq!(tralbum.trackinfo[0].title, "Sweden & Co");
+        assert_eq!(
+            stream_of(&tralbum.trackinfo[0]),
+            Some("https://t4.bcbits.com/stream/abc")
+        );
+        assert_eq!(stream_of(&tralbum.trackinfo[1]), None);
+        assert!(parse_tralbum("<html></html>").is_none());
+    }
+}
@@ -0,0 +1,16 @@
+use rustin_storage::Storage;
+
+// Chats banned with /ban in the bot. Without storage, or when it can't be
+// reached, messages are let through rather than dropping everyone's requests
+pub async fn is_banned(storage: Option<&Storage>, chat_id: i64) -> bool {
+    let Some(storage) = storage else {
+        return false;
+    };
+    match storage.is_banned(chat_id).await {
+        Ok(banned) => banned,
+        Err(e) => {
+            log::warn!("Failed to check whether chat {} is banned: {}", chat_id, e);
+            false
+        }
+    }
+}
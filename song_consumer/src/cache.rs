@@ -0,0 +1,183 @@
+use crate::{config::Config, DynError};
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+// Search results rarely change, conversion links expire within hours
+pub const SEARCH_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+pub const LINK_TTL: Duration = Duration::from_secs(60 * 60);
+// Telegram keeps file_ids valid for a long time
+pub const FILE_ID_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+// Redeliveries happen within minutes, a day leaves plenty of margin
+pub const PROCESSED_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+// A cancelled request that is still queued is usually reached within hours
+pub const CANCELLED_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+// A song keeps its song.link page
+pub const SONG_LINK_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+// Search result buttons stop being useful once the conversation moves on
+pub const CANDIDATE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Key-value cache with per-entry expiry
+#[async_trait]
+pub trait Cache: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, DynError>;
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), DynError>;
+
+    // Whether entries outlive the process and are shared between instances
+    fn is_persistent(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryCache {
+    entries: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+#[async_trait]
+impl Cache for InMemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, DynError> {
+        let mut entries = self.entries.lock().await;
+        match entries.get(key) {
+            Some((value, expires_at)) if Instant::now() < *expires_at => Ok(Some(value.clone())),
+            Some(_) => {
+                entries.remove(key);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), DynError> {
+        let mut entries = self.entries.lock().await;
+        let now = Instant::now();
+        entries.retain(|_, (_, expires_at)| now < *expires_at);
+        entries.insert(key.to_string(), (value.to_string(), now + ttl));
+        Ok(())
+    }
+}
+
+pub struct RedisCache {
+    client: redis::Client,
+}
+
+impl RedisCache {
+    pub fn new(redis_url: &str) -> Result<Self, DynError> {
+        Ok(Self {
+            client: redis::Client::open(redis_url)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Cache for RedisCache {
+    async fn get(&self, key: &str) -> Result<Option<String>, DynError> {
+        let mut connection = self.client.get_multiplexed_async_connection().await?;
+        Ok(redis::cmd("GET")
+            .arg(key)
+            .query_async(&mut connection)
+            .await?)
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) -> Result<(), DynError> {
+        let mut connection = self.client.get_multiplexed_async_connection().await?;
+        redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("EX")
+            .arg(ttl.as_secs())
+            .query_async::<_, ()>(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    fn is_persistent(&self) -> bool {
+        true
+    }
+}
+
+// Redis when redis_url is set, so every consumer instance shares the cache
+pub fn from_config(config: &Config) -> Result<Arc<dyn Cache>, DynError> {
+    match &config.redis_url {
+        Some(redis_url) => Ok(Arc::new(RedisCache::new(redis_url)?)),
+        None => Ok(Arc::new(InMemoryCache::default())),
+    }
+}
+
+// Song title -> YouTube video ID
+pub fn search_key(title: &str) -> String {
+    format!("search:{}", normalize_title(title))
+}
+
+// YouTube video ID -> conversion link at a bitrate
+pub fn link_key(video_id: &str, bitrate: u32) -> String {
+    format!("link:{}:{}", video_id, bitrate)
+}
+
+// YouTube video ID -> Telegram file_id of the uploaded MP3 at a bitrate
+pub fn file_id_key(video_id: &str, bitrate: u32) -> String {
+    format!("file_id:{}:{}", video_id, bitrate)
+}
+
+// The same for MP3s with their loudness evened out, which mustn't be
+// resent to chats that want the original
+pub fn normalized_file_id_key(video_id: &str, bitrate: u32) -> String {
+    format!("file_id:{}:{}-normalized", video_id, bitrate)
+}
+
+// Message ID -> marker that the message has been processed and acked. Only
+// kept in Redis, see dedup.rs
+pub fn processed_key(message_id: &str) -> String {
+    format!("processed:{}", message_id)
+}
+
+// Request ID -> marker that the request was cancelled before it started
+pub fn cancelled_key(request_id: &str) -> String {
+    format!("cancelled:{}", request_id)
+}
+
+// Video ID a file_id_key was built from
+pub fn video_id_from_file_id_key(key: &str) -> Option<&str> {
+    let (video_id, _bitrate) = key.strip_prefix("file_id:")?.rsplit_once(':')?;
+    Some(video_id)
+}
+
+// YouTube video ID -> title of a search result offered in the picker
+pub fn candidate_key(video_id: &str) -> String {
+    format!("candidate:{}", video_id)
+}
+
+// YouTube video ID -> song.link page of the song
+pub fn song_link_key(video_id: &str) -> String {
+    format!("song_link:{}", video_id)
+}
+
+// Lowercase and collapse whitespace so trivially different lines share a key
+fn normalize_title(title: &str) -> String {
+    title
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Cache failures should never fail a conversion, so log them and carry on
+pub async fn get_or_log(cache: &dyn Cache, key: &str) -> Option<String> {
+    match cache.get(key).await {
+        Ok(value) => value,
+        Err(e) => {
+            log::warn!("Cache lookup for {} failed: {}", key, e);
+            None
+        }
+    }
+}
+
+pub async fn set_or_log(cache: &dyn Cache, key: &str, value: &str, ttl: Duration) {
+    if let Err(e) = cache.set(key, value, ttl).await {
+        log::warn!("Cache update for {} failed: {}", key, e);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    cache::{self, Cache},
+    instance, topology, DynError,
+};
+use futures_util::StreamExt;
+use lapin::{
+    options::{BasicAckOptions, BasicConsumeOptions},
+    types::FieldTable,
+    Channel,
+};
+use rustin_models::{MessageBody, RabbitMessage};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio_util::sync::CancellationToken;
+
+// Requests this replica is converting right now, by request ID
+#[derive(Default)]
+pub struct Registry {
+    running: Mutex<HashMap<String, (i64, CancellationToken)>>,
+}
+
+impl Registry {
+    // Track a request until the returned registration is dropped
+    pub fn register(self: &Arc<Self>, request_id: &str, chat_id: i64) -> Registration {
+        let token = CancellationToken::new();
+        self.lock()
+            .insert(request_id.to_string(), (chat_id, token.clone()));
+        Registration {
+            registry: Arc::clone(self),
+            request_id: request_id.to_string(),
+            token,
+        }
+    }
+
+    // Cancel a running request of the chat. Returns false if it isn't
+    // running on this replica
+    pub fn cancel(&self, request_id: &str, chat_id: i64) -> bool {
+        match self.lock().get(request_id) {
+            Some((owner, token)) if *owner == chat_id => {
+                token.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, (i64, CancellationToken)>> {
+        // The map stays consistent even if a holder panicked
+        self.running
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+pub struct Registration {
+    registry: Arc<Registry>,
+    request_id: String,
+    token: CancellationToken,
+}
+
+impl Registration {
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry.lock().remove(&self.request_id);
+    }
+}
+
+// Cancel the requests named on the control exchange. Requests that aren't
+// running here are remembered in the cache, so they are skipped if they are
+// still waiting in the queue
+pub async fn listen_for_cancellations(
+    channel: Channel,
+    registry: Arc<Registry>,
+    cache: Arc<dyn Cache>,
+) -> Result<(), DynError> {
+    let queue = topology::declare_control_queue(&channel).await?;
+    let mut consumer = channel
+        .basic_consume(
+            &queue,
+            &instance::consumer_tag("song_consumer_control"),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    while let Some(delivery) = consumer.next().await {
+        let delivery = match delivery {
+            Ok(delivery) => delivery,
+            Err(e) => {
+                log::error!("Failed to receive cancellation: {}", e);
+                continue;
+            }
+        };
+
+        match serde_json::from_slice::<RabbitMessage>(&delivery.data) {
+            Ok(RabbitMessage {
+                chat_id,
+                body: MessageBody::CancelRequest { request_id },
+                ..
+            }) => {
+                if registry.cancel(&request_id, chat_id) {
+                    log::info!("Cancelling request {}", request_id);
+                } else {
+                    cache::set_or_log(
+                        cache.as_ref(),
+                        &cache::cancelled_key(&request_id),
+                        &chat_id.to_string(),
+                        cache::CANCELLED_TTL,
+                    )
+                    .await;
+                }
+            }
+            Ok(other) => log::warn!("Unexpected message on '{}': {:?}", queue, other),
+            Err(e) => log::error!("Failed to parse cancellation: {}", e),
+        }
+
+        delivery.ack(BasicAckOptions::default()).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancels_running_requests_of_the_same_chat() {
+        let registry = Arc::new(Registry::default());
+        let registration = registry.register("abc", 1);
+        assert!(!registry.cancel("abc", 2));
+        assert!(!registration.token().is_cancelled());
+        assert!(registry.cancel("abc", 1));
+        assert!(registration.token().is_cancelled());
+    }
+
+    #[test]
+    fn forgets_requests_once_they_finish() {
+        let registry = Arc::new(Registry::default());
+        drop(registry.register("abc", 1));
+        assert!(!registry.cancel("abc", 1));
+    }
+}
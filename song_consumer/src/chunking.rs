@@ -0,0 +1,98 @@
+// Telegram rejects messages longer than this many characters
+pub const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+
+// Join the entries with newlines into as few messages as possible. Entries
+// are only split when they don't fit into a message on their own, so
+// numbering and Markdown within an entry stay intact
+pub fn chunk_entries(entries: &[String], limit: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+
+    for entry in entries {
+        for piece in split_oversized(entry, limit) {
+            let piece_len = piece.chars().count();
+            if !current.is_empty() && current_len + 1 + piece_len > limit {
+                chunks.push(std::mem::take(&mut current));
+                current_len = 0;
+            }
+            if !current.is_empty() {
+                current.push('\n');
+                current_len += 1;
+            }
+            current.push_str(&piece);
+            current_len += piece_len;
+        }
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+// An entry that is too long by itself is split on its lines, and lines that
+// are still too long are cut at the limit
+fn split_oversized(entry: &str, limit: usize) -> Vec<String> {
+    if entry.chars().count() <= limit {
+        return vec![entry.to_string()];
+    }
+
+    entry
+        .lines()
+        .flat_map(|line| {
+            let chars: Vec<char> = line.chars().collect();
+            chars
+                .chunks(limit)
+                .map(|chunk| chunk.iter().collect::<String>())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(count: usize, len: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| format!("{}", i % 10).repeat(len))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_short_lists_in_one_message() {
+        let entries = vec!["1. a".to_string(), "2. b".to_string()];
+        assert_eq!(chunk_entries(&entries, 100), vec!["1. a\n2. b"]);
+    }
+
+    #[test]
+    fn splits_on_entry_boundaries() {
+        let chunks = chunk_entries(&entries(10, 30), 100);
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|chunk| chunk.chars().count() <= 100));
+        assert_eq!(chunks.join("\n"), entries(10, 30).join("\n"));
+    }
+
+    #[test]
+    fn keeps_multi_line_entries_together() {
+        let entries = vec![
+            "1. 🎵 *First*\n🔗 https://a".to_string(),
+            "2. 🎵 *Second*\n🔗 https://b".to_string(),
+        ];
+        let chunks = chunk_entries(&entries, 30);
+        assert_eq!(chunks, entries);
+    }
+
+    #[test]
+    fn cuts_entries_longer_than_the_limit() {
+        let chunks = chunk_entries(&["x".repeat(25)], 10);
+        assert_eq!(chunks, vec!["x".repeat(10), "x".repeat(10), "x".repeat(5)]);
+    }
+
+    #[test]
+    fn counts_characters_not_bytes() {
+        let entries = vec!["é".repeat(6), "é".repeat(3)];
+        assert_eq!(chunk_entries(&entries, 10), vec![entries.join("\n")]);
+    }
+}
@@ -0,0 +1,211 @@
+// Stops calling a provider once most of its recent calls failed, so requests
+// go straight to the fallbacks instead of waiting for it to time out. After a
+// cooldown a single trial call is let through, and the breaker closes again
+// if it succeeds
+
+use crate::{alerts, metrics};
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+// Outcomes of the last calls the failure rate is taken over
+const WINDOW: usize = 20;
+// Too few calls say nothing about the provider
+const MIN_CALLS: usize = 10;
+// Failed calls out of the window that open the breaker
+const MAX_FAILURE_RATE: f64 = 0.5;
+const OPEN_DURATION: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Closed,
+    Open { until: Instant },
+    // The trial call is running. A trial whose request was cancelled never
+    // reports back, so another one is allowed after until
+    HalfOpen { until: Instant },
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: State,
+    // true for calls that succeeded, most recent last
+    outcomes: VecDeque<bool>,
+}
+
+impl Default for Circuit {
+    fn default() -> Self {
+        Self {
+            state: State::Closed,
+            outcomes: VecDeque::with_capacity(WINDOW),
+        }
+    }
+}
+
+impl Circuit {
+    fn allow(&mut self, now: Instant) -> bool {
+        match self.state {
+            State::Closed => true,
+            State::Open { until } | State::HalfOpen { until } if now < until => false,
+            State::Open { .. } | State::HalfOpen { .. } => {
+                self.state = State::HalfOpen {
+                    until: now + OPEN_DURATION,
+                };
+                true
+            }
+        }
+    }
+
+    fn record(&mut self, succeeded: bool, now: Instant) {
+        match self.state {
+            State::Closed => {}
+            // A call that started before the breaker opened
+            State::Open { .. } => return,
+            State::HalfOpen { .. } => {
+                self.state = if succeeded {
+                    State::Closed
+                } else {
+                    State::Open {
+                        until: now + OPEN_DURATION,
+                    }
+                };
+                return;
+            }
+        }
+
+        if self.outcomes.len() == WINDOW {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(succeeded);
+        let failures = self
+            .outcomes
+            .iter()
+            .filter(|&&succeeded| !succeeded)
+            .count();
+        if self.outcomes.len() >= MIN_CALLS
+            && failures as f64 / self.outcomes.len() as f64 >= MAX_FAILURE_RATE
+        {
+            self.outcomes.clear();
+            self.state = State::Open {
+                until: now + OPEN_DURATION,
+            };
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.state != State::Closed
+    }
+}
+
+pub struct CircuitBreaker {
+    name: &'static str,
+    circuit: Mutex<Circuit>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str) -> Self {
+        metrics::CIRCUIT_OPEN.with_label_values(&[name]).set(0);
+        Self {
+            name,
+            circuit: Mutex::new(Circuit::default()),
+        }
+    }
+
+    // Whether the provider should be called now
+    pub fn allow(&self) -> bool {
+        self.lock().allow(Instant::now())
+    }
+
+    pub fn record(&self, succeeded: bool) {
+        let mut circuit = self.lock();
+        let was_open = circuit.is_open();
+        circuit.record(succeeded, Instant::now());
+        match (was_open, circuit.is_open()) {
+            (false, true) => {
+                log::warn!(
+                    "Too many calls to {} failed, leaving it alone for {:?}",
+                    self.name,
+                    OPEN_DURATION
+                );
+                alerts::circuit_opened(self.name, OPEN_DURATION);
+            }
+            (true, false) => log::info!("{} is working again", self.name),
+            _ => {}
+        }
+        metrics::CIRCUIT_OPEN
+            .with_label_values(&[self.name])
+            .set(circuit.is_open() as i64);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Circuit> {
+        self.circuit
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(circuit: &mut Circuit, succeeded: bool, times: usize, now: Instant) {
+        for _ in 0..times {
+            circuit.record(succeeded, now);
+        }
+    }
+
+    #[test]
+    fn opens_once_most_calls_fail() {
+        let now = Instant::now();
+        let mut circuit = Circuit::default();
+        record(&mut circuit, false, MIN_CALLS - 1, now);
+        assert!(circuit.allow(now));
+
+        record(&mut circuit, false, 1, now);
+        assert!(!circuit.allow(now));
+        assert!(!circuit.allow(now + OPEN_DURATION / 2));
+    }
+
+    #[test]
+    fn stays_closed_while_most_calls_succeed() {
+        let now = Instant::now();
+        let mut circuit = Circuit::default();
+        for _ in 0..WINDOW {
+            record(&mut circuit, true, 2, now);
+            record(&mut circuit, false, 1, now);
+        }
+        assert!(circuit.allow(now));
+    }
+
+    #[test]
+    fn lets_one_trial_call_through_after_the_cooldown() {
+        let now = Instant::now();
+        let mut circuit = Circuit::default();
+        record(&mut circuit, false, MIN_CALLS, now);
+
+        let later = now + OPEN_DURATION;
+        assert!(circuit.allow(later));
+        assert!(!circuit.allow(later));
+        circuit.record(false, later);
+        assert!(!circuit.allow(later));
+
+        let even_later = later + OPEN_DURATION;
+        assert!(circuit.allow(even_later));
+        circuit.record(true, even_later);
+        assert!(circuit.allow(even_later));
+        assert!(circuit.allow(even_later));
+    }
+
+    #[test]
+    fn allows_another_trial_when_one_never_reports_back() {
+        let now = Instant::now();
+        let mut circuit = Circuit::default();
+        record(&mut circuit, false, MIN_CALLS, now);
+
+        let later = now + OPEN_DURATION;
+        assert!(circuit.allow(later));
+        assert!(!circuit.allow(later + OPEN_DURATION / 2));
+        assert!(circuit.allow(later + OPEN_DURATION));
+    }
+}
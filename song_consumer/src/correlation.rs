@@ -0,0 +1,46 @@
+use lapin::{
+    message::Delivery,
+    types::{AMQPValue, FieldTable, ShortString},
+};
+use rustin_models::REQUEST_ID_HEADER;
+use uuid::Uuid;
+
+// The request ID the bot attached to this delivery. Messages published
+// without one (e.g. by the webhook publisher) get a fresh ID
+pub fn request_id(delivery: &Delivery) -> String {
+    delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(&ShortString::from(REQUEST_ID_HEADER)))
+        .and_then(|value| match value {
+            AMQPValue::LongString(id) => Some(id.to_string()),
+            AMQPValue::ShortString(id) => Some(id.to_string()),
+            _ => None,
+        })
+        .unwrap_or_else(|| Uuid::new_v4().simple().to_string())
+}
+
+// The unique ID the producer gave this message, kept when it's retried.
+// Messages published without one can't be deduplicated
+pub fn message_id(delivery: &Delivery) -> Option<String> {
+    delivery
+        .properties
+        .message_id()
+        .as_ref()
+        .map(|id| id.to_string())
+}
+
+// Headers that pass the request ID and the trace it's part of on to the
+// next queue
+pub fn headers(request_id: &str, trace_context: Option<&str>) -> FieldTable {
+    let mut headers = FieldTable::default();
+    headers.insert(
+        REQUEST_ID_HEADER.into(),
+        AMQPValue::LongString(request_id.into()),
+    );
+    if let Some(trace_context) = trace_context {
+        rustin_telemetry::insert_trace_context(&mut headers, trace_context);
+    }
+    headers
+}
@@ -0,0 +1,50 @@
+use crate::cache::{self, Cache};
+use rustin_storage::Storage;
+
+// Markers that a message has been processed have to outlive a consumer that
+// died before acking, so they're kept in Redis, or in Postgres without it.
+// With neither, a redelivered message is processed again
+pub async fn already_processed(
+    cache: &dyn Cache,
+    storage: Option<&Storage>,
+    message_id: &str,
+) -> bool {
+    if cache.is_persistent() {
+        return cache::get_or_log(cache, &cache::processed_key(message_id))
+            .await
+            .is_some();
+    }
+    let Some(storage) = storage else {
+        return false;
+    };
+    match storage.is_processed(message_id, cache::PROCESSED_TTL).await {
+        Ok(processed) => processed,
+        Err(e) => {
+            log::warn!(
+                "Failed to check whether message {} was processed: {}",
+                message_id,
+                e
+            );
+            false
+        }
+    }
+}
+
+pub async fn mark_processed(cache: &dyn Cache, storage: Option<&Storage>, message_id: &str) {
+    if cache.is_persistent() {
+        cache::set_or_log(
+            cache,
+            &cache::processed_key(message_id),
+            "1",
+            cache::PROCESSED_TTL,
+        )
+        .await;
+    } else if let Some(storage) = storage {
+        if let Err(e) = storage
+            .mark_processed(message_id, cache::PROCESSED_TTL)
+            .await
+        {
+            log::warn!("Failed to mark message {} as processed: {}", message_id, e);
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use crate::DynError;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::{Digest, Md5};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_RANGE, ETAG, IF_RANGE, RANGE},
+    Client, StatusCode,
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+// Attempts at finishing one download, each resuming where the last broke off
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+// A connection this quiet is given up on and resumed
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(30);
+// Obsolete since RFC 7231, so http has no constant for it. Some CDNs still
+// send it
+const CONTENT_MD5: HeaderName = HeaderName::from_static("content-md5");
+
+// A downloaded MP3 waiting to be uploaded to Telegram
+pub struct AudioFile {
+    pub file_path: PathBuf,
+    pub title: String,
+    pub performer: Option<String>,
+    // JPEG next to the MP3, shown by Telegram as the audio thumbnail
+    pub thumbnail_path: Option<PathBuf>,
+    // Where the reply service should report the Telegram file_id
+    pub cache_key: Option<String>,
+    // Shown under the audio, e.g. when another upload than the best match
+    // was used
+    pub caption: Option<String>,
+    // Synced lyrics next to the MP3, for chats that asked for them
+    pub lyrics_path: Option<PathBuf>,
+}
+
+// An MP3 that was uploaded before and can be resent by its file_id
+pub struct CachedAudio {
+    pub file_id: String,
+    pub title: String,
+    pub performer: Option<String>,
+    pub caption: Option<String>,
+    pub lyrics_path: Option<PathBuf>,
+}
+
+// A downloaded MP4 waiting to be uploaded to Telegram as a video
+pub struct VideoFile {
+    pub file_path: PathBuf,
+    pub title: String,
+}
+
+// Stream the MP3 behind a download link into the media directory. The file is
+// written under a temporary name first so a half-finished download is never
+// picked up by the reply service, and a broken connection is resumed with a
+// Range request instead of starting over
+pub async fn download_mp3(
+    client: &Client,
+    dlink: &str,
+    media_dir: &Path,
+    video_id: &str,
+) -> Result<PathBuf, DynError> {
+    download(client, dlink, media_dir, video_id, "mp3").await
+}
+
+// Same as download_mp3, for the MP4 of a video
+pub async fn download_mp4(
+    client: &Client,
+    dlink: &str,
+    media_dir: &Path,
+    video_id: &str,
+) -> Result<PathBuf, DynError> {
+    download(client, dlink, media_dir, video_id, "mp4").await
+}
+
+async fn download(
+    client: &Client,
+    dlink: &str,
+    media_dir: &Path,
+    video_id: &str,
+    extension: &str,
+) -> Result<PathBuf, DynError> {
+    let part_path = media_dir.join(format!("{}.{}.part", video_id, extension));
+    let file_path = media_dir.join(format!("{}.{}", video_id, extension));
+
+    // Whatever an earlier run left behind may be of another file
+    remove_part(&part_path).await;
+    let mut expected = Expected::default();
+    let mut attempt = 1;
+    loop {
+        match resume(client, dlink, &part_path, &mut expected).await {
+            Ok(()) => break,
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                log::warn!(
+                    "Download of video ID {} failed on attempt {}, resuming: {}",
+                    video_id,
+                    attempt,
+                    e
+                );
+                tokio::time::sleep(RETRY_DELAY * attempt).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                remove_part(&part_path).await;
+                return Err(e);
+            }
+        }
+    }
+    tokio::fs::rename(&part_path, &file_path).await?;
+
+    log::info!(
+        "Downloaded {} bytes for video ID {} to {} in {} attempt(s)",
+        tokio::fs::metadata(&file_path).await?.len(),
+        video_id,
+        file_path.display(),
+        attempt
+    );
+    Ok(file_path)
+}
+
+// What the server told about the whole file, kept across attempts
+#[derive(Default)]
+struct Expected {
+    length: Option<u64>,
+    // Hex, from Content-MD5 or an ETag that is a plain MD5
+    md5: Option<String>,
+    // Sent back in If-Range, so a file that changed meanwhile is sent whole
+    etag: Option<HeaderValue>,
+}
+
+// Append what's still missing to the part file and check the finished file.
+// A broken connection keeps what was written, so the next attempt picks up
+// from there, while a corrupt file is removed so it starts over
+async fn resume(
+    client: &Client,
+    dlink: &str,
+    part_path: &Path,
+    expected: &mut Expected,
+) -> Result<(), DynError> {
+    let offset = match tokio::fs::metadata(part_path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut request = client.get(dlink);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+        if let Some(etag) = &expected.etag {
+            request = request.header(IF_RANGE, etag.clone());
+        }
+    }
+    let response = request.send().await?;
+    // The last attempt broke off after the final chunk
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE && expected.length == Some(offset) {
+        return verify(part_path, expected).await;
+    }
+    let mut response = response.error_for_status()?;
+
+    let headers = response.headers();
+    let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+    let mut file = if resumed {
+        let range = header(headers, CONTENT_RANGE)
+            .and_then(parse_content_range)
+            .ok_or("invalid Content-Range")?;
+        if range.start != offset {
+            remove_part(part_path).await;
+            return Err(format!("resumed at {} instead of {}", range.start, offset).into());
+        }
+        expected.length = range.total.or(expected.length);
+        OpenOptions::new().append(true).open(part_path).await?
+    } else {
+        // A server that ignores Range sends the whole file again
+        expected.length = response.content_length();
+        expected.md5 = header(headers, CONTENT_MD5).and_then(md5_from_content_md5);
+        File::create(part_path).await?
+    };
+    if let Some(etag) = headers.get(ETAG) {
+        expected.md5 = expected
+            .md5
+            .take()
+            .or_else(|| etag.to_str().ok().and_then(md5_from_etag));
+        expected.etag = Some(etag.clone());
+    }
+
+    // Written chunks are flushed even when the connection breaks, they're
+    // kept for the next attempt
+    let streamed: Result<(), DynError> = async {
+        loop {
+            let chunk = tokio::time::timeout(CHUNK_TIMEOUT, response.chunk())
+                .await
+                .map_err(|_| "download stalled")??;
+            let Some(chunk) = chunk else {
+                return Ok(());
+            };
+            file.write_all(&chunk).await?;
+        }
+    }
+    .await;
+    file.flush().await?;
+    streamed?;
+    verify(part_path, expected).await
+}
+
+// Check the length and checksum the server announced, when it did
+async fn verify(part_path: &Path, expected: &Expected) -> Result<(), DynError> {
+    let length = tokio::fs::metadata(part_path).await?.len();
+    match expected.length {
+        // Resumed on the next attempt
+        Some(expected) if length < expected => {
+            return Err(format!("connection closed after {} of {} bytes", length, expected).into())
+        }
+        Some(expected) if length > expected => {
+            remove_part(part_path).await;
+            return Err(format!("got {} bytes instead of {}", length, expected).into());
+        }
+        _ => {}
+    }
+    if let Some(md5) = &expected.md5 {
+        let actual = md5_of(part_path).await?;
+        if &actual != md5 {
+            remove_part(part_path).await;
+            return Err(format!("MD5 is {} instead of {}", actual, md5).into());
+        }
+    }
+    Ok(())
+}
+
+async fn md5_of(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Md5::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+async fn remove_part(part_path: &Path) {
+    match tokio::fs::remove_file(part_path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!("Failed to remove {}: {}", part_path.display(), e),
+    }
+}
+
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+#[derive(Debug, PartialEq)]
+struct ContentRange {
+    start: u64,
+    // Unknown when the server sends "*"
+    total: Option<u64>,
+}
+
+// "bytes 1000-4999/5000"
+fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let start: u64 = start.trim().parse().ok()?;
+    let end: u64 = end.trim().parse().ok()?;
+    if end < start {
+        return None;
+    }
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some(ContentRange { start, total })
+}
+
+// Content-MD5 is the base64 of the digest
+fn md5_from_content_md5(value: &str) -> Option<String> {
+    let digest = STANDARD.decode(value.trim()).ok()?;
+    (digest.len() == 16).then(|| digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Storage services like S3 use the MD5 of the file as its ETag, unless it
+// was uploaded in parts or the ETag is weak
+fn md5_from_etag(value: &str) -> Option<String> {
+    let etag = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    (etag.len() == 32 && etag.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| etag.to_ascii_lowercase())
+}
+
+// Split an "Artist - Title" line into the performer and title shown by Telegram
+pub fn split_artist_title(song: &str) -> (Option<String>, String) {
+    match song.split_once(" - ") {
+        Some((artist, title)) if !artist.trim().is_empty() && !title.trim().is_empty() => {
+            (Some(artist.trim().to_string()), title.trim().to_string())
+        }
+        _ => (None, song.trim().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_content_ranges() {
+        assert_eq!(
+            parse_content_range("bytes 1000-4999/5000"),
+            Some(ContentRange {
+                start: 1000,
+                total: Some(5000)
+            })
+        );
+        assert_eq!(
+            parse_content_range("bytes 0-99/*"),
+            Some(ContentRange {
+                start: 0,
+                total: None
+            })
+        );
+        assert_eq!(parse_content_range("bytes */5000"), None);
+        assert_eq!(parse_content_range("bytes 99-0/5000"), None);
+    }
+
+    #[test]
+    fn reads_md5_checksums() {
+        assert_eq!(
+            md5_from_content_md5("1B2M2Y8AsgTpgAmY7PhCfg=="),
+            Some("d41d8cd98f00b204e9800998ecf8427e".to_string())
+        );
+        assert_eq!(md5_from_content_md5("not base64"), None);
+        assert_eq!(
+            md5_from_etag("\"D41D8CD98F00B204E9800998ECF8427E\""),
+            Some("d41d8cd98f00b204e9800998ecf8427e".to_string())
+        );
+        // Multipart uploads and weak ETags aren't digests of the file
+        assert_eq!(
+            md5_from_etag("\"d41d8cd98f00b204e9800998ecf8427e-3\""),
+            None
+        );
+        assert_eq!(
+            md5_from_etag("W/\"d41d8cd98f00b204e9800998ecf8427e\""),
+            None
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use std::{collections::HashMap, hash::Hash};
+
+// For every item, the index of the first item with the same key when it's a
+// repeat of one before it
+pub fn first_occurrences<K: Hash + Eq>(keys: impl IntoIterator<Item = K>) -> Vec<Option<usize>> {
+    let mut seen = HashMap::new();
+    keys.into_iter()
+        .enumerate()
+        .map(|(index, key)| match seen.get(&key) {
+            Some(&first) => Some(first),
+            None => {
+                seen.insert(key, index);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_repeats_at_their_first_occurrence() {
+        assert_eq!(
+            first_occurrences(["a", "b", "a", "c", "b", "a"]),
+            vec![None, None, Some(0), None, Some(1), Some(0)]
+        );
+        assert!(first_occurrences(Vec::<&str>::new()).is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+// Novelty effects users can ask for at the end of a request line
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Effect {
+    // Faster and higher pitched
+    Nightcore,
+    // Slower and lower pitched, with an echo standing in for reverb
+    SlowedReverb,
+}
+
+impl Effect {
+    // The ffmpeg audio filter of the effect. Speed and pitch change together
+    // by playing the samples at another rate, so the rate is pinned to
+    // 44.1 kHz first whatever the source was
+    pub fn filter(&self) -> &'static str {
+        match self {
+            Effect::Nightcore => "aresample=44100,asetrate=55125,aresample=44100",
+            Effect::SlowedReverb => {
+                "aresample=44100,asetrate=37485,aresample=44100,aecho=0.8:0.88:60:0.4"
+            }
+        }
+    }
+
+    // Added to the title, so the edited song is told apart from the original
+    pub fn label(&self) -> &'static str {
+        match self {
+            Effect::Nightcore => "nightcore",
+            Effect::SlowedReverb => "slowed + reverb",
+        }
+    }
+}
+
+// Split a trailing effect suffix such as "+nightcore" off a request line
+pub fn split_effect_suffix(line: &str) -> (&str, Option<Effect>) {
+    let trimmed = line.trim_end();
+    if let Some((rest, suffix)) = trimmed.rsplit_once(" +") {
+        let effect = match suffix.to_lowercase().as_str() {
+            "nightcore" => Some(Effect::Nightcore),
+            "slowed" | "slowed+reverb" | "slowedreverb" => Some(Effect::SlowedReverb),
+            _ => None,
+        };
+        if effect.is_some() {
+            return (rest.trim_end(), effect);
+        }
+    }
+    (line, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_effect_suffix() {
+        assert_eq!(
+            split_effect_suffix("Daft Punk - One More Time +nightcore"),
+            ("Daft Punk - One More Time", Some(Effect::Nightcore))
+        );
+        assert_eq!(
+            split_effect_suffix("Song +Slowed+Reverb "),
+            ("Song", Some(Effect::SlowedReverb))
+        );
+        assert_eq!(split_effect_suffix("Song +1"), ("Song +1", None));
+        assert_eq!(split_effect_suffix("C+nightcore"), ("C+nightcore", None));
+        assert_eq!(split_effect_suffix("Plain title"), ("Plain title", None));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::providers::CloudflareChallenge;
+use reqwest::{Response, StatusCode};
+use rustin_models::i18n;
+use thiserror::Error;
+
+// Why a song (or a whole request) could not be turned into MP3s. The
+// variant decides whether the message is retried and what the user is told
+#[derive(Debug, Error)]
+pub enum SongError {
+    // The YouTube Data API key has used up its daily quota
+    #[error("YouTube API quota exceeded")]
+    QuotaExceeded,
+    #[error("YouTube API returned {0}")]
+    YouTubeApi(StatusCode),
+    #[error("couldn't expand link: {0}")]
+    Expansion(String),
+    #[error("no text found in photo")]
+    NoText,
+    #[error("no songs found in tracklist")]
+    NoTracks,
+    #[error("text recognition failed: {0}")]
+    Ocr(String),
+    #[error("no song recognized in recording")]
+    NotRecognized,
+    #[error("recognition failed: {0}")]
+    Recognition(String),
+    #[error(transparent)]
+    CloudflareBlocked(#[from] CloudflareChallenge),
+    #[error("no lyrics found")]
+    NoLyrics,
+    // Every search result was a live stream or premiere
+    #[error("only live streams found")]
+    LiveStream,
+    #[error("conversion failed: {0}")]
+    Conversion(String),
+    // None of the songs of a YouTube playlist could be found
+    #[error("no songs found")]
+    NotFound,
+    // The user has to sign in to YouTube before a playlist can be created
+    #[error("not signed in to YouTube")]
+    NotSignedIn,
+    #[error("database error: {0}")]
+    Storage(#[from] rustin_storage::Error),
+    #[error("network error: {0}")]
+    Network(#[from] reqwest_middleware::Error),
+}
+
+// Errors of responses, which are read past the middleware
+impl From<reqwest::Error> for SongError {
+    fn from(e: reqwest::Error) -> Self {
+        SongError::Network(e.into())
+    }
+}
+
+impl SongError {
+    // Transient failures that are likely to go away when the message is
+    // processed again. Everything else is reported to the user right away
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SongError::YouTubeApi(status) => status.is_server_error(),
+            SongError::Expansion(_) | SongError::Network(_) | SongError::Storage(_) => true,
+            SongError::QuotaExceeded
+            | SongError::NoText
+            | SongError::NoTracks
+            | SongError::Ocr(_)
+            | SongError::NotRecognized
+            | SongError::Recognition(_)
+            | SongError::NoLyrics
+            | SongError::CloudflareBlocked(_)
+            | SongError::LiveStream
+            | SongError::Conversion(_)
+            | SongError::NotFound
+            | SongError::NotSignedIn => false,
+        }
+    }
+
+    // Failures on our side or of the services we rely on, e.g. a provider
+    // whose API changed, as opposed to requests that can't be fulfilled
+    pub fn is_reportable(&self) -> bool {
+        match self {
+            SongError::QuotaExceeded
+            | SongError::YouTubeApi(_)
+            | SongError::Expansion(_)
+            | SongError::Ocr(_)
+            | SongError::Recognition(_)
+            | SongError::CloudflareBlocked(_)
+            | SongError::Conversion(_)
+            | SongError::Storage(_)
+            | SongError::Network(_) => true,
+            SongError::NoText
+            | SongError::NoTracks
+            | SongError::NotRecognized
+            | SongError::NoLyrics
+            | SongError::LiveStream
+            | SongError::NotFound
+            | SongError::NotSignedIn => false,
+        }
+    }
+
+    // What to tell the user when their whole request is given up on
+    pub fn user_message(&self, language: Option<&str>) -> String {
+        let key = match self {
+            SongError::QuotaExceeded => "request.quota_exceeded",
+            SongError::NoText => "request.no_text",
+            SongError::NoTracks => "request.no_tracks",
+            SongError::NotRecognized => "request.not_recognized",
+            SongError::Expansion(_) => "request.link_failed",
+            SongError::LiveStream => "request.live_stream",
+            SongError::NoLyrics => "request.no_lyrics",
+            SongError::NotFound => "request.not_found",
+            SongError::NotSignedIn => "request.youtube_sign_in",
+            _ => "request.failed",
+        };
+        i18n::tr(language, key)
+    }
+}
+
+// Like error_for_status, but recognizes the 403 YouTube answers with once the
+// API key is out of quota
+pub async fn check_youtube_response(response: Response) -> Result<Response, SongError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    if status == StatusCode::FORBIDDEN && body.contains("quotaExceeded") {
+        log::error!("YouTube Data API quota exceeded");
+        return Err(SongError::QuotaExceeded);
+    }
+    log::error!("YouTube API returned {}: {}", status, body);
+    Err(SongError::YouTubeApi(status))
+}
@@ -0,0 +1,154 @@
+use crate::{effects::Effect, error::SongError};
+use rustin_models::clip::Clip;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+use tokio::process::Command;
+use uuid::Uuid;
+
+// Long enough for a recognizer to match a song, short enough to upload quickly
+pub const SAMPLE_LENGTH: Duration = Duration::from_secs(10);
+// Skip the intro of full songs, which tends to be hard to recognize
+pub const SAMPLE_OFFSET: Duration = Duration::from_secs(30);
+// Loudness every normalized song is brought to, in LUFS, with the true peak
+// and loudness range streaming services commonly aim for
+const LOUDNORM_FILTER: &str = "loudnorm=I=-14:TP=-1.5:LRA=11";
+
+static BINARY: OnceLock<String> = OnceLock::new();
+
+// Set the ffmpeg binary once at startup, before any song is converted
+pub fn init(binary: String) {
+    if BINARY.set(binary).is_err() {
+        log::warn!("ffmpeg path was already initialised, keeping the first one");
+    }
+}
+
+fn binary() -> &'static str {
+    BINARY.get_or_init(|| "ffmpeg".to_string())
+}
+
+// Cut a mono MP3 sample out of any audio or video file ffmpeg can read. Files
+// shorter than the offset are sampled from the start instead
+pub async fn extract_sample(media: &[u8]) -> Result<Vec<u8>, SongError> {
+    let sample = extract(media, SAMPLE_OFFSET).await?;
+    if !sample.is_empty() {
+        return Ok(sample);
+    }
+    extract(media, Duration::ZERO).await
+}
+
+async fn extract(media: &[u8], offset: Duration) -> Result<Vec<u8>, SongError> {
+    // ffmpeg needs to seek in MP4 containers, so work on temporary files
+    // rather than pipes
+    let id = Uuid::new_v4().simple().to_string();
+    let input = temp_path(&format!("{}.in", id));
+    let output = temp_path(&format!("{}.mp3", id));
+    tokio::fs::write(&input, media)
+        .await
+        .map_err(|e| SongError::Conversion(format!("Failed to write sample input: {}", e)))?;
+
+    let result = run(&[
+        "-hide_banner",
+        "-loglevel",
+        "error",
+        "-y",
+        "-ss",
+        &offset.as_secs().to_string(),
+        "-t",
+        &SAMPLE_LENGTH.as_secs().to_string(),
+        "-i",
+        &input.to_string_lossy(),
+        "-vn",
+        "-ac",
+        "1",
+        "-f",
+        "mp3",
+        &output.to_string_lossy(),
+    ])
+    .await;
+    let sample = match result {
+        Ok(()) => Ok(tokio::fs::read(&output).await.unwrap_or_default()),
+        Err(e) => Err(e),
+    };
+
+    for path in [&input, &output] {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    sample
+}
+
+// Re-encode an MP3 in place at a lower bitrate
+pub async fn reencode(file_path: &Path, bitrate: u32) -> Result<(), SongError> {
+    transcode(file_path, bitrate, &[]).await
+}
+
+// Even out the loudness of an MP3 in place, keeping its bitrate
+pub async fn normalize(file_path: &Path, bitrate: u32) -> Result<(), SongError> {
+    transcode(file_path, bitrate, &["-af", LOUDNORM_FILTER]).await
+}
+
+// Keep only the clip of an MP3, in place
+pub async fn cut(file_path: &Path, clip: Clip, bitrate: u32) -> Result<(), SongError> {
+    let (start, end) = (clip.start.to_string(), clip.end.to_string());
+    transcode(file_path, bitrate, &["-ss", &start, "-to", &end]).await
+}
+
+// Apply a speed and pitch effect to an MP3, in place
+pub async fn apply_effect(file_path: &Path, effect: Effect, bitrate: u32) -> Result<(), SongError> {
+    transcode(file_path, bitrate, &["-af", effect.filter()]).await
+}
+
+// The original is only replaced once the new file is complete
+async fn transcode(file_path: &Path, bitrate: u32, filters: &[&str]) -> Result<(), SongError> {
+    let output = file_path.with_extension("transcoded.mp3");
+    let input = file_path.to_string_lossy();
+    let bitrate = format!("{}k", bitrate);
+    let output_arg = output.to_string_lossy();
+    let mut args: Vec<&str> = vec![
+        "-hide_banner",
+        "-loglevel",
+        "error",
+        "-y",
+        "-i",
+        &input,
+        "-vn",
+    ];
+    args.extend_from_slice(filters);
+    args.extend(["-codec:a", "libmp3lame", "-b:a", &*bitrate, &*output_arg]);
+    let result = run(&args).await;
+
+    match result {
+        Ok(()) => tokio::fs::rename(&output, file_path).await.map_err(|e| {
+            SongError::Conversion(format!("Failed to replace {}: {}", file_path.display(), e))
+        }),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&output).await;
+            Err(e)
+        }
+    }
+}
+
+async fn run(args: &[&str]) -> Result<(), SongError> {
+    let output = Command::new(binary())
+        .args(args)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| SongError::Conversion(format!("Failed to run ffmpeg: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(SongError::Conversion(format!(
+            "ffmpeg exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("rustin-sample-{}", name))
+}
@@ -0,0 +1,92 @@
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use lapin::ConnectionStatus;
+use reqwest::Client;
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+// Cheap endpoint that answers 204 whenever YouTube is reachable
+const YOUTUBE_PROBE_URL: &str = "https://www.youtube.com/generate_204";
+
+// What the health endpoints check, shared with the consumer loop
+#[derive(Clone)]
+pub struct HealthState {
+    rabbit: Arc<RwLock<Option<ConnectionStatus>>>,
+    client: Client,
+}
+
+impl HealthState {
+    pub fn new() -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .expect("Failed to build health check client");
+        Self {
+            rabbit: Arc::new(RwLock::new(None)),
+            client,
+        }
+    }
+
+    // Track the status of the current connection, replaced on every reconnect
+    pub fn set_connection(&self, status: ConnectionStatus) {
+        *self.rabbit.write().unwrap() = Some(status);
+    }
+
+    fn rabbit_connected(&self) -> bool {
+        self.rabbit
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|status| status.connected())
+    }
+
+    async fn youtube_reachable(&self) -> bool {
+        match self.client.get(YOUTUBE_PROBE_URL).send().await {
+            Ok(_) => true,
+            Err(e) => {
+                log::warn!("YouTube is not reachable: {}", e);
+                false
+            }
+        }
+    }
+}
+
+impl Default for HealthState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// /healthz checks the RabbitMQ connection, /readyz also checks that
+// YouTube can be reached
+pub fn router(state: HealthState) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(state)
+}
+
+async fn healthz(State(state): State<HealthState>) -> (StatusCode, &'static str) {
+    if state.rabbit_connected() {
+        (StatusCode::OK, "ok")
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "RabbitMQ connection is down",
+        )
+    }
+}
+
+async fn readyz(State(state): State<HealthState>) -> (StatusCode, &'static str) {
+    if !state.rabbit_connected() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "RabbitMQ connection is down",
+        );
+    }
+    if !state.youtube_reachable().await {
+        return (StatusCode::SERVICE_UNAVAILABLE, "YouTube is not reachable");
+    }
+    (StatusCode::OK, "ready")
+}
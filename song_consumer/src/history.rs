@@ -0,0 +1,89 @@
+use rustin_models::MessageBody;
+use rustin_storage::{RequestStage, RequestStatus, SongRecord, Storage};
+
+// History failures should never fail a request, so log them and carry on
+pub async fn record_request(
+    storage: Option<&Storage>,
+    request_id: &str,
+    chat_id: i64,
+    body: &MessageBody,
+) {
+    let (Some(storage), Some(kind)) = (storage, body.request_kind()) else {
+        return;
+    };
+    if let Err(e) = storage.record_request(request_id, chat_id, kind).await {
+        log::warn!("Failed to record request {}: {}", request_id, e);
+    }
+}
+
+pub async fn set_stage(storage: Option<&Storage>, request_id: &str, stage: RequestStage) {
+    let Some(storage) = storage else {
+        return;
+    };
+    if let Err(e) = storage.set_stage(request_id, stage).await {
+        log::warn!(
+            "Failed to record the stage of request {}: {}",
+            request_id,
+            e
+        );
+    }
+}
+
+pub async fn start_conversion(storage: Option<&Storage>, request_id: &str, songs_total: usize) {
+    let Some(storage) = storage else {
+        return;
+    };
+    let songs_total = i32::try_from(songs_total).unwrap_or(i32::MAX);
+    if let Err(e) = storage.start_conversion(request_id, songs_total).await {
+        log::warn!(
+            "Failed to record the song count of request {}: {}",
+            request_id,
+            e
+        );
+    }
+}
+
+pub async fn record_song_done(storage: Option<&Storage>, request_id: &str) {
+    let Some(storage) = storage else {
+        return;
+    };
+    if let Err(e) = storage.record_song_done(request_id).await {
+        log::warn!("Failed to record progress of request {}: {}", request_id, e);
+    }
+}
+
+pub async fn finish_request(
+    storage: Option<&Storage>,
+    request_id: &str,
+    chat_id: i64,
+    status: RequestStatus,
+    songs: &[SongRecord],
+) {
+    let Some(storage) = storage else {
+        return;
+    };
+    if let Err(e) = storage
+        .finish_request(request_id, chat_id, status, songs)
+        .await
+    {
+        log::warn!(
+            "Failed to record the outcome of request {}: {}",
+            request_id,
+            e
+        );
+    }
+}
+
+pub async fn record_file_id(
+    storage: Option<&Storage>,
+    request_id: &str,
+    video_id: &str,
+    file_id: &str,
+) {
+    let Some(storage) = storage else {
+        return;
+    };
+    if let Err(e) = storage.record_file_id(request_id, video_id, file_id).await {
+        log::warn!("Failed to record file_id for request {}: {}", request_id, e);
+    }
+}
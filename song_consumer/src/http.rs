@@ -0,0 +1,380 @@
+// Clients for calls to outside services. Without timeouts a host that takes
+// the connection and never answers holds a worker slot forever. Calls are
+// spread out per host as configured in http_hosts, retried when the host had
+// a hiccup and sent through the configured proxies. Hosts in host_overrides
+// are swapped for other servers, so the consumer can run against mocks
+
+use crate::{
+    config::{Config, HostLimits},
+    proxy::ProxyPool,
+};
+use ::http::Extensions;
+use async_trait::async_trait;
+use governor::{DefaultDirectRateLimiter, Jitter, Quota, RateLimiter};
+use rand::Rng;
+use reqwest::{
+    header::{HeaderValue, USER_AGENT},
+    ClientBuilder, Method, Request, Response, StatusCode, Url,
+};
+use reqwest_middleware::{Middleware, Next};
+use std::{
+    collections::HashMap,
+    num::NonZeroU32,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+
+pub use reqwest_middleware::ClientWithMiddleware as Client;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Per call, long enough for Vision to read a photo or AudD a recording
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_RETRIES: u32 = 2;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+// Keeps calls that were held back together from going out all at once
+const RATE_JITTER: Duration = Duration::from_millis(200);
+
+// Browsers scraped sites see, one picked for every call
+const USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/129.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/129.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 14_6) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.6 Safari/605.1.15",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:131.0) Gecko/20100101 Firefox/131.0",
+    "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0",
+];
+
+static HOSTS: OnceLock<Arc<HostLimiters>> = OnceLock::new();
+static OVERRIDES: OnceLock<Arc<HostOverrides>> = OnceLock::new();
+// Unset when calls go out directly
+static PROXY: OnceLock<Arc<ProxyPool>> = OnceLock::new();
+
+// Set the per-host limits, the host overrides and the global proxies once at
+// startup, before any client is built
+pub fn init(config: &Config, proxy: Option<&Arc<ProxyPool>>) {
+    if HOSTS
+        .set(Arc::new(HostLimiters::new(&config.http_hosts)))
+        .is_err()
+    {
+        log::warn!("HTTP host limits were already initialised, keeping the first ones");
+    }
+    if OVERRIDES
+        .set(Arc::new(HostOverrides::new(&config.host_overrides)))
+        .is_err()
+    {
+        log::warn!("HTTP host overrides were already initialised, keeping the first ones");
+    }
+    if let Some(proxy) = proxy {
+        if PROXY.set(Arc::clone(proxy)).is_err() {
+            log::warn!("HTTP proxies were already initialised, keeping the first ones");
+        }
+    }
+}
+
+pub fn builder() -> ClientBuilder {
+    proxied_builder(PROXY.get())
+}
+
+// Through the given proxies instead of the global ones. Without any, the
+// usual HTTPS_PROXY and the like still apply
+pub fn proxied_builder(proxy: Option<&Arc<ProxyPool>>) -> ClientBuilder {
+    let builder = reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT);
+    match proxy {
+        Some(proxy) => builder.proxy(proxy.reqwest_proxy()),
+        None => builder,
+    }
+}
+
+// For APIs
+pub fn client() -> Client {
+    with_middleware(builder(), false)
+}
+
+// For providers that scrape web pages, which turn away clients that don't
+// look like a browser
+pub fn scraping_client() -> Client {
+    with_middleware(builder(), true)
+}
+
+// A client of the builder's own settings, e.g. cookies, with the same limits
+// and retries as the others. A User-Agent set on a request is kept either way
+pub fn with_middleware(builder: ClientBuilder, rotate_user_agents: bool) -> Client {
+    let client = builder.build().expect("Failed to build HTTP client");
+    let hosts = HOSTS.get_or_init(|| Arc::new(HostLimiters::default()));
+    let overrides = OVERRIDES.get_or_init(|| Arc::new(HostOverrides::default()));
+    let mut client = reqwest_middleware::ClientBuilder::new(client);
+    if rotate_user_agents {
+        client = client.with(RotateUserAgent);
+    }
+    client
+        .with(Retry)
+        .with_arc(hosts.clone() as Arc<dyn Middleware>)
+        .with_arc(overrides.clone() as Arc<dyn Middleware>)
+        .build()
+}
+
+// Large files take longer than any one timeout, the downloader gives up on
+// stalled downloads and resumes them itself
+pub fn download_client(proxy: Option<&Arc<ProxyPool>>) -> reqwest::Client {
+    let builder = reqwest::Client::builder().connect_timeout(CONNECT_TIMEOUT);
+    let builder = match proxy {
+        Some(proxy) => builder.proxy(proxy.reqwest_proxy()),
+        None => builder,
+    };
+    builder.build().expect("Failed to build HTTP client")
+}
+
+struct RotateUserAgent;
+
+#[async_trait]
+impl Middleware for RotateUserAgent {
+    async fn handle(
+        &self,
+        mut request: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        if !request.headers().contains_key(USER_AGENT) {
+            let agent = USER_AGENTS[rand::thread_rng().gen_range(0..USER_AGENTS.len())];
+            request
+                .headers_mut()
+                .insert(USER_AGENT, HeaderValue::from_static(agent));
+        }
+        next.run(request, extensions).await
+    }
+}
+
+// Sends a call again when the host was briefly unavailable, waiting longer
+// with every attempt. The wait is random so callers that failed together
+// don't come back together
+struct Retry;
+
+#[async_trait]
+impl Middleware for Retry {
+    async fn handle(
+        &self,
+        mut request: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let mut attempt = 0;
+        loop {
+            // Streamed bodies can't be sent twice
+            let Some(next_request) = request.try_clone() else {
+                return next.run(request, extensions).await;
+            };
+            let result = next.clone().run(request, extensions).await;
+            let retry = match &result {
+                Ok(response) => should_retry_status(next_request.method(), response.status()),
+                Err(reqwest_middleware::Error::Reqwest(e)) => {
+                    // A call that never connected never reached the host
+                    e.is_connect() || (e.is_timeout() && is_idempotent(next_request.method()))
+                }
+                Err(reqwest_middleware::Error::Middleware(_)) => false,
+            };
+            if !retry || attempt == MAX_RETRIES {
+                return result;
+            }
+
+            attempt += 1;
+            let delay = retry_delay(attempt, rand::thread_rng().gen());
+            log::info!(
+                "Retrying {} {} in {:?}",
+                next_request.method(),
+                next_request.url().host_str().unwrap_or_default(),
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            request = next_request;
+        }
+    }
+}
+
+// Requests that can safely be sent twice
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    )
+}
+
+// A host that is rate limiting hasn't handled the call, so any call can be
+// sent again. Server errors may come after it was handled
+fn should_retry_status(method: &Method, status: StatusCode) -> bool {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => true,
+        StatusCode::REQUEST_TIMEOUT
+        | StatusCode::INTERNAL_SERVER_ERROR
+        | StatusCode::BAD_GATEWAY
+        | StatusCode::SERVICE_UNAVAILABLE
+        | StatusCode::GATEWAY_TIMEOUT => is_idempotent(method),
+        _ => false,
+    }
+}
+
+// Half of the backoff for the attempt, plus up to as much again at random
+fn retry_delay(attempt: u32, random: f64) -> Duration {
+    let backoff = RETRY_DELAY * 2u32.pow(attempt - 1);
+    backoff / 2 + backoff.mul_f64(random.clamp(0.0, 1.0) / 2.0)
+}
+
+// How fast and how many calls at a time may go to a host
+struct HostLimiter {
+    rate: Option<DefaultDirectRateLimiter>,
+    connections: Option<Semaphore>,
+}
+
+// The limiters of the configured hosts, shared by every client
+#[derive(Default)]
+struct HostLimiters {
+    hosts: Vec<(String, HostLimiter)>,
+}
+
+impl HostLimiters {
+    fn new(hosts: &HashMap<String, HostLimits>) -> Self {
+        let hosts = hosts
+            .iter()
+            .map(|(host, limits)| {
+                let limiter = HostLimiter {
+                    rate: limits
+                        .requests_per_minute
+                        .and_then(NonZeroU32::new)
+                        .map(|rate| RateLimiter::direct(Quota::per_minute(rate))),
+                    connections: limits.max_connections.map(Semaphore::new),
+                };
+                (host.to_ascii_lowercase(), limiter)
+            })
+            .collect();
+        Self { hosts }
+    }
+
+    fn get(&self, host: &str) -> Option<&HostLimiter> {
+        self.hosts
+            .iter()
+            .find(|(pattern, _)| matches_host(host, pattern))
+            .map(|(_, limiter)| limiter)
+    }
+}
+
+#[async_trait]
+impl Middleware for HostLimiters {
+    async fn handle(
+        &self,
+        request: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let Some(limiter) = request.url().host_str().and_then(|host| self.get(host)) else {
+            return next.run(request, extensions).await;
+        };
+        // The semaphores are never closed
+        let _permit = match &limiter.connections {
+            Some(connections) => connections.acquire().await.ok(),
+            None => None,
+        };
+        if let Some(rate) = &limiter.rate {
+            rate.until_ready_with_jitter(Jitter::up_to(RATE_JITTER))
+                .await;
+        }
+        next.run(request, extensions).await
+    }
+}
+
+// A configured host covers its subdomains too, e.g. bandcamp.com covers
+// artist.bandcamp.com
+fn matches_host(host: &str, pattern: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    host == pattern
+        || host
+            .strip_suffix(pattern)
+            .is_some_and(|subdomain| subdomain.ends_with('.'))
+}
+
+// Servers that stand in for hosts, by host. The limits of the original host
+// still apply
+#[derive(Default)]
+struct HostOverrides {
+    hosts: HashMap<String, Url>,
+}
+
+impl HostOverrides {
+    // The URLs were checked when the configuration was loaded
+    fn new(overrides: &HashMap<String, String>) -> Self {
+        let hosts = overrides
+            .iter()
+            .filter_map(|(host, url)| Some((host.to_ascii_lowercase(), Url::parse(url).ok()?)))
+            .collect();
+        Self { hosts }
+    }
+}
+
+#[async_trait]
+impl Middleware for HostOverrides {
+    async fn handle(
+        &self,
+        mut request: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let server = request
+            .url()
+            .host_str()
+            .and_then(|host| self.hosts.get(&host.to_ascii_lowercase()));
+        if let Some(server) = server {
+            *request.url_mut() = redirect(request.url(), server);
+        }
+        next.run(request, extensions).await
+    }
+}
+
+// The URL on the other server, with the same path and query
+fn redirect(url: &Url, server: &Url) -> Url {
+    let mut redirected = server.clone();
+    redirected.set_path(url.path());
+    redirected.set_query(url.query());
+    redirected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_hosts_and_their_subdomains() {
+        assert!(matches_host("bandcamp.com", "bandcamp.com"));
+        assert!(matches_host("Artist.Bandcamp.com", "bandcamp.com"));
+        assert!(!matches_host("notbandcamp.com", "bandcamp.com"));
+        assert!(!matches_host("bandcamp.com.evil.org", "bandcamp.com"));
+    }
+
+    #[test]
+    fn redirects_to_the_same_path_on_the_other_server() {
+        let url = Url::parse("https://tomp3.cc/api/ajax/search?q=1").unwrap();
+        let server = Url::parse("http://127.0.0.1:8080").unwrap();
+        assert_eq!(
+            redirect(&url, &server).as_str(),
+            "http://127.0.0.1:8080/api/ajax/search?q=1"
+        );
+    }
+
+    #[test]
+    fn retries_only_what_is_safe_to_send_again() {
+        assert!(should_retry_status(&Method::GET, StatusCode::BAD_GATEWAY));
+        assert!(!should_retry_status(&Method::POST, StatusCode::BAD_GATEWAY));
+        assert!(should_retry_status(
+            &Method::POST,
+            StatusCode::TOO_MANY_REQUESTS
+        ));
+        assert!(!should_retry_status(&Method::GET, StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn waits_longer_after_every_attempt() {
+        assert_eq!(retry_delay(1, 0.0), RETRY_DELAY / 2);
+        assert_eq!(retry_delay(1, 1.0), RETRY_DELAY);
+        assert_eq!(retry_delay(2, 0.0), RETRY_DELAY);
+        assert_eq!(retry_delay(3, 1.0), RETRY_DELAY * 4);
+    }
+}
@@ -0,0 +1,60 @@
+use std::{env, sync::OnceLock};
+use uuid::Uuid;
+
+// Identity of this replica, so several consumers can share the queues
+// without their consumer tags, metrics or files getting mixed up
+static INSTANCE_ID: OnceLock<String> = OnceLock::new();
+
+// Set the ID once at startup, before any metric is registered
+pub fn init(id: String) {
+    if INSTANCE_ID.set(id).is_err() {
+        log::warn!("Instance ID was already initialised, keeping the first one");
+    }
+}
+
+pub fn id() -> &'static str {
+    INSTANCE_ID.get_or_init(default_id)
+}
+
+// The host name is the pod or container name in most deployments, which is
+// unique per replica and stable across restarts of the process
+pub fn default_id() -> String {
+    env::var("HOSTNAME")
+        .ok()
+        .filter(|hostname| is_valid(hostname))
+        .unwrap_or_else(|| Uuid::new_v4().simple().to_string()[..12].to_string())
+}
+
+// The ID ends up in directory names and consumer tags
+pub fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && id != "."
+        && id != ".."
+}
+
+// Consumer tag that is unique across replicas
+pub fn consumer_tag(name: &str) -> String {
+    format!("{}-{}", name, id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_host_names() {
+        assert!(is_valid("song-consumer-7d9f8b6c4-x2x5q"));
+        assert!(is_valid("worker_1.local"));
+    }
+
+    #[test]
+    fn rejects_ids_unsafe_for_paths() {
+        assert!(!is_valid(""));
+        assert!(!is_valid(".."));
+        assert!(!is_valid("a/b"));
+        assert!(!is_valid("with space"));
+    }
+}
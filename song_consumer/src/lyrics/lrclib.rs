@@ -0,0 +1,62 @@
+use super::{Lyrics, LyricsProvider};
+use crate::{error::SongError, http::Client};
+use async_trait::async_trait;
+use rustin_models::lrclib::LrclibTrack;
+
+const API_URL: &str = "https://lrclib.net/api/search";
+// LRCLIB asks clients to identify themselves
+const USER_AGENT: &str = concat!("RustinBot/", env!("CARGO_PKG_VERSION"));
+
+// Lyrics from LRCLIB, an open database of song lyrics
+pub struct LrclibProvider {
+    client: Client,
+}
+
+impl LrclibProvider {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    // Results come best match first. Instrumentals have no lyrics to show
+    async fn search(&self, query: &str) -> Result<Vec<LrclibTrack>, SongError> {
+        let tracks: Vec<LrclibTrack> = self
+            .client
+            .get(API_URL)
+            .query(&[("q", query)])
+            .header(reqwest::header::USER_AGENT, USER_AGENT)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(tracks
+            .into_iter()
+            .filter(|track| !track.instrumental)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl LyricsProvider for LrclibProvider {
+    fn name(&self) -> &'static str {
+        "LRCLIB"
+    }
+
+    async fn lyrics(&self, query: &str) -> Result<Option<Lyrics>, SongError> {
+        Ok(self.search(query).await?.into_iter().find_map(|track| {
+            let text = track.plain_lyrics.filter(|text| !text.trim().is_empty())?;
+            Some(Lyrics {
+                title: format!("{} - {}", track.artist_name, track.track_name),
+                text,
+            })
+        }))
+    }
+
+    async fn synced_lyrics(&self, query: &str) -> Result<Option<String>, SongError> {
+        Ok(self
+            .search(query)
+            .await?
+            .into_iter()
+            .find_map(|track| track.synced_lyrics.filter(|lrc| !lrc.trim().is_empty())))
+    }
+}
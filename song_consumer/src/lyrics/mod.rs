@@ -0,0 +1,66 @@
+use crate::{error::SongError, http};
+use async_trait::async_trait;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+mod lrclib;
+
+pub use lrclib::LrclibProvider;
+
+// The lyrics of a song, as found by a provider
+pub struct Lyrics {
+    // "Artist - Title" of the song the lyrics belong to, which may differ
+    // from what the user typed
+    pub title: String,
+    pub text: String,
+}
+
+// Looks up the lyrics of a song for /lyrics
+#[async_trait]
+pub trait LyricsProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Return the lyrics of the best match for the query, or None if no song
+    // with lyrics matched
+    async fn lyrics(&self, query: &str) -> Result<Option<Lyrics>, SongError>;
+
+    // Return the lyrics of the best match in LRC format, with a timestamp on
+    // every line, or None if no song with synced lyrics matched
+    async fn synced_lyrics(&self, query: &str) -> Result<Option<String>, SongError>;
+}
+
+// LRCLIB needs no API key, so lyrics are always available
+pub fn from_config() -> Arc<dyn LyricsProvider> {
+    Arc::new(LrclibProvider::new(http::client()))
+}
+
+// Write the synced lyrics of a song next to its MP3, where the reply service
+// picks them up. Songs without synced lyrics just go without
+pub async fn write_next_to(
+    provider: &dyn LyricsProvider,
+    song: &str,
+    mp3_path: &Path,
+) -> Option<PathBuf> {
+    let lrc = match provider.synced_lyrics(song).await {
+        Ok(Some(lrc)) => lrc,
+        Ok(None) => {
+            log::info!("{} has no synced lyrics for '{}'", provider.name(), song);
+            return None;
+        }
+        Err(e) => {
+            log::warn!("Failed to look up the synced lyrics of '{}': {}", song, e);
+            return None;
+        }
+    };
+
+    let lrc_path = mp3_path.with_extension("lrc");
+    match tokio::fs::write(&lrc_path, lrc).await {
+        Ok(()) => Some(lrc_path),
+        Err(e) => {
+            log::warn!("Failed to write {}: {}", lrc_path.display(), e);
+            None
+        }
+    }
+}
@@ -1,233 +1,2000 @@
+use api_budget::ApiBudget;
+use cache::Cache;
+use config::Config;
 use dotenvy::dotenv;
-use futures_util::{future::join_all, StreamExt};
+use downloader::{AudioFile, CachedAudio, VideoFile};
+use error::SongError;
+use futures_util::StreamExt;
+use health::HealthState;
 use lapin::{
-    options::{BasicAckOptions, BasicConsumeOptions, BasicPublishOptions},
+    message::Delivery,
+    options::{
+        BasicAckOptions, BasicCancelOptions, BasicConsumeOptions, BasicNackOptions,
+        BasicQosOptions, ConfirmSelectOptions,
+    },
     types::FieldTable,
-    BasicProperties, Channel, Connection, ConnectionProperties, Consumer,
+    Channel, Connection, ConnectionProperties, Consumer,
 };
-use models::{ConvertResponse, RabbitMessage, Tomp3Response, YouTubeResponse};
-use reqwest::{
-    cookie::{CookieStore, Jar},
-    Client,
+use lyrics::LyricsProvider;
+use media_store::MediaStore;
+use ocr::OcrProvider;
+use processor::{cached_fetch_mp3, cached_search, ConvertedSong, SongProcessor, SongRequest};
+use providers::{Mp3Source, ProviderChain};
+use proxy::Proxies;
+use quota::{Quota, QuotaCheck};
+use recognizer::{Recording, SongRecognizer};
+use rustin_models::{
+    analytics::{AnalyticsEvent, Stage},
+    clip::Clip,
+    i18n::{tr, tr_args},
+    markdown, request_text, InlineAudio, InlineAudioSource, MediaFormat, MessageBody,
+    RabbitMessage, RequestOptions, SCHEMA_VERSION,
 };
-use std::{env, error::Error, sync::Arc};
-use urlencoding::encode;
+use rustin_storage::{RequestStage, RequestStatus, SongRecord, SongStatus, Storage};
+use search::SearchChain;
+use spotify::SpotifyClient;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::task::{JoinError, JoinSet};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use url_parser::BandcampLink;
+use youtube_playlists::YouTubePlaylists;
 
-mod models;
+mod alerts;
+mod analytics;
+mod api_budget;
+mod archive;
+mod bandcamp;
+mod blocklist;
+mod cache;
+mod cancellation;
+mod chunking;
+mod circuit_breaker;
+mod config;
+mod correlation;
+mod dedup;
+mod downloader;
+mod duplicates;
+mod effects;
+mod error;
+mod ffmpeg;
+mod health;
+mod history;
+mod http;
+mod instance;
+mod lyrics;
+mod media_store;
+mod metrics;
+mod music_links;
+mod ocr;
+mod odesli;
+mod outbox;
+mod playlist;
+mod processor;
+mod providers;
+mod proxy;
+mod publish_buffer;
+mod quality;
+mod quota;
+mod recognizer;
+mod retry;
+mod scheduler;
+mod search;
+mod search_plan;
+mod settings;
+mod shutdown;
+mod soundcloud;
+mod spotify;
+mod tagging;
+mod thumbnail;
+mod titles;
+mod topology;
+mod tracklist;
+mod tracklist_file;
+mod upload_size;
+mod uploads;
+mod url_parser;
+mod youtube;
+mod youtube_playlists;
 
 type DynError = Box<dyn Error + Send + Sync + 'static>;
 
+const CONSUMER_TAG: &str = "song_consumer";
+const FAST_CONSUMER_TAG: &str = "song_consumer_fast";
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+// Telegram only accepts answers to inline queries for a few seconds
+const INLINE_QUERY_TIMEOUT: Duration = Duration::from_secs(8);
+// Number of search results offered by /search
+const SEARCH_CANDIDATES: usize = 5;
+
 #[tokio::main]
 async fn main() -> Result<(), DynError> {
-    pretty_env_logger::init();
     dotenv().expect("Failed to load .env file");
+    let _telemetry = rustin_telemetry::init("song_consumer");
     log::info!("Application started");
 
-    let rabbit_addr = env::var("RABBIT_ADDRESS")?;
-    let google_api_key = env::var("GOOGLE_VISION_API_KEY")?;
+    // Bad settings stop the consumer here instead of failing mid-run
+    let config = Config::load()?;
+    instance::init(config.instance_id.clone());
+    log::info!("Running as instance {}", instance::id());
+    topology::init(config.queues.clone());
+    analytics::init(config.analytics);
+    alerts::init(config.ops_chat_id, config.alert_error_rate);
+    let proxies = Proxies::from_config(&config)?;
+    http::init(&config, proxies.global());
+    let media_store = MediaStore::open(&config).await?;
+    let media_dir = media_store.as_ref().map(|store| store.dir().to_path_buf());
+    ffmpeg::init(config.ffmpeg_path.clone());
+    let providers = Arc::new(ProviderChain::from_config(&config, &proxies));
+    let quota = Quota::from_config(&config)?;
+    let cache = cache::from_config(&config)?;
+    let spotify = SpotifyClient::from_config(&config);
+    if spotify.is_none() {
+        log::info!("spotify_client_id/secret not set, Spotify links are disabled");
+    }
+    let recognizer = recognizer::from_config(&config);
+    if recognizer.is_none() {
+        log::info!("audd_api_token not set, voice messages are disabled");
+    }
+    let lyrics = lyrics::from_config();
+    let ocr = ocr::from_config(&config);
+    let storage = match &config.database_url {
+        Some(database_url) => Some(Arc::new(Storage::connect(database_url).await?)),
+        None => {
+            log::info!("database_url not set, request history is disabled");
+            None
+        }
+    };
+    let youtube_playlists = YouTubePlaylists::from_config(&config, storage.clone());
+    if youtube_playlists.is_none() {
+        log::info!(
+            "google_client_id/secret or database_url not set, YouTube playlists are disabled"
+        );
+    }
+    let budget = ApiBudget::new(storage.clone(), config.youtube_quota_budget).map(Arc::new);
+    let search = Arc::new(SearchChain::from_config(&config, budget));
+    let health = HealthState::new();
+    // Metrics and the orchestrator probes share one HTTP server
+    let http_addr = config.http_addr.clone();
+    let app = metrics::router().merge(health::router(health.clone()));
+    tokio::spawn(async move {
+        if let Err(e) = serve_http(&http_addr, app).await {
+            log::error!("HTTP server stopped: {}", e);
+        }
+    });
+    let shutdown = shutdown::listen_for_signals();
+    let shutdown_timeout = config.shutdown_timeout();
+    if let Some(media_store) = media_store {
+        tokio::spawn(media_store.clean_up_periodically(shutdown.clone()));
+    }
+    tokio::spawn(proxies.check_periodically(shutdown.clone()));
+    if let Some(storage) = &storage {
+        tokio::spawn(scheduler::release_due_replies(
+            Arc::clone(storage),
+            shutdown.clone(),
+        ));
+    }
+
+    let processor = SongProcessor::from_config(
+        &config,
+        media_dir.clone(),
+        Arc::clone(&providers),
+        Arc::clone(&search),
+        Arc::clone(&cache),
+        Arc::clone(&lyrics),
+        storage.clone(),
+    );
+
+    let worker = Arc::new(Worker {
+        google_api_key: config.google_vision_api_key,
+        max_retries: config.max_retries,
+        media_dir,
+        default_bitrate: config.default_bitrate,
+        providers,
+        search,
+        processor,
+        quota,
+        cache,
+        spotify,
+        youtube_playlists,
+        recognizer,
+        lyrics,
+        ocr,
+        storage,
+        running: Arc::default(),
+    });
+
+    // Reconnect with exponential backoff whenever the connection drops
+    let mut backoff = RECONNECT_MIN_DELAY;
+    loop {
+        match consume(
+            &config.rabbit_address,
+            Prefetch {
+                music: config.prefetch_count,
+                fast: config.fast_prefetch_count,
+            },
+            &worker,
+            &health,
+            &shutdown,
+            shutdown_timeout,
+            &mut backoff,
+        )
+        .await
+        {
+            Ok(()) => break,
+            Err(e) => {
+                log::error!("RabbitMQ connection lost: {}", e);
+                alerts::connection_lost();
+            }
+        }
 
-    let connection = Connection::connect(&rabbit_addr, ConnectionProperties::default()).await?;
+        log::info!("Reconnecting to RabbitMQ in {:?}", backoff);
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = tokio::time::sleep(backoff) => {}
+        }
+        backoff = (backoff * 2).min(RECONNECT_MAX_DELAY);
+    }
+
+    log::info!("Shut down");
+    Ok(())
+}
+
+// How many unacked deliveries each lane may have at a time
+#[derive(Clone, Copy)]
+struct Prefetch {
+    music: u16,
+    fast: u16,
+}
+
+// Connect, consume both Music lanes until shutdown, then close the connection.
+// Returns an error if the connection or a consumer goes away
+async fn consume(
+    rabbit_addr: &str,
+    prefetch: Prefetch,
+    worker: &Arc<Worker>,
+    health: &HealthState,
+    shutdown: &CancellationToken,
+    shutdown_timeout: Duration,
+    backoff: &mut Duration,
+) -> Result<(), DynError> {
+    let connection = Connection::connect(rabbit_addr, ConnectionProperties::default()).await?;
     log::info!("Connected to RabbitMQ at {}", rabbit_addr);
+    health.set_connection(connection.status().clone());
 
     let channel = connection.create_channel().await?;
+    channel
+        .confirm_select(ConfirmSelectOptions::default())
+        .await?;
+    topology::declare(&channel).await?;
+
+    let uploads_channel = connection.create_channel().await?;
+    let uploads_cache = Arc::clone(&worker.cache);
+    let uploads_storage = worker.storage.clone();
+    tokio::spawn(async move {
+        if let Err(e) =
+            uploads::listen_for_uploads(uploads_channel, uploads_cache, uploads_storage).await
+        {
+            log::error!("Upload listener stopped: {}", e);
+        }
+    });
+
+    let control_channel = connection.create_channel().await?;
+    let running = Arc::clone(&worker.running);
+    let control_cache = Arc::clone(&worker.cache);
+    tokio::spawn(async move {
+        if let Err(e) =
+            cancellation::listen_for_cancellations(control_channel, running, control_cache).await
+        {
+            log::error!("Cancellation listener stopped: {}", e);
+        }
+    });
+
+    // Each connection gets its own outbox drainer and analytics and alert
+    // publishers, stopped once this function returns
+    let drainer_token = shutdown.child_token();
+    let _drainer_guard = drainer_token.clone().drop_guard();
+    if alerts::is_enabled() {
+        let alerts_channel = connection.create_channel().await?;
+        alerts_channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await?;
+        let alerts_token = drainer_token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = alerts::publish(alerts_channel, alerts_token).await {
+                log::error!("Alert publisher stopped: {}", e);
+            }
+        });
+    }
+    if analytics::is_enabled() {
+        let analytics_channel = connection.create_channel().await?;
+        let analytics_token = drainer_token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = analytics::publish(analytics_channel, analytics_token).await {
+                log::error!("Analytics publisher stopped: {}", e);
+            }
+        });
+    }
+    if let Some(storage) = &worker.storage {
+        let outbox_channel = connection.create_channel().await?;
+        outbox_channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await?;
+        let storage = Arc::clone(storage);
+        tokio::spawn(async move {
+            if let Err(e) = outbox::drain(outbox_channel, storage, drainer_token).await {
+                log::error!("Outbox drainer stopped: {}", e);
+            }
+        });
+    }
+
+    // Only a few deliveries per lane are unacked at a time, so a burst of
+    // requests waits in the queue (where other consumers can take it) instead
+    // of piling up here. The prefetch applies to consumers started after it
+    // is set, which gives each lane its own share of in-flight requests while
+    // their songs still share the conversion limiter
+    let queues = topology::queues();
+    channel
+        .basic_qos(prefetch.music, BasicQosOptions::default())
+        .await?;
+    let consumer_tag = instance::consumer_tag(CONSUMER_TAG);
     let mut consumer: Consumer = channel
         .basic_consume(
-            "Music",
-            "song_consumer",
+            &queues.music,
+            &consumer_tag,
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    channel
+        .basic_qos(prefetch.fast, BasicQosOptions::default())
+        .await?;
+    let fast_consumer_tag = instance::consumer_tag(FAST_CONSUMER_TAG);
+    let mut fast_consumer: Consumer = channel
+        .basic_consume(
+            &queues.music_fast,
+            &fast_consumer_tag,
             BasicConsumeOptions::default(),
             FieldTable::default(),
         )
         .await?;
-    log::info!("Waiting for messages on 'Music' queue...");
-
-    while let Some(delivery) = consumer.next().await {
-        match delivery {
-            Ok(delivery) => {
-                log::info!("Received message: {:?}", delivery);
-                let message: RabbitMessage = serde_json::from_slice(&delivery.data)?;
-                log::info!("Parsed message: {:?}", message);
-
-                match process_songs(message.text, &google_api_key).await {
-                    Ok(links) => {
-                        publish_to_reply_queue(&channel, message.chat_id, links).await?;
-                        delivery.ack(BasicAckOptions::default()).await?;
-                        log::info!("Message processed and acknowledged successfully");
+    log::info!(
+        "Waiting for messages on '{}' ({} at a time) and '{}' ({} at a time)...",
+        queues.music,
+        prefetch.music,
+        queues.music_fast,
+        prefetch.fast
+    );
+    *backoff = RECONNECT_MIN_DELAY;
+    alerts::connected();
+
+    // Cancelled when in-flight deliveries didn't finish within the shutdown
+    // timeout, which makes them requeue themselves
+    let abandon = CancellationToken::new();
+    let mut in_flight = JoinSet::new();
+
+    loop {
+        let delivery = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            // One failed or panicked delivery mustn't stop the others. Only
+            // the consumer streams end the loop
+            Some(finished) = in_flight.join_next() => {
+                log_task_result(finished);
+                continue;
+            }
+            delivery = fast_consumer.next() => delivery,
+            delivery = consumer.next() => delivery,
+        };
+
+        let delivery = match delivery {
+            Some(delivery) => delivery?,
+            None => return Err("consumer stream ended".into()),
+        };
+
+        in_flight.spawn(process_delivery(
+            Arc::clone(worker),
+            channel.clone(),
+            delivery,
+            abandon.clone(),
+        ));
+    }
+
+    log::info!("Shutting down");
+    // Stop the broker from sending more deliveries, then close cleanly
+    for tag in [&consumer_tag, &fast_consumer_tag] {
+        if let Err(e) = channel
+            .basic_cancel(tag, BasicCancelOptions::default())
+            .await
+        {
+            log::warn!("Failed to cancel consumer {}: {}", tag, e);
+        }
+    }
+    if !in_flight.is_empty() {
+        log::info!(
+            "Waiting up to {:?} for {} in-flight messages",
+            shutdown_timeout,
+            in_flight.len()
+        );
+        let finished = tokio::time::timeout(shutdown_timeout, async {
+            while let Some(result) = in_flight.join_next().await {
+                log_task_result(result);
+            }
+        })
+        .await;
+        if finished.is_err() {
+            log::warn!(
+                "{} messages did not finish in time, requeueing them",
+                in_flight.len()
+            );
+            abandon.cancel();
+            while let Some(result) = in_flight.join_next().await {
+                log_task_result(result);
+            }
+        }
+    }
+    connection.close(200, "Shutting down").await?;
+    log::info!("RabbitMQ connection closed");
+
+    Ok(())
+}
+
+// Handle one Music delivery, putting it back for another consumer to pick up
+// if it gets abandoned during shutdown
+async fn process_delivery(
+    worker: Arc<Worker>,
+    channel: Channel,
+    delivery: Delivery,
+    abandon: CancellationToken,
+) -> Result<(), DynError> {
+    // Everything logged while handling the delivery carries its request ID
+    let request_id = correlation::request_id(&delivery);
+    let span = tracing::info_span!(
+        "request",
+        instance_id = %instance::id(),
+        request_id = %request_id
+    );
+    // Continues the trace the bot started when it received the message
+    rustin_telemetry::set_parent(&span, &delivery);
+    let processing = rustin_telemetry::request_scope(
+        &request_id,
+        worker
+            .handle_delivery(&channel, &delivery, &request_id)
+            .instrument(span),
+    );
+
+    tokio::select! {
+        result = processing => result,
+        _ = abandon.cancelled() => {
+            delivery
+                .nack(BasicNackOptions {
+                    requeue: true,
+                    ..Default::default()
+                })
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+fn log_task_result(result: Result<Result<(), DynError>, JoinError>) {
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log::error!("Failed to handle message: {}", e),
+        Err(e) => log::error!("Message handler panicked: {}", e),
+    }
+}
+
+async fn serve_http(addr: &str, app: axum::Router) -> Result<(), DynError> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    log::info!("Serving /metrics, /healthz and /readyz on {}", addr);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+// Everything needed to handle a delivery from the Music queue
+struct Worker {
+    google_api_key: String,
+    max_retries: i64,
+    media_dir: Option<PathBuf>,
+    // Used for songs without a bitrate preference
+    default_bitrate: u32,
+    providers: Arc<ProviderChain>,
+    search: Arc<SearchChain>,
+    processor: SongProcessor,
+    quota: Quota,
+    cache: Arc<dyn Cache>,
+    spotify: Option<SpotifyClient>,
+    youtube_playlists: Option<YouTubePlaylists>,
+    recognizer: Option<Box<dyn SongRecognizer>>,
+    lyrics: Arc<dyn LyricsProvider>,
+    ocr: Box<dyn OcrProvider>,
+    storage: Option<Arc<Storage>>,
+    // Requests being converted, so /cancel can stop them
+    running: Arc<cancellation::Registry>,
+}
+
+impl Worker {
+    async fn handle_delivery(
+        &self,
+        channel: &Channel,
+        delivery: &Delivery,
+        request_id: &str,
+    ) -> Result<(), DynError> {
+        let google_api_key = &self.google_api_key;
+
+        log::info!("Received message: {:?}", delivery);
+        metrics::MESSAGES_CONSUMED.inc();
+
+        // A redelivery of a message whose replies already went out, e.g.
+        // because the consumer died before it could ack it
+        if let Some(message_id) = correlation::message_id(delivery) {
+            if dedup::already_processed(self.cache.as_ref(), self.storage.as_deref(), &message_id)
+                .await
+            {
+                log::info!("Skipping message {} that was already processed", message_id);
+                delivery.ack(BasicAckOptions::default()).await?;
+                return Ok(());
+            }
+        }
+        // A malformed payload fails the same way on every delivery, so it's
+        // set aside instead of being retried or taking the connection down
+        let mut message: RabbitMessage = match serde_json::from_slice(&delivery.data) {
+            Ok(message) => message,
+            Err(e) => {
+                log::error!("Failed to parse message: {}", e);
+                metrics::INVALID_MESSAGES.inc();
+                retry::move_to_invalid(channel, delivery, &e.to_string()).await?;
+                return Ok(());
+            }
+        };
+        log::info!("Parsed message: {:?}", message);
+
+        if message.version != SCHEMA_VERSION {
+            log::warn!(
+                "Message has schema version {}, expected {}",
+                message.version,
+                SCHEMA_VERSION
+            );
+        }
+
+        // The bot drops these already, but messages can reach the queue
+        // without going through it
+        if blocklist::is_banned(self.storage.as_deref(), message.chat_id).await {
+            log::warn!("Dropping message from banned chat {}", message.chat_id);
+            metrics::MESSAGES_DROPPED.inc();
+            delivery.ack(BasicAckOptions::default()).await?;
+            return Ok(());
+        }
+
+        history::record_request(
+            self.storage.as_deref(),
+            request_id,
+            message.chat_id,
+            &message.body,
+        )
+        .await;
+        if let Some(kind) = message.body.request_kind() {
+            rustin_telemetry::breadcrumb("pipeline", format!("received {} request", kind));
+            analytics::record(AnalyticsEvent::RequestReceived {
+                kind: kind.to_string(),
+            });
+        }
+
+        if message.options == RequestOptions::default() {
+            message.options =
+                settings::chat_options(self.storage.as_deref(), message.chat_id).await;
+        }
+        if let Some(deliver_at) = message.options.deliver_at {
+            scheduler::schedule(
+                self.storage.as_deref(),
+                request_id,
+                message.chat_id,
+                deliver_at,
+            )
+            .await;
+        }
+        let language = message.options.language.as_deref();
+
+        // Cancelled with /cancel while it was still waiting in the queue
+        let cancelled_by =
+            cache::get_or_log(self.cache.as_ref(), &cache::cancelled_key(request_id)).await;
+        if cancelled_by == Some(message.chat_id.to_string()) {
+            log::info!("Skipping request that was cancelled before it started");
+            history::finish_request(
+                self.storage.as_deref(),
+                request_id,
+                message.chat_id,
+                RequestStatus::Cancelled,
+                &[],
+            )
+            .await;
+            let reply = RabbitMessage::new(
+                message.chat_id,
+                MessageBody::Result {
+                    text: markdown::escape(&tr(language, "request.cancelled_before_start")),
+                },
+            );
+            publish_reply(channel, self.storage.as_deref(), request_id, &reply).await?;
+            self.ack(delivery).await?;
+            return Ok(());
+        }
+
+        let text = match &message.body {
+            MessageBody::TextRequest { text, .. } => match request_text::validate(text) {
+                Ok(text) => text,
+                Err(invalid) => {
+                    log::info!("Rejected request text: {:?}", invalid);
+                    let reply = RabbitMessage::new(
+                        message.chat_id,
+                        MessageBody::Error {
+                            message: invalid.message(language),
+                        },
+                    );
+                    publish_reply(channel, self.storage.as_deref(), request_id, &reply).await?;
+                    self.ack(delivery).await?;
+                    return Ok(());
+                }
+            },
+            MessageBody::PhotoRequest { photo_url } => {
+                match ocr::extract_song_lines(&http::client(), self.ocr.as_ref(), photo_url).await {
+                    Ok(lines) => lines.join("\n"),
+                    Err(e) => {
+                        log::error!("Error running OCR on photo: {}", e);
+                        self.handle_request_error(channel, request_id, delivery, &message, e)
+                            .await?;
+                        return Ok(());
                     }
+                }
+            }
+            MessageBody::DocumentRequest {
+                document_url,
+                file_name,
+            } => {
+                match tracklist_file::download_song_lines(&http::client(), document_url, file_name)
+                    .await
+                {
+                    Ok(lines) => lines.join("\n"),
                     Err(e) => {
-                        log::error!("Error processing message: {}", e);
+                        log::error!("Error reading tracklist file: {}", e);
+                        self.handle_request_error(channel, request_id, delivery, &message, e)
+                            .await?;
+                        return Ok(());
+                    }
+                }
+            }
+            MessageBody::VoiceRequest { voice_url } => {
+                match self
+                    .recognize(
+                        channel,
+                        request_id,
+                        delivery,
+                        &message,
+                        voice_url,
+                        Recording::Voice,
+                    )
+                    .await?
+                {
+                    Some(song) => song,
+                    None => return Ok(()),
+                }
+            }
+            MessageBody::MediaRequest { media_url } => {
+                // Rather than converting right away, let the user pick the
+                // clean version of the recognized song
+                if let Some(song) = self
+                    .recognize(
+                        channel,
+                        request_id,
+                        delivery,
+                        &message,
+                        media_url,
+                        Recording::Media,
+                    )
+                    .await?
+                {
+                    self.offer_search_results(channel, request_id, delivery, &message, &song)
+                        .await?;
+                }
+                return Ok(());
+            }
+            MessageBody::PlaylistRequest { url }
+                if url_parser::parse_playlist_id(url).is_some()
+                    || matches!(
+                        url_parser::parse_bandcamp_link(url),
+                        Some(BandcampLink::Album { .. })
+                    )
+                    || (self.spotify.is_some() && spotify::parse_link(url).is_some()) =>
+            {
+                url.clone()
+            }
+            MessageBody::PlaylistRequest { url } => {
+                log::info!("Unsupported playlist link: {}", url);
+                let reply = RabbitMessage::new(
+                    message.chat_id,
+                    MessageBody::Error {
+                        message: tr(language, "request.playlist_unsupported"),
+                    },
+                );
+                publish_reply(channel, self.storage.as_deref(), request_id, &reply).await?;
+                self.ack(delivery).await?;
+                return Ok(());
+            }
+            MessageBody::SearchRequest { query } => {
+                self.offer_search_results(channel, request_id, delivery, &message, query)
+                    .await?;
+                return Ok(());
+            }
+            MessageBody::LyricsRequest { query } => {
+                self.send_lyrics(channel, request_id, delivery, &message, query)
+                    .await?;
+                return Ok(());
+            }
+            MessageBody::YouTubePlaylistRequest { text } => {
+                self.create_youtube_playlist(channel, request_id, delivery, &message, text)
+                    .await?;
+                return Ok(());
+            }
+            MessageBody::PickedVideo { video_id } => {
+                // Fall back to the video ID if the picker has expired from the cache
+                let title = cache::get_or_log(self.cache.as_ref(), &cache::candidate_key(video_id))
+                    .await
+                    .unwrap_or_else(|| video_id.clone());
+                let songs = vec![SongRequest::video(title, video_id.clone())];
+                return self
+                    .convert_and_reply(
+                        channel,
+                        request_id,
+                        delivery,
+                        message.chat_id,
+                        &message.options,
+                        songs,
+                    )
+                    .await;
+            }
+            MessageBody::InlineQuery {
+                inline_query_id,
+                query,
+            } => {
+                self.answer_inline_query(
+                    channel,
+                    request_id,
+                    message.chat_id,
+                    inline_query_id,
+                    query,
+                )
+                .await?;
+                self.ack(delivery).await?;
+                return Ok(());
+            }
+            MessageBody::Error { .. }
+            | MessageBody::Result { .. }
+            | MessageBody::InlineAnswer { .. }
+            | MessageBody::SearchResults { .. }
+            | MessageBody::Progress { .. }
+            | MessageBody::Audio { .. }
+            | MessageBody::Video { .. }
+            | MessageBody::Archive { .. }
+            | MessageBody::CachedAudio { .. }
+            | MessageBody::AudioUploaded { .. }
+            | MessageBody::CancelRequest { .. } => {
+                log::warn!("Ignoring reply message published to the Music queue");
+                self.ack(delivery).await?;
+                return Ok(());
+            }
+        };
+
+        // Only typed requests can ask for clips, their lines match the text's
+        let clips = match &message.body {
+            MessageBody::TextRequest { clips, .. } => clips.clone(),
+            _ => BTreeMap::new(),
+        };
+        let expanding = Instant::now();
+        let expanded = expand_links(&text, &clips, self.spotify.as_ref(), google_api_key)
+            .instrument(tracing::info_span!("expanding"))
+            .await;
+        analytics::record_stage(Stage::Expanding, expanding);
+        let songs = match expanded {
+            Ok(songs) => {
+                rustin_telemetry::breadcrumb(
+                    "pipeline",
+                    format!("expanded to {} songs", songs.len()),
+                );
+                songs
+            }
+            Err(e) => {
+                log::error!("Error expanding links: {}", e);
+                self.handle_request_error(channel, request_id, delivery, &message, e)
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        self.convert_and_reply(
+            channel,
+            request_id,
+            delivery,
+            message.chat_id,
+            &message.options,
+            songs,
+        )
+        .await
+    }
+
+    // Convert the songs of a request and publish everything the user gets back
+    async fn convert_and_reply(
+        &self,
+        channel: &Channel,
+        request_id: &str,
+        delivery: &Delivery,
+        chat_id: i64,
+        options: &RequestOptions,
+        mut songs: Vec<SongRequest>,
+    ) -> Result<(), DynError> {
+        // Numbered before duplicates are dropped, so the replies match the
+        // lines as they were sent
+        for (index, song) in songs.iter_mut().enumerate() {
+            song.index = index;
+        }
+        let (songs, duplicates) = remove_duplicates(songs, options.language.as_deref());
+        match self.quota.check(chat_id, songs.len()).await {
+            Ok(QuotaCheck::Allowed) => {}
+            Ok(QuotaCheck::Exceeded {
+                remaining,
+                resets_in,
+            }) => {
+                log::info!("Chat ID {} is over its daily quota", chat_id);
+                history::finish_request(
+                    self.storage.as_deref(),
+                    request_id,
+                    chat_id,
+                    RequestStatus::Failed,
+                    &[],
+                )
+                .await;
+                let reply = RabbitMessage::new(
+                    chat_id,
+                    MessageBody::Error {
+                        message: tr_args(
+                            options.language.as_deref(),
+                            "request.daily_limit",
+                            &[
+                                ("remaining", &remaining.to_string()),
+                                ("resets_in", &quota::format_reset(resets_in)),
+                            ],
+                        ),
+                    },
+                );
+                publish_reply(channel, self.storage.as_deref(), request_id, &reply).await?;
+                self.ack(delivery).await?;
+                return Ok(());
+            }
+            // Don't turn users away because the quota backend is down
+            Err(e) => log::error!("Error checking quota: {}", e),
+        }
+
+        let zip = self.processor.zips(options, songs.len());
+        let send_playlist = self.sends_playlist(options, songs.len());
+        let stream =
+            SongStream::for_request(channel, self.storage.as_ref(), request_id, chat_id, options);
+        // Streamed songs are their own progress updates, and scheduled
+        // requests don't get any until their results are due
+        let progress = match stream {
+            Some(_) => None,
+            None if scheduler::is_scheduled(options.deliver_at) => None,
+            None => Progress::for_request(channel, request_id, chat_id, options, songs.len()),
+        };
+        history::start_conversion(self.storage.as_deref(), request_id, songs.len()).await;
+        let registration = self.running.register(request_id, chat_id);
+        let converting = Instant::now();
+        let converting_span = tracing::info_span!("converting", songs = songs.len());
+        match self
+            .processor
+            .process_songs(
+                request_id,
+                songs,
+                options,
+                progress,
+                stream,
+                registration.token(),
+            )
+            .instrument(converting_span)
+            .await
+        {
+            Ok(mut processed) => {
+                analytics::record_stage(Stage::Converting, converting);
+                rustin_telemetry::breadcrumb("pipeline", "converted songs");
+                let delivering = Instant::now();
+                history::set_stage(
+                    self.storage.as_deref(),
+                    request_id,
+                    RequestStage::Delivering,
+                )
+                .await;
+                let mut playlist = match send_playlist {
+                    true => processed.playlist_entries(zip),
+                    false => Vec::new(),
+                };
+                if zip {
+                    processed.audio = self
+                        .publish_archives(channel, request_id, chat_id, processed.audio)
+                        .await?;
+                    // The songs that couldn't be zipped are sent one by one
+                    if send_playlist && !processed.audio.is_empty() {
+                        playlist = processed.playlist_entries(false);
                     }
                 }
+                for audio in processed.cached_audio {
+                    publish_cached_audio_to_reply_queue(
+                        channel,
+                        self.storage.as_deref(),
+                        request_id,
+                        chat_id,
+                        audio,
+                    )
+                    .await?;
+                }
+                for audio in processed.audio {
+                    publish_audio_to_reply_queue(
+                        channel,
+                        self.storage.as_deref(),
+                        request_id,
+                        chat_id,
+                        audio,
+                    )
+                    .await?;
+                }
+                for video in processed.videos {
+                    publish_video_to_reply_queue(
+                        channel,
+                        self.storage.as_deref(),
+                        request_id,
+                        chat_id,
+                        video,
+                    )
+                    .await?;
+                }
+                if !processed.links.is_empty() {
+                    publish_to_reply_queue(
+                        channel,
+                        self.storage.as_deref(),
+                        request_id,
+                        chat_id,
+                        processed.links,
+                    )
+                    .await?;
+                }
+                if !playlist.is_empty() {
+                    self.publish_playlist(channel, request_id, chat_id, &playlist)
+                        .await?;
+                }
+                if !duplicates.is_empty() {
+                    publish_to_reply_queue(
+                        channel,
+                        self.storage.as_deref(),
+                        request_id,
+                        chat_id,
+                        duplicates,
+                    )
+                    .await?;
+                }
+                if !processed.failures.is_empty() {
+                    publish_error_to_reply_queue(
+                        channel,
+                        self.storage.as_deref(),
+                        request_id,
+                        chat_id,
+                        processed.failures,
+                    )
+                    .await?;
+                }
+                let status = if processed.cancelled > 0 {
+                    publish_cancelled(
+                        channel,
+                        self.storage.as_deref(),
+                        request_id,
+                        chat_id,
+                        options.language.as_deref(),
+                        &processed.history,
+                    )
+                    .await?;
+                    RequestStatus::Cancelled
+                } else {
+                    RequestStatus::Completed
+                };
+                history::finish_request(
+                    self.storage.as_deref(),
+                    request_id,
+                    chat_id,
+                    status,
+                    &processed.history,
+                )
+                .await;
+                analytics::record_stage(Stage::Delivering, delivering);
+                rustin_telemetry::breadcrumb("pipeline", "delivered replies");
+                analytics::record_finished(&processed.history);
+                alerts::record_request(false);
+                self.ack(delivery).await?;
+                log::info!("Message processed and acknowledged successfully");
             }
             Err(e) => {
-                log::error!("Failed to receive message: {}", e);
+                log::error!("Error processing message: {}", e);
+                alerts::record_request(true);
+                retry::handle_failure(channel, delivery, self.max_retries).await?;
             }
         }
+
+        Ok(())
     }
 
-    Ok(())
-}
+    // Identify the song in a recording. None means the delivery has already
+    // been dealt with, because recognition is disabled or failed
+    async fn recognize(
+        &self,
+        channel: &Channel,
+        request_id: &str,
+        delivery: &Delivery,
+        request: &RabbitMessage,
+        file_url: &str,
+        recording: Recording,
+    ) -> Result<Option<String>, DynError> {
+        let Some(recognizer) = &self.recognizer else {
+            let reply = RabbitMessage::new(
+                request.chat_id,
+                MessageBody::Error {
+                    message: tr(
+                        request.options.language.as_deref(),
+                        "request.recognition_unsupported",
+                    ),
+                },
+            );
+            publish_reply(channel, self.storage.as_deref(), request_id, &reply).await?;
+            self.ack(delivery).await?;
+            return Ok(None);
+        };
+
+        match recognizer::identify(&http::client(), recognizer.as_ref(), file_url, recording).await
+        {
+            Ok(song) => Ok(Some(song)),
+            Err(e) => {
+                log::error!("Error recognizing {:?} recording: {}", recording, e);
+                self.handle_request_error(channel, request_id, delivery, request, e)
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
+    // Offer the top search results as buttons, remembering their titles so
+    // the pick can be converted under a proper name
+    async fn offer_search_results(
+        &self,
+        channel: &Channel,
+        request_id: &str,
+        delivery: &Delivery,
+        request: &RabbitMessage,
+        query: &str,
+    ) -> Result<(), DynError> {
+        let chat_id = request.chat_id;
+        let candidates = match self.search.candidates(query, SEARCH_CANDIDATES).await {
+            Ok(candidates) => candidates,
+            Err(e) => {
+                log::error!("Error searching for '{}': {}", query, e);
+                return self
+                    .handle_request_error(channel, request_id, delivery, request, e)
+                    .await;
+            }
+        };
 
-async fn process_songs(text: String, google_api_key: &str) -> Result<Vec<String>, DynError> {
-    let cookie_jar = Arc::new(Jar::default());
-    let mp3_client = Client::builder()
-        .cookie_provider(cookie_jar) // Attach the cookie jar only for mp3 API requests
-        .build()?;
-    let general_client = Client::new(); // General client for other requests
+        for candidate in &candidates {
+            cache::set_or_log(
+                self.cache.as_ref(),
+                &cache::candidate_key(&candidate.video_id),
+                &candidate.title,
+                cache::CANDIDATE_TTL,
+            )
+            .await;
+        }
 
-    let songs: Vec<&str> = text.lines().collect();
-    let mut tasks = Vec::new();
+        // The reply service asks which one was meant in the user's language
+        let reply = RabbitMessage::new(
+            chat_id,
+            MessageBody::SearchResults {
+                query: query.to_string(),
+                candidates,
+            },
+        )
+        .with_options(RequestOptions {
+            language: request.options.language.clone(),
+            ..Default::default()
+        });
+        publish_reply(channel, self.storage.as_deref(), request_id, &reply).await?;
+        history::finish_request(
+            self.storage.as_deref(),
+            request_id,
+            chat_id,
+            RequestStatus::Completed,
+            &[],
+        )
+        .await;
+        self.ack(delivery).await?;
+        Ok(())
+    }
 
-    for song in songs {
-        let mp3_client = mp3_client.clone();
-        let general_client = general_client.clone();
-        let api_key = google_api_key.to_string();
-        let song = song.to_string();
+    // Look up the lyrics of a song and send them as text, split over as
+    // many messages as Telegram needs
+    async fn send_lyrics(
+        &self,
+        channel: &Channel,
+        request_id: &str,
+        delivery: &Delivery,
+        request: &RabbitMessage,
+        query: &str,
+    ) -> Result<(), DynError> {
+        let lyrics = match self.lyrics.lyrics(query).await {
+            Ok(Some(lyrics)) => lyrics,
+            Ok(None) => {
+                log::info!("{} has no lyrics for '{}'", self.lyrics.name(), query);
+                return self
+                    .handle_request_error(
+                        channel,
+                        request_id,
+                        delivery,
+                        request,
+                        SongError::NoLyrics,
+                    )
+                    .await;
+            }
+            Err(e) => {
+                log::error!("Error looking up the lyrics of '{}': {}", query, e);
+                return self
+                    .handle_request_error(channel, request_id, delivery, request, e)
+                    .await;
+            }
+        };
 
-        let task = tokio::spawn(async move {
-            log::info!("Processing song: {}", song);
+        // One entry per line, so messages are only split between lines
+        let entries: Vec<String> =
+            std::iter::once(format!("📝 *{}*\n", markdown::escape(&lyrics.title)))
+                .chain(lyrics.text.lines().map(markdown::escape))
+                .collect();
+        publish_to_reply_queue(
+            channel,
+            self.storage.as_deref(),
+            request_id,
+            request.chat_id,
+            entries,
+        )
+        .await?;
+        history::finish_request(
+            self.storage.as_deref(),
+            request_id,
+            request.chat_id,
+            RequestStatus::Completed,
+            &[],
+        )
+        .await;
+        self.ack(delivery).await?;
+        Ok(())
+    }
 
-            let video_id = search_youtube(&general_client, &api_key, &song)
-                .await?
-                .ok_or_else(|| Box::<dyn Error + Send + Sync>::from("No video found"))?;
+    // Find the songs on YouTube and put them in a new playlist on the user's
+    // channel, answering with its links and the songs that weren't found
+    async fn create_youtube_playlist(
+        &self,
+        channel: &Channel,
+        request_id: &str,
+        delivery: &Delivery,
+        request: &RabbitMessage,
+        text: &str,
+    ) -> Result<(), DynError> {
+        let language = request.options.language.as_deref();
+        let Some(playlists) = &self.youtube_playlists else {
+            log::info!("YouTube playlists are disabled");
+            let reply = RabbitMessage::new(
+                request.chat_id,
+                MessageBody::Error {
+                    message: tr(language, "request.youtube_unsupported"),
+                },
+            );
+            publish_reply(channel, self.storage.as_deref(), request_id, &reply).await?;
+            self.ack(delivery).await?;
+            return Ok(());
+        };
 
-            log::info!("Using video ID: {}", video_id);
+        let songs = match expand_links(
+            text,
+            &BTreeMap::new(),
+            self.spotify.as_ref(),
+            &self.google_api_key,
+        )
+        .await
+        {
+            Ok(songs) => songs,
+            Err(e) => {
+                log::error!("Error expanding links: {}", e);
+                return self
+                    .handle_request_error(channel, request_id, delivery, request, e)
+                    .await;
+            }
+        };
+        let mut videos = Vec::new();
+        let mut not_found = Vec::new();
+        for song in songs {
+            let video_id = match song.video_id {
+                Some(video_id) => Some(video_id),
+                None => match cached_search(self.cache.as_ref(), &self.search, &song.title).await {
+                    Ok(video_id) => video_id,
+                    Err(e) => {
+                        log::error!("Error searching for '{}': {}", song.title, e);
+                        return self
+                            .handle_request_error(channel, request_id, delivery, request, e)
+                            .await;
+                    }
+                },
+            };
+            match video_id {
+                Some(video_id) => videos.push(youtube_playlists::Video {
+                    title: song.title,
+                    video_id,
+                }),
+                None => not_found.push(song.title),
+            }
+        }
+        if videos.is_empty() {
+            log::info!("None of the songs were found on YouTube");
+            return self
+                .handle_request_error(channel, request_id, delivery, request, SongError::NotFound)
+                .await;
+        }
 
-            let k = get_tomp3_k(&mp3_client, &video_id)
-                .await?
-                .ok_or_else(|| Box::<dyn Error + Send + Sync>::from("Failed to get k parameter"))?;
+        let title = tr(language, "youtube.playlist_title");
+        let playlist = match playlists.create(request.chat_id, &title, &videos).await {
+            Ok(playlist) => playlist,
+            Err(e) => {
+                log::error!("Failed to create a YouTube playlist: {}", e);
+                return self
+                    .handle_request_error(channel, request_id, delivery, request, e)
+                    .await;
+            }
+        };
 
-            log::info!("Retrieved k parameter for video ID: {}", video_id);
+        let added = videos.len() - playlist.not_added.len();
+        let mut entries = vec![markdown::escape(&tr_args(
+            language,
+            "youtube.playlist_created",
+            &[
+                ("count", &added.to_string()),
+                ("youtube", &playlist.youtube_url()),
+                ("music", &playlist.youtube_music_url()),
+            ],
+        ))];
+        entries.extend(not_found.iter().map(|title| {
+            markdown::escape(&tr_args(language, "song.not_found", &[("title", title)]))
+        }));
+        entries.extend(playlist.not_added.iter().map(|title| {
+            markdown::escape(&tr_args(language, "youtube.not_added", &[("title", title)]))
+        }));
+        publish_to_reply_queue(
+            channel,
+            self.storage.as_deref(),
+            request_id,
+            request.chat_id,
+            entries,
+        )
+        .await?;
+        history::finish_request(
+            self.storage.as_deref(),
+            request_id,
+            request.chat_id,
+            RequestStatus::Completed,
+            &[],
+        )
+        .await;
+        self.ack(delivery).await?;
+        Ok(())
+    }
 
-            let dlink = convert_to_mp3(&mp3_client, &video_id, &k)
-                .await?
-                .ok_or_else(|| {
-                    Box::<dyn Error + Send + Sync>::from("Failed to get download link")
-                })?;
+    // Inline queries expire after a few seconds, so they get a single search
+    // result as a link and are never retried
+    async fn answer_inline_query(
+        &self,
+        channel: &Channel,
+        request_id: &str,
+        user_id: i64,
+        inline_query_id: &str,
+        query: &str,
+    ) -> Result<(), DynError> {
+        match self.quota.check(user_id, 1).await {
+            Ok(QuotaCheck::Allowed) => {}
+            Ok(QuotaCheck::Exceeded { .. }) => {
+                log::info!("User ID {} is over its daily quota", user_id);
+                return Ok(());
+            }
+            Err(e) => log::error!("Error checking quota: {}", e),
+        }
 
-            log::info!("Retrieved download link: {}", dlink);
+        let results =
+            match tokio::time::timeout(INLINE_QUERY_TIMEOUT, self.find_inline_audio(query)).await {
+                Ok(Ok(audio)) => audio.into_iter().collect(),
+                Ok(Err(e)) => {
+                    log::error!("Inline query '{}' failed: {}", query, e);
+                    Vec::new()
+                }
+                Err(_) => {
+                    log::warn!("Inline query '{}' timed out", query);
+                    return Ok(());
+                }
+            };
 
-            // Return the formatted link with song name
-            Ok::<String, DynError>(format!("🎵 *{}*\n🔗 {}", song, dlink))
-        });
+        let reply = RabbitMessage::new(
+            user_id,
+            MessageBody::InlineAnswer {
+                inline_query_id: inline_query_id.to_string(),
+                results,
+            },
+        );
+        // Inline answers expire within seconds, so they skip the outbox
+        publish_reply(channel, None, request_id, &reply).await
+    }
+
+    async fn find_inline_audio(&self, query: &str) -> Result<Option<InlineAudio>, SongError> {
+        let _permit = self
+            .processor
+            .conversion_limiter()
+            .acquire()
+            .await
+            .map_err(|e| SongError::Conversion(e.to_string()))?;
+
+        let Some(video_id) = cached_search(self.cache.as_ref(), &self.search, query).await? else {
+            return Ok(None);
+        };
+
+        let bitrate = self.default_bitrate;
+        let file_id_key = cache::file_id_key(&video_id, bitrate);
+        let source = match cache::get_or_log(self.cache.as_ref(), &file_id_key).await {
+            Some(file_id) => InlineAudioSource::Cached { file_id },
+            // Without a media directory every provider hands out a link
+            None => match cached_fetch_mp3(
+                self.cache.as_ref(),
+                &self.providers,
+                &video_id,
+                bitrate,
+                None,
+            )
+            .await?
+            {
+                Mp3Source::Link(url) => InlineAudioSource::Url { url },
+                Mp3Source::File(_) => {
+                    return Err(SongError::Conversion(
+                        "provider downloaded a file for an inline query".to_string(),
+                    ))
+                }
+            },
+        };
+
+        let (performer, title) = downloader::split_artist_title(query);
+        Ok(Some(InlineAudio {
+            id: video_id,
+            title,
+            performer,
+            source,
+        }))
+    }
 
-        tasks.push(task);
+    // Retry the delivery if the error is transient, otherwise tell the user
+    // why their request failed and drop it
+    async fn handle_request_error(
+        &self,
+        channel: &Channel,
+        request_id: &str,
+        delivery: &Delivery,
+        request: &RabbitMessage,
+        error: SongError,
+    ) -> Result<(), DynError> {
+        let chat_id = request.chat_id;
+        alerts::record_request(error.is_reportable());
+        if error.is_retryable() {
+            return retry::handle_failure(channel, delivery, self.max_retries).await;
+        }
+        if error.is_reportable() {
+            rustin_telemetry::report(&error);
+        }
+
+        history::finish_request(
+            self.storage.as_deref(),
+            request_id,
+            chat_id,
+            RequestStatus::Failed,
+            &[],
+        )
+        .await;
+
+        let reply = RabbitMessage::new(
+            chat_id,
+            MessageBody::Error {
+                message: error.user_message(request.options.language.as_deref()),
+            },
+        );
+        publish_reply(channel, self.storage.as_deref(), request_id, &reply).await?;
+        self.ack(delivery).await?;
+        Ok(())
     }
 
-    let results = join_all(tasks).await;
-    let mut links = Vec::new();
+    // Whether an M3U playlist of the songs is sent after them. It needs the
+    // media directory, and streamed songs are gone by the end of the request
+    fn sends_playlist(&self, options: &RequestOptions, song_count: usize) -> bool {
+        options.playlist
+            && !options.stream
+            && options.format == MediaFormat::Mp3
+            && self.media_dir.is_some()
+            && song_count > 1
+    }
 
-    for (index, result) in results.into_iter().enumerate() {
-        match result {
-            Ok(Ok(link)) => links.push(format!("{}. {}", index + 1, link)),
-            Ok(Err(e)) => log::error!("Error in task: {}", e),
-            Err(e) => log::error!("Task panicked: {}", e),
+    // Send the playlist as a document. It's only an extra, so failing to
+    // write it doesn't fail the request
+    async fn publish_playlist(
+        &self,
+        channel: &Channel,
+        request_id: &str,
+        chat_id: i64,
+        entries: &[playlist::Entry],
+    ) -> Result<(), DynError> {
+        let Some(media_dir) = self.media_dir.as_deref() else {
+            return Ok(());
+        };
+        let file_path = match playlist::write(media_dir, entries).await {
+            Ok(file_path) => file_path,
+            Err(e) => {
+                log::error!(
+                    "Failed to write the playlist of request {}: {}",
+                    request_id,
+                    e
+                );
+                return Ok(());
+            }
+        };
+
+        let message = RabbitMessage::new(
+            chat_id,
+            MessageBody::Archive {
+                file_path: file_path.to_string_lossy().into_owned(),
+                file_name: playlist::FILE_NAME.to_string(),
+            },
+        );
+        publish_reply(channel, self.storage.as_deref(), request_id, &message).await?;
+        log::info!("Published playlist reply for chat ID: {}", chat_id);
+        Ok(())
+    }
+
+    // Send the downloaded songs as archives. When they can't be written the
+    // songs are handed back to be sent one by one instead
+    async fn publish_archives(
+        &self,
+        channel: &Channel,
+        request_id: &str,
+        chat_id: i64,
+        audio: Vec<AudioFile>,
+    ) -> Result<Vec<AudioFile>, DynError> {
+        let Some(media_dir) = self.media_dir.as_deref() else {
+            return Ok(audio);
+        };
+        if audio.is_empty() {
+            return Ok(audio);
+        }
+        let archives = match archive::write_archives(media_dir, &audio).await {
+            Ok(archives) => archives,
+            Err(e) => {
+                log::error!("Failed to zip the songs of request {}: {}", request_id, e);
+                return Ok(audio);
+            }
+        };
+        archive::remove_songs(&audio).await;
+
+        for archive in archives {
+            let message = RabbitMessage::new(
+                chat_id,
+                MessageBody::Archive {
+                    file_path: archive.file_path.to_string_lossy().into_owned(),
+                    file_name: archive.file_name,
+                },
+            );
+            publish_reply(channel, self.storage.as_deref(), request_id, &message).await?;
         }
+        log::info!("Published archive reply for chat ID: {}", chat_id);
+        Ok(Vec::new())
     }
 
-    Ok(links)
+    // Ack a delivery that has been dealt with, remembering its message ID so
+    // a redelivery of it is skipped
+    async fn ack(&self, delivery: &Delivery) -> Result<(), DynError> {
+        if let Some(message_id) = correlation::message_id(delivery) {
+            dedup::mark_processed(self.cache.as_ref(), self.storage.as_deref(), &message_id).await;
+        }
+        delivery.ack(BasicAckOptions::default()).await?;
+        Ok(())
+    }
 }
 
-async fn search_youtube(
-    client: &Client,
-    api_key: &str,
-    query: &str,
-) -> Result<Option<String>, DynError> {
-    let encoded_query = encode(query);
-    let url = format!(
-        "https://www.googleapis.com/youtube/v3/search?part=snippet&type=video&order=viewCount&maxResults=1&q={}&key={}",
-        encoded_query, api_key
-    );
+// Pasted playlists often hold the same song twice. Repeats are converted
+// once, and the user is told which songs they repeat
+fn remove_duplicates(
+    songs: Vec<SongRequest>,
+    language: Option<&str>,
+) -> (Vec<SongRequest>, Vec<String>) {
+    let first_occurrences = duplicates::first_occurrences(songs.iter().map(|song| {
+        // Titles that search the same find the same song
+        let id = song
+            .video_id
+            .clone()
+            .unwrap_or_else(|| cache::search_key(&song.title));
+        (id, song.bitrate, song.clip, song.effect)
+    }));
 
-    log::info!("Searching YouTube with query: {}", query);
-    let response: YouTubeResponse = client.get(&url).send().await?.json().await?;
-    Ok(response
-        .items
-        .into_iter()
-        .next()
-        .map(|item| item.id.videoId))
+    let mut unique = Vec::new();
+    let mut notes = Vec::new();
+    for (index, (song, first)) in songs.into_iter().zip(first_occurrences).enumerate() {
+        match first {
+            Some(first) => {
+                log::info!(
+                    "Skipping '{}', a duplicate of song #{}",
+                    song.title,
+                    first + 1
+                );
+                let note = tr_args(
+                    language,
+                    "request.duplicate",
+                    &[
+                        ("index", &(index + 1).to_string()),
+                        ("first", &(first + 1).to_string()),
+                        ("title", &song.title),
+                    ],
+                );
+                notes.push(markdown::escape(&note));
+            }
+            None => unique.push(song),
+        }
+    }
+    (unique, notes)
 }
 
-fn log_cookies(cookie_jar: &Arc<Jar>, url: &str) {
-    let cookies = cookie_jar.cookies(&url.parse().unwrap());
-    match cookies {
-        Some(cookie) => log::info!("Attached cookies for {}: {:#?}", url, cookie),
-        None => log::info!("No cookies attached for {}", url),
+// Turn the request text into songs, replacing Spotify, Apple Music and Deezer
+// links with the "artist - title" lines of their tracks and YouTube playlists
+// with their videos
+async fn expand_links(
+    text: &str,
+    clips: &BTreeMap<usize, Clip>,
+    spotify: Option<&SpotifyClient>,
+    google_api_key: &str,
+) -> Result<Vec<SongRequest>, SongError> {
+    // Bandcamp titles are read off its pages
+    let client = http::scraping_client();
+    let mut songs = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        // Blank lines, also those left by a stripped link, are no song
+        if line.trim().is_empty() {
+            continue;
+        }
+        // The effect may come before or after the quality suffix
+        let (line, effect) = effects::split_effect_suffix(line);
+        let (line, bitrate) = quality::split_bitrate_suffix(line);
+        let (line, effect) = match effect {
+            Some(effect) => (line, Some(effect)),
+            None => effects::split_effect_suffix(line),
+        };
+        let mut expanded = expand_line(line, spotify, &client, google_api_key).await?;
+        for song in &mut expanded {
+            song.bitrate = bitrate;
+            song.clip = clips.get(&index).copied();
+            song.effect = effect;
+        }
+        songs.extend(expanded);
     }
+
+    Ok(songs)
 }
 
-async fn get_tomp3_k(client: &Client, video_id: &str) -> Result<Option<String>, DynError> {
-    let url = "https://tomp3.cc/api/ajax/search";
-    let params = [
-        (
-            "query",
-            format!("https://www.youtube.com/watch?v={}", video_id),
-        ),
-        ("vt", "downloader".to_string()),
-    ];
+async fn expand_line(
+    line: &str,
+    spotify: Option<&SpotifyClient>,
+    client: &http::Client,
+    google_api_key: &str,
+) -> Result<Vec<SongRequest>, SongError> {
+    if let Some(video_id) = url_parser::parse_video_id(line) {
+        return Ok(vec![SongRequest::video(line.trim(), video_id)]);
+    }
 
-    log::info!("Retrieving k parameter for video ID: {}", video_id);
+    if let Some((artist, track)) = url_parser::parse_soundcloud_track(line) {
+        let id = soundcloud::track_id(&artist, &track);
+        let url = soundcloud::track_url(&id).unwrap_or_else(|| line.trim().to_string());
+        // The track can still be converted, just under a plainer name
+        let title = soundcloud::fetch_title(client, &url)
+            .await
+            .unwrap_or_else(|e| {
+                log::warn!("Failed to look up the title of {}: {}", url, e);
+                format!("{} - {}", artist, track)
+            });
+        return Ok(vec![SongRequest::video(title, id)]);
+    }
 
-    let response = client.post(url).form(&params).header("Cookie", "cf_clearance=nfBjEpAsDIH9gI2YRAWoVSkMrAyeiF2ArPYV9WMQop4-1723801695-1.0.1.1-C8QFuaiYCUF9A6Rz8LXox1TOt.xvGErsl_Is71Wyof3mkIu3RbEHxiIOO5z8icN05BoEAaPvkntWZRxWVAXFEw; _ga_JRWV2N11YN=GS1.1.1723801702.1.1.1723801732.0.0.0; _ga=GA1.1.1396507687.1723801703").send().await?;
+    if let Some(link) = url_parser::parse_bandcamp_link(line) {
+        return match link {
+            BandcampLink::Track { artist, slug } => {
+                let title = bandcamp::track_title(client, &artist, &slug)
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::warn!("Failed to look up the title of {}: {}", line.trim(), e);
+                        format!("{} - {}", artist, slug)
+                    });
+                Ok(vec![SongRequest::video(
+                    title,
+                    bandcamp::track_id(&artist, &slug),
+                )])
+            }
+            BandcampLink::Album { artist, slug } => {
+                Ok(bandcamp::album_tracks(client, &artist, &slug)
+                    .await?
+                    .into_iter()
+                    .map(|(title, id)| SongRequest::video(title, id))
+                    .collect())
+            }
+        };
+    }
 
-    let status = response.status();
-    let text = response.text().await?;
-    log::info!("Response status: {}", status);
-    log::info!("Raw response body: {}", text);
+    if let Some(link) = url_parser::parse_music_link(line) {
+        let song = music_links::resolve(client, &link).await?;
+        return Ok(vec![match song.video_id {
+            Some(video_id) => SongRequest::video(song.title, video_id),
+            None => SongRequest::search(song.title),
+        }]);
+    }
 
-    if !status.is_success() {
-        log::error!("Failed request: {}", status);
-        return Err(Box::<dyn Error + Send + Sync>::from(
-            "Non-successful status",
-        ));
+    if let Some(playlist_id) = url_parser::parse_playlist_id(line) {
+        let videos = youtube::expand_playlist(client, google_api_key, &playlist_id).await?;
+        return Ok(videos
+            .into_iter()
+            .map(|video| SongRequest::video(video.title, video.video_id))
+            .collect());
+    }
+
+    match (spotify::parse_link(line), spotify) {
+        (Some(link), Some(spotify)) => Ok(spotify
+            .expand(&link)
+            .await
+            .map_err(|e| SongError::Expansion(e.to_string()))?
+            .into_iter()
+            .map(SongRequest::search)
+            .collect()),
+        (Some(_), None) => {
+            log::warn!("Received a Spotify link but Spotify support is disabled");
+            Ok(vec![SongRequest::search(line)])
+        }
+        (None, _) => {
+            // A line that was nothing but noise is searched as it is
+            let title = titles::clean(line);
+            match title.is_empty() {
+                true => Ok(vec![SongRequest::search(line.trim())]),
+                false => Ok(vec![SongRequest::search(title)]),
+            }
+        }
+    }
+}
+
+// Publishes every song of a request as soon as it's converted, for chats that
+// don't want to wait for the whole request
+struct SongStream {
+    channel: Channel,
+    outbox: Option<Arc<Storage>>,
+    request_id: String,
+    chat_id: i64,
+    language: Option<String>,
+}
+
+impl SongStream {
+    fn for_request(
+        channel: &Channel,
+        outbox: Option<&Arc<Storage>>,
+        request_id: &str,
+        chat_id: i64,
+        options: &RequestOptions,
+    ) -> Option<Arc<Self>> {
+        options.stream.then(|| {
+            Arc::new(Self {
+                channel: channel.clone(),
+                outbox: outbox.cloned(),
+                request_id: request_id.to_string(),
+                chat_id,
+                language: options.language.clone(),
+            })
+        })
     }
 
-    let parsed: Result<Tomp3Response, _> = serde_json::from_str(&text);
-    match parsed {
-        Ok(response) => Ok(response
-            .links
-            .and_then(|l| l.mp3)
-            .and_then(|mp3| mp3.get("mp3128").map(|link| link.k.clone()))),
-        Err(e) => {
-            log::error!("Error decoding response: {}", e);
-            Err(Box::<dyn Error + Send + Sync>::from(
-                "Error decoding response body",
-            ))
+    // Publish a converted song on its own. A song that can't be published is
+    // reported with the failures at the end instead
+    async fn send(&self, title: &str, converted: ConvertedSong) -> Result<ConvertedSong, String> {
+        let channel = &self.channel;
+        let outbox = self.outbox.as_deref();
+        let request_id = self.request_id.as_str();
+        let mut file_id = None;
+        let result = match converted {
+            ConvertedSong::Link { text, .. } => {
+                publish_to_reply_queue(channel, outbox, request_id, self.chat_id, vec![text]).await
+            }
+            ConvertedSong::Audio(audio) => {
+                publish_audio_to_reply_queue(channel, outbox, request_id, self.chat_id, audio).await
+            }
+            ConvertedSong::Video(video) => {
+                publish_video_to_reply_queue(channel, outbox, request_id, self.chat_id, video).await
+            }
+            ConvertedSong::CachedAudio(audio) => {
+                file_id = Some(audio.file_id.clone());
+                publish_cached_audio_to_reply_queue(
+                    channel,
+                    outbox,
+                    request_id,
+                    self.chat_id,
+                    audio,
+                )
+                .await
+            }
+            streamed @ ConvertedSong::Streamed { .. } => return Ok(streamed),
+        };
+
+        match result {
+            Ok(()) => Ok(ConvertedSong::Streamed { file_id }),
+            Err(e) => {
+                log::error!("Failed to publish '{}' on its own: {}", title, e);
+                Err(tr_args(
+                    self.language.as_deref(),
+                    "song.send_failed",
+                    &[("title", title)],
+                ))
+            }
         }
     }
 }
 
-async fn convert_to_mp3(
-    client: &Client,
-    video_id: &str,
-    k: &str,
-) -> Result<Option<String>, DynError> {
-    let url = "https://tomp3.cc/api/ajax/convert";
-    let params = [("vid", video_id.to_string()), ("k", k.to_string())];
+// Requests with fewer songs finish quickly enough without progress updates
+const PROGRESS_MIN_SONGS: usize = 10;
 
-    log::info!("Converting video ID {} to MP3", video_id);
-    let response: ConvertResponse = client.post(url).form(&params).send().await?.json().await?;
-    Ok(Some(response.dlink))
+// Tells the user how far a large request has got, roughly every quarter
+struct Progress {
+    channel: Channel,
+    request_id: String,
+    chat_id: i64,
+    // Lets the reply service word the updates in the user's language
+    options: RequestOptions,
+    total: usize,
+    completed: AtomicUsize,
 }
 
+impl Progress {
+    fn for_request(
+        channel: &Channel,
+        request_id: &str,
+        chat_id: i64,
+        options: &RequestOptions,
+        total: usize,
+    ) -> Option<Arc<Self>> {
+        (total >= PROGRESS_MIN_SONGS).then(|| {
+            Arc::new(Self {
+                channel: channel.clone(),
+                request_id: request_id.to_string(),
+                chat_id,
+                options: RequestOptions {
+                    language: options.language.clone(),
+                    ..Default::default()
+                },
+                total,
+                completed: AtomicUsize::new(0),
+            })
+        })
+    }
+
+    async fn song_done(&self) {
+        let completed = self.completed.fetch_add(1, Ordering::SeqCst) + 1;
+        let step = (self.total / 4).max(1);
+        // The final reply follows right after the last song
+        if !completed.is_multiple_of(step) || completed >= self.total {
+            return;
+        }
+
+        let message = RabbitMessage::new(
+            self.chat_id,
+            MessageBody::Progress {
+                completed: completed as u32,
+                total: self.total as u32,
+            },
+        )
+        .with_options(self.options.clone());
+        // Progress is only useful right away, so it skips the outbox
+        if let Err(e) = publish_reply(&self.channel, None, &self.request_id, &message).await {
+            log::warn!("Failed to publish progress update: {}", e);
+        }
+    }
+}
+
+// Send the converted songs, split over several messages if they don't fit
+// into a single Telegram message
 async fn publish_to_reply_queue(
     channel: &Channel,
+    outbox: Option<&Storage>,
+    request_id: &str,
     chat_id: i64,
     links: Vec<String>,
 ) -> Result<(), DynError> {
-    let message = RabbitMessage {
-        chat_id,
-        text: links.join("\n"),
-    };
-    let serialized_message = serde_json::to_vec(&message)?;
-    channel
-        .basic_publish(
-            "",
-            "Reply",
-            BasicPublishOptions::default(),
-            &serialized_message,
-            BasicProperties::default(),
-        )
-        .await?;
+    for text in chunking::chunk_entries(&links, chunking::TELEGRAM_MESSAGE_LIMIT) {
+        let message = RabbitMessage::new(chat_id, MessageBody::Result { text });
+        publish_reply(channel, outbox, request_id, &message).await?;
+    }
     log::info!("Published reply for chat ID: {}", chat_id);
     Ok(())
 }
+
+// Hand a downloaded MP3 over to the reply service for uploading
+async fn publish_audio_to_reply_queue(
+    channel: &Channel,
+    outbox: Option<&Storage>,
+    request_id: &str,
+    chat_id: i64,
+    audio: AudioFile,
+) -> Result<(), DynError> {
+    let message = RabbitMessage::new(
+        chat_id,
+        MessageBody::Audio {
+            file_path: audio.file_path.to_string_lossy().into_owned(),
+            title: audio.title,
+            performer: audio.performer,
+            thumbnail_path: audio
+                .thumbnail_path
+                .map(|path| path.to_string_lossy().into_owned()),
+            cache_key: audio.cache_key,
+            caption: audio.caption,
+            lyrics_path: audio
+                .lyrics_path
+                .map(|path| path.to_string_lossy().into_owned()),
+        },
+    );
+    publish_reply(channel, outbox, request_id, &message).await?;
+    log::info!("Published audio reply for chat ID: {}", chat_id);
+    Ok(())
+}
+
+async fn publish_video_to_reply_queue(
+    channel: &Channel,
+    outbox: Option<&Storage>,
+    request_id: &str,
+    chat_id: i64,
+    video: VideoFile,
+) -> Result<(), DynError> {
+    let message = RabbitMessage::new(
+        chat_id,
+        MessageBody::Video {
+            file_path: video.file_path.to_string_lossy().into_owned(),
+            title: video.title,
+        },
+    );
+    publish_reply(channel, outbox, request_id, &message).await?;
+    log::info!("Published video reply for chat ID: {}", chat_id);
+    Ok(())
+}
+
+// Have the reply service resend an already uploaded MP3
+async fn publish_cached_audio_to_reply_queue(
+    channel: &Channel,
+    outbox: Option<&Storage>,
+    request_id: &str,
+    chat_id: i64,
+    audio: CachedAudio,
+) -> Result<(), DynError> {
+    let message = RabbitMessage::new(
+        chat_id,
+        MessageBody::CachedAudio {
+            file_id: audio.file_id,
+            title: audio.title,
+            performer: audio.performer,
+            caption: audio.caption,
+            lyrics_path: audio
+                .lyrics_path
+                .map(|path| path.to_string_lossy().into_owned()),
+        },
+    );
+    publish_reply(channel, outbox, request_id, &message).await?;
+    log::info!("Published cached audio reply for chat ID: {}", chat_id);
+    Ok(())
+}
+
+// Tell the user how far a cancelled request got
+async fn publish_cancelled(
+    channel: &Channel,
+    outbox: Option<&Storage>,
+    request_id: &str,
+    chat_id: i64,
+    language: Option<&str>,
+    history: &[SongRecord],
+) -> Result<(), DynError> {
+    let converted = history
+        .iter()
+        .filter(|song| song.status == SongStatus::Converted)
+        .count();
+    let text = tr_args(
+        language,
+        "request.cancelled",
+        &[
+            ("converted", &converted.to_string()),
+            ("total", &history.len().to_string()),
+        ],
+    );
+    let message = RabbitMessage::new(
+        chat_id,
+        MessageBody::Result {
+            text: markdown::escape(&text),
+        },
+    );
+    publish_reply(channel, outbox, request_id, &message).await?;
+    log::info!("Published cancellation notice for chat ID: {}", chat_id);
+    Ok(())
+}
+
+// Tell the user which songs could not be converted and why
+async fn publish_error_to_reply_queue(
+    channel: &Channel,
+    outbox: Option<&Storage>,
+    request_id: &str,
+    chat_id: i64,
+    failures: Vec<String>,
+) -> Result<(), DynError> {
+    // Leave room for the warning sign the reply service puts in front
+    let limit = chunking::TELEGRAM_MESSAGE_LIMIT - 3;
+    for message in chunking::chunk_entries(&failures, limit) {
+        let message = RabbitMessage::new(chat_id, MessageBody::Error { message });
+        publish_reply(channel, outbox, request_id, &message).await?;
+    }
+    log::info!("Published error reply for chat ID: {}", chat_id);
+    Ok(())
+}
+
+// Replies go through the outbox when there is one, so a conversion isn't lost
+// when publishing fails after it. Without one they're published right away
+#[tracing::instrument(skip_all)]
+async fn publish_reply(
+    channel: &Channel,
+    outbox: Option<&Storage>,
+    request_id: &str,
+    message: &RabbitMessage,
+) -> Result<(), DynError> {
+    let payload = serde_json::to_vec(message)?;
+    let queue = &topology::queues().reply;
+    let trace_context = rustin_telemetry::trace_context();
+    match outbox {
+        Some(storage) => {
+            // Replies of requests scheduled with /later wait for their time
+            if !storage.hold_reply(queue, request_id, &payload).await? {
+                storage
+                    .enqueue_outbox(queue, request_id, &payload, trace_context.as_deref())
+                    .await?;
+            }
+        }
+        None => {
+            outbox::publish_confirmed(
+                channel,
+                queue,
+                request_id,
+                trace_context.as_deref(),
+                &payload,
+            )
+            .await?
+        }
+    }
+    Ok(())
+}
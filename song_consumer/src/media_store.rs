@@ -0,0 +1,217 @@
+// Keeps the media directory from filling the disk. The reply service removes
+// what it uploads, but files of failed deliveries, cancelled requests and
+// crashed replicas stay behind. They're removed once they're old, and the
+// least recently used files go first whenever the directory grows too large
+
+use crate::{config::Config, instance, metrics};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio_util::sync::CancellationToken;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// Files this fresh are still being converted or waiting to be uploaded
+const MIN_AGE: Duration = Duration::from_secs(10 * 60);
+
+// A file in the media directory
+#[derive(Debug, Clone)]
+struct StoredFile {
+    path: PathBuf,
+    size: u64,
+    last_used: SystemTime,
+}
+
+pub struct MediaStore {
+    // This replica's own subdirectory of media_dir
+    dir: PathBuf,
+    max_bytes: u64,
+    max_age: Duration,
+}
+
+impl MediaStore {
+    // Every replica downloads into its own subdirectory, so two of them
+    // converting the same video never write to the same file
+    pub async fn open(config: &Config) -> io::Result<Option<Self>> {
+        let Some(media_dir) = &config.media_dir else {
+            return Ok(None);
+        };
+        let dir = media_dir.join(instance::id());
+        tokio::fs::create_dir_all(&dir).await?;
+        Ok(Some(Self {
+            dir,
+            max_bytes: config.media_max_mb * 1024 * 1024,
+            max_age: Duration::from_secs(config.media_max_age_secs),
+        }))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Clean up until shutdown, starting with what a previous run left behind
+    pub async fn clean_up_periodically(self, shutdown: CancellationToken) {
+        loop {
+            if let Err(e) = self.clean_up().await {
+                log::error!("Failed to clean up {}: {}", self.dir.display(), e);
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(CLEANUP_INTERVAL) => {}
+            }
+        }
+    }
+
+    async fn clean_up(&self) -> io::Result<()> {
+        let files = list_files(&self.dir).await?;
+        let evicted = evictions(&files, self.max_bytes, self.max_age, SystemTime::now());
+
+        let mut bytes: u64 = files.iter().map(|file| file.size).sum();
+        let mut count = files.len();
+        for file in evicted {
+            match tokio::fs::remove_file(&file.path).await {
+                Ok(()) => {
+                    bytes -= file.size;
+                    count -= 1;
+                    metrics::MEDIA_FILES_EVICTED.inc();
+                }
+                // The reply service got to it first
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    bytes -= file.size;
+                    count -= 1;
+                }
+                Err(e) => log::warn!("Failed to remove {}: {}", file.path.display(), e),
+            }
+        }
+        metrics::MEDIA_BYTES.set(bytes as i64);
+        metrics::MEDIA_FILES.set(count as i64);
+        log::info!(
+            "{} holds {} files, {} MB",
+            self.dir.display(),
+            count,
+            bytes / 1024 / 1024
+        );
+        Ok(())
+    }
+}
+
+// Every file under the directory, subdirectories included
+async fn list_files(dir: &Path) -> io::Result<Vec<StoredFile>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            // Removed by the reply service since it was listed
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+                continue;
+            }
+            // Access times are often turned off, the modification time is
+            // when the file was written then
+            let last_used = metadata
+                .accessed()
+                .or_else(|_| metadata.modified())
+                .unwrap_or_else(|_| SystemTime::now());
+            files.push(StoredFile {
+                path: entry.path(),
+                size: metadata.len(),
+                last_used,
+            });
+        }
+    }
+    Ok(files)
+}
+
+// The files to remove: those unused for longer than max_age, then the least
+// recently used ones until the rest fits into max_bytes. Files younger than
+// MIN_AGE are kept either way
+fn evictions(
+    files: &[StoredFile],
+    max_bytes: u64,
+    max_age: Duration,
+    now: SystemTime,
+) -> Vec<StoredFile> {
+    let age = |file: &StoredFile| now.duration_since(file.last_used).unwrap_or_default();
+    let mut files: Vec<&StoredFile> = files.iter().collect();
+    files.sort_by_key(|file| file.last_used);
+
+    let mut bytes: u64 = files.iter().map(|file| file.size).sum();
+    let mut evicted = Vec::new();
+    for file in files {
+        let age = age(file);
+        if age < MIN_AGE {
+            break;
+        }
+        if age >= max_age || bytes > max_bytes {
+            bytes -= file.size;
+            evicted.push(file.clone());
+        }
+    }
+    evicted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn file(name: &str, size_mb: u64, age: Duration, now: SystemTime) -> StoredFile {
+        StoredFile {
+            path: PathBuf::from(name),
+            size: size_mb * MB,
+            last_used: now - age,
+        }
+    }
+
+    fn names(files: &[StoredFile]) -> Vec<&str> {
+        files
+            .iter()
+            .map(|file| file.path.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_files_first() {
+        let now = SystemTime::now();
+        let files = [
+            file("new.mp3", 40, HOUR, now),
+            file("old.mp3", 40, 3 * HOUR, now),
+            file("older.mp3", 40, 5 * HOUR, now),
+        ];
+        assert_eq!(
+            names(&evictions(&files, 50 * MB, 24 * HOUR, now)),
+            vec!["older.mp3", "old.mp3"]
+        );
+        assert!(evictions(&files, 200 * MB, 24 * HOUR, now).is_empty());
+    }
+
+    #[test]
+    fn evicts_stale_files_even_under_the_limit() {
+        let now = SystemTime::now();
+        let files = [
+            file("fresh.mp3", 1, HOUR, now),
+            file("stale.mp3", 1, 30 * HOUR, now),
+        ];
+        assert_eq!(
+            names(&evictions(&files, 200 * MB, 24 * HOUR, now)),
+            vec!["stale.mp3"]
+        );
+    }
+
+    #[test]
+    fn keeps_files_still_in_use() {
+        let now = SystemTime::now();
+        let files = [
+            file("converting.mp3", 100, Duration::from_secs(30), now),
+            file("uploading.mp3", 100, Duration::from_secs(5 * 60), now),
+        ];
+        assert!(evictions(&files, 50 * MB, Duration::ZERO, now).is_empty());
+    }
+}
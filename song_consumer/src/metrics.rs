@@ -0,0 +1,198 @@
+use crate::instance;
+use axum::{http::header, routing::get, Router};
+use prometheus::{
+    histogram_opts, opts, register_histogram, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, Histogram, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec, TextEncoder,
+};
+use std::sync::LazyLock;
+
+pub static MESSAGES_CONSUMED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(opts!(
+        "song_consumer_messages_consumed_total",
+        "Messages received from the Music queue"
+    )
+    .const_label("instance_id", instance::id()))
+    .expect("Failed to register metric")
+});
+
+pub static MESSAGES_DROPPED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(opts!(
+        "song_consumer_messages_dropped_total",
+        "Messages from banned chats that were dropped unhandled"
+    )
+    .const_label("instance_id", instance::id()))
+    .expect("Failed to register metric")
+});
+
+pub static INVALID_MESSAGES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(opts!(
+        "song_consumer_invalid_messages_total",
+        "Messages that couldn't be parsed and were moved to the invalid queue"
+    )
+    .const_label("instance_id", instance::id()))
+    .expect("Failed to register metric")
+});
+
+pub static SONGS_CONVERTED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(opts!(
+        "song_consumer_songs_converted_total",
+        "Songs that were converted or resent from the file_id cache"
+    )
+    .const_label("instance_id", instance::id()))
+    .expect("Failed to register metric")
+});
+
+pub static FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        opts!(
+            "song_consumer_failures_total",
+            "Songs that failed, by the stage they failed in"
+        )
+        .const_label("instance_id", instance::id()),
+        &["stage"]
+    )
+    .expect("Failed to register metric")
+});
+
+pub static CONVERSION_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(histogram_opts!(
+        "song_consumer_conversion_seconds",
+        "Time it took to convert a single song to MP3",
+        vec![1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0]
+    )
+    .const_label("instance_id", instance::id()))
+    .expect("Failed to register metric")
+});
+
+pub static SEARCH_LOOKUPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        opts!(
+            "song_consumer_search_lookups_total",
+            "Song lines looked up in the search cache, by whether they were found"
+        )
+        .const_label("instance_id", instance::id()),
+        &["result"]
+    )
+    .expect("Failed to register metric")
+});
+
+pub static SEARCH_CALLS_SAVED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(opts!(
+        "song_consumer_search_calls_saved_total",
+        "YouTube search calls saved by batching and deduplicating the lines of requests"
+    )
+    .const_label("instance_id", instance::id()))
+    .expect("Failed to register metric")
+});
+
+// Shared by all replicas, so every replica reports the same total
+pub static YOUTUBE_QUOTA_UNITS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(opts!(
+        "song_consumer_youtube_quota_units",
+        "Estimated YouTube Data API quota units spent today"
+    )
+    .const_label("instance_id", instance::id()))
+    .expect("Failed to register metric")
+});
+
+pub static MEDIA_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(opts!(
+        "song_consumer_media_bytes",
+        "Size of the files in this replica's media directory"
+    )
+    .const_label("instance_id", instance::id()))
+    .expect("Failed to register metric")
+});
+
+pub static MEDIA_FILES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(opts!(
+        "song_consumer_media_files",
+        "Files in this replica's media directory"
+    )
+    .const_label("instance_id", instance::id()))
+    .expect("Failed to register metric")
+});
+
+pub static MEDIA_FILES_EVICTED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(opts!(
+        "song_consumer_media_files_evicted_total",
+        "Files removed from the media directory for being stale or over the size limit"
+    )
+    .const_label("instance_id", instance::id()))
+    .expect("Failed to register metric")
+});
+
+pub static CIRCUIT_OPEN: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        opts!(
+            "song_consumer_circuit_open",
+            "Whether calls to a provider are skipped after too many of them failed"
+        )
+        .const_label("instance_id", instance::id()),
+        &["provider"]
+    )
+    .expect("Failed to register metric")
+});
+
+// Where a song can fail on its way from title to MP3
+#[derive(Debug, Clone, Copy)]
+pub enum Stage {
+    Search,
+    // Retrieving the tomp3 k parameter
+    K,
+    Convert,
+    Download,
+}
+
+impl Stage {
+    fn as_str(self) -> &'static str {
+        match self {
+            Stage::Search => "search",
+            Stage::K => "k",
+            Stage::Convert => "convert",
+            Stage::Download => "download",
+        }
+    }
+}
+
+pub fn record_failure(stage: Stage) {
+    FAILURES.with_label_values(&[stage.as_str()]).inc();
+}
+
+pub fn record_search_lookup(hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    SEARCH_LOOKUPS.with_label_values(&[result]).inc();
+}
+
+// Serves the metrics in the Prometheus text format on /metrics
+pub fn router() -> Router {
+    // Register everything up front so scrapes see zeroes instead of nothing
+    LazyLock::force(&MESSAGES_CONSUMED);
+    LazyLock::force(&MESSAGES_DROPPED);
+    LazyLock::force(&INVALID_MESSAGES);
+    LazyLock::force(&SONGS_CONVERTED);
+    LazyLock::force(&CONVERSION_SECONDS);
+    LazyLock::force(&SEARCH_CALLS_SAVED);
+    for result in ["hit", "miss"] {
+        SEARCH_LOOKUPS.with_label_values(&[result]);
+    }
+    LazyLock::force(&YOUTUBE_QUOTA_UNITS);
+    LazyLock::force(&MEDIA_BYTES);
+    LazyLock::force(&MEDIA_FILES);
+    LazyLock::force(&MEDIA_FILES_EVICTED);
+    LazyLock::force(&CIRCUIT_OPEN);
+    for stage in [Stage::Search, Stage::K, Stage::Convert, Stage::Download] {
+        FAILURES.with_label_values(&[stage.as_str()]);
+    }
+
+    Router::new().route("/metrics", get(render))
+}
+
+async fn render() -> ([(header::HeaderName, &'static str); 1], Vec<u8>) {
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        log::error!("Failed to encode metrics: {}", e);
+    }
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], buffer)
+}
@@ -0,0 +1,80 @@
+use crate::{
+    error::SongError,
+    http::Client,
+    odesli,
+    url_parser::{self, MusicLink},
+};
+use rustin_models::{deezer::DeezerTrack, itunes::ItunesLookup};
+use serde::de::DeserializeOwned;
+
+// Platforms of a song.link page that lead straight to a YouTube video
+const YOUTUBE_PLATFORMS: [&str; 2] = ["youtube", "youtubeMusic"];
+
+// The song a link of another service is for, to be looked up on YouTube
+pub struct LinkedSong {
+    // "Artist - Title", the way YouTube titles read
+    pub title: String,
+    // Only song.link pages know where the song is on YouTube
+    pub video_id: Option<String>,
+}
+
+pub async fn resolve(client: &Client, link: &MusicLink) -> Result<LinkedSong, SongError> {
+    match link {
+        MusicLink::AppleMusic {
+            storefront,
+            track_id,
+        } => {
+            let url = format!(
+                "https://itunes.apple.com/lookup?id={}&country={}&entity=song",
+                track_id, storefront
+            );
+            let lookup: ItunesLookup = fetch(client, &url).await?;
+            lookup
+                .results
+                .into_iter()
+                .find_map(|result| Some(song(result.artist_name?, result.track_name?)))
+                .ok_or_else(|| SongError::Expansion(format!("no song {} on Apple Music", track_id)))
+        }
+        MusicLink::Deezer { track_id } => {
+            let url = format!("https://api.deezer.com/track/{}", track_id);
+            let track: DeezerTrack = fetch(client, &url).await?;
+            Ok(song(track.artist.name, track.title))
+        }
+        MusicLink::SongLink { url } => {
+            let mut links = odesli::links(client, url).await?;
+            let entity = links
+                .entities_by_unique_id
+                .remove(&links.entity_unique_id)
+                .ok_or_else(|| SongError::Expansion(format!("no song on {}", url)))?;
+            let (Some(artist), Some(title)) = (entity.artist_name, entity.title) else {
+                return Err(SongError::Expansion(format!("no song on {}", url)));
+            };
+            let video_id = YOUTUBE_PLATFORMS.iter().find_map(|platform| {
+                url_parser::parse_video_id(&links.links_by_platform.get(*platform)?.url)
+            });
+            Ok(LinkedSong {
+                video_id,
+                ..song(artist, title)
+            })
+        }
+    }
+}
+
+fn song(artist: String, title: String) -> LinkedSong {
+    LinkedSong {
+        title: format!("{} - {}", artist, title),
+        video_id: None,
+    }
+}
+
+// Deezer answers unknown tracks with an error object instead of an error
+// status, which fails to parse here all the same
+async fn fetch<T: DeserializeOwned>(client: &Client, url: &str) -> Result<T, SongError> {
+    Ok(client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
@@ -0,0 +1,98 @@
+use crate::{
+    config::{Config, OcrKind},
+    error::SongError,
+    http::{self, Client},
+    tracklist::{self, Word},
+};
+use async_trait::async_trait;
+
+#[cfg(feature = "tesseract")]
+mod tesseract;
+#[cfg(any(feature = "tesseract", test))]
+mod tsv;
+mod vision;
+
+#[cfg(feature = "tesseract")]
+pub use tesseract::TesseractOcr;
+pub use vision::VisionOcr;
+
+// What an OCR provider read off an image
+pub struct DetectedText {
+    pub text: String,
+    // Every word with where it is, empty when the provider can't tell
+    pub words: Vec<Word>,
+}
+
+// Reads the text of tracklist screenshots
+#[async_trait]
+pub trait OcrProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Return the text on the image, or None if there is none
+    async fn detect_text(&self, image: &[u8]) -> Result<Option<DetectedText>, SongError>;
+}
+
+// Google Vision unless Tesseract was picked, which runs locally and needs
+// no API key
+pub fn from_config(config: &Config) -> Box<dyn OcrProvider> {
+    match config.ocr_provider {
+        OcrKind::Vision => Box::new(VisionOcr::new(
+            http::client(),
+            config.google_vision_api_key.clone(),
+        )),
+        #[cfg(feature = "tesseract")]
+        OcrKind::Tesseract => Box::new(TesseractOcr::new(
+            config.tesseract_language.clone(),
+            config.tesseract_data_path.clone(),
+        )),
+        // Config::validate turns this down when the feature is off
+        #[cfg(not(feature = "tesseract"))]
+        OcrKind::Tesseract => unreachable!("Tesseract support is not compiled in"),
+    }
+}
+
+// Download the photo, run OCR on it and return the detected lines that look
+// like song titles
+pub async fn extract_song_lines(
+    client: &Client,
+    provider: &dyn OcrProvider,
+    file_url: &str,
+) -> Result<Vec<String>, SongError> {
+    log::info!("Downloading photo for OCR");
+    let image_bytes = client
+        .get(file_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    let detected = provider
+        .detect_text(&image_bytes)
+        .await?
+        .ok_or(SongError::NoText)?;
+
+    // The words are laid out again to pair titles with artists. Without
+    // their positions the text is taken line by line
+    let lines = match detected.words.is_empty() {
+        true => split_into_song_lines(&detected.text),
+        false => tracklist::song_lines(&detected.words),
+    };
+    log::info!("{} detected {} song lines", provider.name(), lines.len());
+    Ok(lines)
+}
+
+// Keep lines that can plausibly be searched for, dropping empty lines and
+// bare numbers or durations such as "3:45"
+pub fn split_into_song_lines(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| line.chars().count() > 1)
+        .filter(|line| {
+            !line
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == ':' || c == '.')
+        })
+        .map(str::to_string)
+        .collect()
+}
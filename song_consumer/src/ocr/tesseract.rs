@@ -0,0 +1,69 @@
+use super::{tsv, DetectedText, OcrProvider};
+use crate::error::SongError;
+use async_trait::async_trait;
+use leptess::LepTess;
+use std::path::{Path, PathBuf};
+
+// Tesseract through leptess, running on the consumer itself. It reads
+// screenshots less reliably than Vision, but costs nothing and works offline
+pub struct TesseractOcr {
+    // Language codes of the trained data to use, e.g. "eng+ron"
+    language: String,
+    // Where the trained data is, Tesseract's own default when unset
+    data_path: Option<PathBuf>,
+}
+
+impl TesseractOcr {
+    pub fn new(language: String, data_path: Option<PathBuf>) -> Self {
+        Self {
+            language,
+            data_path,
+        }
+    }
+}
+
+#[async_trait]
+impl OcrProvider for TesseractOcr {
+    fn name(&self) -> &'static str {
+        "Tesseract"
+    }
+
+    async fn detect_text(&self, image: &[u8]) -> Result<Option<DetectedText>, SongError> {
+        let language = self.language.clone();
+        let data_path = self.data_path.clone();
+        let image = image.to_vec();
+        // Recognition blocks for a while, so it's kept off the runtime
+        tokio::task::spawn_blocking(move || recognize(&language, data_path.as_deref(), &image))
+            .await
+            .map_err(|e| SongError::Ocr(e.to_string()))?
+    }
+}
+
+fn recognize(
+    language: &str,
+    data_path: Option<&Path>,
+    image: &[u8],
+) -> Result<Option<DetectedText>, SongError> {
+    let data_path = data_path.map(|path| path.to_string_lossy().into_owned());
+    let mut tesseract =
+        LepTess::new(data_path.as_deref(), language).map_err(|e| SongError::Ocr(e.to_string()))?;
+    tesseract
+        .set_image_from_mem(image)
+        .map_err(|e| SongError::Ocr(e.to_string()))?;
+    let text = tesseract
+        .get_utf8_text()
+        .map_err(|e| SongError::Ocr(e.to_string()))?;
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+
+    // The text can still be read line by line without the word boxes
+    let words = match tesseract.get_tsv_text(0) {
+        Ok(tsv) => tsv::words(&tsv),
+        Err(e) => {
+            log::warn!("Tesseract couldn't lay out the words: {}", e);
+            Vec::new()
+        }
+    };
+    Ok(Some(DetectedText { text, words }))
+}
@@ -0,0 +1,56 @@
+use crate::tracklist::Word;
+
+// Rows of single words, the others are pages, blocks, paragraphs and lines
+const WORD_LEVEL: &str = "5";
+
+// The words of Tesseract's TSV output. Every row holds the level, the page,
+// block, paragraph, line and word numbers, then the box, the confidence and
+// the text
+pub fn words(tsv: &str) -> Vec<Word> {
+    tsv.lines().filter_map(word).collect()
+}
+
+fn word(row: &str) -> Option<Word> {
+    let fields: Vec<&str> = row.split('\t').collect();
+    let [level, _, _, _, _, _, left, top, width, height, confidence, text] = fields.as_slice()
+    else {
+        return None;
+    };
+    let confidence: f32 = confidence.parse().ok()?;
+    let text = text.trim();
+    if *level != WORD_LEVEL || confidence < 0.0 || text.is_empty() {
+        return None;
+    }
+
+    let (left, top): (i32, i32) = (left.parse().ok()?, top.parse().ok()?);
+    let (width, height): (i32, i32) = (width.parse().ok()?, height.parse().ok()?);
+    Some(Word {
+        text: text.to_string(),
+        left,
+        top,
+        right: left + width,
+        bottom: top + height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_words_with_their_boxes() {
+        let tsv = "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext\n\
+                   1\t1\t0\t0\t0\t0\t0\t0\t1080\t1920\t-1\t\n\
+                   4\t1\t1\t1\t1\t0\t100\t100\t300\t40\t-1\t\n\
+                   5\t1\t1\t1\t1\t1\t100\t100\t80\t40\t96.5\tOne\n\
+                   5\t1\t1\t1\t1\t2\t190\t102\t90\t38\t91\tMore\n\
+                   5\t1\t1\t1\t1\t3\t290\t100\t10\t40\t12\t \n";
+        let words = words(tsv);
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[0].text, "One");
+        assert_eq!(
+            (words[1].left, words[1].top, words[1].right, words[1].bottom),
+            (190, 102, 280, 140)
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use super::{DetectedText, OcrProvider};
+use crate::{error::SongError, http::Client, tracklist::Word};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rustin_models::vision::{
+    Annotation, Feature, ImageContent, VisionRequest, VisionRequestItem, VisionResponse,
+};
+
+// Google Cloud Vision text detection, billed per image
+pub struct VisionOcr {
+    client: Client,
+    api_key: String,
+}
+
+impl VisionOcr {
+    pub fn new(client: Client, api_key: String) -> Self {
+        Self { client, api_key }
+    }
+}
+
+#[async_trait]
+impl OcrProvider for VisionOcr {
+    fn name(&self) -> &'static str {
+        "Google Vision"
+    }
+
+    async fn detect_text(&self, image: &[u8]) -> Result<Option<DetectedText>, SongError> {
+        let request_body = VisionRequest {
+            requests: vec![VisionRequestItem {
+                image: ImageContent {
+                    content: STANDARD.encode(image),
+                },
+                features: vec![Feature {
+                    r#type: "TEXT_DETECTION".to_string(),
+                }],
+            }],
+        };
+
+        let url = format!(
+            "https://vision.googleapis.com/v1/images:annotate?key={}",
+            self.api_key
+        );
+
+        let response: VisionResponse = self
+            .client
+            .post(&url)
+            .json(&request_body)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        let Some(response) = response.responses.into_iter().next() else {
+            return Ok(None);
+        };
+        // Images that can't be read, e.g. corrupt ones, fail inside a
+        // successful response
+        if let Some(error) = response.error {
+            return Err(SongError::Ocr(error.message));
+        }
+        // The first annotation holds all of the text, the rest one word each
+        let mut annotations = response.text_annotations.unwrap_or_default().into_iter();
+        Ok(annotations.next().map(|full_text| DetectedText {
+            text: full_text.description,
+            words: annotations.filter_map(word).collect(),
+        }))
+    }
+}
+
+fn word(annotation: Annotation) -> Option<Word> {
+    let corners: Vec<(i32, i32)> = annotation
+        .bounding_poly?
+        .vertices
+        .iter()
+        .map(|vertex| (vertex.x, vertex.y))
+        .collect();
+    Word::from_corners(annotation.description, &corners)
+}
@@ -0,0 +1,47 @@
+use crate::{
+    cache::{self, Cache},
+    error::SongError,
+    http::Client,
+    url_parser,
+};
+use rustin_models::odesli::OdesliLinks;
+use urlencoding::encode;
+
+// The API behind song.link, which finds a song on every streaming service.
+// It takes about ten requests a minute without a key, so pages are cached
+const API_URL: &str = "https://api.song.link/v1-alpha.1/links";
+
+// Everything song.link knows about the song behind a link of any service
+pub async fn links(client: &Client, url: &str) -> Result<OdesliLinks, SongError> {
+    let api_url = format!("{}?url={}", API_URL, encode(url));
+    Ok(client
+        .get(&api_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+// The song.link page of a YouTube video. The song is sent without one when
+// the lookup fails, so failures are only logged
+pub async fn page_url(client: &Client, cache: &dyn Cache, video_id: &str) -> Option<String> {
+    // SoundCloud and Bandcamp tracks aren't YouTube videos
+    if !url_parser::is_video_id(video_id) {
+        return None;
+    }
+
+    let key = cache::song_link_key(video_id);
+    if let Some(page_url) = cache::get_or_log(cache, &key).await {
+        return Some(page_url);
+    }
+
+    let video_url = format!("https://www.youtube.com/watch?v={}", video_id);
+    let page_url = links(client, &video_url)
+        .await
+        .inspect_err(|e| log::warn!("Failed to look up the song.link of {}: {}", video_id, e))
+        .ok()?
+        .page_url;
+    cache::set_or_log(cache, &key, &page_url, cache::SONG_LINK_TTL).await;
+    Some(page_url)
+}
@@ -0,0 +1,113 @@
+use crate::{correlation, DynError};
+use lapin::{options::BasicPublishOptions, BasicProperties, Channel};
+use rustin_storage::Storage;
+use std::{sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+// AMQP delivery mode that makes the broker write messages to disk
+const PERSISTENT: u8 = 2;
+// Times a nacked publish is retried before giving up
+const PUBLISH_ATTEMPTS: u32 = 3;
+// How long to wait for new messages when the outbox is empty
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Messages claimed per round
+const BATCH_SIZE: i64 = 50;
+// A claimed message that couldn't be published is retried after this long
+const LEASE: Duration = Duration::from_secs(30);
+
+// Publish a persistent message and wait for the broker to confirm it,
+// retrying a few times if it gets nacked
+pub async fn publish_confirmed(
+    channel: &Channel,
+    queue: &str,
+    request_id: &str,
+    trace_context: Option<&str>,
+    payload: &[u8],
+) -> Result<(), DynError> {
+    for attempt in 1..=PUBLISH_ATTEMPTS {
+        let confirmation = channel
+            .basic_publish(
+                "",
+                queue,
+                BasicPublishOptions::default(),
+                payload,
+                BasicProperties::default()
+                    .with_delivery_mode(PERSISTENT)
+                    .with_headers(correlation::headers(request_id, trace_context)),
+            )
+            .await?
+            .await?;
+
+        if !confirmation.is_nack() {
+            return Ok(());
+        }
+        log::warn!(
+            "Message to '{}' was not confirmed (attempt {}/{})",
+            queue,
+            attempt,
+            PUBLISH_ATTEMPTS
+        );
+        tokio::time::sleep(Duration::from_millis(500 * attempt as u64)).await;
+    }
+
+    log::error!(
+        "Giving up on message to '{}' after {} unconfirmed publishes",
+        queue,
+        PUBLISH_ATTEMPTS
+    );
+    Err("message was not confirmed by RabbitMQ".into())
+}
+
+// Publish the messages waiting in the outbox until shutdown. Returns an error
+// when the channel breaks, the messages stay in the outbox for the next drainer
+pub async fn drain(
+    channel: Channel,
+    storage: Arc<Storage>,
+    shutdown: CancellationToken,
+) -> Result<(), DynError> {
+    while !shutdown.is_cancelled() {
+        let entries = match storage.claim_outbox(BATCH_SIZE, LEASE).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::error!("Failed to read the outbox: {}", e);
+                Vec::new()
+            }
+        };
+
+        if entries.is_empty() {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(POLL_INTERVAL) => continue,
+            }
+        }
+
+        for entry in entries {
+            if let Err(e) = publish_confirmed(
+                &channel,
+                &entry.queue,
+                &entry.request_id,
+                entry.trace_context.as_deref(),
+                &entry.payload,
+            )
+            .await
+            {
+                log::warn!(
+                    "Failed to publish outbox message {} (attempt {}): {}",
+                    entry.id,
+                    entry.attempts,
+                    e
+                );
+                if !channel.status().connected() {
+                    return Err(e);
+                }
+                continue;
+            }
+            if let Err(e) = storage.delete_outbox(entry.id).await {
+                // It'll be published again once the lease runs out, which
+                // the consumer's deduplication doesn't cover for replies
+                log::error!("Failed to delete outbox message {}: {}", entry.id, e);
+            }
+        }
+    }
+    Ok(())
+}
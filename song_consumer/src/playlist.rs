@@ -0,0 +1,58 @@
+use crate::DynError;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+// What the playlist is called in the chat. UTF-8, as .m3u8 promises players
+pub const FILE_NAME: &str = "playlist.m3u8";
+
+// A song of the playlist, found by the name it was sent under or by its
+// download link
+pub struct Entry {
+    pub title: String,
+    pub location: String,
+}
+
+pub fn m3u(entries: &[Entry]) -> String {
+    let mut playlist = String::from("#EXTM3U\n");
+    for entry in entries {
+        // A line break in a title would start an entry of its own
+        let title = entry.title.replace(['\r', '\n'], " ");
+        playlist.push_str(&format!("#EXTINF:-1,{}\n{}\n", title, entry.location));
+    }
+    playlist
+}
+
+// Write the playlist into the media directory, for the reply service to send
+// as a document
+pub async fn write(media_dir: &Path, entries: &[Entry]) -> Result<PathBuf, DynError> {
+    let file_path = media_dir.join(format!("{}.m3u8", Uuid::new_v4().simple()));
+    tokio::fs::write(&file_path, m3u(entries)).await?;
+    Ok(file_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_every_song_with_its_title() {
+        let entries = [
+            Entry {
+                title: "Daft Punk - One More Time".to_string(),
+                location: "Daft Punk - One More Time.mp3".to_string(),
+            },
+            Entry {
+                title: "Justice\n- D.A.N.C.E.".to_string(),
+                location: "https://example.com/dance.mp3".to_string(),
+            },
+        ];
+        assert_eq!(
+            m3u(&entries),
+            "#EXTM3U\n\
+             #EXTINF:-1,Daft Punk - One More Time\n\
+             Daft Punk - One More Time.mp3\n\
+             #EXTINF:-1,Justice - D.A.N.C.E.\n\
+             https://example.com/dance.mp3\n"
+        );
+    }
+}
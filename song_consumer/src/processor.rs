@@ -0,0 +1,1227 @@
+// Converts the songs of a request: every song is searched unless its video
+// is known, converted by the first provider that manages it and turned into
+// a link or a tagged file for the reply service. The search, the providers
+// and the restriction lookup are handed in, so the whole of it can be run
+// against fakes
+
+use crate::{
+    archive,
+    cache::{self, Cache},
+    config::Config,
+    downloader::{self, AudioFile, CachedAudio, VideoFile},
+    effects::Effect,
+    error::SongError,
+    ffmpeg, history, http,
+    lyrics::{self, LyricsProvider},
+    metrics, odesli, playlist,
+    providers::{Mp3Source, ProviderChain},
+    search::SearchChain,
+    search_plan, tagging, thumbnail, upload_size,
+    youtube::{FoundVideo, Restriction, RestrictionLookup, YouTubeRestrictions},
+    DynError, Progress, SongStream,
+};
+use futures_util::future::join_all;
+use rustin_models::{
+    clip::Clip,
+    file_name,
+    i18n::{tr, tr_args},
+    markdown, MediaFormat, RequestOptions,
+};
+use rustin_storage::{SongRecord, SongStatus, Storage};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+// Further search results tried when the best match can't be converted
+const CONVERSION_ALTERNATIVES: usize = 2;
+
+pub struct SongProcessor {
+    // Where songs are downloaded to, they're sent as links without one
+    media_dir: Option<PathBuf>,
+    // Used for songs without a bitrate preference
+    default_bitrate: u32,
+    // Requests this large are zipped for chats that asked for it
+    zip_min_songs: usize,
+    providers: Arc<ProviderChain>,
+    search: Arc<SearchChain>,
+    restrictions: Arc<dyn RestrictionLookup>,
+    // Caps how many songs are converted at the same time, across requests
+    conversion_limiter: Arc<Semaphore>,
+    cache: Arc<dyn Cache>,
+    lyrics: Arc<dyn LyricsProvider>,
+    storage: Option<Arc<Storage>>,
+    // For covers and song.link pages
+    client: http::Client,
+}
+
+impl SongProcessor {
+    pub fn from_config(
+        config: &Config,
+        media_dir: Option<PathBuf>,
+        providers: Arc<ProviderChain>,
+        search: Arc<SearchChain>,
+        cache: Arc<dyn Cache>,
+        lyrics: Arc<dyn LyricsProvider>,
+        storage: Option<Arc<Storage>>,
+    ) -> Self {
+        Self {
+            media_dir,
+            default_bitrate: config.default_bitrate,
+            zip_min_songs: config.zip_min_songs,
+            providers,
+            search,
+            restrictions: Arc::new(YouTubeRestrictions::new(
+                config.google_vision_api_key.clone(),
+                config.region.clone(),
+            )),
+            conversion_limiter: Arc::new(Semaphore::new(config.max_concurrent_conversions)),
+            cache,
+            lyrics,
+            storage,
+            client: http::client(),
+        }
+    }
+
+    pub async fn process_songs(
+        &self,
+        request_id: &str,
+        songs: Vec<SongRequest>,
+        options: &RequestOptions,
+        progress: Option<Arc<Progress>>,
+        stream: Option<Arc<SongStream>>,
+        cancel: &CancellationToken,
+    ) -> Result<ProcessedSongs, DynError> {
+        // Uploaded songs can't be put into an archive or sent as a link, only
+        // downloaded ones
+        let reuse_uploads = !options.links && !self.zips(options, songs.len());
+
+        let searched: Vec<&str> = songs
+            .iter()
+            .filter(|song| song.video_id.is_none())
+            .map(|song| song.title.as_str())
+            .collect();
+        search_plan::warm_cache(self.cache.as_ref(), &self.search, &searched).await;
+
+        let mut tasks = Vec::new();
+        // Position and title of every started song, in case its task panics
+        let mut started = Vec::new();
+        let mut songs = songs.into_iter();
+        let mut not_started = Vec::new();
+
+        for song in songs.by_ref() {
+            // Wait for a free slot before starting the next song, so a long
+            // playlist is worked through a few songs at a time. Songs that
+            // already started are finished when the request gets cancelled
+            let permit = tokio::select! {
+                permit = Arc::clone(&self.conversion_limiter).acquire_owned() => permit?,
+                _ = cancel.cancelled() => {
+                    not_started.push(song.title);
+                    break;
+                }
+            };
+            started.push((song.index, song.title.clone()));
+            let task = self.song_task(song, options, reuse_uploads);
+            tasks.push(self.spawn(task, permit, request_id, progress.clone(), stream.clone()));
+        }
+
+        let results = join_all(tasks).await;
+        let mut outcomes: Vec<_> = started
+            .into_iter()
+            .zip(results)
+            .map(|((index, title), result)| match result {
+                Ok((_, result)) => (index, title, result),
+                // The song is still reported, as failed
+                Err(e) => {
+                    log::error!("Task panicked: {}", e);
+                    let failure = tr_args(
+                        options.language.as_deref(),
+                        "song.convert_failed",
+                        &[("title", &title)],
+                    );
+                    (index, title, Err(failure))
+                }
+            })
+            .collect();
+        // Songs are numbered as they were requested, not as they finished
+        outcomes.sort_by_key(|(index, _, _)| *index);
+
+        let mut processed = ProcessedSongs::from_outcomes(outcomes);
+        not_started.extend(songs.map(|song| song.title));
+        processed.add_cancelled(not_started);
+        Ok(processed)
+    }
+
+    fn song_task(
+        &self,
+        song: SongRequest,
+        options: &RequestOptions,
+        reuse_uploads: bool,
+    ) -> SongTask {
+        // Edited songs are never the upload of the song itself
+        let edited = song.clip.is_some() || song.effect.is_some();
+        SongTask {
+            song: song.title,
+            known_video_id: song.video_id,
+            bitrate: song
+                .bitrate
+                .or(options.bitrate)
+                .unwrap_or(self.default_bitrate),
+            clip: song.clip,
+            effect: song.effect,
+            // The timestamps only match the song as it was uploaded
+            lyrics: Some(Arc::clone(&self.lyrics)).filter(|_| options.lyrics && !edited),
+            // Without a media directory the songs are sent as download links
+            media_dir: self.media_dir.clone().filter(|_| !options.links),
+            language: options.language.clone(),
+            normalize: options.normalize,
+            song_link: options.song_link,
+            format: options.format,
+            reuse_uploads,
+            providers: Arc::clone(&self.providers),
+            search: Arc::clone(&self.search),
+            restrictions: Arc::clone(&self.restrictions),
+            cache: Arc::clone(&self.cache),
+            client: self.client.clone(),
+        }
+    }
+
+    // Convert the song on its own task, streaming it out as soon as it's done
+    // when the chat asked for that
+    fn spawn(
+        &self,
+        task: SongTask,
+        permit: OwnedSemaphorePermit,
+        request_id: &str,
+        progress: Option<Arc<Progress>>,
+        stream: Option<Arc<SongStream>>,
+    ) -> JoinHandle<(String, SongResult)> {
+        let storage = self.storage.clone();
+        let request_id = request_id.to_string();
+        let title = task.song.clone();
+        tokio::spawn(rustin_telemetry::in_current_scope(
+            async move {
+                let converted = task.convert().await;
+                drop(permit);
+                let result = match (converted, stream) {
+                    (Ok((video_id, converted)), Some(stream)) => stream
+                        .send(&title, converted)
+                        .await
+                        .map(|converted| (video_id, converted)),
+                    (result, _) => result,
+                };
+                history::record_song_done(storage.as_deref(), &request_id).await;
+                if let Some(progress) = progress {
+                    progress.song_done().await;
+                }
+                (title, result)
+            }
+            .in_current_span(),
+        ))
+    }
+
+    // Inline queries convert songs as well, and take their turn with the
+    // songs of requests
+    pub fn conversion_limiter(&self) -> &Semaphore {
+        &self.conversion_limiter
+    }
+
+    // Whether the songs of a request are sent as ZIP archives. They have to
+    // be downloaded for that, and are all sent together at the end
+    pub fn zips(&self, options: &RequestOptions, song_count: usize) -> bool {
+        options.zip
+            && !options.stream
+            && !options.links
+            && options.format == MediaFormat::Mp3
+            && self.media_dir.is_some()
+            && song_count >= self.zip_min_songs
+    }
+}
+
+// A single song to convert. Songs given as YouTube links or coming from a
+// YouTube playlist already know their video and skip the search
+pub struct SongRequest {
+    // Position in the request as sent, which numbers the song in the reply
+    pub index: usize,
+    pub title: String,
+    pub video_id: Option<String>,
+    // Set when the line carried its own quality suffix, e.g. "@320"
+    pub bitrate: Option<u32>,
+    // Set when the line ended with a time range, e.g. "[0:45-2:30]"
+    pub clip: Option<Clip>,
+    // Set when the line ended with an effect, e.g. "+nightcore"
+    pub effect: Option<Effect>,
+}
+
+impl SongRequest {
+    pub fn search(title: impl Into<String>) -> Self {
+        Self {
+            index: 0,
+            title: title.into(),
+            video_id: None,
+            bitrate: None,
+            clip: None,
+            effect: None,
+        }
+    }
+
+    pub fn video(title: impl Into<String>, video_id: String) -> Self {
+        Self {
+            index: 0,
+            title: title.into(),
+            video_id: Some(video_id),
+            bitrate: None,
+            clip: None,
+            effect: None,
+        }
+    }
+}
+
+// Download links or downloaded files of the converted songs, and a
+// user-facing message for every song that could not be converted
+#[derive(Default)]
+pub struct ProcessedSongs {
+    // Numbered in the order of the request, failed songs included
+    pub links: Vec<String>,
+    pub audio: Vec<AudioFile>,
+    pub cached_audio: Vec<CachedAudio>,
+    pub videos: Vec<VideoFile>,
+    // Only the songs that failed without being listed among the links
+    pub failures: Vec<String>,
+    // Songs that were never started because the request was cancelled
+    pub cancelled: usize,
+    // Every song, converted or not, as it goes into the request history
+    pub history: Vec<SongRecord>,
+    // Where each converted song went, in the order of the request
+    playlist: Vec<PlaylistSong>,
+}
+
+impl ProcessedSongs {
+    // Collect the finished songs, in the order of the request. The links are
+    // numbered after their songs, with the songs that failed in between and
+    // why, so users know which ones to retry
+    fn from_outcomes(outcomes: Vec<(usize, String, SongResult)>) -> Self {
+        let mut processed = ProcessedSongs::default();
+        let mut numbered = Vec::new();
+        let mut has_links = false;
+        let mut failures = Vec::new();
+        for (index, title, result) in outcomes {
+            let mut record = SongRecord {
+                title,
+                video_id: None,
+                status: SongStatus::Failed,
+                file_id: None,
+            };
+            match result {
+                Ok((video_id, converted)) => {
+                    metrics::SONGS_CONVERTED.inc();
+                    record.video_id = Some(video_id);
+                    record.status = SongStatus::Converted;
+                    match converted {
+                        ConvertedSong::Link { text, url } => {
+                            processed.playlist.push(PlaylistSong::Link {
+                                title: record.title.clone(),
+                                url,
+                            });
+                            numbered.push(format!("{}\\. {}", index + 1, text));
+                            has_links = true;
+                        }
+                        ConvertedSong::Audio(audio) => {
+                            processed
+                                .playlist
+                                .push(PlaylistSong::Audio(processed.audio.len()));
+                            processed.audio.push(audio)
+                        }
+                        ConvertedSong::Video(video) => processed.videos.push(video),
+                        ConvertedSong::CachedAudio(audio) => {
+                            record.file_id = Some(audio.file_id.clone());
+                            processed
+                                .playlist
+                                .push(PlaylistSong::CachedAudio(processed.cached_audio.len()));
+                            processed.cached_audio.push(audio);
+                        }
+                        ConvertedSong::Streamed { file_id } => record.file_id = file_id,
+                    }
+                }
+                Err(failure) => {
+                    numbered.push(format!(
+                        "{}\\. ❌ {}",
+                        index + 1,
+                        markdown::escape(&failure)
+                    ));
+                    failures.push(failure);
+                }
+            }
+            processed.history.push(record);
+        }
+        // Without links to go between, the failures are reported on their own
+        match has_links {
+            true => processed.links = numbered,
+            false => processed.failures = failures,
+        }
+        processed
+    }
+
+    fn add_cancelled(&mut self, not_started: Vec<String>) {
+        if !not_started.is_empty() {
+            log::info!(
+                "Request cancelled with {} songs not started",
+                not_started.len()
+            );
+        }
+        self.cancelled = not_started.len();
+        self.history
+            .extend(not_started.into_iter().map(|title| SongRecord {
+                title,
+                video_id: None,
+                status: SongStatus::Cancelled,
+                file_id: None,
+            }));
+    }
+
+    // Zipped songs are found by their name in the archive, the rest by the
+    // name they were sent under or their download link
+    pub fn playlist_entries(&self, zipped: bool) -> Vec<playlist::Entry> {
+        let entry = |title: &str, performer: Option<&str>, location: String| playlist::Entry {
+            title: match performer {
+                Some(performer) => format!("{} - {}", performer, title),
+                None => title.to_string(),
+            },
+            location,
+        };
+        self.playlist
+            .iter()
+            .map(|song| match song {
+                PlaylistSong::Link { title, url } => playlist::Entry {
+                    title: title.clone(),
+                    location: url.clone(),
+                },
+                PlaylistSong::Audio(index) => {
+                    let audio = &self.audio[*index];
+                    let performer = audio.performer.as_deref();
+                    let location = match zipped {
+                        true => archive::entry_name(*index, audio),
+                        false => file_name::song(&audio.title, performer, "mp3"),
+                    };
+                    entry(&audio.title, performer, location)
+                }
+                PlaylistSong::CachedAudio(index) => {
+                    let audio = &self.cached_audio[*index];
+                    let performer = audio.performer.as_deref();
+                    let location = file_name::song(&audio.title, performer, "mp3");
+                    entry(&audio.title, performer, location)
+                }
+            })
+            .collect()
+    }
+}
+
+// A converted song as it goes into the playlist, by its index in the audio
+// of ProcessedSongs
+enum PlaylistSong {
+    Link { title: String, url: String },
+    Audio(usize),
+    CachedAudio(usize),
+}
+
+pub enum ConvertedSong {
+    // MarkdownV2 for the chat, and the bare download link for playlists
+    Link { text: String, url: String },
+    Audio(AudioFile),
+    CachedAudio(CachedAudio),
+    Video(VideoFile),
+    // Already published on its own, only the file_id is kept for the history
+    Streamed { file_id: Option<String> },
+}
+
+// The video ID and the converted song, or what to tell the user about the
+// song instead
+type SongResult = Result<(String, ConvertedSong), String>;
+
+// One song of a request with everything its conversion needs, so it can run
+// on a task of its own. Every stage either moves the song along or ends it
+// with the message the user gets
+struct SongTask {
+    song: String,
+    known_video_id: Option<String>,
+    bitrate: u32,
+    clip: Option<Clip>,
+    effect: Option<Effect>,
+    // Set when the chat wants synced lyrics and the song isn't edited
+    lyrics: Option<Arc<dyn LyricsProvider>>,
+    media_dir: Option<PathBuf>,
+    language: Option<String>,
+    normalize: bool,
+    song_link: bool,
+    format: MediaFormat,
+    reuse_uploads: bool,
+    providers: Arc<ProviderChain>,
+    search: Arc<SearchChain>,
+    restrictions: Arc<dyn RestrictionLookup>,
+    cache: Arc<dyn Cache>,
+    // For covers and song.link pages
+    client: http::Client,
+}
+
+impl SongTask {
+    async fn convert(self) -> SongResult {
+        log::info!("Processing song: {}", self.song);
+        let video_id = self.find_video().await?;
+        log::info!("Using video ID: {}", video_id);
+
+        if self.format == MediaFormat::Mp4 {
+            let timer = metrics::CONVERSION_SECONDS.start_timer();
+            let video = fetch_video(
+                &self.providers,
+                &video_id,
+                &self.song,
+                self.media_dir.as_deref(),
+                self.language.as_deref(),
+            )
+            .await;
+            timer.observe_duration();
+            return video.map(|converted| (video_id, converted));
+        }
+
+        if let Some(cached) = self.reuse_upload(&video_id).await {
+            return Ok((video_id, cached));
+        }
+        let (video_id, source, alternative) = self.fetch_mp3(video_id).await?;
+        self.edit(&source).await?;
+        let (source, too_large) = self.fit_upload(&video_id, source).await?;
+
+        let note = alternative.map(|video| {
+            tr_args(
+                self.language.as_deref(),
+                "song.alternative_used",
+                &[("video", &video.title), ("channel", &video.channel)],
+            )
+        });
+        let song_link = match self.song_link {
+            true => odesli::page_url(&self.client, self.cache.as_ref(), &video_id).await,
+            false => None,
+        };
+        let converted = match source {
+            Mp3Source::File(file_path) => {
+                let caption = [note, song_link]
+                    .into_iter()
+                    .flatten()
+                    .reduce(|note, link| format!("{}\n{}", note, link));
+                self.audio(&video_id, file_path, caption).await
+            }
+            Mp3Source::Link(dlink) => self.link(dlink, too_large, note, song_link),
+        };
+        Ok((video_id, converted))
+    }
+
+    fn edited(&self) -> bool {
+        self.clip.is_some() || self.effect.is_some()
+    }
+
+    // The message telling the user why the song failed
+    fn failure(&self, key: &str) -> String {
+        tr_args(self.language.as_deref(), key, &[("title", &self.song)])
+    }
+
+    fn file_id_key(&self, video_id: &str) -> String {
+        match self.normalize {
+            true => cache::normalized_file_id_key(video_id, self.bitrate),
+            false => cache::file_id_key(video_id, self.bitrate),
+        }
+    }
+
+    // A video the user picked, or that came with a playlist, is what they
+    // asked for. Only songs without one are searched
+    async fn find_video(&self) -> Result<String, String> {
+        if let Some(video_id) = &self.known_video_id {
+            return Ok(video_id.clone());
+        }
+        match cached_search(self.cache.as_ref(), &self.search, &self.song).await {
+            Ok(Some(video_id)) => Ok(video_id),
+            Ok(None) => Err(self.failure("song.not_found")),
+            Err(SongError::QuotaExceeded) => {
+                metrics::record_failure(metrics::Stage::Search);
+                Err(self.failure("song.search_limit"))
+            }
+            Err(SongError::LiveStream) => Err(self.failure("song.live_stream")),
+            Err(e) => {
+                log::error!("YouTube search failed for '{}': {}", self.song, e);
+                metrics::record_failure(metrics::Stage::Search);
+                Err(self.failure("song.search_failed"))
+            }
+        }
+    }
+
+    // Songs that were uploaded before are resent without downloading, unless
+    // they're to be edited
+    async fn reuse_upload(&self, video_id: &str) -> Option<ConvertedSong> {
+        let media_dir = self.media_dir.as_deref()?;
+        if !self.reuse_uploads || self.edited() {
+            return None;
+        }
+        let file_id = cache::get_or_log(self.cache.as_ref(), &self.file_id_key(video_id)).await?;
+        log::info!("Reusing Telegram file_id for video ID {}", video_id);
+        let (performer, title) = downloader::split_artist_title(&self.song);
+        let caption = match self.song_link {
+            true => odesli::page_url(&self.client, self.cache.as_ref(), video_id).await,
+            false => None,
+        };
+        let lyrics_path = match &self.lyrics {
+            Some(lyrics) => {
+                let mp3_path = media_dir.join(format!("{}.mp3", video_id));
+                lyrics::write_next_to(lyrics.as_ref(), &self.song, &mp3_path).await
+            }
+            None => None,
+        };
+        Some(ConvertedSong::CachedAudio(CachedAudio {
+            file_id,
+            title,
+            performer,
+            caption,
+            lyrics_path,
+        }))
+    }
+
+    // Convert the video, falling back to other uploads of searched songs.
+    // Returns the video that was converted and the upload used instead of the
+    // best match, if any
+    async fn fetch_mp3(
+        &self,
+        video_id: String,
+    ) -> Result<(String, Mp3Source, Option<FoundVideo>), String> {
+        let timer = metrics::CONVERSION_SECONDS.start_timer();
+        let source = cached_fetch_mp3(
+            self.cache.as_ref(),
+            &self.providers,
+            &video_id,
+            self.bitrate,
+            self.media_dir.as_deref(),
+        )
+        .await;
+        let result = match source {
+            Ok(source) => Ok((video_id, source, None)),
+            // Only search results get replaced
+            Err(e) if self.known_video_id.is_none() => {
+                log::warn!(
+                    "Conversion failed for '{}', trying other uploads: {}",
+                    self.song,
+                    e
+                );
+                match fetch_alternative_mp3(
+                    self.cache.as_ref(),
+                    &self.providers,
+                    &self.search,
+                    &self.song,
+                    &video_id,
+                    self.bitrate,
+                    self.media_dir.as_deref(),
+                )
+                .await
+                {
+                    Some((video, mp3)) => Ok((video.video_id.clone(), mp3, Some(video))),
+                    None => Err((video_id, e)),
+                }
+            }
+            Err(e) => Err((video_id, e)),
+        };
+        timer.observe_duration();
+
+        match result {
+            Ok(converted) => Ok(converted),
+            Err((video_id, e)) => {
+                log::error!("Conversion failed for '{}': {}", self.song, e);
+                let key = match self.restrictions.restriction(&video_id).await {
+                    Some(Restriction::AgeRestricted) => "song.age_restricted",
+                    Some(Restriction::RegionBlocked) => "song.region_blocked",
+                    None => "song.convert_failed",
+                };
+                Err(self.failure(key))
+            }
+        }
+    }
+
+    // Cut, apply the effect and even out the loudness of a downloaded MP3.
+    // Links are sent as they are
+    async fn edit(&self, source: &Mp3Source) -> Result<(), String> {
+        let Mp3Source::File(file_path) = source else {
+            return Ok(());
+        };
+        if let Some(clip) = self.clip {
+            if let Err(e) = ffmpeg::cut(file_path, clip, self.bitrate).await {
+                log::error!(
+                    "Failed to cut {} out of '{}': {}",
+                    clip.label(),
+                    self.song,
+                    e
+                );
+                remove_file(file_path).await;
+                return Err(self.failure("song.clip_failed"));
+            }
+        }
+        if let Some(effect) = self.effect {
+            if let Err(e) = ffmpeg::apply_effect(file_path, effect, self.bitrate).await {
+                log::error!(
+                    "Failed to apply {} to '{}': {}",
+                    effect.label(),
+                    self.song,
+                    e
+                );
+                remove_file(file_path).await;
+                return Err(self.failure("song.effect_failed"));
+            }
+        }
+        if self.normalize {
+            // The song still plays, just at its original loudness
+            if let Err(e) = ffmpeg::normalize(file_path, self.bitrate).await {
+                log::warn!("Failed to normalize the loudness of '{}': {}", self.song, e);
+            }
+        }
+        Ok(())
+    }
+
+    // Files Telegram won't take even at a lower bitrate are handed out as
+    // links instead. Returns whether that happened
+    async fn fit_upload(
+        &self,
+        video_id: &str,
+        source: Mp3Source,
+    ) -> Result<(Mp3Source, bool), String> {
+        match source {
+            Mp3Source::File(file_path) if !upload_size::fit(&file_path, self.bitrate).await => {
+                remove_file(&file_path).await;
+                let link = self.providers.fetch_mp3(video_id, self.bitrate, None).await;
+                let link = link.map_err(|e| {
+                    log::error!("Failed to get a link for '{}': {}", self.song, e);
+                    self.failure("song.too_large")
+                })?;
+                Ok((link, true))
+            }
+            source => Ok((source, false)),
+        }
+    }
+
+    // Tag the MP3 and put its cover and lyrics next to it
+    async fn audio(
+        &self,
+        video_id: &str,
+        file_path: PathBuf,
+        caption: Option<String>,
+    ) -> ConvertedSong {
+        let (performer, title) = downloader::split_artist_title(&self.song);
+        let edits: Vec<String> = self
+            .clip
+            .map(|clip| clip.label())
+            .into_iter()
+            .chain(self.effect.map(|effect| effect.label().to_string()))
+            .collect();
+        let title = match edits.is_empty() {
+            true => title,
+            false => format!("{} ({})", title, edits.join(", ")),
+        };
+        let cover = tagging::fetch_cover(&self.client, video_id).await;
+        // Untagged files still play, so a failure here is not fatal
+        if let Err(e) =
+            tagging::tag_mp3(&file_path, &title, performer.as_deref(), cover.clone()).await
+        {
+            log::warn!("Failed to tag the MP3 of '{}': {}", self.song, e);
+        }
+        let thumbnail_path = match cover {
+            Some(cover) => thumbnail::write_next_to(cover, &file_path)
+                .await
+                .inspect_err(|e| {
+                    log::warn!("Failed to write the thumbnail of '{}': {}", self.song, e)
+                })
+                .ok(),
+            None => None,
+        };
+        let lyrics_path = match &self.lyrics {
+            Some(lyrics) => lyrics::write_next_to(lyrics.as_ref(), &self.song, &file_path).await,
+            None => None,
+        };
+        ConvertedSong::Audio(AudioFile {
+            file_path,
+            title,
+            performer,
+            thumbnail_path,
+            lyrics_path,
+            // An edit must not stand in for the song itself
+            cache_key: Some(self.file_id_key(video_id)).filter(|_| !self.edited()),
+            caption,
+        })
+    }
+
+    // The download link with the song name, and notes on how it differs from
+    // what was asked for
+    fn link(
+        &self,
+        dlink: String,
+        too_large: bool,
+        note: Option<String>,
+        song_link: Option<String>,
+    ) -> ConvertedSong {
+        log::info!("Retrieved download link: {}", dlink);
+        let language = self.language.as_deref();
+        let mut link = format!(
+            "🎵 *{}*\n🔗 {}",
+            markdown::escape(&self.song),
+            markdown::escape(&dlink)
+        );
+        if too_large {
+            link.push_str(&format!(
+                "\n_{}_",
+                markdown::escape(&tr(language, "song.sent_as_link"))
+            ));
+        }
+        if self.edited() {
+            link.push_str(&format!(
+                "\n_{}_",
+                markdown::escape(&tr(language, "song.link_unedited"))
+            ));
+        }
+        if let Some(note) = note {
+            link.push_str(&format!("\n_{}_", markdown::escape(&note)));
+        }
+        if let Some(song_link) = song_link {
+            link.push_str(&format!("\n🎧 {}", markdown::escape(&song_link)));
+        }
+        ConvertedSong::Link {
+            text: link,
+            url: dlink,
+        }
+    }
+}
+
+async fn remove_file(path: &Path) {
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        log::warn!("Failed to remove {}: {}", path.display(), e);
+    }
+}
+
+// Fetch the video itself for /video. Videos aren't tagged or reused like
+// MP3s, and ones too large for Telegram are sent as links
+async fn fetch_video(
+    providers: &ProviderChain,
+    video_id: &str,
+    song: &str,
+    media_dir: Option<&Path>,
+    language: Option<&str>,
+) -> Result<ConvertedSong, String> {
+    let source = providers
+        .fetch_mp4(video_id, media_dir)
+        .await
+        .map_err(|e| {
+            log::error!("Video conversion failed for '{}': {}", song, e);
+            tr_args(language, "song.convert_failed", &[("title", song)])
+        })?;
+
+    let too_large = match &source {
+        Mp3Source::File(file_path) => tokio::fs::metadata(file_path)
+            .await
+            .is_ok_and(|metadata| metadata.len() > upload_size::MAX_UPLOAD_SIZE),
+        Mp3Source::Link(_) => false,
+    };
+    let source = match source {
+        Mp3Source::File(file_path) if too_large => {
+            log::info!("{} is too large to upload", file_path.display());
+            if let Err(e) = tokio::fs::remove_file(&file_path).await {
+                log::warn!("Failed to remove {}: {}", file_path.display(), e);
+            }
+            providers.fetch_mp4(video_id, None).await.map_err(|e| {
+                log::error!("Failed to get a video link for '{}': {}", song, e);
+                tr_args(language, "song.too_large", &[("title", song)])
+            })?
+        }
+        source => source,
+    };
+
+    match source {
+        Mp3Source::File(file_path) => Ok(ConvertedSong::Video(VideoFile {
+            file_path,
+            title: song.to_string(),
+        })),
+        Mp3Source::Link(dlink) => {
+            log::info!("Retrieved video link: {}", dlink);
+            let mut link = format!(
+                "🎬 *{}*\n🔗 {}",
+                markdown::escape(song),
+                markdown::escape(&dlink)
+            );
+            if too_large {
+                link.push_str(&format!(
+                    "\n_{}_",
+                    markdown::escape(&tr(language, "song.sent_as_link"))
+                ));
+            }
+            Ok(ConvertedSong::Link {
+                text: link,
+                url: dlink,
+            })
+        }
+    }
+}
+
+// Look the song up in the search cache before spending YouTube API quota
+pub async fn cached_search(
+    cache: &dyn Cache,
+    search: &SearchChain,
+    song: &str,
+) -> Result<Option<String>, SongError> {
+    let key = cache::search_key(song);
+    if let Some(video_id) = cache::get_or_log(cache, &key).await {
+        log::info!("Search cache hit for '{}'", song);
+        return Ok(Some(video_id));
+    }
+
+    let video_id = search.best_match(song).await?;
+    if let Some(video_id) = &video_id {
+        cache::set_or_log(cache, &key, video_id, cache::SEARCH_TTL).await;
+    }
+    Ok(video_id)
+}
+
+// Try the next best search results for a song whose best match couldn't be
+// converted, e.g. because it's age-restricted or blocked. The upload that
+// works is remembered as the song's search result
+async fn fetch_alternative_mp3(
+    cache: &dyn Cache,
+    providers: &ProviderChain,
+    search: &SearchChain,
+    song: &str,
+    failed_video_id: &str,
+    bitrate: u32,
+    media_dir: Option<&Path>,
+) -> Option<(FoundVideo, Mp3Source)> {
+    let alternatives = search
+        .alternatives(song, failed_video_id, CONVERSION_ALTERNATIVES)
+        .await
+        .inspect_err(|e| log::warn!("Failed to search other uploads of '{}': {}", song, e))
+        .ok()?;
+    for video in alternatives {
+        match cached_fetch_mp3(cache, providers, &video.video_id, bitrate, media_dir).await {
+            Ok(source) => {
+                log::info!(
+                    "Converted '{}' from video ID {} instead",
+                    song,
+                    video.video_id
+                );
+                let key = cache::search_key(song);
+                cache::set_or_log(cache, &key, &video.video_id, cache::SEARCH_TTL).await;
+                return Some((video, source));
+            }
+            Err(e) => log::warn!("Video ID {} failed as well: {}", video.video_id, e),
+        }
+    }
+    None
+}
+
+// Reuse a recent conversion link. Downloads always go through the providers,
+// as the file is removed once it has been sent
+pub async fn cached_fetch_mp3(
+    cache: &dyn Cache,
+    providers: &ProviderChain,
+    video_id: &str,
+    bitrate: u32,
+    media_dir: Option<&Path>,
+) -> Result<Mp3Source, SongError> {
+    let key = cache::link_key(video_id, bitrate);
+    if media_dir.is_none() {
+        if let Some(link) = cache::get_or_log(cache, &key).await {
+            log::info!("Link cache hit for video ID {}", video_id);
+            return Ok(Mp3Source::Link(link));
+        }
+    }
+
+    let source = providers.fetch_mp3(video_id, bitrate, media_dir).await?;
+    if let Mp3Source::Link(link) = &source {
+        cache::set_or_log(cache, &key, link, cache::LINK_TTL).await;
+    }
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::InMemoryCache, lyrics::Lyrics, providers::Mp3Provider, search::SearchProvider,
+    };
+    use async_trait::async_trait;
+    use std::{collections::HashMap, time::Duration};
+
+    // Finds the video of every known title
+    struct FakeSearch(HashMap<&'static str, &'static str>);
+
+    #[async_trait]
+    impl SearchProvider for FakeSearch {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        async fn search(&self, query: &str, _limit: usize) -> Result<Vec<FoundVideo>, SongError> {
+            Ok(self
+                .0
+                .get(query)
+                .map(|video_id| FoundVideo {
+                    video_id: video_id.to_string(),
+                    title: query.to_string(),
+                    channel: "Channel".to_string(),
+                    duration: None,
+                    live: false,
+                })
+                .into_iter()
+                .collect())
+        }
+    }
+
+    // Converts the known videos into links after their delay
+    struct FakeProvider(HashMap<&'static str, Duration>);
+
+    #[async_trait]
+    impl Mp3Provider for FakeProvider {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        async fn fetch_mp3(
+            &self,
+            video_id: &str,
+            _bitrate: u32,
+            _media_dir: Option<&Path>,
+        ) -> Result<Mp3Source, SongError> {
+            let Some(delay) = self.0.get(video_id) else {
+                return Err(SongError::Conversion(format!("no video {}", video_id)));
+            };
+            tokio::time::sleep(*delay).await;
+            Ok(Mp3Source::Link(format!(
+                "https://example.com/{}.mp3",
+                video_id
+            )))
+        }
+    }
+
+    struct NoRestrictions;
+
+    #[async_trait]
+    impl RestrictionLookup for NoRestrictions {
+        async fn restriction(&self, _video_id: &str) -> Option<Restriction> {
+            None
+        }
+    }
+
+    struct NoLyrics;
+
+    #[async_trait]
+    impl LyricsProvider for NoLyrics {
+        fn name(&self) -> &'static str {
+            "none"
+        }
+
+        async fn lyrics(&self, _query: &str) -> Result<Option<Lyrics>, SongError> {
+            Ok(None)
+        }
+
+        async fn synced_lyrics(&self, _query: &str) -> Result<Option<String>, SongError> {
+            Ok(None)
+        }
+    }
+
+    fn processor(
+        titles: &[(&'static str, &'static str)],
+        videos: &[(&'static str, Duration)],
+    ) -> SongProcessor {
+        SongProcessor {
+            media_dir: None,
+            default_bitrate: 320,
+            zip_min_songs: 10,
+            providers: Arc::new(ProviderChain::new(vec![Box::new(FakeProvider(
+                videos.iter().copied().collect(),
+            ))])),
+            search: Arc::new(SearchChain::new(vec![Box::new(FakeSearch(
+                titles.iter().copied().collect(),
+            ))])),
+            restrictions: Arc::new(NoRestrictions),
+            conversion_limiter: Arc::new(Semaphore::new(4)),
+            cache: Arc::new(InMemoryCache::default()),
+            lyrics: Arc::new(NoLyrics),
+            storage: None,
+            client: http::client(),
+        }
+    }
+
+    // Songs at their positions in the request
+    fn numbered(songs: Vec<(usize, SongRequest)>) -> Vec<SongRequest> {
+        songs
+            .into_iter()
+            .map(|(index, song)| SongRequest { index, ..song })
+            .collect()
+    }
+
+    async fn process(processor: &SongProcessor, songs: Vec<SongRequest>) -> ProcessedSongs {
+        processor
+            .process_songs(
+                "request",
+                songs,
+                &RequestOptions::default(),
+                None,
+                None,
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap()
+    }
+
+    fn statuses(processed: &ProcessedSongs) -> Vec<SongStatus> {
+        processed
+            .history
+            .iter()
+            .map(|record| record.status)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn converts_searched_and_known_videos_into_links() {
+        let processor = processor(
+            &[("Artist - Song", "video1")],
+            &[("video1", Duration::ZERO), ("video2", Duration::ZERO)],
+        );
+        let processed = process(
+            &processor,
+            numbered(vec![
+                (0, SongRequest::search("Artist - Song")),
+                (1, SongRequest::video("Other - Song", "video2".to_string())),
+            ]),
+        )
+        .await;
+
+        assert!(processed.failures.is_empty());
+        assert_eq!(processed.links.len(), 2);
+        assert!(processed.links[0].contains("video1"));
+        assert!(processed.links[1].contains("video2"));
+        assert_eq!(
+            statuses(&processed),
+            vec![SongStatus::Converted, SongStatus::Converted]
+        );
+        assert_eq!(processed.history[0].video_id.as_deref(), Some("video1"));
+    }
+
+    #[tokio::test]
+    async fn reports_songs_that_fail_without_failing_the_others() {
+        let processor = processor(
+            &[("Found - Song", "video1"), ("Broken - Song", "broken")],
+            &[("video1", Duration::ZERO)],
+        );
+        let processed = process(
+            &processor,
+            numbered(vec![
+                (0, SongRequest::search("Missing - Song")),
+                (1, SongRequest::search("Found - Song")),
+                (2, SongRequest::search("Broken - Song")),
+            ]),
+        )
+        .await;
+
+        assert_eq!(processed.links.len(), 3);
+        assert!(processed.links[0].starts_with("1\\. ❌ Couldn't find 'Missing \\- Song'"));
+        assert!(processed.links[1].starts_with("2\\. 🎵"));
+        assert!(processed.links[2].starts_with("3\\. ❌ Couldn't convert 'Broken \\- Song'"));
+        // Already in the reply with the links
+        assert!(processed.failures.is_empty());
+        assert_eq!(
+            statuses(&processed),
+            vec![
+                SongStatus::Failed,
+                SongStatus::Converted,
+                SongStatus::Failed
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_failures_on_their_own_without_links() {
+        let processor = processor(&[], &[]);
+        let processed = process(
+            &processor,
+            numbered(vec![(0, SongRequest::search("Missing - Song"))]),
+        )
+        .await;
+
+        assert!(processed.links.is_empty());
+        assert_eq!(processed.failures.len(), 1);
+        assert!(processed.failures[0].contains("Missing - Song"));
+    }
+
+    #[tokio::test]
+    async fn keeps_the_order_of_the_request() {
+        let processor = processor(
+            &[],
+            &[
+                ("slow", Duration::from_millis(200)),
+                ("fast", Duration::ZERO),
+            ],
+        );
+        let processed = process(
+            &processor,
+            numbered(vec![
+                (0, SongRequest::video("Slow - Song", "slow".to_string())),
+                (1, SongRequest::video("Fast - Song", "fast".to_string())),
+            ]),
+        )
+        .await;
+
+        assert_eq!(processed.links.len(), 2);
+        assert!(processed.links[0].starts_with("1\\.") && processed.links[0].contains("slow"));
+        assert!(processed.links[1].starts_with("2\\.") && processed.links[1].contains("fast"));
+        let titles: Vec<&str> = processed
+            .history
+            .iter()
+            .map(|record| record.title.as_str())
+            .collect();
+        assert_eq!(titles, vec!["Slow - Song", "Fast - Song"]);
+    }
+
+    #[tokio::test]
+    async fn numbers_songs_by_their_position_in_the_request() {
+        let processor = processor(&[], &[("first", Duration::ZERO), ("third", Duration::ZERO)]);
+        // The second song was a duplicate and never reached the processor
+        let processed = process(
+            &processor,
+            numbered(vec![
+                (0, SongRequest::video("First - Song", "first".to_string())),
+                (2, SongRequest::video("Third - Song", "third".to_string())),
+            ]),
+        )
+        .await;
+
+        assert_eq!(processed.links.len(), 2);
+        assert!(processed.links[0].starts_with("1\\.") && processed.links[0].contains("first"));
+        assert!(processed.links[1].starts_with("3\\.") && processed.links[1].contains("third"));
+    }
+
+    #[tokio::test]
+    async fn leaves_songs_of_cancelled_requests_unstarted() {
+        let mut processor = processor(&[], &[("video1", Duration::ZERO)]);
+        // Every conversion slot is taken
+        processor.conversion_limiter = Arc::new(Semaphore::new(0));
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let processed = processor
+            .process_songs(
+                "request",
+                vec![SongRequest::video("Artist - Song", "video1".to_string())],
+                &RequestOptions::default(),
+                None,
+                None,
+                &cancel,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(processed.cancelled, 1);
+        assert_eq!(statuses(&processed), vec![SongStatus::Cancelled]);
+    }
+}
@@ -0,0 +1,63 @@
+use super::{Mp3Provider, Mp3Source};
+use crate::{
+    bandcamp, downloader,
+    error::SongError,
+    http::{self, Client},
+    metrics::{self, Stage},
+    proxy::ProxyPool,
+};
+use async_trait::async_trait;
+use std::{path::Path, sync::Arc};
+
+// Fetches Bandcamp tracks from the stream their embedded player plays, which
+// is already an MP3 so nothing needs converting
+pub struct BandcampProvider {
+    client: Client,
+    download_client: reqwest::Client,
+}
+
+impl BandcampProvider {
+    pub fn new(proxy: Option<&Arc<ProxyPool>>) -> Self {
+        Self {
+            client: http::with_middleware(http::proxied_builder(proxy), true),
+            download_client: http::download_client(proxy),
+        }
+    }
+}
+
+#[async_trait]
+impl Mp3Provider for BandcampProvider {
+    fn name(&self) -> &'static str {
+        "bandcamp"
+    }
+
+    fn handles(&self, video_id: &str) -> bool {
+        bandcamp::track_url(video_id).is_some()
+    }
+
+    // Bandcamp only streams at 128kbps, whatever bitrate was asked for
+    async fn fetch_mp3(
+        &self,
+        video_id: &str,
+        _bitrate: u32,
+        media_dir: Option<&Path>,
+    ) -> Result<Mp3Source, SongError> {
+        let link = bandcamp::stream_url(&self.client, video_id)
+            .await
+            .inspect_err(|_| metrics::record_failure(Stage::Convert))?;
+
+        if let Some(media_dir) = media_dir {
+            match downloader::download_mp3(&self.download_client, &link, media_dir, video_id).await
+            {
+                Ok(file_path) => return Ok(Mp3Source::File(file_path)),
+                // The link still works, so send that instead of nothing
+                Err(e) => {
+                    log::error!("Download failed for video ID {}: {}", video_id, e);
+                    metrics::record_failure(Stage::Download);
+                }
+            }
+        }
+
+        Ok(Mp3Source::Link(link))
+    }
+}
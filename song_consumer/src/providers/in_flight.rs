@@ -0,0 +1,133 @@
+// Conversions of the same video that run at the same time, e.g. when many
+// users ask for a trending song at once, share a single download. Every
+// request still ends up with a file of its own, as the reply service removes
+// what it uploads and clips, effects and tags edit the file in place
+
+use super::Mp3Source;
+use crate::error::SongError;
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+// Downloads running right now, by video ID, bitrate and destination
+#[derive(Default)]
+pub struct InFlight {
+    running: Mutex<HashMap<String, Arc<Download>>>,
+}
+
+// Failures are shared as text, since SongError can't be cloned
+#[derive(Default)]
+struct Download {
+    result: OnceCell<Result<Mp3Source, String>>,
+}
+
+impl Drop for Download {
+    // The last request done with a shared file removes it, each one has its
+    // own copy by then
+    fn drop(&mut self) {
+        if let Some(Ok(Mp3Source::File(path))) = self.result.get() {
+            if let Err(e) = std::fs::remove_file(path) {
+                log::warn!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+impl InFlight {
+    // Run the fetch, or wait for the one already running under the same key.
+    // Should the request running it be cancelled, a waiting one takes over
+    pub async fn fetch<F>(&self, key: String, fetch: F) -> Result<Mp3Source, SongError>
+    where
+        F: Future<Output = Result<Mp3Source, SongError>>,
+    {
+        let download = {
+            let mut running = self.lock();
+            if running.contains_key(&key) {
+                log::info!("Sharing the download of {} that is already running", key);
+            }
+            Arc::clone(running.entry(key.clone()).or_default())
+        };
+        let result = download
+            .result
+            .get_or_init(|| async move { fetch.await.map_err(|e| e.to_string()) })
+            .await;
+        let shared = match result {
+            Ok(Mp3Source::File(path)) => Ok(path.clone()),
+            Ok(Mp3Source::Link(link)) => Err(Ok(Mp3Source::Link(link.clone()))),
+            Err(e) => Err(Err(SongError::Conversion(e.clone()))),
+        };
+
+        // Requests from now on start a download of their own
+        {
+            let mut running = self.lock();
+            if running
+                .get(&key)
+                .is_some_and(|running| Arc::ptr_eq(running, &download))
+            {
+                running.remove(&key);
+            }
+        }
+        let shared = match shared {
+            Ok(path) => path,
+            Err(result) => return result,
+        };
+
+        // The last request still holding the download keeps the file, the
+        // others copy it
+        match Arc::try_unwrap(download) {
+            Ok(mut download) => {
+                download.result.take();
+                Ok(Mp3Source::File(shared))
+            }
+            Err(_download) => {
+                let copy = copy_path(&shared, &Uuid::new_v4().simple().to_string());
+                tokio::fs::copy(&shared, &copy).await.map_err(|e| {
+                    SongError::Conversion(format!("couldn't copy {}: {}", shared.display(), e))
+                })?;
+                Ok(Mp3Source::File(copy))
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Arc<Download>>> {
+        // The map stays consistent even if a holder panicked
+        self.running
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// "<video ID>-<suffix>.mp3" next to the shared file
+fn copy_path(shared: &Path, suffix: &str) -> PathBuf {
+    let stem = shared
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let name = match shared.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, suffix, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, suffix),
+    };
+    shared.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_next_to_the_shared_file() {
+        assert_eq!(
+            copy_path(Path::new("/media/a/dQw4w9WgXcQ.mp3"), "1f2e"),
+            PathBuf::from("/media/a/dQw4w9WgXcQ-1f2e.mp3")
+        );
+        assert_eq!(
+            copy_path(Path::new("/media/a/dQw4w9WgXcQ"), "1f2e"),
+            PathBuf::from("/media/a/dQw4w9WgXcQ-1f2e")
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{
+    analytics,
+    circuit_breaker::CircuitBreaker,
+    config::{Config, ProviderKind},
+    error::SongError,
+    proxy::Proxies,
+};
+use async_trait::async_trait;
+use in_flight::InFlight;
+use rustin_models::analytics::AnalyticsEvent;
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+mod bandcamp;
+mod in_flight;
+mod tomp3;
+mod ytdlp;
+
+pub use bandcamp::BandcampProvider;
+pub use tomp3::{CloudflareChallenge, Tomp3Cookie, Tomp3Provider};
+pub use ytdlp::YtDlpProvider;
+
+// Videos are fetched at this resolution at most, which keeps most of them
+// under Telegram's upload limit
+pub const MAX_VIDEO_HEIGHT: u32 = 720;
+
+// A conversion taking longer than this is hung rather than slow
+const PROVIDER_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// Where a converted song can be picked up from
+pub enum Mp3Source {
+    // Download link the user can open directly
+    Link(String),
+    // MP3 (or MP4 for /video) written to the media directory
+    File(PathBuf),
+}
+
+#[async_trait]
+pub trait Mp3Provider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Whether the provider can convert this kind of ID at all. Providers that
+    // can't are skipped rather than counted as failures
+    fn handles(&self, _video_id: &str) -> bool {
+        true
+    }
+
+    // Convert a YouTube video to MP3 at (or as close as possible to) the given
+    // bitrate. When a media directory is given the MP3 should be downloaded
+    // into it, otherwise a link is enough
+    async fn fetch_mp3(
+        &self,
+        video_id: &str,
+        bitrate: u32,
+        media_dir: Option<&Path>,
+    ) -> Result<Mp3Source, SongError>;
+
+    // Whether the provider can fetch the video itself too, for /video
+    fn converts_video(&self) -> bool {
+        false
+    }
+
+    // Fetch a YouTube video as MP4, at most MAX_VIDEO_HEIGHT tall. Only
+    // called on providers that convert videos
+    async fn fetch_mp4(
+        &self,
+        video_id: &str,
+        _media_dir: Option<&Path>,
+    ) -> Result<Mp3Source, SongError> {
+        Err(SongError::Conversion(format!(
+            "{} can't fetch video ID {} as MP4",
+            self.name(),
+            video_id
+        )))
+    }
+}
+
+// A provider and the breaker that stops calling it while it keeps failing
+struct GuardedProvider {
+    provider: Box<dyn Mp3Provider>,
+    breaker: CircuitBreaker,
+}
+
+impl GuardedProvider {
+    // Run a call to the provider, giving up on it once it hangs. Dropping
+    // the call kills the yt-dlp process along with it
+    async fn call<F>(&self, video_id: &str, fetch: F) -> Result<Mp3Source, SongError>
+    where
+        F: Future<Output = Result<Mp3Source, SongError>>,
+    {
+        let name = self.provider.name();
+        if !self.breaker.allow() {
+            return Err(SongError::Conversion(format!(
+                "{} is skipped after failing too often",
+                name
+            )));
+        }
+        let started = Instant::now();
+        let result = tokio::time::timeout(PROVIDER_TIMEOUT, fetch)
+            .await
+            .unwrap_or_else(|_| {
+                Err(SongError::Conversion(format!(
+                    "{} timed out on video ID {}",
+                    name, video_id
+                )))
+            });
+        self.breaker.record(result.is_ok());
+        match &result {
+            Ok(_) => analytics::record(AnalyticsEvent::SongConverted {
+                provider: name.to_string(),
+                seconds: started.elapsed().as_secs_f64(),
+            }),
+            // Even when a fallback saves the song, e.g. after tomp3 changed
+            // its responses, maintainers should hear about it
+            Err(e) if e.is_reportable() => {
+                rustin_telemetry::breadcrumb("provider", format!("{} failed", name));
+                rustin_telemetry::report(e);
+            }
+            Err(_) => {}
+        }
+        result
+    }
+}
+
+// Tries each provider in order until one of them succeeds. Providers whose
+// calls mostly fail are skipped for a while, going straight to the fallbacks
+pub struct ProviderChain {
+    providers: Vec<GuardedProvider>,
+    // Requests for a video that is already being fetched wait for it
+    in_flight: InFlight,
+}
+
+impl ProviderChain {
+    pub fn new(providers: Vec<Box<dyn Mp3Provider>>) -> Self {
+        Self {
+            providers: providers
+                .into_iter()
+                .map(|provider| GuardedProvider {
+                    breaker: CircuitBreaker::new(provider.name()),
+                    provider,
+                })
+                .collect(),
+            in_flight: InFlight::default(),
+        }
+    }
+
+    // Build the chain with the configured provider first, keeping the other
+    // one as a fallback. Bandcamp tracks are fetched straight from Bandcamp
+    // before either is tried
+    pub fn from_config(config: &Config, proxies: &Proxies) -> Self {
+        let ytdlp: Box<dyn Mp3Provider> = Box::new(YtDlpProvider::new(
+            config.ytdlp_path.clone(),
+            config.ytdlp_cookies.clone(),
+            proxies.for_provider("ytdlp").cloned(),
+        ));
+        let cookie = Tomp3Cookie::from_config(config);
+        cookie.reload_on_sighup();
+        let tomp3: Box<dyn Mp3Provider> = Box::new(Tomp3Provider::new(
+            cookie,
+            config.tomp3_user_agent.clone(),
+            proxies.for_provider("tomp3"),
+        ));
+
+        let mut providers: Vec<Box<dyn Mp3Provider>> = vec![Box::new(BandcampProvider::new(
+            proxies.for_provider("bandcamp"),
+        ))];
+        providers.extend(match config.mp3_provider {
+            ProviderKind::Ytdlp => [ytdlp, tomp3],
+            ProviderKind::Tomp3 => [tomp3, ytdlp],
+        });
+
+        log::info!(
+            "Using MP3 providers: {}",
+            providers
+                .iter()
+                .map(|provider| provider.name())
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        );
+        Self::new(providers)
+    }
+
+    pub async fn fetch_mp3(
+        &self,
+        video_id: &str,
+        bitrate: u32,
+        media_dir: Option<&Path>,
+    ) -> Result<Mp3Source, SongError> {
+        let destination = match media_dir {
+            Some(media_dir) => media_dir.to_string_lossy(),
+            None => "link".into(),
+        };
+        let key = format!("{}@{}:{}", video_id, bitrate, destination);
+        self.in_flight
+            .fetch(key, self.try_providers(video_id, bitrate, media_dir))
+            .await
+    }
+
+    async fn try_providers(
+        &self,
+        video_id: &str,
+        bitrate: u32,
+        media_dir: Option<&Path>,
+    ) -> Result<Mp3Source, SongError> {
+        let mut last_error = None;
+
+        for guarded in self
+            .providers
+            .iter()
+            .filter(|guarded| guarded.provider.handles(video_id))
+        {
+            let provider = &guarded.provider;
+            let fetch = provider.fetch_mp3(video_id, bitrate, media_dir);
+            match guarded.call(video_id, fetch).await {
+                Ok(source) => return Ok(source),
+                Err(e) => {
+                    log::warn!(
+                        "Provider {} failed for video ID {}: {}",
+                        provider.name(),
+                        video_id,
+                        e
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| SongError::Conversion(format!("No MP3 provider for {}", video_id))))
+    }
+
+    pub async fn fetch_mp4(
+        &self,
+        video_id: &str,
+        media_dir: Option<&Path>,
+    ) -> Result<Mp3Source, SongError> {
+        let mut last_error = None;
+
+        for guarded in self.providers.iter().filter(|guarded| {
+            guarded.provider.converts_video() && guarded.provider.handles(video_id)
+        }) {
+            let provider = &guarded.provider;
+            let fetch = provider.fetch_mp4(video_id, media_dir);
+            match guarded.call(video_id, fetch).await {
+                Ok(source) => return Ok(source),
+                Err(e) => {
+                    log::warn!(
+                        "Provider {} failed to fetch the video of ID {}: {}",
+                        provider.name(),
+                        video_id,
+                        e
+                    );
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| SongError::Conversion(format!("No MP4 provider for {}", video_id))))
+    }
+}
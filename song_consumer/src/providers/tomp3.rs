@@ -0,0 +1,349 @@
+use super::{Mp3Provider, Mp3Source, MAX_VIDEO_HEIGHT};
+use crate::{
+    bandcamp,
+    config::Config,
+    downloader,
+    error::SongError,
+    http::{self, Client},
+    metrics::{self, Stage},
+    proxy::ProxyPool,
+    quality, soundcloud, DynError,
+};
+use async_trait::async_trait;
+use reqwest::cookie::Jar;
+use rustin_models::tomp3::{ConvertResponse, Links, Mp3Link, Mp4Link, Tomp3Response};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::signal::unix::{signal, SignalKind};
+
+// tomp3 converts the whole video before it answers with the download link
+const CONVERT_TIMEOUT: Duration = Duration::from_secs(3 * 60);
+
+// Returned when tomp3 answers with a Cloudflare challenge page instead of
+// JSON, which means the cf_clearance cookie has expired
+#[derive(Debug)]
+pub struct CloudflareChallenge;
+
+impl fmt::Display for CloudflareChallenge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tomp3 returned a Cloudflare challenge, the cf_clearance cookie needs to be refreshed"
+        )
+    }
+}
+
+impl Error for CloudflareChallenge {}
+
+// The Cookie header sent to tomp3. It is read from tomp3_cookie, or from the
+// file in tomp3_cookie_file which is re-read whenever the process gets SIGHUP,
+// so an expired cf_clearance can be replaced without a restart
+#[derive(Clone)]
+pub struct Tomp3Cookie {
+    file: Option<PathBuf>,
+    value: Arc<RwLock<Option<String>>>,
+}
+
+impl Tomp3Cookie {
+    pub fn from_config(config: &Config) -> Self {
+        let cookie = Self {
+            file: config.tomp3_cookie_file.clone(),
+            value: Arc::new(RwLock::new(config.tomp3_cookie.clone())),
+        };
+
+        if let Err(e) = cookie.reload() {
+            log::error!("Failed to load tomp3 cookie: {}", e);
+        }
+        if cookie.get().is_none() {
+            log::warn!("No tomp3 cookie configured, requests will likely hit Cloudflare");
+        }
+        cookie
+    }
+
+    pub fn get(&self) -> Option<String> {
+        self.value.read().expect("cookie lock poisoned").clone()
+    }
+
+    fn reload(&self) -> Result<(), DynError> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        let contents = std::fs::read_to_string(file)?;
+        let cookie = contents.trim();
+        *self.value.write().expect("cookie lock poisoned") =
+            (!cookie.is_empty()).then(|| cookie.to_string());
+        log::info!("Loaded tomp3 cookie from {}", file.display());
+        Ok(())
+    }
+
+    // Re-read the cookie file every time the process receives SIGHUP
+    pub fn reload_on_sighup(&self) {
+        if self.file.is_none() {
+            return;
+        }
+
+        let cookie = self.clone();
+        tokio::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    log::error!("Failed to listen for SIGHUP: {}", e);
+                    return;
+                }
+            };
+
+            while hangup.recv().await.is_some() {
+                if let Err(e) = cookie.reload() {
+                    log::error!("Failed to reload tomp3 cookie: {}", e);
+                }
+            }
+        });
+    }
+}
+
+// Converts videos through the tomp3.cc web API
+pub struct Tomp3Provider {
+    mp3_client: Client,
+    download_client: reqwest::Client,
+    cookie: Tomp3Cookie,
+}
+
+impl Tomp3Provider {
+    pub fn new(
+        cookie: Tomp3Cookie,
+        user_agent: Option<String>,
+        proxy: Option<&Arc<ProxyPool>>,
+    ) -> Self {
+        let cookie_jar = Arc::new(Jar::default());
+        // Attach the cookie jar only for mp3 API requests
+        let builder = http::proxied_builder(proxy).cookie_provider(cookie_jar);
+        // Cloudflare only accepts cf_clearance from the browser that got it, so
+        // tomp3_user_agent should be that browser's. Otherwise a different
+        // browser is pretended to be on every call
+        let mp3_client = match user_agent {
+            Some(user_agent) => http::with_middleware(builder.user_agent(user_agent), false),
+            None => http::with_middleware(builder, true),
+        };
+
+        Self {
+            mp3_client,
+            download_client: http::download_client(proxy),
+            cookie,
+        }
+    }
+
+    // Retrieve the tomp3 k parameter of the format picked from the links of
+    // a video and convert it to a download link
+    async fn fetch_download_link(
+        &self,
+        video_id: &str,
+        select: impl FnOnce(Links) -> Option<String>,
+    ) -> Result<String, SongError> {
+        let k = search_links(&self.mp3_client, video_id, self.cookie.get().as_deref())
+            .await
+            .and_then(|links| {
+                links
+                    .and_then(select)
+                    .ok_or_else(|| SongError::Conversion("Failed to get k parameter".to_string()))
+            })
+            .inspect_err(|_| metrics::record_failure(Stage::K))?;
+
+        log::info!("Retrieved k parameter for video ID: {}", video_id);
+
+        convert(&self.mp3_client, video_id, &k)
+            .await
+            .and_then(|dlink| {
+                dlink
+                    .ok_or_else(|| SongError::Conversion("Failed to get download link".to_string()))
+            })
+            .inspect_err(|_| metrics::record_failure(Stage::Convert))
+    }
+}
+
+#[async_trait]
+impl Mp3Provider for Tomp3Provider {
+    fn name(&self) -> &'static str {
+        "tomp3"
+    }
+
+    // tomp3 only converts YouTube videos
+    fn handles(&self, video_id: &str) -> bool {
+        soundcloud::track_url(video_id).is_none() && bandcamp::track_url(video_id).is_none()
+    }
+
+    async fn fetch_mp3(
+        &self,
+        video_id: &str,
+        bitrate: u32,
+        media_dir: Option<&Path>,
+    ) -> Result<Mp3Source, SongError> {
+        let dlink = self
+            .fetch_download_link(video_id, |links| {
+                select_mp3_link(&links.mp3?, video_id, bitrate)
+            })
+            .await?;
+
+        if let Some(media_dir) = media_dir {
+            match downloader::download_mp3(&self.download_client, &dlink, media_dir, video_id).await
+            {
+                Ok(file_path) => return Ok(Mp3Source::File(file_path)),
+                // The link still works, so send that instead of nothing
+                Err(e) => {
+                    log::error!("Download failed for video ID {}: {}", video_id, e);
+                    metrics::record_failure(Stage::Download);
+                }
+            }
+        }
+
+        Ok(Mp3Source::Link(dlink))
+    }
+
+    fn converts_video(&self) -> bool {
+        true
+    }
+
+    async fn fetch_mp4(
+        &self,
+        video_id: &str,
+        media_dir: Option<&Path>,
+    ) -> Result<Mp3Source, SongError> {
+        let dlink = self
+            .fetch_download_link(video_id, |links| select_mp4_link(&links.mp4?))
+            .await?;
+
+        if let Some(media_dir) = media_dir {
+            match downloader::download_mp4(&self.download_client, &dlink, media_dir, video_id).await
+            {
+                Ok(file_path) => return Ok(Mp3Source::File(file_path)),
+                Err(e) => {
+                    log::error!("Video download failed for video ID {}: {}", video_id, e);
+                    metrics::record_failure(Stage::Download);
+                }
+            }
+        }
+
+        Ok(Mp3Source::Link(dlink))
+    }
+}
+
+// Search tomp3 for a video, which answers with the k parameter of every
+// format it can convert the video to
+async fn search_links(
+    client: &Client,
+    video_id: &str,
+    cookie: Option<&str>,
+) -> Result<Option<Links>, SongError> {
+    let url = "https://tomp3.cc/api/ajax/search";
+    let params = [
+        (
+            "query",
+            format!("https://www.youtube.com/watch?v={}", video_id),
+        ),
+        ("vt", "downloader".to_string()),
+    ];
+
+    log::info!("Retrieving k parameter for video ID: {}", video_id);
+
+    let mut request = client.post(url).form(&params);
+    if let Some(cookie) = cookie {
+        request = request.header("Cookie", cookie);
+    }
+    let response = request.send().await?;
+
+    let status = response.status();
+    let challenged = is_cloudflare_challenge(&response);
+    let text = response.text().await?;
+    log::info!("Response status: {}", status);
+    log::info!("Raw response body: {}", text);
+
+    if challenged || text.contains("challenge-platform") {
+        log::error!("{}", CloudflareChallenge);
+        return Err(CloudflareChallenge.into());
+    }
+
+    if !status.is_success() {
+        log::error!("Failed request: {}", status);
+        return Err(SongError::Conversion(format!(
+            "tomp3 search returned {}",
+            status
+        )));
+    }
+
+    let parsed: Result<Tomp3Response, _> = serde_json::from_str(&text);
+    match parsed {
+        Ok(response) => Ok(response.links),
+        Err(e) => {
+            log::error!("Error decoding response: {}", e);
+            Err(SongError::Conversion(
+                "Error decoding tomp3 search response".to_string(),
+            ))
+        }
+    }
+}
+
+// The links map is keyed by format and bitrate, e.g. "mp3320"
+fn select_mp3_link(mp3: &HashMap<String, Mp3Link>, video_id: &str, bitrate: u32) -> Option<String> {
+    let available: Vec<u32> = mp3
+        .keys()
+        .filter_map(|key| key.strip_prefix("mp3")?.parse().ok())
+        .collect();
+    let chosen = quality::choose_bitrate(bitrate, &available)?;
+    if chosen != bitrate {
+        log::info!(
+            "{}kbps not available for video ID {}, using {}kbps",
+            bitrate,
+            video_id,
+            chosen
+        );
+    }
+    mp3.get(&format!("mp3{}", chosen))
+        .map(|link| link.k.clone())
+}
+
+// The mp4 links are keyed by an ID, with the resolution in q, e.g. "720p".
+// The best one up to MAX_VIDEO_HEIGHT is picked, or the smallest when they're
+// all taller
+fn select_mp4_link(mp4: &HashMap<String, Mp4Link>) -> Option<String> {
+    let mut links: Vec<(u32, &Mp4Link)> = mp4
+        .values()
+        .filter_map(|link| Some((link.q.strip_suffix('p')?.parse().ok()?, link)))
+        .collect();
+    links.sort_by_key(|&(height, _)| height);
+    links
+        .iter()
+        .rev()
+        .find(|&&(height, _)| height <= MAX_VIDEO_HEIGHT)
+        .or(links.first())
+        .map(|(_, link)| link.k.clone())
+}
+
+// Cloudflare marks challenge responses with the cf-mitigated header
+fn is_cloudflare_challenge(response: &reqwest::Response) -> bool {
+    response
+        .headers()
+        .get("cf-mitigated")
+        .is_some_and(|value| value == "challenge")
+}
+
+async fn convert(client: &Client, video_id: &str, k: &str) -> Result<Option<String>, SongError> {
+    let url = "https://tomp3.cc/api/ajax/convert";
+    let params = [("vid", video_id.to_string()), ("k", k.to_string())];
+
+    log::info!("Converting video ID {}", video_id);
+    let response: ConvertResponse = client
+        .post(url)
+        .form(&params)
+        .timeout(CONVERT_TIMEOUT)
+        .send()
+        .await?
+        .json()
+        .await?;
+    Ok(Some(response.dlink))
+}
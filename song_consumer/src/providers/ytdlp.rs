@@ -0,0 +1,210 @@
+use super::{Mp3Provider, Mp3Source, MAX_VIDEO_HEIGHT};
+use crate::{
+    bandcamp,
+    error::SongError,
+    metrics::{self, Stage},
+    proxy::ProxyPool,
+    soundcloud,
+};
+use async_trait::async_trait;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::process::Command;
+
+// Converts videos, SoundCloud and Bandcamp tracks locally by running yt-dlp (and the ffmpeg it relies on)
+pub struct YtDlpProvider {
+    binary: String,
+    // Signs yt-dlp in, so age-restricted videos can be downloaded too
+    cookies: Option<PathBuf>,
+    proxy: Option<Arc<ProxyPool>>,
+}
+
+impl YtDlpProvider {
+    pub fn new(binary: String, cookies: Option<PathBuf>, proxy: Option<Arc<ProxyPool>>) -> Self {
+        Self {
+            binary,
+            cookies,
+            proxy,
+        }
+    }
+
+    // Download the MP3 into the media directory, or resolve a stream link
+    async fn convert(
+        &self,
+        video_id: &str,
+        bitrate: u32,
+        media_dir: Option<&Path>,
+    ) -> Result<Mp3Source, SongError> {
+        // yt-dlp handles SoundCloud and Bandcamp tracks just like videos
+        let video_url = soundcloud::track_url(video_id)
+            .or_else(|| bandcamp::track_url(video_id))
+            .unwrap_or_else(|| youtube_url(video_id));
+
+        let Some(media_dir) = media_dir else {
+            // Without a media directory only a link can be handed out, which
+            // for yt-dlp is the direct URL of the best audio stream
+            log::info!("Resolving audio stream URL for video ID {}", video_id);
+            let stdout = self
+                .run(&["--no-playlist", "-f", "bestaudio", "-g", &video_url])
+                .await?;
+            let link = stdout
+                .lines()
+                .next()
+                .filter(|line| !line.is_empty())
+                .ok_or_else(|| {
+                    SongError::Conversion("yt-dlp returned no stream URL".to_string())
+                })?;
+            return Ok(Mp3Source::Link(link.to_string()));
+        };
+
+        log::info!(
+            "Downloading video ID {} with yt-dlp at {}kbps",
+            video_id,
+            bitrate
+        );
+        let output_template = media_dir.join(format!("{}.%(ext)s", video_id));
+        let audio_quality = format!("{}K", bitrate);
+        self.run(&[
+            "--no-playlist",
+            "-x",
+            "--audio-format",
+            "mp3",
+            "--audio-quality",
+            &audio_quality,
+            "-o",
+            &output_template.to_string_lossy(),
+            &video_url,
+        ])
+        .await?;
+
+        let file_path = media_dir.join(format!("{}.mp3", video_id));
+        if !tokio::fs::try_exists(&file_path).await.unwrap_or(false) {
+            return Err(SongError::Conversion(format!(
+                "yt-dlp did not produce {}",
+                file_path.display()
+            )));
+        }
+        Ok(Mp3Source::File(file_path))
+    }
+
+    // Download the MP4 into the media directory, or resolve the URL of a
+    // stream that has both the video and the audio
+    async fn convert_video(
+        &self,
+        video_id: &str,
+        media_dir: Option<&Path>,
+    ) -> Result<Mp3Source, SongError> {
+        let video_url = youtube_url(video_id);
+        // Prefer the tallest MP4 up to the limit
+        let sort = format!("res:{},ext:mp4:m4a", MAX_VIDEO_HEIGHT);
+
+        let Some(media_dir) = media_dir else {
+            log::info!("Resolving video stream URL for video ID {}", video_id);
+            let stdout = self
+                .run(&["--no-playlist", "-f", "b", "-S", &sort, "-g", &video_url])
+                .await?;
+            let link = stdout
+                .lines()
+                .next()
+                .filter(|line| !line.is_empty())
+                .ok_or_else(|| {
+                    SongError::Conversion("yt-dlp returned no stream URL".to_string())
+                })?;
+            return Ok(Mp3Source::Link(link.to_string()));
+        };
+
+        log::info!("Downloading the video of ID {} with yt-dlp", video_id);
+        let output_template = media_dir.join(format!("{}.%(ext)s", video_id));
+        self.run(&[
+            "--no-playlist",
+            "-S",
+            &sort,
+            "--merge-output-format",
+            "mp4",
+            "-o",
+            &output_template.to_string_lossy(),
+            &video_url,
+        ])
+        .await?;
+
+        let file_path = media_dir.join(format!("{}.mp4", video_id));
+        if !tokio::fs::try_exists(&file_path).await.unwrap_or(false) {
+            return Err(SongError::Conversion(format!(
+                "yt-dlp did not produce {}",
+                file_path.display()
+            )));
+        }
+        Ok(Mp3Source::File(file_path))
+    }
+
+    async fn run(&self, args: &[&str]) -> Result<String, SongError> {
+        let mut command = Command::new(&self.binary);
+        if let Some(cookies) = &self.cookies {
+            command.arg("--cookies").arg(cookies);
+        }
+        if let Some(proxy) = &self.proxy {
+            command.arg("--proxy").arg(proxy.pick().as_str());
+        }
+        let output = command
+            .args(args)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| SongError::Conversion(format!("Failed to run yt-dlp: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(SongError::Conversion(format!(
+                "yt-dlp exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+#[async_trait]
+impl Mp3Provider for YtDlpProvider {
+    fn name(&self) -> &'static str {
+        "yt-dlp"
+    }
+
+    async fn fetch_mp3(
+        &self,
+        video_id: &str,
+        bitrate: u32,
+        media_dir: Option<&Path>,
+    ) -> Result<Mp3Source, SongError> {
+        self.convert(video_id, bitrate, media_dir)
+            .await
+            .inspect_err(|_| metrics::record_failure(Stage::Convert))
+    }
+
+    fn converts_video(&self) -> bool {
+        true
+    }
+
+    async fn fetch_mp4(
+        &self,
+        video_id: &str,
+        media_dir: Option<&Path>,
+    ) -> Result<Mp3Source, SongError> {
+        // SoundCloud and Bandcamp tracks have no video to fetch
+        if soundcloud::track_url(video_id).is_some() || bandcamp::track_url(video_id).is_some() {
+            return Err(SongError::Conversion(format!(
+                "{} is not a YouTube video",
+                video_id
+            )));
+        }
+        self.convert_video(video_id, media_dir)
+            .await
+            .inspect_err(|_| metrics::record_failure(Stage::Convert))
+    }
+}
+
+fn youtube_url(video_id: &str) -> String {
+    format!("https://www.youtube.com/watch?v={}", video_id)
+}
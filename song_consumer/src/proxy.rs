@@ -0,0 +1,189 @@
+// Proxies for operators in regions where YouTube or tomp3 are blocked. Calls
+// take turns over the proxies of a pool, skipping those that failed their
+// last health check
+
+use crate::{config::Config, DynError};
+use reqwest::{Proxy, Url};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+
+// The providers that can be given proxies of their own
+pub const PROXIED_PROVIDERS: [&str; 3] = ["ytdlp", "tomp3", "bandcamp"];
+// What a working proxy has to reach
+const PROBE_URL: &str = "https://www.youtube.com/generate_204";
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct PoolProxy {
+    url: Url,
+    // The URL without credentials, for the logs
+    label: String,
+    healthy: AtomicBool,
+    probe: reqwest::Client,
+}
+
+pub struct ProxyPool {
+    proxies: Vec<PoolProxy>,
+    next: AtomicUsize,
+}
+
+impl ProxyPool {
+    fn new(urls: &[String]) -> Result<Self, DynError> {
+        let proxies = urls
+            .iter()
+            .map(|url| {
+                let url = Url::parse(url)?;
+                let probe = reqwest::Client::builder()
+                    .proxy(Proxy::all(url.clone())?)
+                    .timeout(CHECK_TIMEOUT)
+                    .build()?;
+                Ok(PoolProxy {
+                    label: format!(
+                        "{}://{}:{}",
+                        url.scheme(),
+                        url.host_str().unwrap_or_default(),
+                        url.port_or_known_default().unwrap_or_default()
+                    ),
+                    url,
+                    healthy: AtomicBool::new(true),
+                    probe,
+                })
+            })
+            .collect::<Result<_, DynError>>()?;
+        Ok(Self {
+            proxies,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    // The proxy for the next call
+    pub fn pick(&self) -> &Url {
+        let healthy: Vec<bool> = self
+            .proxies
+            .iter()
+            .map(|proxy| proxy.healthy.load(Ordering::Relaxed))
+            .collect();
+        let index = pick_index(&healthy, self.next.fetch_add(1, Ordering::Relaxed));
+        &self.proxies[index].url
+    }
+
+    // A proxy for reqwest that picks from the pool on every call
+    pub fn reqwest_proxy(self: &Arc<Self>) -> Proxy {
+        let pool = Arc::clone(self);
+        Proxy::custom(move |_| Some(pool.pick().clone()))
+    }
+
+    async fn check(&self) {
+        for proxy in &self.proxies {
+            let healthy = match proxy.probe.get(PROBE_URL).send().await {
+                Ok(_) => true,
+                Err(e) => {
+                    log::debug!("Proxy {} failed its health check: {}", proxy.label, e);
+                    false
+                }
+            };
+            match (proxy.healthy.swap(healthy, Ordering::Relaxed), healthy) {
+                (true, false) => log::warn!("Proxy {} is down, skipping it", proxy.label),
+                (false, true) => log::info!("Proxy {} is back up", proxy.label),
+                _ => {}
+            }
+        }
+    }
+}
+
+// The first healthy proxy from start on. When none is, calls still take
+// turns over all of them, as going direct is what they're there to avoid
+fn pick_index(healthy: &[bool], start: usize) -> usize {
+    (0..healthy.len())
+        .map(|offset| (start + offset) % healthy.len())
+        .find(|&index| healthy[index])
+        .unwrap_or(start % healthy.len())
+}
+
+// The global pool and those of single providers
+#[derive(Clone, Default)]
+pub struct Proxies {
+    global: Option<Arc<ProxyPool>>,
+    providers: HashMap<String, Arc<ProxyPool>>,
+}
+
+impl Proxies {
+    pub fn from_config(config: &Config) -> Result<Self, DynError> {
+        let pool = |urls: &[String]| -> Result<Option<Arc<ProxyPool>>, DynError> {
+            if urls.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(Arc::new(ProxyPool::new(urls)?)))
+        };
+        let mut providers = HashMap::new();
+        for (provider, urls) in &config.provider_proxies {
+            if let Some(pool) = pool(urls)? {
+                providers.insert(provider.clone(), pool);
+            }
+        }
+        let proxies = Self {
+            global: pool(&config.proxies)?,
+            providers,
+        };
+        if let Some(global) = &proxies.global {
+            log::info!("Connecting through {} proxies", global.proxies.len());
+        }
+        Ok(proxies)
+    }
+
+    pub fn global(&self) -> Option<&Arc<ProxyPool>> {
+        self.global.as_ref()
+    }
+
+    // The provider's own proxies, or the global ones
+    pub fn for_provider(&self, provider: &str) -> Option<&Arc<ProxyPool>> {
+        self.providers.get(provider).or(self.global.as_ref())
+    }
+
+    // Check every proxy until shutdown, so broken ones are skipped
+    pub async fn check_periodically(self, shutdown: CancellationToken) {
+        let pools: Vec<Arc<ProxyPool>> = self
+            .global
+            .into_iter()
+            .chain(self.providers.into_values())
+            .collect();
+        if pools.is_empty() {
+            return;
+        }
+        loop {
+            for pool in &pools {
+                pool.check().await;
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_turns_over_healthy_proxies() {
+        let healthy = [true, false, true];
+        let picked: Vec<usize> = (0..4).map(|start| pick_index(&healthy, start)).collect();
+        assert_eq!(picked, vec![0, 2, 2, 0]);
+    }
+
+    #[test]
+    fn keeps_taking_turns_when_all_are_down() {
+        let healthy = [false, false];
+        assert_eq!(pick_index(&healthy, 0), 0);
+        assert_eq!(pick_index(&healthy, 3), 1);
+    }
+}
@@ -0,0 +1,150 @@
+// Messages recorded anywhere in the worker and published by whichever
+// connection is current, e.g. alerts and analytics events. Recording never
+// waits: the messages are dropped when the buffer is full
+
+use crate::DynError;
+use std::future::Future;
+use tokio::sync::{mpsc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+pub struct PublishBuffer<T> {
+    // What a message is, e.g. "an alert", for the log when one is dropped
+    name: &'static str,
+    // Dropping analytics is routine, dropping alerts is not
+    drop_level: log::Level,
+    sender: mpsc::Sender<T>,
+    // Taken by the publisher of the current connection
+    queue: Mutex<Queue<T>>,
+}
+
+struct Queue<T> {
+    receiver: mpsc::Receiver<T>,
+    // The message whose publishing failed, sent first on the next connection
+    pending: Option<T>,
+}
+
+impl<T: Clone> PublishBuffer<T> {
+    pub fn new(name: &'static str, capacity: usize, drop_level: log::Level) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+        Self {
+            name,
+            drop_level,
+            sender,
+            queue: Mutex::new(Queue {
+                receiver,
+                pending: None,
+            }),
+        }
+    }
+
+    pub fn push(&self, message: T) {
+        if self.sender.try_send(message).is_err() {
+            log::log!(
+                self.drop_level,
+                "Dropping {}, the buffer is full",
+                self.name
+            );
+        }
+    }
+
+    // Hand the buffered messages to publish one by one until shutdown.
+    // Returns the error publishing one, which keeps it for the next connection
+    pub async fn publish<F, Fut>(
+        &self,
+        shutdown: CancellationToken,
+        mut publish: F,
+    ) -> Result<(), DynError>
+    where
+        F: FnMut(T) -> Fut,
+        Fut: Future<Output = Result<(), DynError>>,
+    {
+        let mut queue = self.queue.lock().await;
+        loop {
+            let message = match queue.pending.take() {
+                Some(message) => message,
+                None => {
+                    let message = tokio::select! {
+                        _ = shutdown.cancelled() => return Ok(()),
+                        message = queue.receiver.recv() => message,
+                    };
+                    let Some(message) = message else {
+                        return Ok(());
+                    };
+                    message
+                }
+            };
+            if let Err(e) = publish(message.clone()).await {
+                queue.pending = Some(message);
+                return Err(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn publishes_buffered_messages_in_order() {
+        let buffer = PublishBuffer::new("test messages", 2, log::Level::Debug);
+        buffer.push(1);
+        buffer.push(2);
+        // Dropped, the buffer is full
+        buffer.push(3);
+
+        let shutdown = CancellationToken::new();
+        let mut published = Vec::new();
+        let _ = buffer
+            .publish(shutdown.clone(), |message| {
+                published.push(message);
+                if published.len() == 2 {
+                    shutdown.cancel();
+                }
+                async { Ok(()) }
+            })
+            .await;
+        assert_eq!(published, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_first_failure() {
+        let buffer = PublishBuffer::new("test messages", 2, log::Level::Debug);
+        buffer.push(1);
+        buffer.push(2);
+
+        let result = buffer
+            .publish(CancellationToken::new(), |_| async {
+                Err::<(), DynError>("channel closed".into())
+            })
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn retries_a_failed_message_first() {
+        let buffer = PublishBuffer::new("test messages", 2, log::Level::Debug);
+        buffer.push(1);
+        buffer.push(2);
+
+        let result = buffer
+            .publish(CancellationToken::new(), |_| async {
+                Err::<(), DynError>("channel closed".into())
+            })
+            .await;
+        assert!(result.is_err());
+
+        let shutdown = CancellationToken::new();
+        let mut published = Vec::new();
+        let _ = buffer
+            .publish(shutdown.clone(), |message| {
+                published.push(message);
+                if published.len() == 2 {
+                    shutdown.cancel();
+                }
+                async { Ok(()) }
+            })
+            .await;
+        assert_eq!(published, vec![1, 2]);
+    }
+}
@@ -0,0 +1,57 @@
+// MP3 bitrate handling, in kbps
+
+pub const DEFAULT_BITRATE: u32 = 128;
+
+// Bitrates users can ask for
+pub const SUPPORTED_BITRATES: [u32; 3] = [128, 256, 320];
+
+// Split a trailing quality suffix such as "@320" off a request line
+pub fn split_bitrate_suffix(line: &str) -> (&str, Option<u32>) {
+    let trimmed = line.trim_end();
+    if let Some((rest, suffix)) = trimmed.rsplit_once('@') {
+        if let Ok(bitrate) = suffix.trim_end_matches("kbps").parse::<u32>() {
+            if SUPPORTED_BITRATES.contains(&bitrate) {
+                return (rest.trim_end(), Some(bitrate));
+            }
+        }
+    }
+    (line, None)
+}
+
+// Pick the bitrate to download from what a provider offers: the requested one
+// if available, otherwise the best lower one, otherwise the lowest higher one
+pub fn choose_bitrate(requested: u32, available: &[u32]) -> Option<u32> {
+    if available.contains(&requested) {
+        return Some(requested);
+    }
+
+    let best_lower = available.iter().filter(|&&b| b < requested).max();
+    let lowest_higher = available.iter().filter(|&&b| b > requested).min();
+    best_lower.or(lowest_higher).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_bitrate_suffix() {
+        assert_eq!(
+            split_bitrate_suffix("Daft Punk - One More Time @320"),
+            ("Daft Punk - One More Time", Some(320))
+        );
+        assert_eq!(split_bitrate_suffix("Song @256kbps "), ("Song", Some(256)));
+        assert_eq!(split_bitrate_suffix("Song @999"), ("Song @999", None));
+        assert_eq!(split_bitrate_suffix("me@home"), ("me@home", None));
+        assert_eq!(split_bitrate_suffix("Plain title"), ("Plain title", None));
+    }
+
+    #[test]
+    fn chooses_closest_available_bitrate() {
+        assert_eq!(choose_bitrate(320, &[128, 256, 320]), Some(320));
+        assert_eq!(choose_bitrate(320, &[128, 256]), Some(256));
+        assert_eq!(choose_bitrate(128, &[256, 320]), Some(256));
+        assert_eq!(choose_bitrate(256, &[64, 128]), Some(128));
+        assert_eq!(choose_bitrate(128, &[]), None);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{config::Config, DynError};
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Mutex;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// Keeps track of how many songs each chat converted on a given day
+#[async_trait]
+pub trait QuotaStore: Send + Sync {
+    // Add `songs` to the chat's usage for `day` unless that would go over
+    // `limit`. Returns the usage before the request
+    async fn try_consume(
+        &self,
+        chat_id: i64,
+        day: u64,
+        songs: u32,
+        limit: u32,
+    ) -> Result<Result<u32, u32>, DynError>;
+}
+
+#[derive(Default)]
+pub struct InMemoryQuotaStore {
+    usage: Mutex<HashMap<(i64, u64), u32>>,
+}
+
+#[async_trait]
+impl QuotaStore for InMemoryQuotaStore {
+    async fn try_consume(
+        &self,
+        chat_id: i64,
+        day: u64,
+        songs: u32,
+        limit: u32,
+    ) -> Result<Result<u32, u32>, DynError> {
+        let mut usage = self.usage.lock().await;
+        // Yesterday's counters are no longer needed
+        usage.retain(|(_, counter_day), _| *counter_day >= day);
+
+        let used = usage.entry((chat_id, day)).or_insert(0);
+        if *used + songs > limit {
+            return Ok(Err(*used));
+        }
+        let before = *used;
+        *used += songs;
+        Ok(Ok(before))
+    }
+}
+
+// Shares the counters between consumer instances
+pub struct RedisQuotaStore {
+    client: redis::Client,
+}
+
+impl RedisQuotaStore {
+    pub fn new(redis_url: &str) -> Result<Self, DynError> {
+        Ok(Self {
+            client: redis::Client::open(redis_url)?,
+        })
+    }
+}
+
+#[async_trait]
+impl QuotaStore for RedisQuotaStore {
+    async fn try_consume(
+        &self,
+        chat_id: i64,
+        day: u64,
+        songs: u32,
+        limit: u32,
+    ) -> Result<Result<u32, u32>, DynError> {
+        let mut connection = self.client.get_multiplexed_async_connection().await?;
+        let key = format!("quota:{}:{}", chat_id, day);
+
+        let (used,): (u32,) = redis::pipe()
+            .atomic()
+            .cmd("INCRBY")
+            .arg(&key)
+            .arg(songs)
+            .cmd("EXPIRE")
+            .arg(&key)
+            .arg(2 * SECONDS_PER_DAY)
+            .ignore()
+            .query_async(&mut connection)
+            .await?;
+
+        if used > limit {
+            // Give back what this request took, it is not going to run
+            redis::cmd("DECRBY")
+                .arg(&key)
+                .arg(songs)
+                .query_async::<_, ()>(&mut connection)
+                .await?;
+            return Ok(Err(used - songs));
+        }
+        Ok(Ok(used - songs))
+    }
+}
+
+pub enum QuotaCheck {
+    Allowed,
+    Exceeded { remaining: u32, resets_in: Duration },
+}
+
+// Daily per-chat limit on converted songs, protecting the shared Google API key
+pub struct Quota {
+    store: Box<dyn QuotaStore>,
+    daily_limit: u32,
+}
+
+impl Quota {
+    pub fn new(store: Box<dyn QuotaStore>, daily_limit: u32) -> Self {
+        Self { store, daily_limit }
+    }
+
+    // Counters are kept in Redis when redis_url is set, in memory otherwise
+    pub fn from_config(config: &Config) -> Result<Self, DynError> {
+        let daily_limit = config.daily_song_quota;
+        let store: Box<dyn QuotaStore> = match &config.redis_url {
+            Some(redis_url) => Box::new(RedisQuotaStore::new(redis_url)?),
+            None => Box::new(InMemoryQuotaStore::default()),
+        };
+
+        log::info!("Daily song quota per chat: {}", daily_limit);
+        Ok(Self::new(store, daily_limit))
+    }
+
+    pub async fn check(&self, chat_id: i64, songs: usize) -> Result<QuotaCheck, DynError> {
+        let songs = u32::try_from(songs).unwrap_or(u32::MAX);
+        let (day, resets_in) = current_day();
+
+        match self
+            .store
+            .try_consume(chat_id, day, songs, self.daily_limit)
+            .await?
+        {
+            Ok(_) => Ok(QuotaCheck::Allowed),
+            Err(used) => Ok(QuotaCheck::Exceeded {
+                remaining: self.daily_limit.saturating_sub(used),
+                resets_in,
+            }),
+        }
+    }
+}
+
+// Days since the epoch in UTC, and the time left until the next one starts
+fn current_day() -> (u64, Duration) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let day = now / SECONDS_PER_DAY;
+    let resets_in = Duration::from_secs((day + 1) * SECONDS_PER_DAY - now);
+    (day, resets_in)
+}
+
+// Human-readable time until the quota resets, e.g. "5h 12m"
+pub fn format_reset(resets_in: Duration) -> String {
+    let minutes = resets_in.as_secs().div_ceil(60);
+    format!("{}h {}m", minutes / 60, minutes % 60)
+}
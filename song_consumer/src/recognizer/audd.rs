@@ -0,0 +1,51 @@
+use super::SongRecognizer;
+use crate::{error::SongError, http::Client};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rustin_models::audd::AuddResponse;
+
+const API_URL: &str = "https://api.audd.io/";
+
+// Music recognition through the AudD API, which also matches humming
+pub struct AuddRecognizer {
+    client: Client,
+    api_token: String,
+}
+
+impl AuddRecognizer {
+    pub fn new(client: Client, api_token: String) -> Self {
+        Self { client, api_token }
+    }
+}
+
+#[async_trait]
+impl SongRecognizer for AuddRecognizer {
+    fn name(&self) -> &'static str {
+        "AudD"
+    }
+
+    async fn recognize(&self, audio: &[u8]) -> Result<Option<String>, SongError> {
+        let audio = STANDARD.encode(audio);
+        let response: AuddResponse = self
+            .client
+            .post(API_URL)
+            .form(&[("api_token", self.api_token.as_str()), ("audio", &audio)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if response.status != "success" {
+            let message = response
+                .error
+                .map(|e| format!("{} ({})", e.error_message, e.error_code))
+                .unwrap_or(response.status);
+            return Err(SongError::Recognition(message));
+        }
+
+        Ok(response
+            .result
+            .map(|song| format!("{} - {}", song.artist, song.title)))
+    }
+}
@@ -0,0 +1,69 @@
+use crate::{
+    config::Config,
+    error::SongError,
+    ffmpeg,
+    http::{self, Client},
+};
+use async_trait::async_trait;
+
+mod audd;
+
+pub use audd::AuddRecognizer;
+
+// Identifies the song playing (or being hummed) in a recording
+#[async_trait]
+pub trait SongRecognizer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Return the song as an "Artist - Title" line, or None if nothing matched
+    async fn recognize(&self, audio: &[u8]) -> Result<Option<String>, SongError>;
+}
+
+// Voice messages are only supported when an AudD token is configured
+pub fn from_config(config: &Config) -> Option<Box<dyn SongRecognizer>> {
+    let api_token = config.audd_api_token.clone()?;
+    Some(Box::new(AuddRecognizer::new(http::client(), api_token)))
+}
+
+// What kind of recording a song should be recognized in
+#[derive(Clone, Copy, Debug)]
+pub enum Recording {
+    // Short voice note, sent to the recognizer as is
+    Voice,
+    // Forwarded audio file or video note, only a sample of which is sent
+    Media,
+}
+
+// Download a recording and identify the song in it
+pub async fn identify(
+    client: &Client,
+    recognizer: &dyn SongRecognizer,
+    file_url: &str,
+    recording: Recording,
+) -> Result<String, SongError> {
+    log::info!("Downloading {:?} recording for recognition", recording);
+    let media = client
+        .get(file_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    let audio = match recording {
+        Recording::Voice => media.to_vec(),
+        Recording::Media => ffmpeg::extract_sample(&media).await?,
+    };
+
+    let song = recognizer
+        .recognize(&audio)
+        .await?
+        .ok_or(SongError::NotRecognized)?;
+    log::info!(
+        "{} recognized the {:?} recording as '{}'",
+        recognizer.name(),
+        recording,
+        song
+    );
+    Ok(song)
+}
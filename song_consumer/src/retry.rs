@@ -0,0 +1,115 @@
+use crate::{topology, DynError};
+use lapin::{
+    message::Delivery,
+    options::{BasicAckOptions, BasicNackOptions, BasicPublishOptions},
+    types::{AMQPValue, ShortString},
+    Channel,
+};
+
+// Header counting how many times a message has already been retried
+const RETRY_HEADER: &str = "x-retry-count";
+// Header with the reason a message was moved to the invalid queue
+const INVALID_REASON_HEADER: &str = "x-invalid-reason";
+
+// Number of times this delivery has been retried so far
+pub fn retry_count(delivery: &Delivery) -> i64 {
+    delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(&ShortString::from(RETRY_HEADER)))
+        .and_then(|value| value.as_long_long_int())
+        .unwrap_or(0)
+}
+
+// Requeue a failed delivery with an incremented retry counter, or reject it
+// into the dead-letter queue once it has used up its retries
+pub async fn handle_failure(
+    channel: &Channel,
+    delivery: &Delivery,
+    max_retries: i64,
+) -> Result<(), DynError> {
+    let retries = retry_count(delivery);
+
+    if retries >= max_retries {
+        log::warn!(
+            "Message failed after {} retries, moving it to '{}'",
+            retries,
+            topology::queues().dead_letter_queue
+        );
+        delivery
+            .nack(BasicNackOptions {
+                requeue: false,
+                ..Default::default()
+            })
+            .await?;
+        return Ok(());
+    }
+
+    // A plain requeue can't change the headers, so publish a copy with the
+    // bumped counter and ack the original
+    let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+    headers.insert(RETRY_HEADER.into(), AMQPValue::LongLongInt(retries + 1));
+
+    // Only ack the original once the broker has confirmed the copy
+    let confirmation = channel
+        .basic_publish(
+            "",
+            // Back to the queue it came from, which for the default exchange
+            // is its routing key
+            delivery.routing_key.as_str(),
+            BasicPublishOptions::default(),
+            &delivery.data,
+            delivery
+                .properties
+                .clone()
+                .with_headers(headers)
+                .with_delivery_mode(2),
+        )
+        .await?
+        .await?;
+    if confirmation.is_nack() {
+        return Err("retry copy was not confirmed by RabbitMQ".into());
+    }
+    delivery.ack(BasicAckOptions::default()).await?;
+
+    log::info!("Requeued message for retry {}/{}", retries + 1, max_retries);
+    Ok(())
+}
+
+// Move a delivery that can't be handled at all, e.g. one that isn't valid
+// JSON, to the invalid queue. Retrying it would fail the same way every time
+pub async fn move_to_invalid(
+    channel: &Channel,
+    delivery: &Delivery,
+    reason: &str,
+) -> Result<(), DynError> {
+    let queue = &topology::queues().invalid;
+    let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+    headers.insert(
+        INVALID_REASON_HEADER.into(),
+        AMQPValue::LongString(reason.into()),
+    );
+
+    let confirmation = channel
+        .basic_publish(
+            "",
+            queue,
+            BasicPublishOptions::default(),
+            &delivery.data,
+            delivery
+                .properties
+                .clone()
+                .with_headers(headers)
+                .with_delivery_mode(2),
+        )
+        .await?
+        .await?;
+    if confirmation.is_nack() {
+        return Err("invalid message copy was not confirmed by RabbitMQ".into());
+    }
+    delivery.ack(BasicAckOptions::default()).await?;
+
+    log::info!("Moved invalid message to '{}'", queue);
+    Ok(())
+}
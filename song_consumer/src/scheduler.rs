@@ -0,0 +1,58 @@
+use rustin_storage::Storage;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio_util::sync::CancellationToken;
+
+// How often held replies are checked for being due. /later takes minutes at
+// the finest, so this is plenty
+const RELEASE_INTERVAL: Duration = Duration::from_secs(15);
+
+// Hold the replies of a request asked for with /later. Without storage, or
+// when it can't be reached, the results are sent as soon as they're ready
+pub async fn schedule(storage: Option<&Storage>, request_id: &str, chat_id: i64, deliver_at: u64) {
+    let Some(storage) = storage else {
+        log::warn!(
+            "Request {} is scheduled, but without storage it's delivered right away",
+            request_id
+        );
+        return;
+    };
+    let deliver_at = UNIX_EPOCH + Duration::from_secs(deliver_at);
+    if let Err(e) = storage
+        .schedule_delivery(request_id, chat_id, deliver_at)
+        .await
+    {
+        log::warn!(
+            "Failed to schedule the delivery of request {}: {}",
+            request_id,
+            e
+        );
+    }
+}
+
+// Hand the replies of due deliveries to the outbox drainer until shutdown.
+// Every replica runs this, the release itself is a single statement
+pub async fn release_due_replies(storage: Arc<Storage>, shutdown: CancellationToken) {
+    loop {
+        match storage.release_due_replies().await {
+            Ok(0) => {}
+            Ok(count) => log::info!("Released {} scheduled replies", count),
+            Err(e) => log::error!("Failed to release scheduled replies: {}", e),
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = tokio::time::sleep(RELEASE_INTERVAL) => {}
+        }
+    }
+}
+
+// Whether a request's results are still to be held back
+pub fn is_scheduled(deliver_at: Option<u64>) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    deliver_at.is_some_and(|deliver_at| deliver_at > now)
+}
@@ -0,0 +1,62 @@
+use super::SearchProvider;
+use crate::{
+    error::SongError,
+    http::{self, Client},
+    youtube::FoundVideo,
+};
+use async_trait::async_trait;
+use rustin_models::invidious::InvidiousResult;
+use std::time::Duration;
+use urlencoding::encode;
+
+// Searches through the API of an Invidious instance, which needs no key
+pub struct InvidiousSearch {
+    client: Client,
+    base_url: String,
+}
+
+impl InvidiousSearch {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            client: http::client(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl SearchProvider for InvidiousSearch {
+    fn name(&self) -> &'static str {
+        "invidious"
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<FoundVideo>, SongError> {
+        let url = format!(
+            "{}/api/v1/search?type=video&q={}",
+            self.base_url,
+            encode(query)
+        );
+        log::info!("Searching Invidious with query: {}", query);
+        let results: Vec<InvidiousResult> = self
+            .client
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(results
+            .into_iter()
+            .filter(|result| result.kind == "video" && !result.video_id.is_empty())
+            .take(limit)
+            .map(|result| FoundVideo {
+                video_id: result.video_id,
+                title: result.title,
+                channel: result.author,
+                duration: result.length_seconds.map(Duration::from_secs),
+                live: result.live_now || result.is_upcoming,
+            })
+            .collect())
+    }
+}
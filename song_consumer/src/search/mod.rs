@@ -0,0 +1,271 @@
+use crate::{
+    api_budget::ApiBudget,
+    config::Config,
+    error::SongError,
+    youtube::{self, FoundVideo},
+};
+use async_trait::async_trait;
+use rustin_models::SearchCandidate;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+mod invidious;
+mod piped;
+mod youtube_api;
+
+pub use invidious::InvidiousSearch;
+pub use piped::PipedSearch;
+pub use youtube_api::YouTubeSearch;
+
+// Number of search results ranked when picking the best match for a title
+const RANKED_RESULTS: usize = 10;
+
+// A failing provider is left alone for this long, doubling with every
+// further failure in a row
+const MIN_COOLDOWN: Duration = Duration::from_secs(30);
+const MAX_COOLDOWN: Duration = Duration::from_secs(30 * 60);
+// The Data API quota resets once a day, checking back hourly is plenty
+const QUOTA_COOLDOWN: Duration = Duration::from_secs(60 * 60);
+
+#[async_trait]
+pub trait SearchProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Search for videos, most relevant first
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<FoundVideo>, SongError>;
+}
+
+// Whether a provider is worth asking, based on how it fared recently
+#[derive(Default)]
+struct Health {
+    failures: u32,
+    unavailable_until: Option<Instant>,
+}
+
+impl Health {
+    fn is_available(&self, now: Instant) -> bool {
+        self.unavailable_until.is_none_or(|until| now >= until)
+    }
+
+    fn record_success(&mut self) {
+        *self = Self::default();
+    }
+
+    fn record_failure(&mut self, now: Instant, error: &SongError) {
+        self.failures += 1;
+        let cooldown = match error {
+            SongError::QuotaExceeded => QUOTA_COOLDOWN,
+            _ => MIN_COOLDOWN
+                .saturating_mul(2u32.saturating_pow(self.failures - 1))
+                .min(MAX_COOLDOWN),
+        };
+        self.unavailable_until = Some(now + cooldown);
+    }
+}
+
+struct TrackedProvider {
+    provider: Box<dyn SearchProvider>,
+    health: Mutex<Health>,
+}
+
+impl TrackedProvider {
+    fn health(&self) -> std::sync::MutexGuard<'_, Health> {
+        self.health
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Searches with the first healthy provider, failing over to the next one
+pub struct SearchChain {
+    providers: Vec<TrackedProvider>,
+}
+
+impl SearchChain {
+    pub fn new(providers: Vec<Box<dyn SearchProvider>>) -> Self {
+        Self {
+            providers: providers
+                .into_iter()
+                .map(|provider| TrackedProvider {
+                    provider,
+                    health: Mutex::new(Health::default()),
+                })
+                .collect(),
+        }
+    }
+
+    // The Data API first, then the configured Invidious and Piped instances
+    pub fn from_config(config: &Config, budget: Option<Arc<ApiBudget>>) -> Self {
+        let mut providers: Vec<Box<dyn SearchProvider>> = vec![Box::new(YouTubeSearch::new(
+            config.google_vision_api_key.clone(),
+            budget,
+        ))];
+        if let Some(url) = &config.invidious_url {
+            providers.push(Box::new(InvidiousSearch::new(url)));
+        }
+        if let Some(url) = &config.piped_url {
+            providers.push(Box::new(PipedSearch::new(url)));
+        }
+
+        log::info!(
+            "Using search providers: {}",
+            providers
+                .iter()
+                .map(|provider| provider.name())
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        );
+        Self::new(providers)
+    }
+
+    // Search for the video that most likely is the song itself, rather than
+    // simply taking the most relevant result
+    pub async fn best_match(&self, query: &str) -> Result<Option<String>, SongError> {
+        let videos = self.search(query, RANKED_RESULTS).await?;
+        Ok(youtube::best_match(&videos).map(|video| video.video_id.clone()))
+    }
+
+    // The next best matches after a video that couldn't be converted
+    pub async fn alternatives(
+        &self,
+        query: &str,
+        failed_video_id: &str,
+        limit: usize,
+    ) -> Result<Vec<FoundVideo>, SongError> {
+        let videos = self.search(query, RANKED_RESULTS).await?;
+        Ok(youtube::rank(videos)
+            .into_iter()
+            .filter(|video| video.video_id != failed_video_id)
+            .take(limit)
+            .collect())
+    }
+
+    // Find the top search results for a query to offer in the picker
+    pub async fn candidates(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<SearchCandidate>, SongError> {
+        let videos = self.search(query, limit).await?;
+        Ok(videos
+            .into_iter()
+            .map(|video| SearchCandidate {
+                video_id: video.video_id,
+                title: video.title,
+                channel: video.channel,
+                duration: video.duration.map(youtube::format_duration),
+            })
+            .collect())
+    }
+
+    // Search for several titles of one artist in a single call, using the OR
+    // operator of the Data API. The results still have to be matched to the
+    // titles
+    pub async fn batch(&self, artist: &str, titles: &[&str]) -> Result<Vec<FoundVideo>, SongError> {
+        let query = format!("{} ({})", artist, titles.join("|"));
+        self.search(&query, RANKED_RESULTS * titles.len()).await
+    }
+
+    // Healthy providers are tried in order. Only when all of them fail are
+    // the ones cooling down given another chance
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<FoundVideo>, SongError> {
+        let now = Instant::now();
+        let (healthy, cooling_down): (Vec<_>, Vec<_>) = self
+            .providers
+            .iter()
+            .partition(|tracked| tracked.health().is_available(now));
+
+        let mut last_error = None;
+        for tracked in healthy.into_iter().chain(cooling_down) {
+            let name = tracked.provider.name();
+            match tracked.provider.search(query, limit).await {
+                Ok(videos) => {
+                    tracked.health().record_success();
+                    return skip_live(videos);
+                }
+                Err(e) => {
+                    log::warn!("Search provider {} failed for '{}': {}", name, query, e);
+                    tracked.health().record_failure(Instant::now(), &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| SongError::Conversion("No search provider configured".to_string())))
+    }
+}
+
+// Live streams and premieres fail once they get to the converter, so they
+// aren't offered at all. When nothing else was found the user is told why
+fn skip_live(videos: Vec<FoundVideo>) -> Result<Vec<FoundVideo>, SongError> {
+    let found_any = !videos.is_empty();
+    let videos: Vec<_> = videos.into_iter().filter(|video| !video.live).collect();
+    if found_any && videos.is_empty() {
+        return Err(SongError::LiveStream);
+    }
+    Ok(videos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_longer_after_every_failure() {
+        let now = Instant::now();
+        let mut health = Health::default();
+        assert!(health.is_available(now));
+
+        let error = SongError::Conversion("down".to_string());
+        health.record_failure(now, &error);
+        assert!(!health.is_available(now + MIN_COOLDOWN / 2));
+        assert!(health.is_available(now + MIN_COOLDOWN));
+
+        health.record_failure(now, &error);
+        assert!(!health.is_available(now + MIN_COOLDOWN));
+        assert!(health.is_available(now + MIN_COOLDOWN * 2));
+
+        for _ in 0..20 {
+            health.record_failure(now, &error);
+        }
+        assert!(health.is_available(now + MAX_COOLDOWN));
+    }
+
+    fn video(video_id: &str, live: bool) -> FoundVideo {
+        FoundVideo {
+            video_id: video_id.to_string(),
+            title: video_id.to_string(),
+            channel: "Channel".to_string(),
+            duration: None,
+            live,
+        }
+    }
+
+    #[test]
+    fn skips_live_streams() {
+        let videos = skip_live(vec![video("live", true), video("song", false)]).unwrap();
+        assert_eq!(videos.len(), 1);
+        assert_eq!(videos[0].video_id, "song");
+
+        assert!(skip_live(Vec::new()).unwrap().is_empty());
+        assert!(matches!(
+            skip_live(vec![video("live", true)]),
+            Err(SongError::LiveStream)
+        ));
+    }
+
+    #[test]
+    fn waits_for_the_quota_and_recovers_on_success() {
+        let now = Instant::now();
+        let mut health = Health::default();
+        health.record_failure(now, &SongError::QuotaExceeded);
+        assert!(!health.is_available(now + MAX_COOLDOWN));
+        assert!(health.is_available(now + QUOTA_COOLDOWN));
+
+        health.record_success();
+        assert!(health.is_available(now));
+    }
+}
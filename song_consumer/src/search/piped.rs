@@ -0,0 +1,67 @@
+use super::SearchProvider;
+use crate::{
+    error::SongError,
+    http::{self, Client},
+    url_parser,
+    youtube::FoundVideo,
+};
+use async_trait::async_trait;
+use rustin_models::piped::PipedSearchResponse;
+use std::time::Duration;
+use urlencoding::encode;
+
+// Searches through the API of a Piped instance, which needs no key
+pub struct PipedSearch {
+    client: Client,
+    base_url: String,
+}
+
+impl PipedSearch {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            client: http::client(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl SearchProvider for PipedSearch {
+    fn name(&self) -> &'static str {
+        "piped"
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<FoundVideo>, SongError> {
+        let url = format!("{}/search?filter=videos&q={}", self.base_url, encode(query));
+        log::info!("Searching Piped with query: {}", query);
+        let response: PipedSearchResponse = self
+            .client
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response
+            .items
+            .into_iter()
+            .filter(|item| item.kind == "stream")
+            .filter_map(|item| {
+                let video_id =
+                    url_parser::parse_video_id(&format!("https://www.youtube.com{}", item.url))?;
+                Some(FoundVideo {
+                    video_id,
+                    title: item.title,
+                    channel: item.uploader_name,
+                    duration: item
+                        .duration
+                        .and_then(|seconds| u64::try_from(seconds).ok())
+                        .map(Duration::from_secs),
+                    live: item.duration.is_some_and(|seconds| seconds < 0),
+                })
+            })
+            .take(limit)
+            .collect())
+    }
+}
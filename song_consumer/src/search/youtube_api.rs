@@ -0,0 +1,56 @@
+use super::SearchProvider;
+use crate::{
+    api_budget::{self, ApiBudget},
+    error::SongError,
+    http::{self, Client},
+    youtube::{self, FoundVideo},
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+// The YouTube Data API, which costs quota on every search
+pub struct YouTubeSearch {
+    client: Client,
+    api_key: String,
+    budget: Option<Arc<ApiBudget>>,
+}
+
+impl YouTubeSearch {
+    pub fn new(api_key: String, budget: Option<Arc<ApiBudget>>) -> Self {
+        Self {
+            client: http::client(),
+            api_key,
+            budget,
+        }
+    }
+}
+
+#[async_trait]
+impl SearchProvider for YouTubeSearch {
+    fn name(&self) -> &'static str {
+        "youtube"
+    }
+
+    // Once the budget is spent this fails like Google would, which moves
+    // searches to the fallback providers
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<FoundVideo>, SongError> {
+        let Some(budget) = &self.budget else {
+            return youtube::search_videos(&self.client, &self.api_key, query, limit).await;
+        };
+        if !budget.allows(api_budget::SEARCH_UNITS).await {
+            log::info!(
+                "YouTube quota budget is spent, not searching for '{}'",
+                query
+            );
+            return Err(SongError::QuotaExceeded);
+        }
+
+        let result = youtube::search_videos(&self.client, &self.api_key, query, limit).await;
+        match &result {
+            Err(SongError::QuotaExceeded) => budget.exhaust().await,
+            // Failed calls are charged as well
+            _ => budget.spend(api_budget::SEARCH_UNITS).await,
+        }
+        result
+    }
+}
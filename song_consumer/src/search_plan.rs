@@ -0,0 +1,299 @@
+// Every song line costs a search call of its own, which is most of the
+// YouTube quota a request spends. Before the songs of a request start
+// converting, the planner answers what it can from the search cache, searches
+// repeated lines once and looks up songs of the same artist in one call. What
+// it finds goes into the search cache, where the songs pick it up
+
+use crate::{
+    cache::{self, Cache},
+    downloader, metrics,
+    search::SearchChain,
+    youtube::{self, FoundVideo},
+};
+use std::collections::HashMap;
+
+// Titles looked up in one call. The search endpoint returns at most 50 results
+const BATCH_SIZE: usize = 5;
+
+// Titles of one artist that are searched together
+#[derive(Debug, PartialEq)]
+struct Batch {
+    artist: String,
+    songs: Vec<String>,
+}
+
+// The searches of a request's uncached lines that can be saved on
+#[derive(Debug, Default, PartialEq)]
+struct SearchPlan {
+    batches: Vec<Batch>,
+    // Titles on more than one line, searched once up front
+    repeated: Vec<String>,
+}
+
+// Group the lines by title and artist. Artists with a single title, and
+// titles without an artist, are left to the songs unless they repeat
+fn plan(lines: &[&str]) -> SearchPlan {
+    let mut titles: Vec<(&str, usize)> = Vec::new();
+    let mut positions = HashMap::new();
+    for &line in lines {
+        let position = *positions.entry(cache::search_key(line)).or_insert_with(|| {
+            titles.push((line, 0));
+            titles.len() - 1
+        });
+        titles[position].1 += 1;
+    }
+
+    let mut by_artist: Vec<(String, Vec<&str>)> = Vec::new();
+    for &(title, _) in &titles {
+        let (Some(artist), _) = downloader::split_artist_title(title) else {
+            continue;
+        };
+        let key = artist.to_lowercase();
+        match by_artist.iter_mut().find(|(artist, _)| *artist == key) {
+            Some((_, songs)) => songs.push(title),
+            None => by_artist.push((key, vec![title])),
+        }
+    }
+
+    let mut plan = SearchPlan::default();
+    let mut batched = Vec::new();
+    for (_, songs) in by_artist {
+        for chunk in songs.chunks(BATCH_SIZE).filter(|chunk| chunk.len() > 1) {
+            let (artist, _) = downloader::split_artist_title(chunk[0]);
+            plan.batches.push(Batch {
+                artist: artist.unwrap_or_default(),
+                songs: chunk.iter().map(|song| song.to_string()).collect(),
+            });
+            batched.extend_from_slice(chunk);
+        }
+    }
+    plan.repeated = titles
+        .into_iter()
+        .filter(|(title, count)| *count > 1 && !batched.contains(title))
+        .map(|(title, _)| title.to_string())
+        .collect();
+    plan
+}
+
+// The video of each song of the batch, if one of the results is named after
+// it. Songs without one are searched on their own
+fn match_batch<'a>(batch: &'a Batch, videos: &[FoundVideo]) -> Vec<(&'a str, String)> {
+    batch
+        .songs
+        .iter()
+        .filter_map(|song| {
+            let (_, title) = downloader::split_artist_title(song);
+            let title = words(&title);
+            let named: Vec<FoundVideo> = videos
+                .iter()
+                .filter(|video| song_title(&video.title) == title)
+                .cloned()
+                .collect();
+            youtube::best_match(&named).map(|video| (song.as_str(), video.video_id.clone()))
+        })
+        .collect()
+}
+
+// The song a video is named after, without its artist and bracketed extras
+// such as "(Official Video)"
+fn song_title(video_title: &str) -> String {
+    let mut title = String::new();
+    let mut depth = 0;
+    for c in video_title.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = (depth - 1).max(0),
+            _ if depth == 0 => title.push(c),
+            _ => {}
+        }
+    }
+    words(&downloader::split_artist_title(&title).1)
+}
+
+// Lower case words, so "Don't  Stop Me Now!" is "don't stop me now"
+fn words(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Fill the search cache for the lines of a request. Failures only cost the
+// saving, the songs search on their own
+pub async fn warm_cache(cache: &dyn Cache, search: &SearchChain, lines: &[&str]) {
+    let mut found: HashMap<String, bool> = HashMap::new();
+    let mut uncached = Vec::new();
+    for &line in lines {
+        let key = cache::search_key(line);
+        let hit = match found.get(&key) {
+            Some(&hit) => hit,
+            None => {
+                let hit = cache::get_or_log(cache, &key).await.is_some();
+                found.insert(key, hit);
+                hit
+            }
+        };
+        metrics::record_search_lookup(hit);
+        if !hit {
+            uncached.push(line);
+        }
+    }
+
+    let plan = plan(&uncached);
+    if plan.batches.is_empty() && plan.repeated.is_empty() {
+        return;
+    }
+    let mut calls = 0;
+    for batch in &plan.batches {
+        let titles: Vec<String> = batch
+            .songs
+            .iter()
+            .map(|song| downloader::split_artist_title(song).1)
+            .collect();
+        let titles: Vec<&str> = titles.iter().map(String::as_str).collect();
+        calls += 1;
+        let videos = match search.batch(&batch.artist, &titles).await {
+            Ok(videos) => videos,
+            Err(e) => {
+                log::warn!("Batched search for {} failed: {}", batch.artist, e);
+                break;
+            }
+        };
+        for (song, video_id) in match_batch(batch, &videos) {
+            cache::set_or_log(
+                cache,
+                &cache::search_key(song),
+                &video_id,
+                cache::SEARCH_TTL,
+            )
+            .await;
+            found.insert(cache::search_key(song), true);
+        }
+    }
+    for song in &plan.repeated {
+        calls += 1;
+        match search.best_match(song).await {
+            Ok(Some(video_id)) => {
+                cache::set_or_log(
+                    cache,
+                    &cache::search_key(song),
+                    &video_id,
+                    cache::SEARCH_TTL,
+                )
+                .await;
+                found.insert(cache::search_key(song), true);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log::warn!("Search for repeated song '{}' failed: {}", song, e);
+                break;
+            }
+        }
+    }
+
+    // Without the plan every uncached line would have cost a call. Lines it
+    // couldn't resolve still do
+    let unresolved = uncached
+        .iter()
+        .filter(|line| !found[&cache::search_key(line)])
+        .count();
+    let saved = uncached.len().saturating_sub(calls + unresolved);
+    log::info!(
+        "Planned searches of {} lines in {} calls, saving {}",
+        uncached.len(),
+        calls,
+        saved
+    );
+    metrics::SEARCH_CALLS_SAVED.inc_by(saved as u64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(id: &str, title: &str) -> FoundVideo {
+        FoundVideo {
+            video_id: id.to_string(),
+            title: title.to_string(),
+            channel: "Queen Official".to_string(),
+            duration: None,
+            live: false,
+        }
+    }
+
+    #[test]
+    fn batches_titles_of_the_same_artist() {
+        let plan = plan(&[
+            "Queen - Bohemian Rhapsody",
+            "Daft Punk - One More Time",
+            "queen - Radio Ga Ga",
+        ]);
+        assert_eq!(
+            plan.batches,
+            vec![Batch {
+                artist: "Queen".to_string(),
+                songs: vec![
+                    "Queen - Bohemian Rhapsody".to_string(),
+                    "queen - Radio Ga Ga".to_string()
+                ],
+            }]
+        );
+        assert!(plan.repeated.is_empty());
+    }
+
+    #[test]
+    fn searches_repeated_lines_once() {
+        let plan = plan(&["Hey Jude", "hey  jude", "Yesterday"]);
+        assert!(plan.batches.is_empty());
+        assert_eq!(plan.repeated, vec!["Hey Jude".to_string()]);
+    }
+
+    #[test]
+    fn leaves_repeated_lines_of_a_batch_to_the_batch() {
+        let plan = plan(&[
+            "Queen - Bohemian Rhapsody",
+            "Queen - Bohemian Rhapsody",
+            "Queen - Radio Ga Ga",
+        ]);
+        assert_eq!(plan.batches.len(), 1);
+        assert_eq!(plan.batches[0].songs.len(), 2);
+        assert!(plan.repeated.is_empty());
+    }
+
+    #[test]
+    fn splits_large_batches() {
+        let lines: Vec<String> = (0..BATCH_SIZE + 2)
+            .map(|n| format!("Queen - Song {}", n))
+            .collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let plan = plan(&lines);
+        let sizes: Vec<usize> = plan.batches.iter().map(|batch| batch.songs.len()).collect();
+        assert_eq!(sizes, vec![BATCH_SIZE, 2]);
+    }
+
+    #[test]
+    fn matches_results_by_title() {
+        let batch = Batch {
+            artist: "Queen".to_string(),
+            songs: vec![
+                "Queen - Don't Stop Me Now".to_string(),
+                "Queen - Love".to_string(),
+            ],
+        };
+        let videos = [
+            video("a", "Queen - Don't Stop Me Now (Official Video)"),
+            video("b", "Queen - Love Of My Life"),
+        ];
+        assert_eq!(
+            match_batch(&batch, &videos),
+            vec![("Queen - Don't Stop Me Now", "a".to_string())]
+        );
+
+        let batch = Batch {
+            artist: "Queen".to_string(),
+            songs: vec!["Queen - Innuendo".to_string()],
+        };
+        assert!(match_batch(&batch, &videos).is_empty());
+    }
+}
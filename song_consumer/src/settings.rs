@@ -0,0 +1,34 @@
+use rustin_models::{MediaFormat, RequestOptions};
+use rustin_storage::Storage;
+
+// Messages that don't go through the bot carry no options, so the chat's
+// /settings are looked up instead. Without storage, or when it can't be
+// reached, the consumer defaults are used
+pub async fn chat_options(storage: Option<&Storage>, chat_id: i64) -> RequestOptions {
+    let Some(storage) = storage else {
+        return RequestOptions::default();
+    };
+    let settings = match storage.chat_settings(chat_id).await {
+        Ok(Some(settings)) => settings,
+        Ok(None) => return RequestOptions::default(),
+        Err(e) => {
+            log::warn!("Failed to load the settings of chat {}: {}", chat_id, e);
+            return RequestOptions::default();
+        }
+    };
+    RequestOptions {
+        bitrate: settings
+            .bitrate
+            .and_then(|bitrate| u32::try_from(bitrate).ok()),
+        stream: settings.stream,
+        zip: settings.zip,
+        normalize: settings.normalize,
+        links: settings.links,
+        language: settings.language,
+        lyrics: settings.lyrics,
+        song_link: settings.song_link,
+        playlist: settings.playlist,
+        deliver_at: None,
+        format: MediaFormat::Mp3,
+    }
+}
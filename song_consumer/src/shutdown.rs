@@ -0,0 +1,26 @@
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+
+// Cancel the returned token on SIGTERM (container stop) or Ctrl+C
+pub fn listen_for_signals() -> CancellationToken {
+    let token = CancellationToken::new();
+    let signal_token = token.clone();
+
+    tokio::spawn(async move {
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                log::error!("Failed to listen for SIGTERM: {}", e);
+                return;
+            }
+        };
+
+        tokio::select! {
+            _ = terminate.recv() => log::info!("Received SIGTERM"),
+            _ = tokio::signal::ctrl_c() => log::info!("Received Ctrl+C"),
+        }
+        signal_token.cancel();
+    });
+
+    token
+}
@@ -0,0 +1,51 @@
+use crate::{error::SongError, http::Client};
+use rustin_models::soundcloud::SoundCloudOEmbed;
+use urlencoding::encode;
+
+// SoundCloud tracks go through the same caches, providers and history as
+// YouTube videos under an ID of this form, soundcloud:<artist>:<track>
+const ID_PREFIX: &str = "soundcloud:";
+
+pub fn track_id(artist: &str, track: &str) -> String {
+    format!("{}{}:{}", ID_PREFIX, artist, track)
+}
+
+// The page of a track ID, or None for YouTube video IDs
+pub fn track_url(id: &str) -> Option<String> {
+    let (artist, track) = id.strip_prefix(ID_PREFIX)?.split_once(':')?;
+    Some(format!("https://soundcloud.com/{}/{}", artist, track))
+}
+
+// Look up the "Artist - Track" title of a track, the way YouTube titles read
+pub async fn fetch_title(client: &Client, track_url: &str) -> Result<String, SongError> {
+    let url = format!(
+        "https://soundcloud.com/oembed?format=json&url={}",
+        encode(track_url)
+    );
+    let oembed: SoundCloudOEmbed = client
+        .get(&url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let suffix = format!(" by {}", oembed.author_name);
+    let track = oembed.title.strip_suffix(&suffix).unwrap_or(&oembed.title);
+    Ok(format!("{} - {}", oembed.author_name, track))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_ids_lead_back_to_the_track() {
+        let id = track_id("daftpunkofficialmusic", "one-more-time");
+        assert_eq!(
+            track_url(&id).as_deref(),
+            Some("https://soundcloud.com/daftpunkofficialmusic/one-more-time")
+        );
+        assert_eq!(track_url("dQw4w9WgXcQ"), None);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::{
+    config::Config,
+    http::{self, Client},
+    DynError,
+};
+use rustin_models::{
+    oauth::TokenResponse,
+    spotify::{AlbumTracksPage, PlaylistTracksPage, Track},
+};
+use serde::de::DeserializeOwned;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const API_URL: &str = "https://api.spotify.com/v1";
+
+#[derive(Debug, PartialEq)]
+pub enum SpotifyLink {
+    Track(String),
+    Album(String),
+    Playlist(String),
+}
+
+// Parse open.spotify.com URLs and spotify: URIs for tracks, albums and playlists
+pub fn parse_link(line: &str) -> Option<SpotifyLink> {
+    let line = line.trim();
+    let path = if let Some(uri) = line.strip_prefix("spotify:") {
+        uri.replace(':', "/")
+    } else {
+        let rest = line
+            .strip_prefix("https://")
+            .or_else(|| line.strip_prefix("http://"))
+            .unwrap_or(line);
+        rest.strip_prefix("open.spotify.com/")?.to_string()
+    };
+
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    let mut kind = segments.next()?;
+    // Localized links look like open.spotify.com/intl-de/track/<id>
+    if kind.starts_with("intl-") {
+        kind = segments.next()?;
+    }
+    let id = segments.next()?.split(['?', '#']).next()?.to_string();
+    if id.is_empty() {
+        return None;
+    }
+
+    match kind {
+        "track" => Some(SpotifyLink::Track(id)),
+        "album" => Some(SpotifyLink::Album(id)),
+        "playlist" => Some(SpotifyLink::Playlist(id)),
+        _ => None,
+    }
+}
+
+// Spotify Web API client using the client credentials flow, which is enough
+// to read public tracks, albums and playlists
+pub struct SpotifyClient {
+    client: Client,
+    client_id: String,
+    client_secret: String,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl SpotifyClient {
+    // Spotify support is only enabled when the client ID and secret are both
+    // configured
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let client_id = config.spotify_client_id.clone()?;
+        let client_secret = config.spotify_client_secret.clone()?;
+        Some(Self {
+            client: http::client(),
+            client_id,
+            client_secret,
+            token: Mutex::new(None),
+        })
+    }
+
+    // Turn a Spotify link into "artist - title" lines
+    pub async fn expand(&self, link: &SpotifyLink) -> Result<Vec<String>, DynError> {
+        let tracks = match link {
+            SpotifyLink::Track(id) => {
+                vec![
+                    self.get::<Track>(&format!("{}/tracks/{}", API_URL, id))
+                        .await?,
+                ]
+            }
+            SpotifyLink::Album(id) => {
+                let mut tracks = Vec::new();
+                let mut next = Some(format!("{}/albums/{}/tracks?limit=50", API_URL, id));
+                while let Some(url) = next {
+                    let page: AlbumTracksPage = self.get(&url).await?;
+                    tracks.extend(page.items);
+                    next = page.next;
+                }
+                tracks
+            }
+            SpotifyLink::Playlist(id) => {
+                let mut tracks = Vec::new();
+                let mut next = Some(format!(
+                    "{}/playlists/{}/tracks?limit=100&fields=items(track(name,artists(name))),next",
+                    API_URL, id
+                ));
+                while let Some(url) = next {
+                    let page: PlaylistTracksPage = self.get(&url).await?;
+                    tracks.extend(page.items.into_iter().filter_map(|item| item.track));
+                    next = page.next;
+                }
+                tracks
+            }
+        };
+
+        log::info!("Expanded {:?} into {} tracks", link, tracks.len());
+        Ok(tracks.iter().map(format_track).collect())
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T, DynError> {
+        let token = self.access_token().await?;
+        Ok(self
+            .client
+            .get(url)
+            .bearer_auth(token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    // Reuse the access token until shortly before it expires
+    async fn access_token(&self) -> Result<String, DynError> {
+        let mut token = self.token.lock().await;
+        if let Some((access_token, expires_at)) = token.as_ref() {
+            if Instant::now() < *expires_at {
+                return Ok(access_token.clone());
+            }
+        }
+
+        let response: TokenResponse = self
+            .client
+            .post("https://accounts.spotify.com/api/token")
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&[("grant_type", "client_credentials")])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let expires_at =
+            Instant::now() + Duration::from_secs(response.expires_in.saturating_sub(60));
+        *token = Some((response.access_token.clone(), expires_at));
+        Ok(response.access_token)
+    }
+}
+
+fn format_track(track: &Track) -> String {
+    let artists: Vec<&str> = track.artists.iter().map(|a| a.name.as_str()).collect();
+    if artists.is_empty() {
+        track.name.clone()
+    } else {
+        format!("{} - {}", artists.join(", "), track.name)
+    }
+}
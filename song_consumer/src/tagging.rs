@@ -0,0 +1,127 @@
+use crate::{bandcamp, http::Client, soundcloud, DynError};
+use id3::{
+    frame::{Picture, PictureType},
+    Tag, TagLike, Version,
+};
+use std::path::Path;
+
+// Thumbnail every YouTube video has, used as the album art
+pub fn thumbnail_url(video_id: &str) -> String {
+    format!("https://i.ytimg.com/vi/{}/hqdefault.jpg", video_id)
+}
+
+// Download the thumbnail to use as album art. Songs are still sent without
+// one if it can't be fetched
+pub async fn fetch_cover(client: &Client, video_id: &str) -> Option<Vec<u8>> {
+    // Only YouTube thumbnails can be built from the ID
+    if soundcloud::track_url(video_id).is_some() || bandcamp::track_url(video_id).is_some() {
+        return None;
+    }
+    match download_cover(client, video_id).await {
+        Ok(cover) => Some(cover),
+        Err(e) => {
+            log::warn!(
+                "Failed to fetch the thumbnail of video ID {}: {}",
+                video_id,
+                e
+            );
+            None
+        }
+    }
+}
+
+async fn download_cover(client: &Client, video_id: &str) -> Result<Vec<u8>, DynError> {
+    let bytes = client
+        .get(thumbnail_url(video_id))
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    Ok(bytes.to_vec())
+}
+
+// Write the title, artist and album art into a downloaded MP3 so players show
+// proper metadata
+pub async fn tag_mp3(
+    file_path: &Path,
+    title: &str,
+    artist: Option<&str>,
+    cover: Option<Vec<u8>>,
+) -> Result<(), DynError> {
+    let tag = build_tag(title, artist, cover);
+    let path = file_path.to_path_buf();
+    tokio::task::spawn_blocking(move || write_tag(&tag, &path)).await??;
+    log::info!("Tagged {}", file_path.display());
+    Ok(())
+}
+
+pub fn build_tag(title: &str, artist: Option<&str>, cover: Option<Vec<u8>>) -> Tag {
+    let mut tag = Tag::new();
+    tag.set_title(title);
+    if let Some(artist) = artist {
+        tag.set_artist(artist);
+    }
+    if let Some(data) = cover {
+        tag.add_frame(Picture {
+            mime_type: "image/jpeg".to_string(),
+            picture_type: PictureType::CoverFront,
+            description: String::new(),
+            data,
+        });
+    }
+    tag
+}
+
+fn write_tag(tag: &Tag, file_path: &Path) -> Result<(), DynError> {
+    tag.write_to_path(file_path, Version::Id3v24)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // An empty file stands in for the MP3, the tag is written in front of it
+    fn temp_mp3(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.mp3", name, std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        path
+    }
+
+    #[test]
+    fn writes_title_and_artist() {
+        let path = temp_mp3("tagging-text");
+        write_tag(&build_tag("Yesterday", Some("The Beatles"), None), &path).unwrap();
+
+        let tag = Tag::read_from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(tag.title(), Some("Yesterday"));
+        assert_eq!(tag.artist(), Some("The Beatles"));
+        assert_eq!(tag.pictures().count(), 0);
+    }
+
+    #[test]
+    fn embeds_cover_art() {
+        let path = temp_mp3("tagging-cover");
+        let cover = vec![0xFF, 0xD8, 0xFF, 0xE0];
+        write_tag(&build_tag("Song", None, Some(cover.clone())), &path).unwrap();
+
+        let tag = Tag::read_from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(tag.artist(), None);
+        let picture = tag.pictures().next().unwrap();
+        assert_eq!(picture.picture_type, PictureType::CoverFront);
+        assert_eq!(picture.mime_type, "image/jpeg");
+        assert_eq!(picture.data, cover);
+    }
+
+    #[test]
+    fn builds_thumbnail_url() {
+        assert_eq!(
+            thumbnail_url("dQw4w9WgXcQ"),
+            "https://i.ytimg.com/vi/dQw4w9WgXcQ/hqdefault.jpg"
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use crate::DynError;
+use image::codecs::jpeg::JpegEncoder;
+use std::path::{Path, PathBuf};
+
+// Telegram only shows audio thumbnails that are JPEGs of at most 320x320
+// pixels and under 200 kB
+pub const MAX_DIMENSION: u32 = 320;
+const JPEG_QUALITY: u8 = 85;
+
+// Scale the cover art down to a JPEG Telegram accepts as an audio thumbnail,
+// keeping its aspect ratio
+pub fn downscale(cover: &[u8]) -> Result<Vec<u8>, DynError> {
+    let image = image::load_from_memory(cover)?
+        .thumbnail(MAX_DIMENSION, MAX_DIMENSION)
+        .to_rgb8();
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY).encode_image(&image)?;
+    Ok(jpeg)
+}
+
+// Write the thumbnail next to the MP3, where the reply service picks it up
+pub async fn write_next_to(cover: Vec<u8>, mp3_path: &Path) -> Result<PathBuf, DynError> {
+    let jpeg = tokio::task::spawn_blocking(move || downscale(&cover)).await??;
+    let thumbnail_path = mp3_path.with_extension("jpg");
+    tokio::fs::write(&thumbnail_path, jpeg).await?;
+    Ok(thumbnail_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, ImageFormat, RgbImage};
+    use std::io::Cursor;
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        RgbImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn scales_youtube_thumbnails_down() {
+        let thumbnail = downscale(&jpeg(480, 360)).unwrap();
+
+        assert_eq!(image::guess_format(&thumbnail).unwrap(), ImageFormat::Jpeg);
+        assert_eq!(
+            image::load_from_memory(&thumbnail).unwrap().dimensions(),
+            (320, 240)
+        );
+        assert!(thumbnail.len() < 200 * 1024);
+    }
+
+    #[test]
+    fn keeps_small_images_within_limits() {
+        let thumbnail = downscale(&jpeg(120, 90)).unwrap();
+        let (width, height) = image::load_from_memory(&thumbnail).unwrap().dimensions();
+        assert!(width <= MAX_DIMENSION && height <= MAX_DIMENSION);
+    }
+
+    #[test]
+    fn rejects_invalid_images() {
+        assert!(downscale(b"not an image").is_err());
+    }
+}
@@ -0,0 +1,283 @@
+// Cleans pasted and OCR'd lines up before they're searched for. YouTube
+// search does best with just the artist and the song, everything else a
+// playlist or video title tends to carry only gets in the way
+
+// Words that mark brackets as noise, e.g. "(Official Video)" or "[HD]".
+// Brackets such as "(Live)" or "(Remix)" tell versions apart and are kept
+const NOISE_WORDS: [&str; 17] = [
+    "official",
+    "oficial",
+    "video",
+    "audio",
+    "lyric",
+    "lyrics",
+    "visualizer",
+    "visualiser",
+    "clip",
+    "hd",
+    "hq",
+    "4k",
+    "1080p",
+    "720p",
+    "mv",
+    "explicit",
+    "videoclip",
+];
+// Ways of crediting the featured artists
+const FEATURING: [&str; 5] = ["feat.", "feat", "ft.", "ft", "featuring"];
+// Separators left dangling at either end once the noise is gone
+const SEPARATORS: [char; 5] = ['-', '–', '—', '|', '/'];
+
+pub fn clean(line: &str) -> String {
+    let line = strip_track_number(line.trim());
+    let line: String = line
+        .chars()
+        .map(|c| if is_emoji(c) { ' ' } else { c })
+        .collect();
+    let line = strip_noise_brackets(&line);
+    let line = strip_featuring(&line);
+    let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    line.trim_matches(|c: char| c.is_whitespace() || SEPARATORS.contains(&c))
+        .to_string()
+}
+
+// "01. ", "3) ", "12 - " or "#4 " in front of a tracklist line. Numbers that
+// are part of the name, e.g. "50 Cent" or "99 Luftballons", are kept
+fn strip_track_number(line: &str) -> &str {
+    let rest = line.strip_prefix('#').unwrap_or(line);
+    let digits = rest.chars().take_while(char::is_ascii_digit).count();
+    if digits == 0 || digits > 3 {
+        return line;
+    }
+    let after = rest[digits..].trim_start();
+    if line.starts_with('#') && after.len() < rest[digits..].len() {
+        return after;
+    }
+    match after.strip_prefix(['.', ')', ']', ':', '-', '–']) {
+        Some(title) if title.starts_with(char::is_whitespace) => title.trim_start(),
+        _ => line,
+    }
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        // Pictographs, emoticons, transport, flags and the like
+        0x1F000..=0x1FAFF
+            // Miscellaneous symbols and dingbats, e.g. ☀ and ✨
+            | 0x2600..=0x27BF
+            // Stars and arrows such as ⭐
+            | 0x2B00..=0x2BFF
+            // Joiners and variation selectors that glue emoji together
+            | 0x200D
+            | 0xFE0E..=0xFE0F
+    )
+}
+
+// Remove (...) and [...] groups that hold noise or featured artists
+fn strip_noise_brackets(line: &str) -> String {
+    let mut cleaned = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(open) = rest.find(['(', '[']) {
+        let close = if rest[open..].starts_with('(') {
+            ')'
+        } else {
+            ']'
+        };
+        let Some(length) = rest[open + 1..].find(close) else {
+            break;
+        };
+        let inner = &rest[open + 1..open + 1 + length];
+        cleaned.push_str(&rest[..open]);
+        if !is_noise(inner) {
+            cleaned.push_str(&rest[open..open + length + 2]);
+        }
+        rest = &rest[open + length + 2..];
+    }
+    cleaned.push_str(rest);
+    cleaned
+}
+
+fn is_noise(inner: &str) -> bool {
+    let lowered = inner.to_lowercase();
+    let mut words = lowered
+        .split(|c: char| !c.is_alphanumeric() && c != '.')
+        .filter(|word| !word.is_empty());
+    let Some(first) = words.next() else {
+        return true;
+    };
+    FEATURING.contains(&first)
+        || std::iter::once(first)
+            .chain(words)
+            .any(|word| NOISE_WORDS.contains(&word.trim_end_matches('.')))
+}
+
+// Remove "feat. X" up to the next " - " or the end of the line, so
+// "A ft. B - Song" becomes "A - Song" and "A - Song feat. B" becomes "A - Song"
+fn strip_featuring(line: &str) -> String {
+    let mut words = line.split(' ').peekable();
+    let mut kept = Vec::new();
+    while let Some(word) = words.next() {
+        if !FEATURING.contains(&word.to_lowercase().as_str()) {
+            kept.push(word);
+            continue;
+        }
+        while words.peek().is_some_and(|next| !is_separator(next)) {
+            words.next();
+        }
+    }
+    kept.join(" ")
+}
+
+fn is_separator(word: &str) -> bool {
+    let mut chars = word.chars();
+    matches!((chars.next(), chars.next()), (Some(c), None) if SEPARATORS.contains(&c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_video_noise_in_brackets() {
+        for (line, expected) in [
+            (
+                "Daft Punk - One More Time (Official Video)",
+                "Daft Punk - One More Time",
+            ),
+            (
+                "Daft Punk - One More Time [HD]",
+                "Daft Punk - One More Time",
+            ),
+            (
+                "Rick Astley - Never Gonna Give You Up (Official Music Video) [4K Remaster]",
+                "Rick Astley - Never Gonna Give You Up",
+            ),
+            ("Adele - Hello (Lyrics)", "Adele - Hello"),
+            ("Adele - Hello [Official Lyric Video]", "Adele - Hello"),
+            ("Adele - Hello (Audio)", "Adele - Hello"),
+            ("Adele - Hello (Official Visualizer)", "Adele - Hello"),
+            ("Smiley - Oarecare (Videoclip Oficial)", "Smiley - Oarecare"),
+        ] {
+            assert_eq!(clean(line), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn keeps_brackets_that_name_a_version() {
+        for line in [
+            "Daft Punk - One More Time (Live)",
+            "Avicii - Levels (Skrillex Remix)",
+            "Nirvana - About a Girl (Acoustic)",
+            "Queen - Bohemian Rhapsody [Remastered 2011]",
+        ] {
+            assert_eq!(clean(line), line);
+        }
+    }
+
+    #[test]
+    fn strips_featured_artists() {
+        for (line, expected) in [
+            (
+                "Daft Punk - Get Lucky (feat. Pharrell Williams)",
+                "Daft Punk - Get Lucky",
+            ),
+            (
+                "Daft Punk - Get Lucky [ft. Pharrell Williams & Nile Rodgers]",
+                "Daft Punk - Get Lucky",
+            ),
+            (
+                "Daft Punk - Get Lucky feat. Pharrell Williams",
+                "Daft Punk - Get Lucky",
+            ),
+            (
+                "Daft Punk ft. Pharrell Williams - Get Lucky",
+                "Daft Punk - Get Lucky",
+            ),
+            (
+                "Daft Punk Featuring Pharrell Williams - Get Lucky",
+                "Daft Punk - Get Lucky",
+            ),
+            (
+                "Calvin Harris Feat Rihanna - This Is What You Came For",
+                "Calvin Harris - This Is What You Came For",
+            ),
+        ] {
+            assert_eq!(clean(line), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn keeps_words_that_only_contain_feat() {
+        for line in [
+            "Daft Punk - Aerodynamic",
+            "Soft Cell - Tainted Love",
+            "Defeat",
+        ] {
+            assert_eq!(clean(line), line);
+        }
+    }
+
+    #[test]
+    fn strips_emoji() {
+        assert_eq!(
+            clean("🔥 Daft Punk - One More Time 🔥"),
+            "Daft Punk - One More Time"
+        );
+        assert_eq!(
+            clean("Daft Punk - Digital Love ❤️"),
+            "Daft Punk - Digital Love"
+        );
+        assert_eq!(
+            clean("⭐️Daft Punk - Veridis Quo"),
+            "Daft Punk - Veridis Quo"
+        );
+        assert_eq!(clean("Björk - Jóga"), "Björk - Jóga");
+    }
+
+    #[test]
+    fn strips_track_numbers() {
+        for line in [
+            "01. Daft Punk - One More Time",
+            "1) Daft Punk - One More Time",
+            "12 - Daft Punk - One More Time",
+            "#3 Daft Punk - One More Time",
+            "7: Daft Punk - One More Time",
+            "4] Daft Punk - One More Time",
+        ] {
+            assert_eq!(clean(line), "Daft Punk - One More Time", "{}", line);
+        }
+    }
+
+    #[test]
+    fn keeps_numbers_that_are_part_of_the_name() {
+        for line in [
+            "50 Cent - In Da Club",
+            "99 Luftballons",
+            "2Pac - California Love",
+            "Blink-182 - All the Small Things",
+            "1975 - The Sound",
+            "3.14 - Pi",
+        ] {
+            assert_eq!(clean(line), line);
+        }
+    }
+
+    #[test]
+    fn drops_separators_left_dangling() {
+        assert_eq!(clean("Daft Punk - (Official Video)"), "Daft Punk");
+        assert_eq!(
+            clean("  Daft Punk -  One More Time | "),
+            "Daft Punk - One More Time"
+        );
+        assert_eq!(clean("(Official Video)"), "");
+    }
+
+    #[test]
+    fn leaves_unbalanced_brackets_alone() {
+        assert_eq!(
+            clean("Daft Punk - One More Time (Live"),
+            "Daft Punk - One More Time (Live"
+        );
+    }
+}
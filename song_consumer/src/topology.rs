@@ -0,0 +1,199 @@
+use crate::DynError;
+use lapin::{
+    options::{ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions},
+    types::{AMQPValue, FieldTable},
+    Channel, ExchangeKind,
+};
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+// Names of the exchanges and queues, configurable so several deployments can
+// share one broker
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Queues {
+    pub music: String,
+    // Requests for a single song, consumed next to music so they don't wait
+    // behind playlists
+    pub music_fast: String,
+    pub reply: String,
+    // The reply service reports the file_id of every uploaded MP3 on this queue
+    pub audio_cache: String,
+    pub dead_letter_exchange: String,
+    pub dead_letter_queue: String,
+    // Messages that couldn't be parsed, kept for inspection instead of being
+    // retried
+    pub invalid: String,
+    // Fanout exchange the bot publishes cancellations on
+    pub control_exchange: String,
+    // Fanout exchange of the anonymized usage events, see analytics.rs
+    pub analytics_exchange: String,
+}
+
+impl Default for Queues {
+    fn default() -> Self {
+        Self {
+            music: "Music".to_string(),
+            music_fast: "Music.fast".to_string(),
+            reply: "Reply".to_string(),
+            audio_cache: "AudioCache".to_string(),
+            dead_letter_exchange: "Music.dlx".to_string(),
+            dead_letter_queue: "Music.dlq".to_string(),
+            invalid: "Music.invalid".to_string(),
+            control_exchange: "Control".to_string(),
+            analytics_exchange: "Analytics".to_string(),
+        }
+    }
+}
+
+impl Queues {
+    pub fn validate(&self) -> Result<(), String> {
+        let names = [
+            ("music", &self.music),
+            ("music_fast", &self.music_fast),
+            ("reply", &self.reply),
+            ("audio_cache", &self.audio_cache),
+            ("dead_letter_exchange", &self.dead_letter_exchange),
+            ("dead_letter_queue", &self.dead_letter_queue),
+            ("invalid", &self.invalid),
+            ("control_exchange", &self.control_exchange),
+            ("analytics_exchange", &self.analytics_exchange),
+        ];
+        match names.iter().find(|(_, name)| name.trim().is_empty()) {
+            Some((field, _)) => Err(format!("queues.{} must not be empty", field)),
+            None => Ok(()),
+        }
+    }
+}
+
+static QUEUES: OnceLock<Queues> = OnceLock::new();
+
+// Set the queue names once at startup, before anything is declared
+pub fn init(queues: Queues) {
+    if QUEUES.set(queues).is_err() {
+        log::warn!("Queue names were already initialised, keeping the first ones");
+    }
+}
+
+pub fn queues() -> &'static Queues {
+    QUEUES.get_or_init(Queues::default)
+}
+
+// Declare every exchange and queue the consumer uses. Declaring is
+// idempotent, so this is safe to run on every (re)connect
+pub async fn declare(channel: &Channel) -> Result<(), DynError> {
+    let queues = queues();
+    // Rejected requests are routed to the dead-letter queue instead of being
+    // dropped
+    channel
+        .exchange_declare(
+            &queues.dead_letter_exchange,
+            ExchangeKind::Fanout,
+            ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+    declare_queue(channel, &queues.dead_letter_queue, FieldTable::default()).await?;
+    channel
+        .queue_bind(
+            &queues.dead_letter_queue,
+            &queues.dead_letter_exchange,
+            "",
+            QueueBindOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    let mut music_arguments = FieldTable::default();
+    music_arguments.insert(
+        "x-dead-letter-exchange".into(),
+        AMQPValue::LongString(queues.dead_letter_exchange.clone().into()),
+    );
+    declare_queue(channel, &queues.music, music_arguments.clone()).await?;
+    declare_queue(channel, &queues.music_fast, music_arguments).await?;
+    declare_queue(channel, &queues.reply, FieldTable::default()).await?;
+    declare_queue(channel, &queues.invalid, FieldTable::default()).await?;
+    channel
+        .exchange_declare(
+            &queues.control_exchange,
+            ExchangeKind::Fanout,
+            ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+    declare_queue(channel, &queues.audio_cache, FieldTable::default()).await?;
+    // Whoever builds the dashboards binds a queue of their own
+    channel
+        .exchange_declare(
+            &queues.analytics_exchange,
+            ExchangeKind::Fanout,
+            ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+
+    log::info!(
+        "Declared queues '{}', '{}', '{}', '{}', '{}' and dead-letter queue '{}'",
+        queues.music,
+        queues.music_fast,
+        queues.reply,
+        queues.audio_cache,
+        queues.invalid,
+        queues.dead_letter_queue
+    );
+    Ok(())
+}
+
+// Every replica needs to see every cancellation, so each one gets its own
+// queue on the control exchange, deleted again when its connection closes
+pub async fn declare_control_queue(channel: &Channel) -> Result<String, DynError> {
+    let queue = channel
+        .queue_declare(
+            "",
+            QueueDeclareOptions {
+                exclusive: true,
+                auto_delete: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+    let name = queue.name().to_string();
+    channel
+        .queue_bind(
+            &name,
+            &queues().control_exchange,
+            "",
+            QueueBindOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    Ok(name)
+}
+
+async fn declare_queue(
+    channel: &Channel,
+    name: &str,
+    arguments: FieldTable,
+) -> Result<(), DynError> {
+    channel
+        .queue_declare(
+            name,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            arguments,
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,307 @@
+// Reads the tracklist of a playlist screenshot from the words OCR found on
+// it. Music apps show every track as its title with the artist in smaller
+// print underneath, next to durations and buttons, so the words are put back
+// into lines by where they are on the screen and every title is paired with
+// its artist
+
+// Text of buttons and headers music apps show around a tracklist
+const CHROME: [&str; 24] = [
+    "shuffle",
+    "shuffle play",
+    "play",
+    "pause",
+    "download",
+    "downloaded",
+    "add",
+    "add songs",
+    "follow",
+    "following",
+    "more",
+    "edit",
+    "sort",
+    "filter",
+    "search",
+    "home",
+    "library",
+    "your library",
+    "premium",
+    "liked songs",
+    "now playing",
+    "up next",
+    "queue",
+    "see all",
+];
+// Words of lines that count the songs or plays instead of naming a song,
+// e.g. "42 songs, 2 hr 30 min" or "1,234 likes"
+const COUNT_WORDS: [&str; 8] = [
+    "song",
+    "songs",
+    "hr",
+    "min",
+    "likes",
+    "saves",
+    "plays",
+    "followers",
+];
+// Separators of the artist from the album or duration, e.g. "Daft Punk • Discovery"
+const DETAIL_SEPARATORS: [char; 2] = ['•', '·'];
+
+// A word OCR found, with the box around it in pixels
+#[derive(Debug, Clone)]
+pub struct Word {
+    pub text: String,
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Word {
+    // The box around the corners of a bounding polygon
+    pub fn from_corners(text: String, corners: &[(i32, i32)]) -> Option<Self> {
+        let xs = corners.iter().map(|&(x, _)| x);
+        let ys = corners.iter().map(|&(_, y)| y);
+        Some(Self {
+            text,
+            left: xs.clone().min()?,
+            right: xs.max()?,
+            top: ys.clone().min()?,
+            bottom: ys.max()?,
+        })
+    }
+
+    fn height(&self) -> i32 {
+        (self.bottom - self.top).max(1)
+    }
+
+    fn center_y(&self) -> i32 {
+        (self.top + self.bottom) / 2
+    }
+}
+
+// One search query per track of the screenshot, "Artist - Title" where the
+// artist could be told apart from the title
+pub fn song_lines(words: &[Word]) -> Vec<String> {
+    let lines: Vec<Word> = text_lines(words)
+        .into_iter()
+        .filter(|line| !is_chrome(&line.text))
+        .collect();
+
+    let mut songs = Vec::new();
+    let mut index = 0;
+    while index < lines.len() {
+        let title = &lines[index];
+        match lines
+            .get(index + 1)
+            .and_then(|next| artist_below(title, next))
+        {
+            Some(artist) => {
+                songs.push(format!("{} - {}", artist, title.text));
+                index += 2;
+            }
+            None => {
+                songs.push(title.text.clone());
+                index += 1;
+            }
+        }
+    }
+    songs
+}
+
+// Put the words back into lines, top to bottom. Words on the same row that
+// are far apart, such as a title and its duration, make separate lines
+fn text_lines(words: &[Word]) -> Vec<Word> {
+    let mut words: Vec<&Word> = words.iter().collect();
+    words.sort_by_key(|word| (word.center_y(), word.left));
+
+    let mut rows: Vec<Vec<&Word>> = Vec::new();
+    for word in words {
+        let row = rows.last_mut().filter(|row| {
+            let (top, bottom) = row.iter().fold((i32::MAX, i32::MIN), |(top, bottom), w| {
+                (top.min(w.top), bottom.max(w.bottom))
+            });
+            (top..=bottom).contains(&word.center_y())
+        });
+        match row {
+            Some(row) => row.push(word),
+            None => rows.push(vec![word]),
+        }
+    }
+
+    let mut lines = Vec::new();
+    for mut row in rows {
+        row.sort_by_key(|word| word.left);
+        let height = row.iter().map(|word| word.height()).max().unwrap_or(1);
+        let mut line: Option<Word> = None;
+        for word in row {
+            line = match line {
+                Some(mut line) if word.left - line.right <= height * 3 / 2 => {
+                    line.text.push(' ');
+                    line.text.push_str(&word.text);
+                    line.right = line.right.max(word.right);
+                    line.top = line.top.min(word.top);
+                    line.bottom = line.bottom.max(word.bottom);
+                    Some(line)
+                }
+                Some(line) => {
+                    lines.push(line);
+                    Some(word.clone())
+                }
+                None => Some(word.clone()),
+            };
+        }
+        lines.extend(line);
+    }
+    lines
+}
+
+fn is_chrome(text: &str) -> bool {
+    let text = text.trim().to_lowercase();
+    if text.chars().filter(|c| c.is_alphabetic()).count() < 2 {
+        // Durations, track numbers, badges and icons
+        return true;
+    }
+    if CHROME.contains(&text.as_str()) {
+        return true;
+    }
+    text.starts_with(|c: char| c.is_ascii_digit())
+        && text
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| COUNT_WORDS.contains(&word))
+}
+
+// The artist on the line under a title, if that's what the line is: in
+// smaller print or marked as details, close below and lined up with it
+fn artist_below(title: &Word, line: &Word) -> Option<String> {
+    // A line that already names the artist is a whole song
+    if title.text.contains(" - ") {
+        return None;
+    }
+    let close = line.top - title.bottom <= title.height();
+    let aligned = (line.left - title.left).abs() <= title.height() * 2;
+    let smaller = line.height() * 100 <= title.height() * 92;
+    let details = line.text.contains(DETAIL_SEPARATORS) || explicit_badge(&line.text).is_some();
+    if !(close && aligned && (smaller || details)) {
+        return None;
+    }
+
+    let artist = explicit_badge(&line.text).unwrap_or(&line.text);
+    let artist = artist.split(DETAIL_SEPARATORS).next().unwrap_or(artist);
+    let artist = artist.trim();
+    (!artist.is_empty()).then(|| artist.to_string())
+}
+
+// The rest of an artist line that starts with the "E" of explicit songs
+fn explicit_badge(text: &str) -> Option<&str> {
+    text.strip_prefix("E ")
+        .filter(|rest| !rest.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A line of words starting at (left, top), each word as wide as its
+    // text and one space apart
+    fn line(text: &str, left: i32, top: i32, height: i32) -> Vec<Word> {
+        let mut words = Vec::new();
+        let mut x = left;
+        for word in text.split(' ') {
+            let width = word.chars().count() as i32 * height / 2;
+            words.push(Word {
+                text: word.to_string(),
+                left: x,
+                top,
+                right: x + width,
+                bottom: top + height,
+            });
+            x += width + height / 3;
+        }
+        words
+    }
+
+    #[test]
+    fn pairs_titles_with_the_artists_under_them() {
+        let words: Vec<Word> = [
+            line("One More Time", 100, 100, 40),
+            line("Daft Punk", 100, 145, 32),
+            line("Mr. Brightside", 100, 220, 40),
+            line("The Killers", 100, 265, 32),
+        ]
+        .concat();
+        assert_eq!(
+            song_lines(&words),
+            vec!["Daft Punk - One More Time", "The Killers - Mr. Brightside"]
+        );
+    }
+
+    #[test]
+    fn drops_durations_buttons_and_counts() {
+        let words: Vec<Word> = [
+            line("42 songs, 2 hr 30 min", 100, 20, 30),
+            line("Shuffle Play", 100, 60, 30),
+            line("One More Time", 100, 100, 40),
+            line("5:20", 900, 105, 32),
+            line("Daft Punk", 100, 145, 32),
+            line("E", 20, 150, 32),
+        ]
+        .concat();
+        assert_eq!(song_lines(&words), vec!["Daft Punk - One More Time"]);
+    }
+
+    #[test]
+    fn strips_badges_and_details_off_artists() {
+        let words: Vec<Word> = [
+            line("Lose Yourself", 100, 100, 40),
+            line("E Eminem", 100, 145, 40),
+            line("Around the World", 100, 220, 40),
+            line("Daft Punk • Homework", 100, 265, 40),
+        ]
+        .concat();
+        assert_eq!(
+            song_lines(&words),
+            vec!["Eminem - Lose Yourself", "Daft Punk - Around the World"]
+        );
+    }
+
+    #[test]
+    fn keeps_lists_of_whole_songs_apart() {
+        let words: Vec<Word> = [
+            line("Daft Punk - One More Time", 100, 100, 40),
+            line("The Killers - Mr. Brightside", 100, 145, 40),
+            line("Around the World", 100, 190, 40),
+            line("Harder Better Faster Stronger", 100, 235, 40),
+        ]
+        .concat();
+        assert_eq!(
+            song_lines(&words),
+            vec![
+                "Daft Punk - One More Time",
+                "The Killers - Mr. Brightside",
+                "Around the World",
+                "Harder Better Faster Stronger"
+            ]
+        );
+    }
+
+    #[test]
+    fn does_not_pair_lines_that_are_far_apart() {
+        let words: Vec<Word> = [
+            line("One More Time", 100, 100, 40),
+            line("Daft Punk", 100, 300, 32),
+        ]
+        .concat();
+        assert_eq!(song_lines(&words), vec!["One More Time", "Daft Punk"]);
+    }
+
+    #[test]
+    fn builds_boxes_from_corners() {
+        let word = Word::from_corners("Daft".to_string(), &[(10, 5), (50, 5), (50, 25), (10, 25)])
+            .unwrap();
+        assert_eq!(
+            (word.left, word.top, word.right, word.bottom),
+            (10, 5, 50, 25)
+        );
+        assert!(Word::from_corners("Daft".to_string(), &[]).is_none());
+    }
+}
@@ -0,0 +1,231 @@
+// Reads the songs of an uploaded tracklist: plain text with one song per
+// line, CSV exports of playlists, or M3U playlists of a media player
+
+use crate::{error::SongError, http::Client};
+
+// Column headers that hold the title and the artist, as written by
+// playlist exporters such as Exportify, TuneMyMusic or Soundiiz
+const TITLE_COLUMNS: [&str; 6] = ["title", "track", "track name", "song", "song name", "name"];
+const ARTIST_COLUMNS: [&str; 5] = [
+    "artist",
+    "artists",
+    "artist name",
+    "artist name(s)",
+    "artist names",
+];
+// Spreadsheets in many locales separate the columns with semicolons
+const CSV_DELIMITERS: [char; 3] = [',', ';', '\t'];
+
+pub async fn download_song_lines(
+    client: &Client,
+    file_url: &str,
+    file_name: &str,
+) -> Result<Vec<String>, SongError> {
+    log::info!("Downloading tracklist {}", file_name);
+    let contents = client
+        .get(file_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    let lines = song_lines(file_name, &contents);
+    log::info!("Found {} songs in {}", lines.len(), file_name);
+    if lines.is_empty() {
+        return Err(SongError::NoTracks);
+    }
+    Ok(lines)
+}
+
+// One line per song, in the order of the file
+pub fn song_lines(file_name: &str, contents: &str) -> Vec<String> {
+    let contents = contents.trim_start_matches('\u{feff}');
+    let extension = file_name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "csv" | "tsv" => csv_lines(contents),
+        "m3u" | "m3u8" => m3u_lines(contents),
+        _ => contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+    }
+}
+
+// "Artist - Title" per row when the header names the columns, otherwise the
+// fields of every row joined together
+fn csv_lines(contents: &str) -> Vec<String> {
+    let delimiter = contents
+        .lines()
+        .next()
+        .and_then(|header| {
+            CSV_DELIMITERS
+                .into_iter()
+                .max_by_key(|&delimiter| header.matches(delimiter).count())
+        })
+        .unwrap_or(',');
+    let mut rows = contents
+        .lines()
+        .map(|line| csv_fields(line, delimiter))
+        .filter(|fields| fields.iter().any(|field| !field.is_empty()));
+
+    let Some(header) = rows.next() else {
+        return Vec::new();
+    };
+    let column = |names: &[&str]| {
+        header
+            .iter()
+            .position(|field| names.contains(&field.to_lowercase().as_str()))
+    };
+    let Some(title) = column(&TITLE_COLUMNS) else {
+        // No header, the first row is a song too
+        return std::iter::once(header)
+            .chain(rows)
+            .map(|fields| join_fields(&fields))
+            .filter(|line| !line.is_empty())
+            .collect();
+    };
+    let artist = column(&ARTIST_COLUMNS);
+
+    rows.filter_map(|fields| {
+        let title = fields.get(title).filter(|title| !title.is_empty())?;
+        match artist.and_then(|artist| fields.get(artist)) {
+            Some(artist) if !artist.is_empty() => Some(format!("{} - {}", artist, title)),
+            _ => Some(title.clone()),
+        }
+    })
+    .collect()
+}
+
+fn join_fields(fields: &[String]) -> String {
+    fields
+        .iter()
+        .filter(|field| !field.is_empty())
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(" - ")
+}
+
+// Split a CSV row, with quoted fields that may hold the delimiter and ""
+// for a quote. Fields don't span lines in tracklists, so rows are lines
+fn csv_fields(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+        .into_iter()
+        .map(|field| field.trim().to_string())
+        .collect()
+}
+
+// The "#EXTINF:duration,Artist - Title" of every entry, or the name of the
+// file when there is none. Links are kept so they're converted as links
+fn m3u_lines(contents: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut title: Option<String> = None;
+    for line in contents.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            title = info
+                .split_once(',')
+                .map(|(_, title)| title.trim().to_string())
+                .filter(|title| !title.is_empty());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let entry = match title.take() {
+                Some(title) => title,
+                None if line.contains("://") => line.to_string(),
+                None => file_stem(line),
+            };
+            lines.push(entry);
+        }
+    }
+    lines
+}
+
+fn file_stem(path: &str) -> String {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    stem.replace('_', " ").trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_text_files_line_by_line() {
+        let contents = "\u{feff}Daft Punk - One More Time\r\n\r\n  Justice - D.A.N.C.E.  \n";
+        assert_eq!(
+            song_lines("songs.TXT", contents),
+            vec!["Daft Punk - One More Time", "Justice - D.A.N.C.E."]
+        );
+    }
+
+    #[test]
+    fn pairs_csv_artists_with_titles() {
+        let contents = "\"Track Name\",\"Artist Name(s)\",\"Album Name\"\n\
+            \"One More Time\",\"Daft Punk\",\"Discovery\"\n\
+            \"Get Lucky\",\"Daft Punk,Pharrell Williams\",\"Random Access Memories\"\n\
+            \"Untitled\",\"\",\"\"\n";
+        assert_eq!(
+            song_lines("playlist.csv", contents),
+            vec![
+                "Daft Punk - One More Time",
+                "Daft Punk,Pharrell Williams - Get Lucky",
+                "Untitled"
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_csv_with_semicolons_and_quotes() {
+        let contents = "Artist;Title\nQueen;\"Bohemian Rhapsody; \"\"Live\"\"\"\n";
+        assert_eq!(
+            song_lines("export.csv", contents),
+            vec!["Queen - Bohemian Rhapsody; \"Live\""]
+        );
+    }
+
+    #[test]
+    fn joins_the_fields_of_csv_without_a_header() {
+        let contents = "Daft Punk,One More Time\nJustice,D.A.N.C.E.\n,\n";
+        assert_eq!(
+            song_lines("songs.csv", contents),
+            vec!["Daft Punk - One More Time", "Justice - D.A.N.C.E."]
+        );
+    }
+
+    #[test]
+    fn reads_m3u_titles_and_file_names() {
+        let contents = "#EXTM3U\n\
+            #EXTINF:320,Daft Punk - One More Time\n\
+            /music/Daft Punk/01 One More Time.mp3\n\
+            C:\\Music\\Justice_-_D.A.N.C.E.flac\n\
+            https://www.youtube.com/watch?v=dQw4w9WgXcQ\n";
+        assert_eq!(
+            song_lines("mix.m3u8", contents),
+            vec![
+                "Daft Punk - One More Time",
+                "Justice - D.A.N.C.E",
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ"
+            ]
+        );
+    }
+}
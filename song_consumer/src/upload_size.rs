@@ -0,0 +1,77 @@
+use crate::ffmpeg;
+use std::path::Path;
+
+// Telegram bots can upload files of up to 50 MB
+pub const MAX_UPLOAD_SIZE: u64 = 50 * 1024 * 1024;
+
+// Bitrates a too large MP3 may be re-encoded at, best first. Some room is
+// left below the limit for the tags and cover added afterwards
+const FALLBACK_BITRATES: [u32; 7] = [256, 192, 160, 128, 96, 64, 32];
+const TARGET_SIZE: u64 = MAX_UPLOAD_SIZE / 10 * 9;
+
+// Make sure the MP3 can be uploaded, re-encoding it at a lower bitrate when
+// it's too large. False means it's still too large and has to be sent some
+// other way
+pub async fn fit(file_path: &Path, bitrate: u32) -> bool {
+    let size = match tokio::fs::metadata(file_path).await {
+        Ok(metadata) => metadata.len(),
+        // The upload will report it if something's wrong with the file
+        Err(e) => {
+            log::warn!("Failed to read the size of {}: {}", file_path.display(), e);
+            return true;
+        }
+    };
+    if size <= MAX_UPLOAD_SIZE {
+        return true;
+    }
+
+    let Some(lower) = fitting_bitrate(size, bitrate) else {
+        log::info!(
+            "{} is {} bytes, too long to fit at any bitrate",
+            file_path.display(),
+            size
+        );
+        return false;
+    };
+    log::info!(
+        "{} is {} bytes, re-encoding it at {}kbps",
+        file_path.display(),
+        size,
+        lower
+    );
+    if let Err(e) = ffmpeg::reencode(file_path, lower).await {
+        log::warn!("Failed to re-encode {}: {}", file_path.display(), e);
+        return false;
+    }
+    tokio::fs::metadata(file_path)
+        .await
+        .is_ok_and(|metadata| metadata.len() <= MAX_UPLOAD_SIZE)
+}
+
+// The best bitrate at which an MP3 of this size and bitrate stays under the
+// target size, estimated from how much shorter the bitrate makes it
+fn fitting_bitrate(size: u64, bitrate: u32) -> Option<u32> {
+    let fitting = u64::from(bitrate) * TARGET_SIZE / size;
+    FALLBACK_BITRATES
+        .into_iter()
+        .find(|&candidate| u64::from(candidate) <= fitting && candidate < bitrate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn halves_the_bitrate_of_a_file_twice_too_large() {
+        assert_eq!(fitting_bitrate(100 * MB, 320), Some(128));
+        assert_eq!(fitting_bitrate(60 * MB, 320), Some(192));
+    }
+
+    #[test]
+    fn gives_up_on_files_too_long_for_any_bitrate() {
+        assert_eq!(fitting_bitrate(600 * MB, 320), None);
+        assert_eq!(fitting_bitrate(60 * MB, 32), None);
+    }
+}
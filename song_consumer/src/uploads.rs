@@ -0,0 +1,63 @@
+use crate::{
+    cache::{self, Cache},
+    correlation, history, instance, topology, DynError,
+};
+use futures_util::StreamExt;
+use lapin::{
+    options::{BasicAckOptions, BasicConsumeOptions},
+    types::FieldTable,
+    Channel,
+};
+use rustin_models::{MessageBody, RabbitMessage};
+use rustin_storage::Storage;
+use std::sync::Arc;
+
+// Store the file_ids reported by the reply service in the cache, so later
+// requests for the same video can be answered without downloading it again,
+// and in the history of the request that uploaded it
+pub async fn listen_for_uploads(
+    channel: Channel,
+    cache: Arc<dyn Cache>,
+    storage: Option<Arc<Storage>>,
+) -> Result<(), DynError> {
+    let queue = &topology::queues().audio_cache;
+    let mut consumer = channel
+        .basic_consume(
+            queue,
+            &instance::consumer_tag("song_consumer_uploads"),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    while let Some(delivery) = consumer.next().await {
+        let delivery = match delivery {
+            Ok(delivery) => delivery,
+            Err(e) => {
+                log::error!("Failed to receive upload report: {}", e);
+                continue;
+            }
+        };
+
+        match serde_json::from_slice::<RabbitMessage>(&delivery.data) {
+            Ok(RabbitMessage {
+                body: MessageBody::AudioUploaded { cache_key, file_id },
+                ..
+            }) => {
+                cache::set_or_log(cache.as_ref(), &cache_key, &file_id, cache::FILE_ID_TTL).await;
+                log::info!("Cached Telegram file_id for {}", cache_key);
+                if let Some(video_id) = cache::video_id_from_file_id_key(&cache_key) {
+                    let request_id = correlation::request_id(&delivery);
+                    history::record_file_id(storage.as_deref(), &request_id, video_id, &file_id)
+                        .await;
+                }
+            }
+            Ok(other) => log::warn!("Unexpected message on '{}': {:?}", queue, other),
+            Err(e) => log::error!("Failed to parse upload report: {}", e),
+        }
+
+        delivery.ack(BasicAckOptions::default()).await?;
+    }
+
+    Ok(())
+}
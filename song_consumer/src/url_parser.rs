@@ -0,0 +1,420 @@
+// Recognizes YouTube, SoundCloud and Bandcamp links in request lines so they
+// can skip the search, and links of other services that only name a song
+
+const YOUTUBE_HOSTS: [&str; 2] = ["youtube.com", "music.youtube.com"];
+const APPLE_MUSIC_HOSTS: [&str; 2] = ["music.apple.com", "itunes.apple.com"];
+const SONG_LINK_HOSTS: [&str; 2] = ["song.link", "odesli.co"];
+
+// Pages of a SoundCloud profile that have the same shape as a track link
+const SOUNDCLOUD_PAGES: [&str; 9] = [
+    "sets",
+    "likes",
+    "tracks",
+    "albums",
+    "reposts",
+    "popular-tracks",
+    "followers",
+    "following",
+    "comments",
+];
+
+// Extract the video ID of watch, short, embed and youtu.be links
+pub fn parse_video_id(line: &str) -> Option<String> {
+    let (host, path) = split_url(line)?;
+
+    let id = if host == "youtu.be" {
+        path_segment(path)
+    } else if YOUTUBE_HOSTS.contains(&host) {
+        if let Some(query) = path.strip_prefix("watch?") {
+            query_param(query, "v")
+        } else {
+            ["shorts/", "embed/", "live/", "v/"]
+                .iter()
+                .find_map(|prefix| path.strip_prefix(prefix))
+                .and_then(path_segment)
+        }
+    } else {
+        None
+    }?;
+
+    is_video_id(id).then(|| id.to_string())
+}
+
+// Extract the list parameter of a youtube.com/playlist?list= URL
+pub fn parse_playlist_id(line: &str) -> Option<String> {
+    let (host, path) = split_url(line)?;
+    if !YOUTUBE_HOSTS.contains(&host) {
+        return None;
+    }
+
+    let query = path.strip_prefix("playlist?")?;
+    query_param(query, "list")
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+}
+
+// Extract the artist and track of a soundcloud.com/<artist>/<track> link
+pub fn parse_soundcloud_track(line: &str) -> Option<(String, String)> {
+    let (host, path) = split_url(line)?;
+    if host != "soundcloud.com" {
+        return None;
+    }
+
+    let path = path.split(['?', '#']).next().unwrap_or(path);
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    let (artist, track) = (segments.next()?, segments.next()?);
+    if segments.next().is_some() || SOUNDCLOUD_PAGES.contains(&track) {
+        return None;
+    }
+    (is_slug(artist) && is_slug(track)).then(|| (artist.to_string(), track.to_string()))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BandcampLink {
+    Track { artist: String, slug: String },
+    Album { artist: String, slug: String },
+}
+
+// Recognize <artist>.bandcamp.com/track/<slug> and /album/<slug> links
+pub fn parse_bandcamp_link(line: &str) -> Option<BandcampLink> {
+    let (host, path) = split_url(line)?;
+    let artist = host.strip_suffix(".bandcamp.com")?;
+
+    let path = path.split(['?', '#']).next().unwrap_or(path);
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    let (kind, slug) = (segments.next()?, segments.next()?);
+    if segments.next().is_some() || !is_slug(artist) || !is_slug(slug) {
+        return None;
+    }
+
+    let (artist, slug) = (artist.to_string(), slug.to_string());
+    match kind {
+        "track" => Some(BandcampLink::Track { artist, slug }),
+        "album" => Some(BandcampLink::Album { artist, slug }),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MusicLink {
+    AppleMusic {
+        storefront: String,
+        track_id: String,
+    },
+    Deezer {
+        track_id: String,
+    },
+    // Kept whole, song.link's API takes the page URL itself
+    SongLink {
+        url: String,
+    },
+}
+
+// Recognize Apple Music and Deezer song links and song.link pages
+pub fn parse_music_link(line: &str) -> Option<MusicLink> {
+    let (host, path) = split_url(line)?;
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let path = path.split('#').next().unwrap_or(path);
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    if APPLE_MUSIC_HOSTS.contains(&host) {
+        // <storefront>/song/[<name>/]<id>, or <storefront>/album/<name>/<id>?i=<id>
+        // for a song picked out of an album
+        let (&storefront, rest) = segments.split_first()?;
+        let track_id = match rest {
+            ["song", .., id] => Some(*id),
+            ["album", ..] => query_param(query, "i"),
+            _ => None,
+        }?;
+        let is_storefront =
+            storefront.len() == 2 && storefront.chars().all(|c| c.is_ascii_alphabetic());
+        return (is_storefront && is_numeric_id(track_id)).then(|| MusicLink::AppleMusic {
+            storefront: storefront.to_string(),
+            track_id: track_id.to_string(),
+        });
+    }
+
+    if host == "deezer.com" {
+        // Localized links look like deezer.com/<language>/track/<id>
+        let track_id = match segments.as_slice() {
+            ["track", id] | [_, "track", id] => Some(*id),
+            _ => None,
+        }?;
+        return is_numeric_id(track_id).then(|| MusicLink::Deezer {
+            track_id: track_id.to_string(),
+        });
+    }
+
+    if SONG_LINK_HOSTS.contains(&host) && !segments.is_empty() {
+        return Some(MusicLink::SongLink {
+            url: format!("https://{}/{}", host, segments.join("/")),
+        });
+    }
+    None
+}
+
+fn is_numeric_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_digit())
+}
+
+// Split a URL into its host, without www. or m., and the rest of the URL
+fn split_url(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let rest = line
+        .strip_prefix("https://")
+        .or_else(|| line.strip_prefix("http://"))
+        .unwrap_or(line);
+    let (host, path) = rest.split_once('/')?;
+    let host = host
+        .strip_prefix("www.")
+        .or_else(|| host.strip_prefix("m."))
+        .unwrap_or(host);
+    Some((host, path))
+}
+
+// The first path segment, without any query string or fragment
+fn path_segment(path: &str) -> Option<&str> {
+    path.split(['/', '?', '#']).next().filter(|s| !s.is_empty())
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    let query = query.split('#').next().unwrap_or(query);
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key == name).then_some(value)
+    })
+}
+
+// YouTube video IDs are 11 characters of URL-safe base64
+pub fn is_video_id(id: &str) -> bool {
+    id.len() == 11
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// SoundCloud and Bandcamp user and track names in links
+fn is_slug(segment: &str) -> bool {
+    segment
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "dQw4w9WgXcQ";
+
+    #[test]
+    fn parses_watch_urls() {
+        for url in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "http://youtube.com/watch?v=dQw4w9WgXcQ",
+            "www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://m.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ&feature=share",
+            "https://www.youtube.com/watch?feature=youtu.be&v=dQw4w9WgXcQ&t=42s",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL590L5WQmH8fJ54F369BLDSqIwcs-TCfs",
+            "  https://www.youtube.com/watch?v=dQw4w9WgXcQ  ",
+        ] {
+            assert_eq!(parse_video_id(url).as_deref(), Some(ID), "{}", url);
+        }
+    }
+
+    #[test]
+    fn parses_short_links() {
+        for url in [
+            "https://youtu.be/dQw4w9WgXcQ",
+            "youtu.be/dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ?si=abcdef",
+            "https://youtu.be/dQw4w9WgXcQ?t=30",
+        ] {
+            assert_eq!(parse_video_id(url).as_deref(), Some(ID), "{}", url);
+        }
+    }
+
+    #[test]
+    fn parses_shorts_embed_and_live_links() {
+        for url in [
+            "https://www.youtube.com/shorts/dQw4w9WgXcQ",
+            "https://www.youtube.com/embed/dQw4w9WgXcQ?autoplay=1",
+            "https://www.youtube.com/live/dQw4w9WgXcQ?feature=share",
+            "https://www.youtube.com/v/dQw4w9WgXcQ",
+        ] {
+            assert_eq!(parse_video_id(url).as_deref(), Some(ID), "{}", url);
+        }
+    }
+
+    #[test]
+    fn rejects_titles_and_other_links() {
+        for line in [
+            "Rick Astley - Never Gonna Give You Up",
+            "never gonna give you up youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?v=tooshort",
+            "https://www.youtube.com/channel/UCuAXFkgsw1L7xaCfnd5JJOw",
+            "https://www.youtube.com/playlist?list=PL590L5WQmH8fJ54F369BLDSqIwcs-TCfs",
+            "https://vimeo.com/dQw4w9WgXcQ",
+            "https://open.spotify.com/track/4PTG3Z6ehGkBFwjybzWkR8",
+            "",
+        ] {
+            assert_eq!(parse_video_id(line), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn parses_playlist_urls() {
+        let list = "PL590L5WQmH8fJ54F369BLDSqIwcs-TCfs";
+        for url in [
+            "https://www.youtube.com/playlist?list=PL590L5WQmH8fJ54F369BLDSqIwcs-TCfs",
+            "https://music.youtube.com/playlist?list=PL590L5WQmH8fJ54F369BLDSqIwcs-TCfs&si=x",
+            "youtube.com/playlist?feature=share&list=PL590L5WQmH8fJ54F369BLDSqIwcs-TCfs",
+        ] {
+            assert_eq!(parse_playlist_id(url).as_deref(), Some(list), "{}", url);
+        }
+    }
+
+    #[test]
+    fn does_not_treat_videos_in_a_playlist_as_playlists() {
+        assert_eq!(
+            parse_playlist_id(
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL590L5WQmH8fJ54F369BLDSqIwcs-TCfs"
+            ),
+            None
+        );
+        assert_eq!(
+            parse_playlist_id("https://www.youtube.com/playlist?list="),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_soundcloud_tracks() {
+        for url in [
+            "https://soundcloud.com/daftpunkofficialmusic/one-more-time",
+            "soundcloud.com/daftpunkofficialmusic/one-more-time?si=abc&utm_source=clipboard",
+            "https://m.soundcloud.com/daftpunkofficialmusic/one-more-time/",
+        ] {
+            assert_eq!(
+                parse_soundcloud_track(url),
+                Some((
+                    "daftpunkofficialmusic".to_string(),
+                    "one-more-time".to_string()
+                )),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_soundcloud_profiles_and_sets() {
+        for url in [
+            "https://soundcloud.com/daftpunkofficialmusic",
+            "https://soundcloud.com/daftpunkofficialmusic/sets/discovery",
+            "https://soundcloud.com/daftpunkofficialmusic/likes",
+            "https://soundcloud.com/daftpunkofficialmusic/one-more-time/s-SecretToken",
+            "https://notsoundcloud.com/daftpunkofficialmusic/one-more-time",
+        ] {
+            assert_eq!(parse_soundcloud_track(url), None, "{}", url);
+        }
+    }
+
+    #[test]
+    fn parses_bandcamp_tracks_and_albums() {
+        assert_eq!(
+            parse_bandcamp_link("https://c418.bandcamp.com/track/sweden?from=embed"),
+            Some(BandcampLink::Track {
+                artist: "c418".to_string(),
+                slug: "sweden".to_string()
+            })
+        );
+        assert_eq!(
+            parse_bandcamp_link("c418.bandcamp.com/album/minecraft-volume-alpha/"),
+            Some(BandcampLink::Album {
+                artist: "c418".to_string(),
+                slug: "minecraft-volume-alpha".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_other_bandcamp_pages() {
+        for url in [
+            "https://c418.bandcamp.com",
+            "https://c418.bandcamp.com/music",
+            "https://c418.bandcamp.com/merch/some-shirt",
+            "https://bandcamp.com/track/sweden",
+            "https://c418.bandcamp.com.evil.com/track/sweden",
+        ] {
+            assert_eq!(parse_bandcamp_link(url), None, "{}", url);
+        }
+    }
+
+    #[test]
+    fn parses_apple_music_and_deezer_songs() {
+        let apple = |track_id: &str| {
+            Some(MusicLink::AppleMusic {
+                storefront: "us".to_string(),
+                track_id: track_id.to_string(),
+            })
+        };
+        assert_eq!(
+            parse_music_link(
+                "https://music.apple.com/us/album/one-more-time/697194953?i=697195462"
+            ),
+            apple("697195462")
+        );
+        assert_eq!(
+            parse_music_link("https://music.apple.com/us/song/one-more-time/697195462"),
+            apple("697195462")
+        );
+        assert_eq!(
+            parse_music_link("https://www.deezer.com/en/track/3135556?utm_source=share"),
+            Some(MusicLink::Deezer {
+                track_id: "3135556".to_string()
+            })
+        );
+        assert_eq!(
+            parse_music_link("deezer.com/track/3135556"),
+            Some(MusicLink::Deezer {
+                track_id: "3135556".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn parses_song_link_pages() {
+        assert_eq!(
+            parse_music_link("https://song.link/us/i/697195462?ref=share"),
+            Some(MusicLink::SongLink {
+                url: "https://song.link/us/i/697195462".to_string()
+            })
+        );
+        assert_eq!(
+            parse_music_link("odesli.co/y/FGBhQbmPwH8"),
+            Some(MusicLink::SongLink {
+                url: "https://odesli.co/y/FGBhQbmPwH8".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_albums_and_other_music_pages() {
+        for url in [
+            "https://music.apple.com/us/album/discovery/697194953",
+            "https://music.apple.com/us/artist/daft-punk/5468295",
+            "https://music.apple.com/us/playlist/pl.u-abc",
+            "https://www.deezer.com/en/album/302127",
+            "https://www.deezer.com/en/track/not-an-id",
+            "https://song.link/",
+            "https://open.spotify.com/track/4PTG3Z6ehGkBFwjybzWkR8",
+        ] {
+            assert_eq!(parse_music_link(url), None, "{}", url);
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use crate::{
+    error::{self, SongError},
+    http::{self, Client},
+    url_parser,
+};
+use async_trait::async_trait;
+use rustin_models::youtube::{
+    PlaylistItemsResponse, VideoContentDetails, VideoListResponse, YouTubeResponse,
+};
+use std::{cmp::Reverse, time::Duration};
+use urlencoding::encode;
+
+// Titles YouTube shows for playlist entries that can no longer be played
+const UNAVAILABLE_TITLES: [&str; 2] = ["Private video", "Deleted video"];
+
+// A video found in a playlist
+pub struct PlaylistVideo {
+    pub video_id: String,
+    pub title: String,
+}
+
+// List every playable video of a playlist through the playlistItems endpoint
+pub async fn expand_playlist(
+    client: &Client,
+    api_key: &str,
+    playlist_id: &str,
+) -> Result<Vec<PlaylistVideo>, SongError> {
+    let mut videos = Vec::new();
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let mut url = format!(
+            "https://www.googleapis.com/youtube/v3/playlistItems?part=snippet&maxResults=50&playlistId={}&key={}",
+            playlist_id, api_key
+        );
+        if let Some(token) = &page_token {
+            url.push_str(&format!("&pageToken={}", token));
+        }
+
+        let response = client.get(&url).send().await?;
+        let response: PlaylistItemsResponse = error::check_youtube_response(response)
+            .await?
+            .json()
+            .await?;
+
+        videos.extend(
+            response
+                .items
+                .into_iter()
+                .filter(|item| !UNAVAILABLE_TITLES.contains(&item.snippet.title.as_str()))
+                .map(|item| PlaylistVideo {
+                    video_id: item.snippet.resource_id.video_id,
+                    title: item.snippet.title,
+                }),
+        );
+
+        match response.next_page_token {
+            Some(token) => page_token = Some(token),
+            None => break,
+        }
+    }
+
+    log::info!(
+        "Expanded YouTube playlist {} into {} videos",
+        playlist_id,
+        videos.len()
+    );
+    Ok(videos)
+}
+
+// Songs rarely run shorter or longer than this, unlike compilations and
+// music videos with long intros
+const MIN_SONG_LENGTH: Duration = Duration::from_secs(60);
+const MAX_SONG_LENGTH: Duration = Duration::from_secs(12 * 60);
+
+// Auto-generated channels of artists carry the plain studio recordings
+const TOPIC_CHANNEL_SUFFIX: &str = " - Topic";
+
+// A video found by one of the search providers
+#[derive(Clone)]
+pub struct FoundVideo {
+    pub video_id: String,
+    pub title: String,
+    pub channel: String,
+    pub duration: Option<Duration>,
+    // Live streams and premieres, which can't be converted
+    pub live: bool,
+}
+
+// Search ordered by view count, with the durations looked up through the
+// videos endpoint
+pub async fn search_videos(
+    client: &Client,
+    api_key: &str,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<FoundVideo>, SongError> {
+    let url = format!(
+        "https://www.googleapis.com/youtube/v3/search?part=snippet&type=video&order=viewCount&maxResults={}&q={}&key={}",
+        limit,
+        encode(query),
+        api_key
+    );
+
+    log::info!("Searching YouTube with query: {}", query);
+    let response = client.get(&url).send().await?;
+    let response: YouTubeResponse = error::check_youtube_response(response)
+        .await?
+        .json()
+        .await?;
+
+    let mut videos: Vec<FoundVideo> = response
+        .items
+        .into_iter()
+        .filter_map(|item| {
+            let snippet = item.snippet?;
+            Some(FoundVideo {
+                video_id: item.id.video_id,
+                title: unescape_html(&snippet.title),
+                channel: unescape_html(&snippet.channel_title),
+                duration: None,
+                live: matches!(snippet.live_broadcast_content.as_str(), "live" | "upcoming"),
+            })
+        })
+        .collect();
+    if videos.is_empty() {
+        return Ok(videos);
+    }
+
+    // Without durations the results are still usable, just ranked worse
+    let ids: Vec<&str> = videos.iter().map(|v| v.video_id.as_str()).collect();
+    match fetch_details(client, api_key, &ids).await {
+        Ok(details) => {
+            for detail in details.items {
+                if let Some(video) = videos.iter_mut().find(|v| v.video_id == detail.id) {
+                    video.duration = parse_duration(&detail.content_details.duration);
+                }
+            }
+        }
+        Err(e) => log::warn!("Failed to look up video durations: {}", e),
+    }
+
+    Ok(videos)
+}
+
+async fn fetch_details(
+    client: &Client,
+    api_key: &str,
+    video_ids: &[&str],
+) -> Result<VideoListResponse, SongError> {
+    let url = format!(
+        "https://www.googleapis.com/youtube/v3/videos?part=contentDetails&id={}&key={}",
+        video_ids.join(","),
+        api_key
+    );
+    let response = client.get(&url).send().await?;
+    Ok(error::check_youtube_response(response)
+        .await?
+        .json()
+        .await?)
+}
+
+// Why YouTube won't let a video be converted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restriction {
+    AgeRestricted,
+    RegionBlocked,
+}
+
+// Look up whether a video is age-restricted, or blocked in the region the
+// MP3 providers download from
+pub async fn video_restriction(
+    client: &Client,
+    api_key: &str,
+    video_id: &str,
+    region: &str,
+) -> Result<Option<Restriction>, SongError> {
+    let details = fetch_details(client, api_key, &[video_id]).await?;
+    Ok(details
+        .items
+        .first()
+        .and_then(|item| restriction(&item.content_details, region)))
+}
+
+// Tells why a video that failed to convert can't be converted, if it's
+// restricted
+#[async_trait]
+pub trait RestrictionLookup: Send + Sync {
+    async fn restriction(&self, video_id: &str) -> Option<Restriction>;
+}
+
+// Looks restrictions up through the Data API, for the region the MP3
+// providers download from
+pub struct YouTubeRestrictions {
+    client: Client,
+    api_key: String,
+    region: String,
+}
+
+impl YouTubeRestrictions {
+    pub fn new(api_key: String, region: String) -> Self {
+        Self {
+            client: http::client(),
+            api_key,
+            region,
+        }
+    }
+}
+
+#[async_trait]
+impl RestrictionLookup for YouTubeRestrictions {
+    // A failed lookup only costs the user the more specific explanation
+    async fn restriction(&self, video_id: &str) -> Option<Restriction> {
+        if !url_parser::is_video_id(video_id) {
+            return None;
+        }
+        let restriction = video_restriction(&self.client, &self.api_key, video_id, &self.region)
+            .await
+            .inspect_err(|e| log::warn!("Failed to look up video ID {}: {}", video_id, e))
+            .ok()
+            .flatten();
+        if let Some(restriction) = restriction {
+            log::info!("Video ID {} is restricted: {:?}", video_id, restriction);
+        }
+        restriction
+    }
+}
+
+fn restriction(details: &VideoContentDetails, region: &str) -> Option<Restriction> {
+    if details.content_rating.yt_rating.as_deref() == Some("ytAgeRestricted") {
+        return Some(Restriction::AgeRestricted);
+    }
+    let regions = details.region_restriction.as_ref()?;
+    let blocked = match &regions.allowed {
+        Some(allowed) => !allowed.iter().any(|code| code.eq_ignore_ascii_case(region)),
+        None => regions
+            .blocked
+            .iter()
+            .any(|code| code.eq_ignore_ascii_case(region)),
+    };
+    blocked.then_some(Restriction::RegionBlocked)
+}
+
+// Pick the highest scoring video, ties go to the more viewed one
+pub fn best_match(videos: &[FoundVideo]) -> Option<&FoundVideo> {
+    videos
+        .iter()
+        .enumerate()
+        .max_by_key(|(position, video)| (score(video), Reverse(*position)))
+        .map(|(_, video)| video)
+}
+
+// All videos from the best match down, ties keep their view order
+pub fn rank(mut videos: Vec<FoundVideo>) -> Vec<FoundVideo> {
+    videos.sort_by_key(|video| Reverse(score(video)));
+    videos
+}
+
+fn score(video: &FoundVideo) -> i32 {
+    let mut score = 0;
+    if video.channel.ends_with(TOPIC_CHANNEL_SUFFIX) {
+        score += 2;
+    }
+    match video.duration {
+        Some(duration) if (MIN_SONG_LENGTH..=MAX_SONG_LENGTH).contains(&duration) => score += 2,
+        Some(_) => score -= 2,
+        None => {}
+    }
+    score
+}
+
+// Parse an ISO 8601 duration such as PT1H2M3S
+pub fn parse_duration(iso: &str) -> Option<Duration> {
+    let time = iso.strip_prefix("PT")?;
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in time.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: u64 = number.parse().ok()?;
+        number.clear();
+        seconds += match c {
+            'H' => value * 60 * 60,
+            'M' => value * 60,
+            'S' => value,
+            _ => return None,
+        };
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+// Format a duration the way YouTube shows it, e.g. 4:13 or 1:02:03
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+// The search endpoint returns titles with HTML entities escaped
+fn unescape_html(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(id: &str, channel: &str, seconds: Option<u64>) -> FoundVideo {
+        FoundVideo {
+            video_id: id.to_string(),
+            title: id.to_string(),
+            channel: channel.to_string(),
+            duration: seconds.map(Duration::from_secs),
+            live: false,
+        }
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("PT4M13S"), Some(Duration::from_secs(253)));
+        assert_eq!(parse_duration("PT1H2M3S"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_duration("PT3M"), Some(Duration::from_secs(180)));
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        assert_eq!(parse_duration("P1D"), None);
+        assert_eq!(parse_duration("PT12"), None);
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(Duration::from_secs(45)), "0:45");
+        assert_eq!(format_duration(Duration::from_secs(253)), "4:13");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1:02:03");
+    }
+
+    #[test]
+    fn prefers_song_length_over_compilations() {
+        let videos = [
+            video("compilation", "Fan Channel", Some(2 * 60 * 60)),
+            video("song", "Fan Channel", Some(200)),
+        ];
+        assert_eq!(best_match(&videos).unwrap().video_id, "song");
+    }
+
+    #[test]
+    fn prefers_topic_channels() {
+        let videos = [
+            video("video", "ArtistVEVO", Some(300)),
+            video("audio", "Artist - Topic", Some(240)),
+        ];
+        assert_eq!(best_match(&videos).unwrap().video_id, "audio");
+    }
+
+    #[test]
+    fn keeps_view_order_on_ties() {
+        let videos = [video("first", "A", None), video("second", "B", None)];
+        assert_eq!(best_match(&videos).unwrap().video_id, "first");
+    }
+
+    #[test]
+    fn ranks_every_video() {
+        let videos = vec![
+            video("compilation", "Fan Channel", Some(2 * 60 * 60)),
+            video("first", "A", None),
+            video("audio", "Artist - Topic", Some(240)),
+            video("second", "B", None),
+        ];
+        let ids: Vec<_> = rank(videos)
+            .into_iter()
+            .map(|video| video.video_id)
+            .collect();
+        assert_eq!(ids, ["audio", "first", "second", "compilation"]);
+    }
+
+    fn details(json: &str) -> VideoContentDetails {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn detects_age_restrictions() {
+        let video =
+            details(r#"{"duration": "PT3M", "contentRating": {"ytRating": "ytAgeRestricted"}}"#);
+        assert_eq!(restriction(&video, "US"), Some(Restriction::AgeRestricted));
+        assert_eq!(restriction(&details(r#"{"duration": "PT3M"}"#), "US"), None);
+    }
+
+    #[test]
+    fn detects_region_blocks() {
+        let blocked =
+            details(r#"{"duration": "PT3M", "regionRestriction": {"blocked": ["DE", "us"]}}"#);
+        assert_eq!(
+            restriction(&blocked, "US"),
+            Some(Restriction::RegionBlocked)
+        );
+        assert_eq!(restriction(&blocked, "RO"), None);
+
+        let allowed = details(r#"{"duration": "PT3M", "regionRestriction": {"allowed": ["RO"]}}"#);
+        assert_eq!(
+            restriction(&allowed, "US"),
+            Some(Restriction::RegionBlocked)
+        );
+        assert_eq!(restriction(&allowed, "RO"), None);
+    }
+
+    #[test]
+    fn unescapes_entities() {
+        assert_eq!(unescape_html("Tom &amp; Jerry&#39;s"), "Tom & Jerry's");
+    }
+}
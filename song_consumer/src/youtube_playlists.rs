@@ -0,0 +1,178 @@
+// Creates YouTube playlists of the songs users sent, on their own channels.
+// The bot signs them in and saves their tokens, which are refreshed here
+
+use crate::{
+    config::Config,
+    error::{self, SongError},
+    http::{self, Client},
+};
+use reqwest::StatusCode;
+use rustin_models::{oauth::TokenResponse, youtube::PlaylistResource};
+use rustin_storage::{OAuthProvider, OAuthToken, Storage};
+use serde_json::json;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const API_URL: &str = "https://www.googleapis.com/youtube/v3";
+
+// A song that was found on YouTube
+pub struct Video {
+    pub title: String,
+    pub video_id: String,
+}
+
+pub struct CreatedPlaylist {
+    pub id: String,
+    // Titles of the videos YouTube wouldn't add
+    pub not_added: Vec<String>,
+}
+
+impl CreatedPlaylist {
+    pub fn youtube_url(&self) -> String {
+        format!("https://www.youtube.com/playlist?list={}", self.id)
+    }
+
+    pub fn youtube_music_url(&self) -> String {
+        format!("https://music.youtube.com/playlist?list={}", self.id)
+    }
+}
+
+pub struct YouTubePlaylists {
+    client: Client,
+    client_id: String,
+    client_secret: String,
+    storage: Arc<Storage>,
+}
+
+impl YouTubePlaylists {
+    // Needs the OAuth client the bot signs users in with, and the database
+    // the bot keeps their tokens in
+    pub fn from_config(config: &Config, storage: Option<Arc<Storage>>) -> Option<Self> {
+        Some(Self {
+            client: http::client(),
+            client_id: config.google_client_id.clone()?,
+            client_secret: config.google_client_secret.clone()?,
+            storage: storage?,
+        })
+    }
+
+    // A private playlist of the videos, in order. Once it exists, videos
+    // that fail to be added are skipped, so a retry doesn't create it twice
+    pub async fn create(
+        &self,
+        user_id: i64,
+        title: &str,
+        videos: &[Video],
+    ) -> Result<CreatedPlaylist, SongError> {
+        let token = self.access_token(user_id).await?;
+        let response = self
+            .client
+            .post(format!("{}/playlists?part=snippet,status", API_URL))
+            .bearer_auth(&token)
+            .json(&json!({
+                "snippet": { "title": title },
+                "status": { "privacyStatus": "private" },
+            }))
+            .send()
+            .await?;
+        let playlist: PlaylistResource = error::check_youtube_response(response)
+            .await?
+            .json()
+            .await?;
+        log::info!(
+            "Created YouTube playlist {} for user {}",
+            playlist.id,
+            user_id
+        );
+
+        // One at a time, YouTube rejects concurrent inserts into a playlist
+        let mut not_added = Vec::new();
+        for video in videos {
+            if let Err(e) = self.add_video(&token, &playlist.id, &video.video_id).await {
+                log::warn!(
+                    "Failed to add video ID {} to playlist {}: {}",
+                    video.video_id,
+                    playlist.id,
+                    e
+                );
+                not_added.push(video.title.clone());
+            }
+        }
+        Ok(CreatedPlaylist {
+            id: playlist.id,
+            not_added,
+        })
+    }
+
+    async fn add_video(
+        &self,
+        token: &str,
+        playlist_id: &str,
+        video_id: &str,
+    ) -> Result<(), SongError> {
+        let response = self
+            .client
+            .post(format!("{}/playlistItems?part=snippet", API_URL))
+            .bearer_auth(token)
+            .json(&json!({
+                "snippet": {
+                    "playlistId": playlist_id,
+                    "resourceId": { "kind": "youtube#video", "videoId": video_id },
+                },
+            }))
+            .send()
+            .await?;
+        error::check_youtube_response(response).await?;
+        Ok(())
+    }
+
+    // The user's access token, refreshed once it's about to expire. A login
+    // the user revoked is forgotten, so the bot asks them to sign in again
+    async fn access_token(&self, user_id: i64) -> Result<String, SongError> {
+        let Some(token) = self
+            .storage
+            .oauth_token(user_id, OAuthProvider::YouTube)
+            .await?
+        else {
+            return Err(SongError::NotSignedIn);
+        };
+        if SystemTime::now() < token.expires_at {
+            return Ok(token.access_token);
+        }
+
+        let response = self
+            .client
+            .post(TOKEN_URL)
+            .form(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", token.refresh_token.as_str()),
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+            ])
+            .send()
+            .await?;
+        if response.status() == StatusCode::BAD_REQUEST {
+            log::info!("YouTube login of user {} was revoked", user_id);
+            self.storage
+                .delete_oauth_token(user_id, OAuthProvider::YouTube)
+                .await?;
+            return Err(SongError::NotSignedIn);
+        }
+        let response: TokenResponse = response.error_for_status()?.json().await?;
+
+        // Renewed a minute early, so it doesn't expire halfway through
+        let token = OAuthToken {
+            access_token: response.access_token,
+            refresh_token: response.refresh_token.unwrap_or(token.refresh_token),
+            expires_at: SystemTime::now()
+                + Duration::from_secs(response.expires_in.saturating_sub(60)),
+        };
+        self.storage
+            .save_oauth_token(user_id, OAuthProvider::YouTube, &token)
+            .await?;
+        Ok(token.access_token)
+    }
+}
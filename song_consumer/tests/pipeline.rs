@@ -0,0 +1,263 @@
+// Runs the consumer binary against a RabbitMQ container, with mock servers
+// standing in for the YouTube Data API and tomp3. Needs Docker, so it's
+// ignored by default: cargo test -p song_consumer --test pipeline -- --ignored
+
+use futures_util::StreamExt;
+use lapin::{
+    options::{BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, QueueDeclareOptions},
+    types::{AMQPValue, FieldTable, ShortString},
+    BasicProperties, Channel, Connection, ConnectionProperties,
+};
+use rustin_models::{MessageBody, RabbitMessage, REQUEST_ID_HEADER};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    net::TcpListener,
+    path::PathBuf,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+use testcontainers_modules::{rabbitmq::RabbitMq, testcontainers::runners::AsyncRunner};
+use tokio::process::{Child, Command};
+use wiremock::{
+    matchers::{body_string_contains, method, path, query_param},
+    Mock, MockServer, ResponseTemplate,
+};
+
+const VIDEO_ID: &str = "dQw4w9WgXcQ";
+const CHAT_ID: i64 = 42;
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(60);
+
+async fn mock_youtube() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/youtube/v3/search"))
+        .and(query_param("key", "test-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "items": [{
+                "id": { "videoId": VIDEO_ID },
+                "snippet": {
+                    "title": "Rick Astley - Never Gonna Give You Up (Official Video)",
+                    "channelTitle": "Rick Astley",
+                    "liveBroadcastContent": "none"
+                }
+            }]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/youtube/v3/videos"))
+        .and(query_param("id", VIDEO_ID))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "items": [{
+                "id": VIDEO_ID,
+                "contentDetails": { "duration": "PT3M33S" }
+            }]
+        })))
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn mock_tomp3() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/ajax/search"))
+        .and(body_string_contains(VIDEO_ID))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "links": {
+                "mp3": {
+                    "mp3128": { "k": "k128" },
+                    "mp3320": { "k": "k320" }
+                }
+            }
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/ajax/convert"))
+        .and(body_string_contains(format!("vid={}", VIDEO_ID)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "dlink": format!("{}/dl/{}", server.uri(), VIDEO_ID)
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    server
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("no free port")
+}
+
+// The consumer loads .env from its working directory, so it runs in a
+// directory of its own next to its configuration
+fn spawn_consumer(rabbit_address: &str, youtube: &MockServer, tomp3: &MockServer) -> Child {
+    let dir: PathBuf = std::env::temp_dir().join(format!("song_consumer-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(".env"), "").unwrap();
+    let config = format!(
+        "rabbit_address = \"{}\"\n\
+         google_vision_api_key = \"test-key\"\n\
+         mp3_provider = \"tomp3\"\n\
+         http_addr = \"127.0.0.1:{}\"\n\
+         [host_overrides]\n\
+         \"www.googleapis.com\" = \"{}\"\n\
+         \"tomp3.cc\" = \"{}\"\n",
+        rabbit_address,
+        free_port(),
+        youtube.uri(),
+        tomp3.uri()
+    );
+    std::fs::write(dir.join("song_consumer.toml"), config).unwrap();
+
+    Command::new(env!("CARGO_BIN_EXE_song_consumer"))
+        .current_dir(&dir)
+        .env("CONFIG_FILE", dir.join("song_consumer.toml"))
+        .env("RUST_LOG", "info")
+        .env_remove("DATABASE_URL")
+        .env_remove("REDIS_URL")
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("failed to start song_consumer")
+}
+
+// The consumer declares the queues once it's connected. A passive declare
+// of a missing queue closes the channel, so every attempt opens a new one
+async fn wait_for_queue(connection: &Connection, queue: &str) {
+    let started = Instant::now();
+    loop {
+        let channel = connection.create_channel().await.unwrap();
+        let declared = channel
+            .queue_declare(
+                queue,
+                QueueDeclareOptions {
+                    passive: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await;
+        if declared.is_ok() {
+            return;
+        }
+        assert!(
+            started.elapsed() < STARTUP_TIMEOUT,
+            "song_consumer didn't declare {}",
+            queue
+        );
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+async fn publish_request(channel: &Channel, request_id: &str, text: &str) {
+    let message = RabbitMessage::new(
+        CHAT_ID,
+        MessageBody::TextRequest {
+            text: text.to_string(),
+            clips: BTreeMap::new(),
+        },
+    );
+    let mut headers = FieldTable::default();
+    headers.insert(
+        REQUEST_ID_HEADER.into(),
+        AMQPValue::LongString(request_id.into()),
+    );
+    channel
+        .basic_publish(
+            "",
+            "Music",
+            BasicPublishOptions::default(),
+            &serde_json::to_vec(&message).unwrap(),
+            BasicProperties::default()
+                .with_headers(headers)
+                .with_message_id(request_id.into()),
+        )
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+}
+
+// The first reply that isn't a progress update
+async fn next_reply(channel: &Channel) -> (RabbitMessage, Option<String>) {
+    let mut consumer = channel
+        .basic_consume(
+            "Reply",
+            "pipeline-test",
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await
+        .unwrap();
+    let reply = async {
+        while let Some(delivery) = consumer.next().await {
+            let delivery = delivery.unwrap();
+            delivery.ack(BasicAckOptions::default()).await.unwrap();
+            let message: RabbitMessage = serde_json::from_slice(&delivery.data).unwrap();
+            if matches!(message.body, MessageBody::Progress { .. }) {
+                continue;
+            }
+            let request_id = delivery
+                .properties
+                .headers()
+                .as_ref()
+                .and_then(|headers| {
+                    headers
+                        .inner()
+                        .get(&ShortString::from(REQUEST_ID_HEADER))
+                        .cloned()
+                })
+                .and_then(|value| match value {
+                    AMQPValue::LongString(id) => Some(id.to_string()),
+                    _ => None,
+                });
+            return (message, request_id);
+        }
+        panic!("the Reply consumer was closed");
+    };
+    tokio::time::timeout(REPLY_TIMEOUT, reply)
+        .await
+        .expect("no reply from song_consumer")
+}
+
+#[tokio::test]
+#[ignore = "needs Docker"]
+async fn converts_a_text_request_into_a_download_link() {
+    let rabbit = RabbitMq::default().start().await.unwrap();
+    let rabbit_address = format!(
+        "amqp://guest:guest@{}:{}/%2f",
+        rabbit.get_host().await.unwrap(),
+        rabbit.get_host_port_ipv4(5672).await.unwrap()
+    );
+    let youtube = mock_youtube().await;
+    let tomp3 = mock_tomp3().await;
+    let _consumer = spawn_consumer(&rabbit_address, &youtube, &tomp3);
+
+    let connection = Connection::connect(&rabbit_address, ConnectionProperties::default())
+        .await
+        .unwrap();
+    wait_for_queue(&connection, "Reply").await;
+    let channel = connection.create_channel().await.unwrap();
+    publish_request(
+        &channel,
+        "pipeline-test-1",
+        "Rick Astley - Never Gonna Give You Up",
+    )
+    .await;
+
+    let (reply, request_id) = next_reply(&channel).await;
+    assert_eq!(reply.chat_id, CHAT_ID);
+    assert_eq!(request_id.as_deref(), Some("pipeline-test-1"));
+    let MessageBody::Result { text } = reply.body else {
+        panic!("expected a result, got {:?}", reply.body);
+    };
+    assert!(text.contains("Never Gonna Give You Up"), "{}", text);
+    assert!(text.contains(&format!("/dl/{}", VIDEO_ID)), "{}", text);
+    tomp3.verify().await;
+}